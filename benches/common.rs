@@ -10,6 +10,17 @@ use std::path::Path;
 #[allow(dead_code)]
 const X: TableDefinition<&[u8], &[u8]> = TableDefinition::new("x");
 
+// Prime, so it is coprime with any number of keys which is not a multiple of it, and multiplying by
+// it permutes the keys
+#[allow(dead_code)]
+pub const STRIDE: u64 = 7919;
+
+// Maps 0..elements onto itself in a pseudo-random order
+#[allow(dead_code)]
+pub fn random_key(i: u64, elements: u64) -> u64 {
+    i * STRIDE % elements
+}
+
 pub trait BenchDatabase {
     type W<'db>: BenchWriteTransaction
     where
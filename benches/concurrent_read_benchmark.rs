@@ -0,0 +1,78 @@
+use std::env::current_dir;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use tempfile::NamedTempFile;
+
+use redb::{Database, Durability, ReadableTable, TableDefinition};
+use std::time::Instant;
+
+const ELEMENTS: u64 = 100_000;
+const READERS: usize = 8;
+const READS_PER_READER: u64 = 200_000;
+
+const TABLE: TableDefinition<u64, u64> = TableDefinition::new("x");
+
+// Each reader begins a new read transaction for every lookup, so that reading the latest commit
+// from the header is on the hot path
+fn run_readers(db: &Database, writer: bool) {
+    let done = AtomicBool::new(false);
+    let start = Instant::now();
+    thread::scope(|s| {
+        if writer {
+            s.spawn(|| {
+                let mut i = 0;
+                while !done.load(Ordering::Relaxed) {
+                    let mut txn = db.begin_write().unwrap();
+                    txn.set_durability(Durability::None);
+                    txn.open_table(TABLE)
+                        .unwrap()
+                        .insert(i % ELEMENTS, i)
+                        .unwrap();
+                    txn.commit().unwrap();
+                    i += 1;
+                }
+            });
+        }
+        let readers: Vec<_> = (0..READERS)
+            .map(|id| {
+                s.spawn(move || {
+                    for i in 0..READS_PER_READER {
+                        let txn = db.begin_read().unwrap();
+                        let table = txn.open_table(TABLE).unwrap();
+                        let key = (i * 7919 + id as u64) % ELEMENTS;
+                        assert!(table.get(key).unwrap().is_some());
+                    }
+                })
+            })
+            .collect();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        done.store(true, Ordering::Relaxed);
+    });
+    let duration = Instant::now() - start;
+    println!(
+        "{} readers{}: {} transactions in {}ms",
+        READERS,
+        if writer { " and a writer" } else { "" },
+        READERS as u64 * READS_PER_READER,
+        duration.as_millis()
+    );
+}
+
+fn main() {
+    let tmpfile: NamedTempFile = NamedTempFile::new_in(current_dir().unwrap()).unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(TABLE).unwrap();
+        for i in 0..ELEMENTS {
+            table.insert(i, i).unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+
+    run_readers(&db, false);
+    run_readers(&db, true);
+}
@@ -0,0 +1,96 @@
+use std::env::current_dir;
+use tempfile::NamedTempFile;
+
+#[allow(dead_code)]
+mod common;
+use common::*;
+
+use redb::{Database, Durability, ReadableTable, TableDefinition};
+use std::time::Instant;
+
+const ELEMENTS: u64 = 1_000_000;
+const VALUE_SIZE: usize = 32;
+const WRITE_BATCH: u64 = 1000;
+
+const TABLE: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+
+// Loads the keys in order, and then reads them in a random order
+fn read_heavy(fill_target: f64) {
+    let tmpfile: NamedTempFile = NamedTempFile::new_in(current_dir().unwrap()).unwrap();
+    let db = Database::builder()
+        .set_node_fill_target(fill_target)
+        .create(tmpfile.path())
+        .unwrap();
+
+    let value = vec![0xFFu8; VALUE_SIZE];
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(TABLE).unwrap();
+        for i in 0..ELEMENTS {
+            table.insert(i, value.as_slice()).unwrap();
+        }
+    }
+    let stats = write_txn.stats().unwrap();
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(TABLE).unwrap();
+    let start = Instant::now();
+    let mut checksum = 0u64;
+    for i in 0..ELEMENTS {
+        let key = random_key(i, ELEMENTS);
+        checksum += table.get(key).unwrap().unwrap().value().len() as u64;
+    }
+    let duration = Instant::now() - start;
+    println!(
+        "fill target {fill_target}: read-heavy: {} random reads in {}ms, tree height {}, {} leaf pages ({checksum})",
+        ELEMENTS,
+        duration.as_millis(),
+        stats.tree_height(),
+        stats.leaf_pages(),
+    );
+}
+
+// Inserts the keys in a random order, in many small transactions
+fn write_heavy(fill_target: f64) {
+    let tmpfile: NamedTempFile = NamedTempFile::new_in(current_dir().unwrap()).unwrap();
+    let db = Database::builder()
+        .set_node_fill_target(fill_target)
+        .create(tmpfile.path())
+        .unwrap();
+
+    let value = vec![0xFFu8; VALUE_SIZE];
+    let start = Instant::now();
+    for batch in 0..(ELEMENTS / WRITE_BATCH) {
+        let mut write_txn = db.begin_write().unwrap();
+        write_txn.set_durability(Durability::None);
+        {
+            let mut table = write_txn.open_table(TABLE).unwrap();
+            for i in (batch * WRITE_BATCH)..((batch + 1) * WRITE_BATCH) {
+                let key = random_key(i, ELEMENTS);
+                table.insert(key, value.as_slice()).unwrap();
+            }
+        }
+        write_txn.commit().unwrap();
+    }
+    let duration = Instant::now() - start;
+    let write_txn = db.begin_write().unwrap();
+    let stats = write_txn.stats().unwrap();
+    write_txn.abort().unwrap();
+    println!(
+        "fill target {fill_target}: write-heavy: {} random inserts in {}ms, tree height {}, {} leaf pages",
+        ELEMENTS,
+        duration.as_millis(),
+        stats.tree_height(),
+        stats.leaf_pages(),
+    );
+}
+
+fn main() {
+    for fill_target in [0.3, 0.5, 0.7, 0.9] {
+        read_heavy(fill_target);
+    }
+    for fill_target in [0.3, 0.5, 0.7, 0.9] {
+        write_heavy(fill_target);
+    }
+}
@@ -0,0 +1,64 @@
+use std::env::current_dir;
+use tempfile::NamedTempFile;
+
+#[allow(dead_code)]
+mod common;
+use common::*;
+
+use redb::{Database, ReadableTable, TableDefinition};
+use std::time::Instant;
+
+const ELEMENTS: u64 = 1_000_000;
+
+// 16 byte keys, such as UUIDs
+fn key(i: u64) -> [u8; 16] {
+    u128::from(random_key(i, ELEMENTS)).to_be_bytes()
+}
+
+fn run(name: &str, table: TableDefinition<&[u8], u64>) {
+    let tmpfile: NamedTempFile = NamedTempFile::new_in(current_dir().unwrap()).unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+
+    let start = Instant::now();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(table).unwrap();
+        for i in 0..ELEMENTS {
+            table.insert(key(i).as_slice(), i).unwrap();
+        }
+    }
+    let stats = write_txn.stats().unwrap();
+    write_txn.commit().unwrap();
+    let write_duration = Instant::now() - start;
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(table).unwrap();
+    let start = Instant::now();
+    let mut checksum = 0u64;
+    for i in 0..ELEMENTS {
+        checksum += table.get(key(i).as_slice()).unwrap().unwrap().value();
+    }
+    let read_duration = Instant::now() - start;
+
+    // Every page but the root is the child of a branch page
+    let fanout =
+        (stats.leaf_pages() + stats.branch_pages() - 1) as f64 / stats.branch_pages() as f64;
+    println!(
+        "{name}: {} random inserts in {}ms, {} random reads in {}ms, tree height {}, {} leaf pages, {} branch pages, fanout {fanout:.1} ({checksum})",
+        ELEMENTS,
+        write_duration.as_millis(),
+        ELEMENTS,
+        read_duration.as_millis(),
+        stats.tree_height(),
+        stats.leaf_pages(),
+        stats.branch_pages(),
+    );
+}
+
+fn main() {
+    run("variable width keys", TableDefinition::new("x"));
+    run(
+        "fixed width keys",
+        TableDefinition::new("x").with_fixed_key_width(16),
+    );
+}
@@ -0,0 +1,61 @@
+use std::env::current_dir;
+use tempfile::NamedTempFile;
+
+#[allow(dead_code)]
+mod common;
+use common::*;
+
+use redb::{Database, ReadableTable, TableDefinition};
+use std::time::Instant;
+
+const ELEMENTS: u64 = 1_000_000;
+const VALUE_SIZE: usize = 128;
+
+const TABLE: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+
+fn main() {
+    let tmpfile: NamedTempFile = NamedTempFile::new_in(current_dir().unwrap()).unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+
+    let value = vec![0xFFu8; VALUE_SIZE];
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(TABLE).unwrap();
+        for i in 0..ELEMENTS {
+            table.insert(i, value.as_slice()).unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(TABLE).unwrap();
+
+    let start = Instant::now();
+    let mut checksum = 0u64;
+    for i in 0..ELEMENTS {
+        let key = random_key(i, ELEMENTS);
+        let owned = table.get(key).unwrap().unwrap().value().to_vec();
+        checksum += owned.len() as u64;
+    }
+    let duration = Instant::now() - start;
+    println!(
+        "fresh allocation: {} random reads in {}ms ({checksum})",
+        ELEMENTS,
+        duration.as_millis()
+    );
+
+    let start = Instant::now();
+    let mut checksum = 0u64;
+    let mut buffer = vec![];
+    for i in 0..ELEMENTS {
+        let key = random_key(i, ELEMENTS);
+        assert!(table.get_into(key, &mut buffer).unwrap());
+        checksum += buffer.len() as u64;
+    }
+    let duration = Instant::now() - start;
+    println!(
+        "reused buffer: {} random reads in {}ms ({checksum})",
+        ELEMENTS,
+        duration.as_millis()
+    );
+}
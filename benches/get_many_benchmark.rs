@@ -0,0 +1,75 @@
+use std::env::current_dir;
+use tempfile::NamedTempFile;
+
+#[allow(dead_code)]
+mod common;
+use common::*;
+
+use redb::{Database, ReadableTable, TableDefinition};
+use std::time::Instant;
+
+const ELEMENTS: u64 = 1_000_000;
+const VALUE_SIZE: usize = 128;
+const BATCHES: u64 = 20_000;
+const BATCH_SIZE: u64 = 100;
+// Keys of a batch are spread over this many consecutive keys, so that they share most of the
+// pages on their paths
+const CLUSTER_SPAN: u64 = 2000;
+
+const TABLE: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+
+fn batch(i: u64) -> Vec<u64> {
+    let base = i * STRIDE % (ELEMENTS - CLUSTER_SPAN);
+    (0..BATCH_SIZE)
+        .map(|j| base + j * STRIDE % CLUSTER_SPAN)
+        .collect()
+}
+
+fn main() {
+    let tmpfile: NamedTempFile = NamedTempFile::new_in(current_dir().unwrap()).unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+
+    let value = vec![0xFFu8; VALUE_SIZE];
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(TABLE).unwrap();
+        for i in 0..ELEMENTS {
+            table.insert(i, value.as_slice()).unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(TABLE).unwrap();
+    let batches: Vec<Vec<u64>> = (0..BATCHES).map(batch).collect();
+
+    let start = Instant::now();
+    let mut checksum = 0u64;
+    for keys in batches.iter() {
+        for key in keys {
+            checksum += table.get(key).unwrap().unwrap().value().len() as u64;
+        }
+    }
+    let duration = Instant::now() - start;
+    println!(
+        "individual lookups: {} batches of {} clustered keys in {}ms ({checksum})",
+        BATCHES,
+        BATCH_SIZE,
+        duration.as_millis()
+    );
+
+    let start = Instant::now();
+    let mut checksum = 0u64;
+    for keys in batches.iter() {
+        for value in table.get_many(keys).unwrap() {
+            checksum += value.unwrap().value().len() as u64;
+        }
+    }
+    let duration = Instant::now() - start;
+    println!(
+        "get_many: {} batches of {} clustered keys in {}ms ({checksum})",
+        BATCHES,
+        BATCH_SIZE,
+        duration.as_millis()
+    );
+}
@@ -0,0 +1,48 @@
+use std::env::current_dir;
+use tempfile::NamedTempFile;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use redb::{Database, TableDefinition};
+use std::time::Instant;
+
+const ELEMENTS: u64 = 5_000_000;
+const RNG_SEED: u64 = 3;
+
+const TABLE: TableDefinition<u128, u128> = TableDefinition::new("x");
+
+fn main() {
+    let mut rng = StdRng::seed_from_u64(RNG_SEED);
+    let tmpfile: NamedTempFile = NamedTempFile::new_in(current_dir().unwrap()).unwrap();
+    let mut db = Database::builder().create(tmpfile.path()).unwrap();
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(TABLE).unwrap();
+        for _ in 0..ELEMENTS {
+            let value: u128 = rng.gen();
+            table.insert(value, value).unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+
+    let max_threads = std::thread::available_parallelism()
+        .map(|x| x.get())
+        .unwrap_or(1);
+    let mut threads = 1;
+    loop {
+        let start = Instant::now();
+        db.check_integrity_with_threads(threads).unwrap();
+        let duration = Instant::now() - start;
+        println!(
+            "integrity check ({} threads): {} pairs in {}ms",
+            threads,
+            ELEMENTS,
+            duration.as_millis()
+        );
+        if threads >= max_threads {
+            break;
+        }
+        threads = std::cmp::min(threads * 2, max_threads);
+    }
+}
@@ -0,0 +1,55 @@
+use std::env::current_dir;
+use tempfile::NamedTempFile;
+
+use redb::{Database, ReadableTable, TableDefinition};
+use std::time::Instant;
+
+const ELEMENTS: u64 = 100_000;
+const VALUE_SIZE: usize = 4096;
+
+const TABLE: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+
+fn main() {
+    let tmpfile: NamedTempFile = NamedTempFile::new_in(current_dir().unwrap()).unwrap();
+    let db = Database::builder().create(tmpfile.path()).unwrap();
+
+    let value = vec![0xFFu8; VALUE_SIZE];
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(TABLE).unwrap();
+        for i in 0..ELEMENTS {
+            table.insert(i, value.as_slice()).unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(TABLE).unwrap();
+
+    let start = Instant::now();
+    let mut checksum = 0u64;
+    for entry in table.iter().unwrap() {
+        let (key, value) = entry.unwrap();
+        checksum += key.value() + value.value().len() as u64;
+    }
+    let duration = Instant::now() - start;
+    println!(
+        "full iteration: {} pairs in {}ms ({})",
+        ELEMENTS,
+        duration.as_millis(),
+        checksum
+    );
+
+    let start = Instant::now();
+    let mut checksum = 0u64;
+    for key in table.keys().unwrap() {
+        checksum += key.unwrap().value();
+    }
+    let duration = Instant::now() - start;
+    println!(
+        "key iteration: {} keys in {}ms ({})",
+        ELEMENTS,
+        duration.as_millis(),
+        checksum
+    );
+}
@@ -0,0 +1,64 @@
+use std::env::current_dir;
+use tempfile::NamedTempFile;
+
+#[allow(dead_code)]
+mod common;
+use common::*;
+
+use redb::{Database, ReadableTable, RedbValue, TableDefinition};
+use std::time::Instant;
+
+const ELEMENTS: u64 = 1_000_000;
+
+// Small values, such as counters, are stored in the leaf next to their keys either way. Values of
+// a variable width type also store their length with each entry
+fn run<V: RedbValue + 'static>(name: &str, value: impl Fn(u64) -> V::SelfType<'static>) {
+    let tmpfile: NamedTempFile = NamedTempFile::new_in(current_dir().unwrap()).unwrap();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let definition: TableDefinition<u64, V> = TableDefinition::new("x");
+
+    let start = Instant::now();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(definition).unwrap();
+        for i in 0..ELEMENTS {
+            table.insert(random_key(i, ELEMENTS), value(i)).unwrap();
+        }
+    }
+    let stats = write_txn.stats().unwrap();
+    write_txn.commit().unwrap();
+    let write_duration = Instant::now() - start;
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(definition).unwrap();
+    let start = Instant::now();
+    let mut checksum = 0usize;
+    for i in 0..ELEMENTS {
+        let value = table.get(random_key(i, ELEMENTS)).unwrap().unwrap();
+        checksum += V::as_bytes(&value.value()).as_ref().len();
+    }
+    let read_duration = Instant::now() - start;
+
+    println!(
+        "{name}: {} random inserts in {}ms, {} random reads in {}ms, {} leaf pages, {} stored bytes, {} fragmented bytes ({checksum})",
+        ELEMENTS,
+        write_duration.as_millis(),
+        ELEMENTS,
+        read_duration.as_millis(),
+        stats.leaf_pages(),
+        stats.stored_bytes(),
+        stats.fragmented_bytes(),
+    );
+}
+
+fn main() {
+    // One byte flags, stored with and without their lengths
+    run::<u8>("fixed width values", |i| (i % 2) as u8);
+    run::<&[u8]>("variable width values", |i| {
+        if i % 2 == 0 {
+            [1u8].as_slice()
+        } else {
+            [0u8].as_slice()
+        }
+    });
+}
@@ -0,0 +1,59 @@
+use std::env::current_dir;
+use tempfile::NamedTempFile;
+
+use redb::{Database, TableDefinition, WriteTransaction};
+use std::time::Instant;
+
+const ELEMENTS: u64 = 2_000_000;
+const VALUE_SIZE: usize = 24;
+const RUNS: usize = 3;
+
+const TABLE: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+
+fn bulk_insert(txn: WriteTransaction) -> usize {
+    let value = vec![0xFFu8; VALUE_SIZE];
+    {
+        let mut table = txn.open_table(TABLE).unwrap();
+        for i in 0..ELEMENTS {
+            // Spread the keys, so that inserts split pages all over the tree
+            let key = i.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+            table.insert(key, value.as_slice()).unwrap();
+        }
+    }
+    txn.commit_with_stats().unwrap().allocated()
+}
+
+fn main() {
+    // Size the hint from a run without it
+    let expected_pages = {
+        let tmpfile: NamedTempFile = NamedTempFile::new_in(current_dir().unwrap()).unwrap();
+        let db = Database::create(tmpfile.path()).unwrap();
+        bulk_insert(db.begin_write().unwrap())
+    };
+
+    for _ in 0..RUNS {
+        for hint in [false, true] {
+            let tmpfile: NamedTempFile = NamedTempFile::new_in(current_dir().unwrap()).unwrap();
+            let db = Database::create(tmpfile.path()).unwrap();
+
+            let start = Instant::now();
+            let txn = if hint {
+                db.begin_write_with_hint(expected_pages).unwrap()
+            } else {
+                db.begin_write().unwrap()
+            };
+            bulk_insert(txn);
+            let duration = Instant::now() - start;
+            println!(
+                "bulk insert of {} entries{}: {}ms",
+                ELEMENTS,
+                if hint {
+                    format!(" with a hint of {expected_pages} pages")
+                } else {
+                    String::new()
+                },
+                duration.as_millis()
+            );
+        }
+    }
+}
@@ -0,0 +1,60 @@
+#![no_main]
+
+use libfuzzer_sys::arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use redb::{Database, ReadableTable, TableDefinition};
+use std::fs;
+use tempfile::NamedTempFile;
+
+const TABLE_DEF: TableDefinition<u64, &[u8]> = TableDefinition::new("fuzz_table");
+
+#[derive(Arbitrary, Debug)]
+struct Patch {
+    offset: u32,
+    value: u8,
+}
+
+#[derive(Arbitrary, Debug)]
+struct FuzzOpen {
+    entries: u8,
+    patches: Vec<Patch>,
+}
+
+// Opening a corrupted file must return an error rather than panicking. The file is first written as
+// a valid database so that the magic number is present and the patches land in real structures
+fn exec_open(config: &FuzzOpen) -> Result<(), redb::Error> {
+    let file = NamedTempFile::new().unwrap();
+    {
+        let db = Database::create(file.path())?;
+        let txn = db.begin_write()?;
+        {
+            let mut table = txn.open_table(TABLE_DEF)?;
+            for i in 0..(config.entries as u64) {
+                table.insert(i, [0xAB; 100].as_slice())?;
+            }
+        }
+        txn.commit()?;
+    }
+
+    let mut data = fs::read(file.path()).unwrap();
+    for patch in config.patches.iter() {
+        let offset = patch.offset as usize % data.len();
+        data[offset] = patch.value;
+    }
+    // Keep the magic number intact, otherwise the file is treated as a new database
+    data[..9].copy_from_slice(b"redb\x1A\x0A\xA9\x0D\x0A");
+    fs::write(file.path(), &data).unwrap();
+
+    let db = Database::open(file.path())?;
+    let txn = db.begin_read()?;
+    let table = txn.open_table(TABLE_DEF)?;
+    for entry in table.iter()? {
+        entry?;
+    }
+
+    Ok(())
+}
+
+fuzz_target!(|config: FuzzOpen| {
+    let _ = exec_open(&config);
+});
@@ -1,28 +1,43 @@
 use crate::transaction_tracker::{SavepointId, TransactionId, TransactionTracker};
 use crate::tree_store::{
-    AllPageNumbersBtreeIter, BtreeRangeIter, Checksum, FreedPageList, FreedTableKey,
-    InternalTableDefinition, PageHint, PageNumber, RawBtree, SerializedSavepoint, TableTree,
-    TableType, TransactionalMemory, PAGE_SIZE,
+    holds_database, is_zeroed, migrate, reconcile_mirror, restore_backup, stored_format_version,
+    write_backup,
+};
+use crate::tree_store::{
+    AllPageNumbersBtreeIter, BtreeRangeIter, Checksum, ColdPageCallback, EmbeddedRange,
+    FreedPageList, FreedTableKey, GrowthCallback, InternalTableDefinition, PageHint, PageNumber,
+    RawBtree, RawLeafBuilder, SerializedSavepoint, TableTree, TableType, TransactionalMemory,
+    DEFAULT_NODE_FILL_TARGET, PAGE_SIZE,
 };
 use crate::types::{RedbKey, RedbValue};
 use crate::{
-    CompactionError, DatabaseError, Durability, ReadOnlyTable, ReadableTable, SavepointError,
-    StorageError,
+    AccessGuard, AllocationPolicy, Capacity, CompactionError, CorruptionFinding, CorruptionReport,
+    DatabaseError, Durability, Error, EtlScan, EtlScanToken, GrowthPolicy, MetapageDump,
+    MigrationStep, ReadOnlyTable, ReadableTable, Savepoint, SavepointError, StorageError,
+    TableError, TransactionSlotDump, FILE_FORMAT_VERSION,
 };
-use crate::{ReadTransaction, Result, WriteTransaction};
+use crate::{ChangeKind, ReadTransaction, Result, WriteTransaction};
+use std::borrow::Borrow;
+use std::cmp::max;
+use std::collections::HashSet;
+use std::ffi::OsString;
 use std::fmt::{Display, Formatter};
 use std::fs::{File, OpenOptions};
 use std::io;
-use std::io::ErrorKind;
+use std::io::{ErrorKind, Read, Write};
 use std::marker::PhantomData;
-use std::ops::RangeFull;
-use std::path::Path;
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::{Arc, Mutex};
+use std::ops::{Bound, Range, RangeFull};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, TryLockError};
+use std::time::{Duration, Instant};
 
 use crate::error::TransactionError;
-use crate::multimap_table::parse_subtree_roots;
+use crate::multimap_table::{
+    find_tree_and_subtree_corruption, parse_subtree_roots, verify_tree_and_subtree_checksums,
+};
 use crate::sealed::Sealed;
+use crate::table::{check_entry_size, check_token_key};
 use crate::transactions::SAVEPOINT_TABLE;
 #[cfg(feature = "logging")]
 use log::{info, warn};
@@ -100,6 +115,7 @@ impl Sealed for UntypedMultimapTableHandle {}
 /// that is stored or retreived from the table
 pub struct TableDefinition<'a, K: RedbKey + 'static, V: RedbValue + 'static> {
     name: &'a str,
+    fixed_key_width: Option<usize>,
     _key_type: PhantomData<K>,
     _value_type: PhantomData<V>,
 }
@@ -114,10 +130,28 @@ impl<'a, K: RedbKey + 'static, V: RedbValue + 'static> TableDefinition<'a, K, V>
         assert!(!name.is_empty());
         Self {
             name,
+            fixed_key_width: None,
             _key_type: PhantomData,
             _value_type: PhantomData,
         }
     }
+
+    /// Declare that every key of the table is `width` bytes long
+    ///
+    /// The keys are then stored without their lengths, as for a key type with a fixed width such
+    /// as `&[u8; N]`, so that more of them fit in each page. The width is recorded when the table
+    /// is created, and the table must always be opened with the same width. Inserting a key of any
+    /// other width returns [`crate::StorageError::KeyWrongSize`]. If `K` already has a fixed
+    /// width, `width` must equal it
+    pub const fn with_fixed_key_width(mut self, width: usize) -> Self {
+        self.fixed_key_width = Some(width);
+        self
+    }
+
+    // The fixed width of the table's keys, whether declared for the table or by K
+    pub(crate) fn key_width(&self) -> Option<usize> {
+        self.fixed_key_width.or(K::fixed_width())
+    }
 }
 
 impl<'a, K: RedbKey + 'static, V: RedbValue + 'static> TableHandle for TableDefinition<'a, K, V> {
@@ -238,6 +272,17 @@ pub struct Database {
     next_transaction_id: AtomicTransactionId,
     transaction_tracker: Arc<Mutex<TransactionTracker>>,
     pub(crate) live_write_transaction: Mutex<Option<TransactionId>>,
+    auto_compaction_threshold: Option<f64>,
+    // Cleared when auto-compaction runs, and set again once the free ratio falls well below the
+    // threshold, so that a free ratio which compaction cannot reduce doesn't trigger it on every commit
+    auto_compaction_armed: AtomicBool,
+    compacting: AtomicBool,
+    transaction_epoch: Option<u32>,
+    track_changes: bool,
+    last_commit_changes: Mutex<Vec<ChangeKind>>,
+    // Signalled after each commit
+    commit_signal: Condvar,
+    commit_signal_lock: Mutex<()>,
 }
 
 impl Database {
@@ -254,6 +299,14 @@ impl Database {
         Self::builder().open(path)
     }
 
+    /// Opens an existing redb database, discarding any commit which was never made durable. See
+    /// [`Builder::open_discarding_non_durable`]
+    pub fn open_discarding_non_durable(
+        path: impl AsRef<Path>,
+    ) -> Result<(Database, Option<TransactionSlotDump>), DatabaseError> {
+        Self::builder().open_discarding_non_durable(path)
+    }
+
     pub(crate) fn get_memory(&self) -> &TransactionalMemory {
         &self.mem
     }
@@ -263,7 +316,15 @@ impl Database {
         self.mem.set_crash_countdown(value);
     }
 
-    fn verify_primary_checksums(mem: &TransactionalMemory) -> Result<bool> {
+    /// Simulates a power loss right after the `value`th following fsync: every write made after it
+    /// is discarded when the database is dropped. If `value` is zero, the power loss happens
+    /// immediately
+    #[cfg(any(fuzzing, test))]
+    pub fn set_power_loss_countdown(&self, value: u64) -> Result {
+        self.mem.set_power_loss_countdown(value)
+    }
+
+    fn verify_primary_checksums(mem: &TransactionalMemory, threads: usize) -> Result<bool> {
         if let Some((root, root_checksum)) = mem.get_data_root() {
             if !RawBtree::new(
                 Some((root, root_checksum)),
@@ -271,7 +332,7 @@ impl Database {
                 InternalTableDefinition::fixed_width(),
                 mem,
             )
-            .verify_checksum()?
+            .verify_checksum(threads)?
             {
                 return Ok(false);
             }
@@ -281,14 +342,24 @@ impl Database {
                 BtreeRangeIter::new::<RangeFull, &str>(&(..), Some(root), mem)?;
             for entry in iter {
                 let definition = entry?.value();
-                if let Some((table_root, table_checksum)) = definition.get_root() {
+                if definition.get_type() == TableType::Multimap {
+                    if !verify_tree_and_subtree_checksums(
+                        definition.get_root(),
+                        definition.get_fixed_key_size(),
+                        definition.get_fixed_value_size(),
+                        threads,
+                        mem,
+                    )? {
+                        return Ok(false);
+                    }
+                } else if let Some((table_root, table_checksum)) = definition.get_root() {
                     if !RawBtree::new(
                         Some((table_root, table_checksum)),
                         definition.get_fixed_key_size(),
                         definition.get_fixed_value_size(),
                         mem,
                     )
-                    .verify_checksum()?
+                    .verify_checksum(threads)?
                     {
                         return Ok(false);
                     }
@@ -303,7 +374,7 @@ impl Database {
                 InternalTableDefinition::fixed_width(),
                 mem,
             )
-            .verify_checksum()?
+            .verify_checksum(threads)?
             {
                 return Ok(false);
             }
@@ -320,7 +391,7 @@ impl Database {
                         definition.get_fixed_value_size(),
                         mem,
                     )
-                    .verify_checksum()?
+                    .verify_checksum(threads)?
                     {
                         return Ok(false);
                     }
@@ -335,7 +406,7 @@ impl Database {
                 None,
                 mem,
             )
-            .verify_checksum()?
+            .verify_checksum(threads)?
             {
                 return Ok(false);
             }
@@ -344,27 +415,311 @@ impl Database {
         Ok(true)
     }
 
+    // Records every damaged page of the table tree rooted at `root`, and of the tables in it.
+    // Returns true if they are all intact
+    fn find_table_tree_corruption(
+        root: (PageNumber, Checksum),
+        mem: &TransactionalMemory,
+        findings: &mut Vec<CorruptionFinding>,
+    ) -> Result<bool> {
+        if !RawBtree::new(
+            Some(root),
+            <&str>::fixed_width(),
+            InternalTableDefinition::fixed_width(),
+            mem,
+        )
+        .find_corruption(findings, &mut vec![])?
+        {
+            // The definitions of the tables can't be trusted
+            return Ok(false);
+        }
+
+        let mut intact = true;
+        let iter: BtreeRangeIter<&str, InternalTableDefinition> =
+            BtreeRangeIter::new::<RangeFull, &str>(&(..), Some(root.0), mem)?;
+        for entry in iter {
+            let entry = entry?;
+            let definition = entry.value();
+            let table_root = if let Some(table_root) = definition.get_root() {
+                table_root
+            } else {
+                continue;
+            };
+            if !mem.contains_page(table_root.0) {
+                findings.push(CorruptionFinding::DanglingTableRoot {
+                    table: entry.key().to_string(),
+                    root: u64::from_le_bytes(table_root.0.to_le_bytes()),
+                });
+                intact = false;
+                continue;
+            }
+            let table_intact = if definition.get_type() == TableType::Multimap {
+                find_tree_and_subtree_corruption(
+                    Some(table_root),
+                    definition.get_fixed_key_size(),
+                    definition.get_fixed_value_size(),
+                    mem,
+                    findings,
+                )?
+            } else {
+                RawBtree::new(
+                    Some(table_root),
+                    definition.get_fixed_key_size(),
+                    definition.get_fixed_value_size(),
+                    mem,
+                )
+                .find_corruption(findings, &mut vec![])?
+            };
+            if !table_intact {
+                intact = false;
+            }
+        }
+
+        Ok(intact)
+    }
+
+    /// Rebuild the allocator state from the pages which are reachable from the last commit
+    ///
+    /// Every page referenced by a table, by the tree of pages waiting to be freed, or by a
+    /// persistent savepoint is marked as allocated, and every other page as free. This is the
+    /// same rebuild that runs when a database is opened after a crash, and fixes an allocator state
+    /// which is wrong while the tables are intact. On a consistent database it has no effect.
+    ///
+    /// Returns [`StorageError::Corrupted`], without changing anything, if the last commit fails
+    /// checksum verification. [`Database::check_integrity`] can roll back to the previous commit
+    /// in that case
+    pub fn rebuild_free_list(&mut self) -> Result {
+        if !Self::verify_primary_checksums(&self.mem, 1)? {
+            return Err(StorageError::Corrupted(
+                "The last commit failed checksum verification".to_string(),
+            ));
+        }
+        Self::rebuild_allocator_state(&mut self.mem)?;
+        self.mem.begin_writable()?;
+        // The rebuild was committed as a new transaction
+        self.next_transaction_id = AtomicTransactionId::new(self.mem.next_transaction_id()?);
+        Ok(())
+    }
+
     /// Check the integrity of the database file, and repair it if possible.
     ///
     /// Returns `Ok(true)` if the database passed integrity checks; `Ok(false)` if it failed but was repaired,
     /// and `Err(Corrupted)` if the check failed and the file could not be repaired
     pub fn check_integrity(&mut self) -> Result<bool> {
+        self.check_integrity_with_threads(1)
+    }
+
+    /// Same as [`Database::check_integrity`], but the checksums of each table are verified using up
+    /// to `threads` threads. Rebuilding the allocator state is not parallelized
+    ///
+    /// The result is identical to that of [`Database::check_integrity`]
+    pub fn check_integrity_with_threads(&mut self, threads: usize) -> Result<bool> {
         self.mem.clear_cache_and_reload()?;
 
-        if !self.mem.needs_repair()? && Self::verify_primary_checksums(&self.mem)? {
+        if !self.mem.needs_repair()? && Self::verify_primary_checksums(&self.mem, threads)? {
             return Ok(true);
         }
 
-        Self::do_repair(&mut self.mem)?;
+        Self::do_repair(&mut self.mem, threads)?;
         self.mem.begin_writable()?;
+        // The repair was committed as a new transaction
+        self.next_transaction_id = AtomicTransactionId::new(self.mem.next_transaction_id()?);
 
         Ok(false)
     }
 
+    /// Writes a full backup of the database to `out`
+    ///
+    /// Returns a watermark, which can be passed to [`Database::backup_delta_since`] to back up
+    /// only the changes made after this backup. The watermark is the id of a persistent savepoint,
+    /// which prevents the pages of this backup from being reused. It should be deleted with
+    /// [`WriteTransaction::delete_persistent_savepoint`] once no further deltas will be taken
+    /// from it
+    ///
+    /// Only the contents of tables are backed up. Savepoints and commit metadata are not
+    pub fn backup<W: Write>(&self, out: &mut W) -> Result<u64, Error> {
+        self.backup_inner(None, out)
+    }
+
+    /// Writes the pages which changed since the backup with watermark `since` to `out`
+    ///
+    /// Returns the watermark of this delta. See [`Database::backup`] for details. Use
+    /// [`Database::restore`] to restore a full backup followed by a chain of deltas
+    ///
+    /// Returns [`Error::InvalidSavepoint`] if `since` does not exist
+    pub fn backup_delta_since<W: Write>(&self, since: u64, out: &mut W) -> Result<u64, Error> {
+        self.backup_inner(Some(since), out)
+    }
+
+    fn backup_inner<W: Write>(&self, since: Option<u64>, out: &mut W) -> Result<u64, Error> {
+        let txn = self.begin_write()?;
+        let watermark = txn.persistent_savepoint()?;
+        let savepoint = txn.get_persistent_savepoint(watermark)?;
+        let base = match since.map(|id| txn.get_persistent_savepoint(id)).transpose() {
+            Ok(base) => base,
+            Err(err) => {
+                txn.abort()?;
+                return Err(err.into());
+            }
+        };
+        txn.commit()?;
+
+        // Pages referenced by a savepoint are never freed, so any page reachable from both
+        // savepoints has not changed. A page which was freed & reused since the base is not
+        // reachable from it, and so is included
+        let mut base_pages = HashSet::new();
+        if let Some((root, _)) = base.and_then(|x| x.get_user_root()) {
+            Self::visit_table_tree_pages(root, &self.mem, |pages| {
+                for page in pages {
+                    base_pages.insert(page?);
+                }
+                Ok(())
+            })?;
+        }
+        let mut pages = vec![];
+        if let Some((root, _)) = savepoint.get_user_root() {
+            Self::visit_table_tree_pages(root, &self.mem, |iter| {
+                for page in iter {
+                    let page = page?;
+                    if !base_pages.contains(&page) {
+                        pages.push(page);
+                    }
+                }
+                Ok(())
+            })?;
+        }
+
+        let (header, file_len) = self
+            .mem
+            .backup_header(savepoint.get_user_root(), savepoint.get_transaction_id());
+        write_backup(&self.mem, &header, file_len, since, watermark, &pages, out)?;
+
+        Ok(watermark)
+    }
+
+    /// Restores a database into the file at `path` from a full backup, followed by zero or more
+    /// deltas, in the order they were taken
+    ///
+    /// Any existing contents of the file are overwritten
+    pub fn restore<R: Read>(
+        path: impl AsRef<Path>,
+        backups: impl IntoIterator<Item = R>,
+    ) -> Result<Database, DatabaseError> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path.as_ref())?;
+        restore_backup(&file, backups)?;
+        drop(file);
+
+        Database::open(path)
+    }
+
     /// Compacts the database file
     ///
     /// Returns `true` if compaction was performed, and `false` if no futher compaction was possible
     pub fn compact(&mut self) -> Result<bool, CompactionError> {
+        self.compact_helper(true)
+    }
+
+    /// Returns the fraction of the pages in the database file which are not allocated
+    ///
+    /// Pages freed by a commit only become free once no read transaction or savepoint references
+    /// them
+    pub fn free_ratio(&self) -> Result<f64, StorageError> {
+        self.mem.free_ratio()
+    }
+
+    /// Returns the ranges of consecutive free pages, in increasing order
+    ///
+    /// Pages are numbered by their offset from the start of the file, in units of the page size,
+    /// so the ranges give a compact picture of how fragmented the free space is. Pages used by the
+    /// header of the file and of each region are never free. Pages freed by a commit only become
+    /// free once no read transaction or savepoint references them
+    pub fn free_ranges(&self) -> Vec<Range<u64>> {
+        self.mem.free_ranges()
+    }
+
+    /// Returns the number of usable and free pages, and the longest run of free pages
+    ///
+    /// This only reads the allocator state, so it is cheap, and can be used to check whether a
+    /// large write transaction fits in the file before starting it. As with
+    /// [`Self::free_ratio`], pages freed by a commit only become free once no read transaction or
+    /// savepoint references them
+    pub fn capacity(&self) -> Capacity {
+        self.mem.capacity()
+    }
+
+    /// Returns the length of the database, in bytes
+    ///
+    /// This includes pages which are free, or have never been written. See [`Self::physical_size`]
+    /// for the storage actually used
+    pub fn logical_size(&self) -> Result<u64, StorageError> {
+        self.mem.logical_size()
+    }
+
+    /// Returns the number of bytes of storage allocated to the database file
+    ///
+    /// On file systems which support sparse files, space which has never been written is not
+    /// allocated, so this can be much less than [`Self::logical_size`], for example for a newly
+    /// created database. Writes which are still held in the write cache are not included. Only
+    /// Unix reports the allocated size, so on other platforms this is the length of the file. For
+    /// a database created with [`Builder::create_embedded`], this covers the whole containing file
+    pub fn physical_size(&self) -> Result<u64, StorageError> {
+        self.mem.physical_size()
+    }
+
+    // Runs auto-compaction, if it is enabled and the free ratio has crossed the threshold.
+    // Must be called without a write transaction in progress
+    pub(crate) fn maybe_auto_compact(&self) {
+        let threshold = match self.auto_compaction_threshold {
+            Some(threshold) => threshold,
+            None => return,
+        };
+        // The commits made by compaction come back through here
+        if self.compacting.load(Ordering::Acquire) {
+            return;
+        }
+        let free_ratio = match self.mem.free_ratio() {
+            Ok(free_ratio) => free_ratio,
+            Err(_) => return,
+        };
+        if free_ratio < threshold / 2.0 {
+            self.auto_compaction_armed.store(true, Ordering::Release);
+            return;
+        }
+        if free_ratio <= threshold || !self.auto_compaction_armed.load(Ordering::Acquire) {
+            return;
+        }
+        {
+            // Pages referenced by a reader or savepoint can't be released, so leave it for a later commit
+            let tracker = self.transaction_tracker.lock().unwrap();
+            if tracker.oldest_live_read_transaction().is_some() || tracker.any_savepoint_exists() {
+                return;
+            }
+        }
+        self.auto_compaction_armed.store(false, Ordering::Release);
+        if let Err(_err) = self.compact_helper(false) {
+            #[cfg(feature = "logging")]
+            warn!("Auto-compaction failed: {}", _err);
+        }
+    }
+
+    // If exclusive is false, read transactions may begin while compacting. That is safe, since
+    // pages are freed through the normal commit path, but fewer pages may be released
+    fn compact_helper(&self, exclusive: bool) -> Result<bool, CompactionError> {
+        if self.compacting.swap(true, Ordering::AcqRel) {
+            // Another thread is already compacting
+            return Ok(false);
+        }
+        let result = self.compact_inner(exclusive);
+        self.compacting.store(false, Ordering::Release);
+        result
+    }
+
+    fn compact_inner(&self, exclusive: bool) -> Result<bool, CompactionError> {
         // Commit to free up any pending free pages
         // Use 2-phase commit to avoid any possible security issues. Plus this compaction is going to be so slow that it doesn't matter
         let mut txn = self.begin_write().map_err(|e| e.into_storage_error())?;
@@ -379,7 +734,9 @@ impl Database {
         txn.commit().map_err(|e| e.into_storage_error())?;
         // There can't be any outstanding transactions because we have a `&mut self`, so all pending free pages
         // should have been cleared out by the above commit()
-        assert!(self.mem.get_freed_root().is_none());
+        if exclusive {
+            assert!(self.mem.get_freed_root().is_none());
+        }
 
         let mut compacted = false;
         // Iteratively compact until no progress is made
@@ -398,7 +755,9 @@ impl Database {
             let mut txn = self.begin_write().map_err(|e| e.into_storage_error())?;
             txn.set_durability(Durability::Paranoid);
             txn.commit().map_err(|e| e.into_storage_error())?;
-            assert!(self.mem.get_freed_root().is_none());
+            if exclusive {
+                assert!(self.mem.get_freed_root().is_none());
+            }
 
             if !progress {
                 break;
@@ -428,7 +787,12 @@ impl Database {
             })?
         {
             let savepoint_table: ReadOnlyTable<SavepointId, SerializedSavepoint> =
-                ReadOnlyTable::new(savepoint_table_def.get_root(), PageHint::None, mem)?;
+                ReadOnlyTable::new(
+                    savepoint_table_def.get_root(),
+                    savepoint_table_def.get_fixed_key_size(),
+                    PageHint::None,
+                    mem,
+                )?;
             for result in savepoint_table.range::<SavepointId>(..)? {
                 let (_, savepoint_data) = result?;
                 let savepoint = savepoint_data
@@ -463,8 +827,12 @@ impl Database {
             mem.mark_pages_allocated(freed_pages_iter, true)?;
         }
 
-        let freed_table: ReadOnlyTable<FreedTableKey, FreedPageList<'static>> =
-            ReadOnlyTable::new(freed_root, PageHint::None, mem)?;
+        let freed_table: ReadOnlyTable<FreedTableKey, FreedPageList<'static>> = ReadOnlyTable::new(
+            freed_root,
+            FreedTableKey::fixed_width(),
+            PageHint::None,
+            mem,
+        )?;
         let lookup_key = FreedTableKey {
             transaction_id: oldest_unprocessed_free_transaction.0,
             pagination_id: 0,
@@ -487,9 +855,20 @@ impl Database {
         allow_duplicates: bool,
     ) -> Result {
         // Repair the allocator state
+        Self::visit_table_tree_pages(root, mem, |pages| {
+            mem.mark_pages_allocated(pages, allow_duplicates)
+        })
+    }
+
+    // Calls `visitor` with the pages of the table tree rooted at `root`, and of every table in it
+    fn visit_table_tree_pages(
+        root: PageNumber,
+        mem: &TransactionalMemory,
+        mut visitor: impl FnMut(AllPageNumbersBtreeIter) -> Result,
+    ) -> Result {
         // All pages in the master table
         let master_pages_iter = AllPageNumbersBtreeIter::new(root, None, None, mem)?;
-        mem.mark_pages_allocated(master_pages_iter, allow_duplicates)?;
+        visitor(master_pages_iter)?;
 
         // Iterate over all other tables
         let iter: BtreeRangeIter<&str, InternalTableDefinition> =
@@ -505,7 +884,7 @@ impl Database {
                     definition.get_fixed_value_size(),
                     mem,
                 )?;
-                mem.mark_pages_allocated(table_pages_iter, allow_duplicates)?;
+                visitor(table_pages_iter)?;
 
                 // Multimap tables may have additional subtrees in their values
                 if definition.get_type() == TableType::Multimap {
@@ -526,7 +905,7 @@ impl Database {
                                 <()>::fixed_width(),
                                 mem,
                             )?;
-                            mem.mark_pages_allocated(sub_root_iter, allow_duplicates)?;
+                            visitor(sub_root_iter)?;
                         }
                     }
                 }
@@ -536,22 +915,51 @@ impl Database {
         Ok(())
     }
 
-    fn do_repair(mem: &mut TransactionalMemory) -> Result {
-        if !Self::verify_primary_checksums(mem)? {
+    fn do_repair(mem: &mut TransactionalMemory, threads: usize) -> Result {
+        if !Self::verify_primary_checksums(mem, threads)? {
             mem.repair_primary_corrupted();
             // We need to invalidate the userspace cache, because walking the tree in verify_primary_checksums() may
             // have poisoned it with pages that just got rolled back by repair_primary_corrupted(), since
             // that rolls back a partially committed transaction.
             mem.clear_read_cache();
-            if !Self::verify_primary_checksums(mem)? {
+            if !Self::verify_primary_checksums(mem, threads)? {
                 return Err(StorageError::Corrupted(
                     "Failed to repair database. All roots are corrupted".to_string(),
                 ));
             }
         }
 
+        Self::rebuild_allocator_state(mem)
+    }
+
+    // Rebuilds the allocator state from the pages reachable from the primary commit slot, and
+    // commits it
+    fn rebuild_allocator_state(mem: &mut TransactionalMemory) -> Result {
         mem.begin_repair()?;
+        Self::mark_reachable_pages(mem)?;
+        mem.end_repair()?;
+
+        // We need to invalidate the userspace cache, because we're about to implicitly free the freed table
+        // by storing an empty root during the below commit()
+        mem.clear_read_cache();
+
+        let transaction_id = mem.next_transaction_id()?;
+        mem.commit(
+            mem.get_data_root(),
+            mem.get_system_root(),
+            mem.get_freed_root(),
+            transaction_id,
+            mem.get_entry_count(),
+            false,
+            true,
+            true,
+        )?;
 
+        Ok(())
+    }
+
+    // Marks every page which is referenced from the primary commit slot as allocated
+    fn mark_reachable_pages(mem: &mut TransactionalMemory) -> Result {
         let data_root = mem.get_data_root();
         if let Some((root, _)) = data_root {
             Self::mark_tables_recursive(root, mem, false)?;
@@ -560,8 +968,12 @@ impl Database {
         let freed_root = mem.get_freed_root();
         // Allow processing of all transactions, since this is the main freed tree
         Self::mark_freed_tree(freed_root, mem, TransactionId(0))?;
-        let freed_table: ReadOnlyTable<FreedTableKey, FreedPageList<'static>> =
-            ReadOnlyTable::new(freed_root, PageHint::None, mem)?;
+        let freed_table: ReadOnlyTable<FreedTableKey, FreedPageList<'static>> = ReadOnlyTable::new(
+            freed_root,
+            FreedTableKey::fixed_width(),
+            PageHint::None,
+            mem,
+        )?;
         // The persistent savepoints might hold references to older freed trees that are partially processed.
         // Make sure we don't reprocess those frees, as that would result in a double-free
         let oldest_unprocessed_transaction =
@@ -576,59 +988,100 @@ impl Database {
         if let Some((root, _)) = system_root {
             Self::mark_tables_recursive(root, mem, false)?;
         }
-        Self::mark_persistent_savepoints(system_root, mem, oldest_unprocessed_transaction)?;
-
-        mem.end_repair()?;
-
-        // We need to invalidate the userspace cache, because we're about to implicitly free the freed table
-        // by storing an empty root during the below commit()
-        mem.clear_read_cache();
-
-        let transaction_id = mem.get_last_committed_transaction_id()?.next();
-        mem.commit(
-            data_root,
-            system_root,
-            freed_root,
-            transaction_id,
-            false,
-            true,
-        )?;
-
-        Ok(())
+        Self::mark_persistent_savepoints(system_root, mem, oldest_unprocessed_transaction)
     }
 
+    // `region_size` is only used when initializing a new database
     fn new(
         file: File,
-        page_size: usize,
+        embedded: Option<EmbeddedRange>,
         region_size: Option<u64>,
-        read_cache_size_bytes: usize,
-        write_cache_size_bytes: usize,
+        discard_non_durable: bool,
+        builder: &Builder,
     ) -> Result<Self, DatabaseError> {
         #[cfg(feature = "logging")]
         let file_path = format!("{:?}", &file);
         #[cfg(feature = "logging")]
         info!("Opening database {:?}", &file_path);
+        let mirror = match builder.mirror.as_deref() {
+            Some(path) => {
+                let mirror = OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(false)
+                    .open(path)?;
+                reconcile_mirror(&file, &mirror)?;
+                Some(mirror)
+            }
+            None => None,
+        };
         let mut mem = TransactionalMemory::new(
             file,
-            page_size,
+            embedded,
+            builder.page_size,
+            builder.strict_page_size,
             region_size,
-            read_cache_size_bytes,
-            write_cache_size_bytes,
+            builder.read_cache_size_bytes,
+            builder.write_cache_size_bytes,
+            builder.growth_policy,
+            builder.repair_truncated,
+            discard_non_durable,
+            builder.skip_init_fsync,
+            false,
+        )?;
+        mem.set_transaction_page_limit(builder.transaction_page_limit);
+        mem.set_zero_on_free(builder.zero_on_free);
+        mem.set_punch_holes_on_free(builder.punch_holes_on_free);
+        mem.set_allocation_policy(builder.allocation_policy);
+        mem.set_overflow_threshold(builder.overflow_threshold);
+        mem.set_node_fill_target(builder.node_fill_target);
+        mem.set_verify_commits(builder.verify_commits);
+        mem.set_growth_callback(builder.growth_callback.clone());
+        mem.set_cold_page_callback(builder.cold_page_callback.clone());
+        mem.set_commit_latency_budget(builder.commit_latency_budget);
+        mem.set_commit_journal(builder.commit_journal);
+        mem.set_max_value_pages(builder.max_value_pages);
+        if builder.deterministic_seed.is_some() {
+            mem.set_allocation_policy(AllocationPolicy::LowestIndex);
+        }
+        mem.set_fixed_clock(builder.deterministic_seed);
+        if let Some(mirror) = mirror {
+            mem.set_mirror(mirror)?;
+        }
+        // Commits which were not synced within the budget are synced in the background right away
+        mem.set_max_sync_interval(
+            builder
+                .max_sync_interval
+                .or_else(|| builder.commit_latency_budget.map(|_| Duration::ZERO)),
         )?;
         if mem.needs_repair()? {
             #[cfg(feature = "logging")]
             warn!("Database {:?} not shutdown cleanly. Repairing", &file_path);
-            Self::do_repair(&mut mem)?;
+            Self::do_repair(&mut mem, 1)?;
         }
 
         mem.begin_writable()?;
-        let next_transaction_id = mem.get_last_committed_transaction_id()?.next();
+        let mut next_transaction_id = mem.next_transaction_id()?;
+        if let Some(epoch) = builder.transaction_epoch {
+            // Ids must keep increasing, so an earlier epoch continues from the last commit, and
+            // is then refused when committing
+            next_transaction_id = max(next_transaction_id, TransactionId::first_in_epoch(epoch));
+        }
 
         let db = Database {
             mem,
             next_transaction_id: AtomicTransactionId::new(next_transaction_id),
             transaction_tracker: Arc::new(Mutex::new(TransactionTracker::new())),
             live_write_transaction: Mutex::new(None),
+            auto_compaction_threshold: builder.auto_compaction_threshold,
+            auto_compaction_armed: AtomicBool::new(true),
+            compacting: AtomicBool::new(false),
+            transaction_epoch: builder.transaction_epoch,
+            track_changes: builder.track_changes,
+            last_commit_changes: Mutex::new(vec![]),
+            commit_signal: Condvar::new(),
+            commit_signal_lock: Mutex::new(()),
         };
 
         // Restore the tracker state for any persistent savepoints
@@ -680,383 +1133,3355 @@ impl Database {
         self.next_transaction_id.next()
     }
 
+    pub(crate) fn transaction_epoch(&self) -> Option<u32> {
+        self.transaction_epoch
+    }
+
     /// Convenience method for [`Builder::new`]
     pub fn builder() -> Builder {
         Builder::new()
     }
 
-    /// Begins a write transaction
+    /// Returns `true` if new read transactions will read the state of a [`Durability::None`]
+    /// commit, which has not been fsync'ed
     ///
-    /// Returns a [`WriteTransaction`] which may be used to read/write to the database. Only a single
-    /// write may be in progress at a time. If a write is in progress, this function will block
-    /// until it completes.
-    pub fn begin_write(&self) -> Result<WriteTransaction, TransactionError> {
-        WriteTransaction::new(self, self.transaction_tracker.clone()).map_err(|e| e.into())
+    /// This is the case from a non-durable commit until the next durable commit. See
+    /// [`ReadTransaction::is_reading_non_durable`] for the guarantees that hold in each state. Use
+    /// that method instead to check the data seen by an existing transaction
+    pub fn is_reading_non_durable(&self) -> bool {
+        self.get_memory().is_reading_from_secondary()
     }
 
-    /// Begins a read transaction
+    /// Makes the latest [`Durability::None`] commit durable
     ///
-    /// Captures a snapshot of the database, so that only data committed before calling this method
-    /// is visible in the transaction
+    /// All outstanding non-durable commits become durable atomically, with a single fsync, as
+    /// they otherwise would on the next durable commit or when the database is dropped.
     ///
-    /// Returns a [`ReadTransaction`] which may be used to read from the database. Read transactions
-    /// may exist concurrently with writes
-    pub fn begin_read(&self) -> Result<ReadTransaction, TransactionError> {
-        let id = self.allocate_read_transaction()?;
-        #[cfg(feature = "logging")]
-        info!("Beginning read transaction id={:?}", id);
-        Ok(ReadTransaction::new(
-            self.get_memory(),
-            self.transaction_tracker.clone(),
-            id,
-        ))
-    }
-}
+    /// Returns [`Error::WriteTransactionInProgress`] if a write transaction is in progress, on this
+    /// thread or any other, rather than waiting for it, since a thread waiting on its own
+    /// transaction would deadlock. Otherwise, returns `false` if there was no non-durable commit
+    /// to flush
+    pub fn flush_non_durable(&self) -> Result<bool, Error> {
+        // Hold the write lock, so that no transaction commits concurrently
+        let _live_write_transaction = match self.live_write_transaction.try_lock() {
+            Ok(guard) => guard,
+            Err(TryLockError::WouldBlock) => return Err(Error::WriteTransactionInProgress),
+            Err(TryLockError::Poisoned(err)) => return Err(err.into()),
+        };
+        if !self.mem.flush_non_durable()? {
+            return Ok(false);
+        }
+        self.transaction_tracker
+            .lock()
+            .unwrap()
+            .clear_pending_non_durable_commits();
 
-/// Configuration builder of a redb [Database].
-pub struct Builder {
-    page_size: usize,
-    region_size: Option<u64>,
-    read_cache_size_bytes: usize,
-    write_cache_size_bytes: usize,
-}
+        Ok(true)
+    }
 
-impl Builder {
-    /// Construct a new [Builder] with sensible defaults.
+    /// Brings the database file to the same state as when the database is closed cleanly
     ///
-    /// ## Defaults
+    /// Outstanding [`Durability::None`] commits are made durable, and the allocator state, which
+    /// is otherwise only written out when the database is dropped, is flushed to the file and
+    /// synced. If the process is killed immediately after, the database reopens without a repair.
+    /// Unlike [`Self::flush_non_durable`], this also clears the recovery flag in the file, which is
+    /// set again by the next commit.
     ///
-    /// - `cache_size_bytes`: 1GiB
-    #[allow(clippy::new_without_default)]
-    pub fn new() -> Self {
-        let mut result = Self {
-            // Default to 4k pages. Benchmarking showed that this was a good default on all platforms,
-            // including MacOS with 16k pages. Therefore, users are not allowed to configure it at the moment.
-            // It is part of the file format, so can be enabled in the future.
-            page_size: PAGE_SIZE,
-            region_size: None,
-            // TODO: Default should probably take into account the total system memory
-            read_cache_size_bytes: 0,
-            // TODO: Default should probably take into account the total system memory
-            write_cache_size_bytes: 0,
+    /// Returns [`Error::WriteTransactionInProgress`] if a write transaction is in progress, on this
+    /// thread or any other, rather than waiting for it, since a thread waiting on its own
+    /// transaction would deadlock.
+    ///
+    /// This is intended to be called at safe points of a long lived process, such as after a batch
+    /// of writes, to bound the time that a repair would take after a crash
+    pub fn checkpoint(&self) -> Result<(), Error> {
+        // Hold the write lock, so that no pages are allocated or freed concurrently
+        let _live_write_transaction = match self.live_write_transaction.try_lock() {
+            Ok(guard) => guard,
+            Err(TryLockError::WouldBlock) => return Err(Error::WriteTransactionInProgress),
+            Err(TryLockError::Poisoned(err)) => return Err(err.into()),
         };
+        self.mem.checkpoint()?;
+        self.transaction_tracker
+            .lock()
+            .unwrap()
+            .clear_pending_non_durable_commits();
 
-        result.set_cache_size(1024 * 1024 * 1024);
-        result
+        Ok(())
     }
 
-    /// Set the internal page size of the database
-    ///
-    /// Valid values are powers of two, greater than or equal to 512
+    /// Evicts all pages from the read cache, releasing the memory that it holds
     ///
-    /// ## Defaults
+    /// This is intended for use after a large scan, to return memory promptly rather than waiting
+    /// for the cache to be reused. Pages are read from the file again the next time they are
+    /// needed. Pages which are still referenced, by an [`AccessGuard`](crate::AccessGuard) for example, stay in memory
+    /// until they are dropped. Uncommitted writes are not affected
+    pub fn drop_caches(&self) {
+        self.mem.clear_read_cache();
+    }
+
+    /// Returns the `top_n` most accessed pages, along with the number of times each was read or
+    /// written since the database was opened, most accessed first
     ///
-    /// Default to 4 Kib pages.
-    #[cfg(any(fuzzing, test))]
-    pub fn set_page_size(&mut self, size: usize) -> &mut Self {
-        assert!(size.is_power_of_two());
-        self.page_size = std::cmp::max(size, 512);
-        self
+    /// This is a profiling aid, for finding b-tree nodes which a workload hammers. Pages are
+    /// identified by their page number, as in [`CorruptionFinding`]. The counters are only kept in
+    /// memory, and are only maintained with the `page_metrics` feature
+    #[cfg(feature = "page_metrics")]
+    pub fn hot_pages(&self, top_n: usize) -> Vec<(u64, u64)> {
+        self.mem
+            .hot_pages(top_n)
+            .into_iter()
+            .map(|(page, count)| (u64::from_le_bytes(page.to_le_bytes()), count))
+            .collect()
     }
 
-    /// Set the amount of memory (in bytes) used for caching data
-    pub fn set_cache_size(&mut self, bytes: usize) -> &mut Self {
-        // TODO: allow dynamic expansion of the read/write cache
-        self.read_cache_size_bytes = bytes / 10 * 9;
-        self.write_cache_size_bytes = bytes / 10;
-        self
+    /// Reads the database header from the file, for debugging
+    ///
+    /// This is purely informational. The header is read as it is stored on disk, so it does not
+    /// reflect [`Durability::None`] commits which have not yet been flushed
+    pub fn dump_metapage(&self) -> Result<MetapageDump, StorageError> {
+        self.mem.dump_metapage()
     }
 
-    #[cfg(any(test, fuzzing))]
-    pub fn set_region_size(&mut self, size: u64) -> &mut Self {
-        assert!(size.is_power_of_two());
-        self.region_size = Some(size);
-        self
+    /// Checks that the file at `path` has a valid database header, without opening the database
+    ///
+    /// The file is only read, and is not locked, so this can be used as a fast health check of a
+    /// database which is open in another process. The magic number, file format version and layout
+    /// are validated, and the file must be a whole number of pages, at least as long as the layout.
+    /// Tables are not read; use [`Database::check_integrity`] for a full check.
+    ///
+    /// [`MetapageDump::recovery_required`] reports whether the database was closed cleanly. It is
+    /// also set while the database is open, except from a [`Database::checkpoint`] until the next
+    /// commit. A header which is being written concurrently may be
+    /// observed partially written, in which case the check should be retried
+    pub fn verify_header(path: impl AsRef<Path>) -> Result<MetapageDump, DatabaseError> {
+        TransactionalMemory::verify_header_only(path.as_ref())
+    }
+
+    /// Returns the file format version of the database file at `path`
+    ///
+    /// This is the version recorded by the latest commit. If it is older than
+    /// [`FILE_FORMAT_VERSION`], the file must be upgraded with [`Database::migrate`] before it can
+    /// be opened
+    pub fn file_format_version(path: impl AsRef<Path>) -> Result<u8, DatabaseError> {
+        stored_format_version(&File::open(path)?)
+    }
+
+    /// Upgrades the database file at `path` to [`FILE_FORMAT_VERSION`]
+    ///
+    /// The steps which apply to the file are run in turn, each upgrading it by one version. Once a
+    /// step returns, the file is synced, and only then is the new version recorded in its header,
+    /// so a crash repeats at most the step which was in progress. Does nothing if the file is
+    /// already at the current version.
+    ///
+    /// Returns [`DatabaseError::UpgradeRequired`] if there is no step for the version of the file,
+    /// or for one of the versions it passes through, and [`DatabaseError::NewerFileFormat`] if the
+    /// file is newer than [`FILE_FORMAT_VERSION`]. The file is locked while it is upgraded, so
+    /// this returns [`DatabaseError::DatabaseAlreadyOpen`] if the database is open
+    pub fn migrate(path: impl AsRef<Path>, steps: &[MigrationStep]) -> Result<(), DatabaseError> {
+        migrate(OpenOptions::new().read(true).write(true).open(path)?, steps)
+    }
+
+    /// Checks the database file at `path` for corruption, and reports every problem found
+    ///
+    /// Opening a database stops at the first problem, and [`Database::check_integrity`] repairs
+    /// what it can. This instead lists each problem, so that a recovery tool can decide whether to
+    /// salvage, repair or give up. The file is not modified.
+    ///
+    /// The header is checked first, and if it is too damaged to locate the tables, nothing else
+    /// is. Otherwise every page of the tables, of the system tables, and of the tree of pages
+    /// waiting to be freed is verified against its checksum. Pages referenced only by persistent
+    /// savepoints are not verified. Leaked pages are only reported if the allocator state is
+    /// intact and no page is damaged, since otherwise it is not known which pages are referenced.
+    ///
+    /// The file is locked while it is checked, so this returns
+    /// [`DatabaseError::DatabaseAlreadyOpen`] if the database is open
+    pub fn corruption_report(path: impl AsRef<Path>) -> Result<CorruptionReport, DatabaseError> {
+        let path = path.as_ref();
+        let mut findings = vec![];
+        if !TransactionalMemory::check_header(path, &mut findings)? {
+            return Ok(CorruptionReport::new(findings));
+        }
+
+        let mut mem = match TransactionalMemory::new(
+            File::open(path)?,
+            None,
+            PAGE_SIZE,
+            false,
+            None,
+            0,
+            0,
+            GrowthPolicy::default(),
+            false,
+            false,
+            false,
+            true,
+        ) {
+            Ok(mem) => mem,
+            Err(DatabaseError::Storage(StorageError::Corrupted(reason))) => {
+                findings.push(CorruptionFinding::InvalidHeader(reason));
+                return Ok(CorruptionReport::new(findings));
+            }
+            Err(err) => return Err(err),
+        };
+        if mem.needs_repair()? && !findings.contains(&CorruptionFinding::DirtyAllocator) {
+            findings.push(CorruptionFinding::DirtyAllocator);
+        }
+
+        let mut intact = true;
+        for root in [mem.get_data_root(), mem.get_system_root()]
+            .into_iter()
+            .flatten()
+        {
+            if !Self::find_table_tree_corruption(root, &mem, &mut findings)? {
+                intact = false;
+            }
+        }
+        if !RawBtree::new(
+            mem.get_freed_root(),
+            FreedTableKey::fixed_width(),
+            None,
+            &mem,
+        )
+        .find_corruption(&mut findings, &mut vec![])?
+        {
+            intact = false;
+        }
+
+        if intact && !mem.needs_repair()? {
+            // Rebuild the allocator state in memory, and compare it to the stored one
+            let allocated = mem.allocated_pages();
+            mem.begin_repair()?;
+            Self::mark_reachable_pages(&mut mem)?;
+            let reachable: HashSet<PageNumber> = mem.allocated_pages().into_iter().collect();
+            for page in allocated {
+                if !reachable.contains(&page) {
+                    findings.push(CorruptionFinding::LeakedPage {
+                        page: u64::from_le_bytes(page.to_le_bytes()),
+                    });
+                }
+            }
+        }
+
+        Ok(CorruptionReport::new(findings))
+    }
+
+    /// Checks the database file at `path`, as a file system checker would, without repairing it
+    ///
+    /// This is the entry point for command line tools: it runs every check of
+    /// [`Database::corruption_report`] (the header, a walk of every tree verifying checksums, and
+    /// a cross-check of the allocator state against the pages referenced), and returns the
+    /// problems found. Use the [`Display`](std::fmt::Display) implementation of the report to
+    /// print a summary. An error is only returned if the file could not be checked at all, for
+    /// example because it could not be read or the database is open
+    pub fn fsck(path: impl AsRef<Path>) -> Result<CorruptionReport, Error> {
+        Ok(Self::corruption_report(path)?)
+    }
+
+    /// Returns the entries changed by the most recent commit, ordered by table name and then by
+    /// serialized key
+    ///
+    /// Changes are only tracked if enabled with [`Builder::set_track_changes`], and none are
+    /// reported otherwise. Each key is reported once, with its net change: a key which was
+    /// inserted and then removed by the same transaction is not reported. Only tables opened with
+    /// [`WriteTransaction::open_table`], or replaced with [`WriteTransaction::replace_table`], are
+    /// tracked, and deleting a table or restoring a savepoint is not reported as changes to its
+    /// entries. The changes are cleared when the next write transaction begins
+    pub fn last_commit_changes(&self) -> impl Iterator<Item = ChangeKind> {
+        self.last_commit_changes.lock().unwrap().clone().into_iter()
+    }
+
+    pub(crate) fn tracks_changes(&self) -> bool {
+        self.track_changes
+    }
+
+    // The commits made by compaction don't change any entries, so they leave the changes of the
+    // previous commit in place
+    pub(crate) fn clear_last_commit_changes(&self) {
+        if !self.compacting.load(Ordering::Acquire) {
+            self.last_commit_changes.lock().unwrap().clear();
+        }
+    }
+
+    pub(crate) fn set_last_commit_changes(&self, changes: Vec<ChangeKind>) {
+        if !self.compacting.load(Ordering::Acquire) {
+            *self.last_commit_changes.lock().unwrap() = changes;
+        }
+    }
+
+    /// Begins a write transaction
+    ///
+    /// Returns a [`WriteTransaction`] which may be used to read/write to the database. Only a single
+    /// write may be in progress at a time. If a write is in progress, this function will block
+    /// until it completes.
+    pub fn begin_write(&self) -> Result<WriteTransaction, TransactionError> {
+        WriteTransaction::new(self, self.transaction_tracker.clone()).map_err(|e| e.into())
+    }
+
+    /// Begins a write transaction, which is expected to allocate about `expected_pages` pages
+    ///
+    /// This is the same as [`Self::begin_write`], except that the sets of pages which the
+    /// transaction tracks until it is committed are sized for `expected_pages` up front, instead
+    /// of growing incrementally. This saves some time in large bulk writes. The hint is not a
+    /// limit: the transaction may allocate more, or fewer, pages
+    pub fn begin_write_with_hint(
+        &self,
+        expected_pages: usize,
+    ) -> Result<WriteTransaction, TransactionError> {
+        let txn = self.begin_write()?;
+        txn.reserve_pages(expected_pages);
+        Ok(txn)
+    }
+
+    /// Begins a read transaction
+    ///
+    /// Captures a snapshot of the database, so that only data committed before calling this method
+    /// is visible in the transaction: it provides snapshot isolation. Pages freed by later commits
+    /// are not reused until the transaction is dropped
+    ///
+    /// Returns a [`ReadTransaction`] which may be used to read from the database. Read transactions
+    /// may exist concurrently with writes
+    pub fn begin_read(&self) -> Result<ReadTransaction, TransactionError> {
+        let id = self.allocate_read_transaction()?;
+        #[cfg(feature = "logging")]
+        info!("Beginning read transaction id={:?}", id);
+        Ok(ReadTransaction::new(
+            self.get_memory(),
+            self.transaction_tracker.clone(),
+            id,
+        ))
+    }
+
+    /// Begins a read transaction, once the commit with id `transaction_id`, or a later one, has
+    /// been made
+    ///
+    /// Returns immediately if it already has. Otherwise blocks until a write transaction commits,
+    /// for example one applying commits shipped from a primary, and returns [`Error::Timeout`] if
+    /// no such commit is made within `timeout`. The ids of commits are returned by
+    /// [`WriteTransaction::transaction_id`] and [`ReadTransaction::transaction_id`]
+    pub fn await_transaction(
+        &self,
+        transaction_id: u64,
+        timeout: Duration,
+    ) -> Result<ReadTransaction, Error> {
+        // No deadline, if the timeout is too long to represent
+        let deadline = Instant::now().checked_add(timeout);
+        let mut guard = self.commit_signal_lock.lock()?;
+        while self.mem.get_last_committed_transaction_id()?.0 < transaction_id {
+            guard = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(Error::Timeout);
+                    }
+                    self.commit_signal.wait_timeout(guard, deadline - now)?.0
+                }
+                None => self.commit_signal.wait(guard)?,
+            };
+        }
+        drop(guard);
+
+        Ok(self.begin_read()?)
+    }
+
+    pub(crate) fn notify_commit(&self) {
+        let _guard = self.commit_signal_lock.lock().unwrap();
+        self.commit_signal.notify_all();
+    }
+
+    /// Returns the value of `key` in the table `definition`, as of the latest commit
+    ///
+    /// This is a shorthand for beginning a read transaction, opening the table, and copying the
+    /// value out. The transaction only lasts for the call, so the value is read from a consistent
+    /// snapshot even if a write commits concurrently. Returns `None` if the table does not exist
+    pub fn get<'a, K: RedbKey + 'static, V: RedbValue + 'static>(
+        &self,
+        definition: TableDefinition<K, V>,
+        key: impl Borrow<K::SelfType<'a>>,
+    ) -> Result<Option<AccessGuard<'static, V>>, Error> {
+        let txn = self.begin_read()?;
+        let table = match txn.open_table(definition) {
+            Ok(table) => table,
+            Err(TableError::TableDoesNotExist(_)) => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let value = table.get(key)?.map(|guard| {
+            AccessGuard::with_owned_value(V::as_bytes(&guard.value()).as_ref().to_vec())
+        });
+        Ok(value)
+    }
+
+    /// Checks whether `key` and `value` can be inserted into the table `definition`, without
+    /// beginning a transaction
+    ///
+    /// Returns the error which [`Table::insert`](crate::Table::insert) would return because of the
+    /// size of the entry: a key or value longer than the maximum, a key of the wrong width for a
+    /// table declared with [`TableDefinition::with_fixed_key_width`], or an entry which does not
+    /// fit in the largest page of the database. If the table exists, its key width is the one it
+    /// was created with. Only the catalog of tables is read, and not the table or the allocator, so
+    /// an insert which passes may still fail for other reasons, such as the transaction page
+    /// limit. An entry close to the largest page size may also fail to insert if it must share a
+    /// page with its neighbors
+    pub fn can_insert<'a, K: RedbKey + 'static, V: RedbValue + 'static>(
+        &self,
+        definition: TableDefinition<K, V>,
+        key: impl Borrow<K::SelfType<'a>>,
+        value: impl Borrow<V::SelfType<'a>>,
+    ) -> Result<(), Error> {
+        let key_len = K::as_bytes(key.borrow()).as_ref().len();
+        let value_len = V::as_bytes(value.borrow()).as_ref().len();
+        let key_width = self
+            .begin_read()?
+            .stored_key_width(definition.name())?
+            .unwrap_or_else(|| definition.key_width());
+        check_entry_size(&self.mem, key_width, key_len, value_len)?;
+        // Matches the leaf which an insert builds for the entry on its own
+        let leaf_len = RawLeafBuilder::required_bytes(1, key_len + value_len);
+        if leaf_len > self.mem.max_allocation_size() {
+            return Err(StorageError::AllocationTooLarge(leaf_len).into());
+        }
+        Ok(())
+    }
+
+    /// Begins a scan of every entry of a table, in key order, which can be resumed after a restart
+    ///
+    /// The scan reads a snapshot of the last commit, which is held by a new persistent savepoint
+    /// so that it survives the process exiting. [`EtlScan::position_token`] records the position of
+    /// the scan, and [`Database::resume_etl_scan`] continues from it, in the same snapshot. The
+    /// savepoint, returned by [`EtlScan::savepoint`], prevents the pages of the snapshot from being
+    /// reused, and should be deleted with [`WriteTransaction::delete_persistent_savepoint`] once
+    /// the scan is complete
+    pub fn etl_scan<K: RedbKey + 'static, V: RedbValue + 'static>(
+        &self,
+        definition: TableDefinition<K, V>,
+    ) -> Result<EtlScan<K, V>, Error> {
+        let txn = self.begin_write()?;
+        let id = txn.persistent_savepoint()?;
+        let savepoint = txn.get_persistent_savepoint(id)?;
+        match self.etl_scan_at(definition, &savepoint, None) {
+            Ok(scan) => {
+                txn.commit()?;
+                Ok(scan)
+            }
+            Err(err) => {
+                txn.abort()?;
+                Err(err)
+            }
+        }
+    }
+
+    /// Resumes a scan begun by [`Database::etl_scan`], after the last entry it returned before
+    /// `token` was taken
+    ///
+    /// Returns [`Error::InvalidSavepoint`] if the snapshot of the scan has since been discarded,
+    /// because its savepoint was deleted or restored over
+    pub fn resume_etl_scan<K: RedbKey + 'static, V: RedbValue + 'static>(
+        &self,
+        definition: TableDefinition<K, V>,
+        token: &EtlScanToken,
+    ) -> Result<EtlScan<K, V>, Error> {
+        let savepoint = match self.begin_read()?.persistent_savepoint(token.savepoint())? {
+            // Savepoint ids are not unique across database files, so the commit is checked as well
+            Some(savepoint) if savepoint.get_transaction_id().0 == token.transaction_id() => {
+                savepoint
+            }
+            _ => return Err(Error::InvalidSavepoint),
+        };
+        self.etl_scan_at(definition, &savepoint, token.key())
+    }
+
+    // Fails with InvalidSavepoint if the savepoint was deleted, or restored over, since it was read
+    fn etl_scan_at<K: RedbKey + 'static, V: RedbValue + 'static>(
+        &self,
+        definition: TableDefinition<K, V>,
+        savepoint: &Savepoint,
+        after: Option<&[u8]>,
+    ) -> Result<EtlScan<K, V>, Error> {
+        let key_width = definition.key_width();
+        if let Some(key) = after {
+            check_token_key(key_width, key)?;
+        }
+        let snapshot = ReadTransaction::at_savepoint(
+            self.get_memory(),
+            self.transaction_tracker.clone(),
+            savepoint,
+        )
+        .ok_or(Error::InvalidSavepoint)?;
+        let root = snapshot.table_root(definition)?.map(|(page, _)| page);
+        let inner = match after {
+            Some(key) => BtreeRangeIter::with_key_width::<_, K::SelfType<'_>>(
+                &(Bound::Excluded(K::from_bytes(key)), Bound::Unbounded),
+                root,
+                key_width,
+                &self.mem,
+            )?,
+            None => BtreeRangeIter::with_key_width::<RangeFull, K::SelfType<'_>>(
+                &(..),
+                root,
+                key_width,
+                &self.mem,
+            )?,
+        };
+        Ok(EtlScan::new(
+            snapshot,
+            crate::Range::new(inner),
+            savepoint.get_id().0,
+            after.map(<[u8]>::to_vec),
+        ))
+    }
+}
+
+/// A redb database opened with [`Builder::open_read_only`]
+///
+/// Nothing is written to the file, so write transactions can not be started, and a database which
+/// was not shut down cleanly is read as of its last commit, without repairing it. The handle is
+/// `Send` and `Sync`, so it can be wrapped in an [`Arc`] and shared by many threads: each read
+/// transaction only takes a lock briefly, while it is started and dropped, to record the snapshot
+/// it is reading
+pub struct ReadOnlyDatabase {
+    mem: TransactionalMemory,
+    transaction_tracker: Arc<Mutex<TransactionTracker>>,
+}
+
+impl ReadOnlyDatabase {
+    fn new(
+        file: File,
+        page_size: usize,
+        strict_page_size: bool,
+        read_cache_size_bytes: usize,
+    ) -> Result<Self, DatabaseError> {
+        let mem = TransactionalMemory::new(
+            file,
+            None,
+            page_size,
+            strict_page_size,
+            None,
+            read_cache_size_bytes,
+            0,
+            GrowthPolicy::default(),
+            false,
+            false,
+            false,
+            true,
+        )?;
+
+        Ok(Self {
+            mem,
+            transaction_tracker: Arc::new(Mutex::new(TransactionTracker::new())),
+        })
+    }
+
+    /// Begins a read transaction
+    ///
+    /// Captures a snapshot of the database, like [`Database::begin_read`]. Since nothing can be
+    /// written through this handle, every transaction sees the same data
+    pub fn begin_read(&self) -> Result<ReadTransaction, TransactionError> {
+        let id = {
+            let mut guard = self.transaction_tracker.lock().unwrap();
+            let id = self.mem.get_last_committed_transaction_id()?;
+            guard.register_read_transaction(id);
+            id
+        };
+        Ok(ReadTransaction::new(
+            &self.mem,
+            self.transaction_tracker.clone(),
+            id,
+        ))
+    }
+
+    /// Verifies the checksums of every table, without repairing anything
+    ///
+    /// Returns `Ok(true)` if the database passed integrity checks, and `Ok(false)` if it failed.
+    /// Unlike [`Database::check_integrity`], the file is never written, so a database which fails
+    /// must be repaired by opening it with a [`Database`]
+    pub fn check_integrity(&self) -> Result<bool> {
+        Database::verify_primary_checksums(&self.mem, 1)
+    }
+}
+
+impl std::fmt::Debug for ReadOnlyDatabase {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReadOnlyDatabase").finish()
+    }
+}
+
+/// Configuration builder of a redb [Database].
+pub struct Builder {
+    page_size: usize,
+    strict_page_size: bool,
+    region_size: Option<u64>,
+    read_cache_size_bytes: usize,
+    write_cache_size_bytes: usize,
+    growth_policy: GrowthPolicy,
+    transaction_page_limit: Option<usize>,
+    zero_on_free: bool,
+    punch_holes_on_free: bool,
+    auto_compaction_threshold: Option<f64>,
+    repair_truncated: bool,
+    allocation_policy: AllocationPolicy,
+    overflow_threshold: Option<usize>,
+    skip_init_fsync: bool,
+    node_fill_target: f64,
+    verify_commits: bool,
+    growth_callback: Option<GrowthCallback>,
+    migrations: Vec<MigrationStep>,
+    transaction_epoch: Option<u32>,
+    max_sync_interval: Option<Duration>,
+    mirror: Option<PathBuf>,
+    cold_page_callback: Option<ColdPageCallback>,
+    commit_latency_budget: Option<Duration>,
+    commit_journal: bool,
+    max_value_pages: u64,
+    track_changes: bool,
+    deterministic_seed: Option<u64>,
+}
+
+impl Builder {
+    /// Construct a new [Builder] with sensible defaults.
+    ///
+    /// ## Defaults
+    ///
+    /// - `page_size`: 4KiB
+    /// - `strict_page_size`: false
+    /// - `cache_size_bytes`: 1GiB
+    /// - `growth_policy`: double the size of the database file each time it is grown
+    /// - `transaction_page_limit`: unlimited
+    /// - `zero_on_free`: false
+    /// - `punch_holes_on_free`: false
+    /// - `auto_compaction_threshold`: disabled
+    /// - `repair_truncated`: false
+    /// - `allocation_policy`: [`AllocationPolicy::SmallestFit`]
+    /// - `overflow_threshold`: disabled
+    /// - `skip_init_fsync`: false
+    /// - `node_fill_target`: 0.5
+    /// - `verify_commits`: false
+    /// - `growth_callback`: none
+    /// - `migrations`: none
+    /// - `transaction_epoch`: none
+    /// - `max_sync_interval`: none
+    /// - `mirror`: none
+    /// - `cold_page_callback`: none
+    /// - `commit_latency_budget`: none
+    /// - `commit_journal`: false
+    /// - `max_value_pages`: unlimited, other than by the 3GiB maximum length of a value
+    /// - `track_changes`: false
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        let mut result = Self {
+            // Default to 4k pages. Benchmarking showed that this was a good default on all platforms,
+            // including MacOS with 16k pages
+            page_size: PAGE_SIZE,
+            strict_page_size: false,
+            region_size: None,
+            // TODO: Default should probably take into account the total system memory
+            read_cache_size_bytes: 0,
+            // TODO: Default should probably take into account the total system memory
+            write_cache_size_bytes: 0,
+            growth_policy: GrowthPolicy::default(),
+            transaction_page_limit: None,
+            zero_on_free: false,
+            punch_holes_on_free: false,
+            auto_compaction_threshold: None,
+            repair_truncated: false,
+            allocation_policy: AllocationPolicy::default(),
+            overflow_threshold: None,
+            skip_init_fsync: false,
+            node_fill_target: DEFAULT_NODE_FILL_TARGET,
+            verify_commits: false,
+            growth_callback: None,
+            migrations: vec![],
+            transaction_epoch: None,
+            max_sync_interval: None,
+            mirror: None,
+            cold_page_callback: None,
+            commit_latency_budget: None,
+            commit_journal: false,
+            max_value_pages: u64::MAX,
+            track_changes: false,
+            deterministic_seed: None,
+        };
+
+        result.set_cache_size(1024 * 1024 * 1024);
+        result
+    }
+
+    /// Set the internal page size of the database
+    ///
+    /// Valid values are powers of two, from 512 bytes to 1MiB. Other values cause
+    /// [`DatabaseError::InvalidPageSize`] to be returned when the database is opened. The page
+    /// size is stored in the file when it is created, so this has no effect on an existing
+    /// database, unless [`Self::set_strict_page_size`] is also set
+    ///
+    /// ## Defaults
+    ///
+    /// Default to 4 Kib pages.
+    pub fn set_page_size(&mut self, size: usize) -> &mut Self {
+        self.page_size = size;
+        self
+    }
+
+    /// Require an existing database to have been created with the configured page size
+    ///
+    /// By default, the page size of an existing database is used and the configured page size
+    /// only applies to newly created databases. If set, opening a database with a different page
+    /// size returns [`DatabaseError::PageSizeMismatch`]. The page size is 4KiB unless configured
+    /// otherwise
+    pub fn set_strict_page_size(&mut self, strict: bool) -> &mut Self {
+        self.strict_page_size = strict;
+        self
+    }
+
+    /// Make every write deterministic, so that failures can be reproduced
+    ///
+    /// A given sequence of operations then produces a byte identical file on every run. Pages are
+    /// allocated with [`AllocationPolicy::LowestIndex`], overriding
+    /// [`Builder::set_allocation_policy`], and commits are timestamped as though the wall clock
+    /// read `seed` milliseconds since the UNIX epoch
+    #[cfg(any(fuzzing, test))]
+    pub fn set_deterministic(&mut self, seed: u64) -> &mut Self {
+        self.deterministic_seed = Some(seed);
+        self
+    }
+
+    /// Set the amount of memory (in bytes) used for caching data
+    ///
+    /// The database file is not memory mapped, so this bounds the memory used to hold pages of the
+    /// file. Reading more data than fits, for example in a large scan, evicts cached pages instead
+    /// of growing the resident set
+    pub fn set_cache_size(&mut self, bytes: usize) -> &mut Self {
+        // TODO: allow dynamic expansion of the read/write cache
+        self.read_cache_size_bytes = bytes / 10 * 9;
+        self.write_cache_size_bytes = bytes / 10;
+        self
+    }
+
+    /// Set the policy used to determine how much the database file is grown by, when it runs out of space
+    pub fn set_growth_policy(&mut self, policy: GrowthPolicy) -> &mut Self {
+        self.growth_policy = policy;
+        self
+    }
+
+    /// Limit the number of pages that a write transaction may allocate
+    ///
+    /// Once a transaction has allocated this many pages, further writes fail with
+    /// [`Error::TransactionTooLarge`] and the transaction must be aborted. Pages allocated while
+    /// committing do not count towards the limit. This guards against accidentally unbounded
+    /// transactions, which hold all of their pages dirty until they commit
+    pub fn set_transaction_page_limit(&mut self, pages: usize) -> &mut Self {
+        self.transaction_page_limit = Some(pages);
+        self
+    }
+
+    /// Overwrite pages with zeros when they are freed, so that deleted data does not remain in
+    /// the database file
+    ///
+    /// Pages are freed once no read transaction or savepoint references them, so deleted data
+    /// remains readable by older snapshots until then. Freeing a page costs an additional write
+    pub fn set_zero_on_free(&mut self, enabled: bool) -> &mut Self {
+        self.zero_on_free = enabled;
+        self
+    }
+
+    /// Return the storage of pages to the file system when they are freed, by punching holes in
+    /// the database file
+    ///
+    /// This reclaims the space of free pages in the middle of the file, which compaction can only
+    /// do by moving the pages after them. The length of the file is unchanged, and the freed pages
+    /// read back as zeros. It is only supported on Linux, and by file systems which support sparse
+    /// files. Elsewhere, pages are freed as usual. Pages are freed once no read transaction or
+    /// savepoint references them
+    pub fn set_punch_holes_on_free(&mut self, enabled: bool) -> &mut Self {
+        self.punch_holes_on_free = enabled;
+        self
+    }
+
+    /// Compact the database automatically after a durable commit, when the fraction of its pages
+    /// which are free exceeds `free_ratio`. See [`Database::free_ratio`]
+    ///
+    /// Compaction runs synchronously as part of [`WriteTransaction::commit`], and is skipped while
+    /// any read transaction or savepoint exists. Once it has run, it is not triggered again until
+    /// the free ratio has fallen below half of the threshold. `free_ratio` must be between 0 and 1
+    ///
+    /// The commit which crosses the threshold does not return until compaction has finished, which
+    /// takes as long as [`Database::compact`]: every page after the free space is moved, and the
+    /// file is synced several times. No other write transaction can begin meanwhile. Applications
+    /// which need a bound on commit latency should leave this disabled, and call
+    /// [`Database::compact`] at a time of their choosing, using [`Database::free_ratio`] to decide
+    /// when
+    pub fn set_auto_compaction_threshold(&mut self, free_ratio: f64) -> &mut Self {
+        self.auto_compaction_threshold = Some(free_ratio);
+        self
+    }
+
+    /// Open a database file which is shorter than the length recorded in its header, instead of
+    /// returning [`DatabaseError::FileTruncated`]
+    ///
+    /// The database is shrunk to the regions which remain complete, the file is truncated to match,
+    /// and the allocator state is rebuilt for the new length. Opening fails with
+    /// [`StorageError::Corrupted`] if any page which is still in use was lost
+    pub fn set_repair_truncated(&mut self, enabled: bool) -> &mut Self {
+        self.repair_truncated = enabled;
+        self
+    }
+
+    /// Set the policy used to choose which free page to allocate
+    ///
+    /// The policy only affects new allocations, so it may differ each time a database is opened
+    pub fn set_allocation_policy(&mut self, policy: AllocationPolicy) -> &mut Self {
+        self.allocation_policy = policy;
+        self
+    }
+
+    /// Place allocations of at least `bytes` at the end of the free space
+    ///
+    /// Large values are stored in pages spanning several pages, while b-tree nodes use a single
+    /// page. With this option the two are allocated from opposite ends of the free space, so that
+    /// freeing and reallocating large values does not fragment the space used by small pages, and
+    /// vice versa. Once the free space between them is used up, the file grows and both kinds of
+    /// allocation share the new space. Smaller allocations always use the lowest free page, as with
+    /// [`AllocationPolicy::LowestIndex`]. It only affects new allocations, so it may differ each
+    /// time a database is opened. `bytes` must be larger than the page size
+    pub fn set_overflow_threshold(&mut self, bytes: usize) -> &mut Self {
+        self.overflow_threshold = Some(bytes);
+        self
+    }
+
+    /// Set the most pages which a single entry may span
+    ///
+    /// An entry which does not fit in a page is stored in a leaf spanning several pages. An insert
+    /// whose leaf would span more than `pages` pages fails with
+    /// [`crate::Error::ValueTooLarge`] before anything is allocated, so that a single runaway value
+    /// can't fill the database. This applies to the key and value together, and only to new
+    /// inserts, so it may differ each time a database is opened. `pages` must be at least one
+    pub fn set_max_value_pages(&mut self, pages: u64) -> &mut Self {
+        self.max_value_pages = pages;
+        self
+    }
+
+    /// Set the fraction of the contents of an overfull b-tree node which is kept in the first of
+    /// the two nodes that it is split into
+    ///
+    /// Keys inserted in increasing order always go to the second node, so a higher target leaves
+    /// fuller nodes and a shallower tree, which benefits reads. A lower target leaves more space
+    /// in the first node for later insertions between existing keys, so that it splits again
+    /// less often. Each node always gets at least one entry. It only affects new splits, so it may
+    /// differ each time a database is opened. Must be between 0.1 and 0.9
+    pub fn set_node_fill_target(&mut self, fraction: f64) -> &mut Self {
+        self.node_fill_target = fraction;
+        self
+    }
+
+    /// Skip the fsyncs which are made while initializing a new database file
+    ///
+    /// The header is still written after the rest of the file, so the database is consistent unless
+    /// the operating system crashes during creation, in which case the file may not be recognized
+    /// when it is next opened. This is intended for file systems without durability, such as
+    /// `tmpfs`, and for tests which create many short-lived databases
+    pub fn set_skip_init_fsync(&mut self, enabled: bool) -> &mut Self {
+        self.skip_init_fsync = enabled;
+        self
+    }
+
+    /// Read the header back from the file after each durable commit, and check that it matches
+    /// what was written
+    ///
+    /// This catches a storage device which reports a write as successful, but loses it. The commit
+    /// then returns [`crate::StorageError::WriteVerificationFailed`], and every later operation
+    /// fails with an I/O error until the database is reopened. It costs an extra read of the header
+    /// per commit. The data pages themselves are not read back, since their checksums are verified
+    /// whenever they are read
+    ///
+    /// On Linux, the header is evicted from the OS page cache before it is read back, so that the
+    /// read reaches the storage device. On other platforms the read may be served from the page
+    /// cache, and only checks the write path up to the OS
+    pub fn set_verify_commits(&mut self, enabled: bool) -> &mut Self {
+        self.verify_commits = enabled;
+        self
+    }
+
+    /// Set a function to be called each time the database file is grown
+    ///
+    /// It is called with the length of the file before and after growing it, and the id of the
+    /// write transaction whose allocation required the growth. The file has already been resized
+    /// when it is called, but the allocation has not yet completed, and the database is locked: the
+    /// callback must return quickly, and must not use the database
+    pub fn set_growth_callback(
+        &mut self,
+        callback: impl Fn(u64, u64, u64) + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.growth_callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// Set a function to be called each time a page is read which was not read recently
+    ///
+    /// It is called with the offset of the page in the database file. Whether a page was read
+    /// recently is approximated by a small fixed size table of the pages last read, so it
+    /// estimates the rate of reads which miss a cache of that size, and a page may occasionally be
+    /// reported as cold again while it is in use. It is called on the thread making the read, which
+    /// may be inside a transaction: it must return quickly, and must not use the database. When no
+    /// callback is set, reads are not tracked
+    pub fn set_cold_page_callback(
+        &mut self,
+        callback: impl Fn(u64) + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.cold_page_callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// Set the steps used to upgrade the file format of an existing database when it is opened
+    ///
+    /// A database whose file format version is older than [`FILE_FORMAT_VERSION`] is upgraded with
+    /// [`Database::migrate`] before it is opened. Without a step for its version, opening it returns
+    /// [`DatabaseError::UpgradeRequired`]
+    pub fn set_migrations(&mut self, steps: impl IntoIterator<Item = MigrationStep>) -> &mut Self {
+        self.migrations = steps.into_iter().collect();
+        self
+    }
+
+    /// Set the epoch of the transaction ids of write transactions
+    ///
+    /// Transaction ids are split into an epoch, in their high 32 bits, and a counter within the
+    /// epoch, in their low 32 bits. This is useful in distributed setups, where the epoch is bumped
+    /// whenever leadership changes. If the epoch is later than that of the last commit, the
+    /// counter starts again from zero. A commit is refused with
+    /// [`StorageError::TransactionEpochMismatch`] if its id is not in this epoch: that is, if a
+    /// later epoch has already committed, or if the counter would overflow into the next epoch.
+    /// The epoch of a commit is returned by [`TransactionSlotDump::epoch`]
+    pub fn set_transaction_epoch(&mut self, epoch: u32) -> &mut Self {
+        self.transaction_epoch = Some(epoch);
+        self
+    }
+
+    /// Set the longest time a commit may be left unsynced
+    ///
+    /// Commits made with [`Durability::NoSync`], or with [`Durability::Eventual`] on platforms
+    /// where it only issues a write barrier, are handed to the operating system without an fsync.
+    /// With this set, a background thread syncs the file once such a commit has been unsynced for
+    /// `interval`, which bounds the commits that can be lost on power failure. The thread sleeps
+    /// while there is nothing to sync, and commits never wait for it
+    ///
+    /// If the thread can't be spawned, opening the database fails with the I/O error
+    pub fn set_max_sync_interval(&mut self, interval: Duration) -> &mut Self {
+        self.max_sync_interval = Some(interval);
+        self
+    }
+
+    /// Set the longest time a commit should spend syncing the database file
+    ///
+    /// The duration of recent fsyncs is tracked, and a commit with [`Durability::Eventual`],
+    /// [`Durability::Immediate`] or [`Durability::Paranoid`] whose fsyncs are expected to take
+    /// longer than `budget` is handed to the operating system without an fsync, as with
+    /// [`Durability::NoSync`]. This is reported by
+    /// [`TransactionStats::durability_degraded`](crate::TransactionStats::durability_degraded), as
+    /// returned by [`WriteTransaction::commit_with_stats`].
+    /// Such a commit is synced by a background thread, within the interval set with
+    /// [`Builder::set_max_sync_interval`], or immediately if none is set. The first commit is
+    /// always synced, since there is no estimate before then
+    pub fn set_commit_latency_budget(&mut self, budget: Duration) -> &mut Self {
+        self.commit_latency_budget = Some(budget);
+        self
+    }
+
+    /// Enable or disable the commit journal
+    ///
+    /// Each commit is recorded in two places: the commit slot which it overwrites in the file
+    /// header, and an entry of a journal which fills the rest of the header page. If the commit
+    /// slot is torn, or was never written, the commit is recovered from the journal when the
+    /// database is opened. The journal holds as many recent commits as fit in the header page,
+    /// and adds no fsyncs, since it is synced along with the header. The journal is consulted on
+    /// every open, so a database may be written with and without it
+    pub fn set_commit_journal(&mut self, enabled: bool) -> &mut Self {
+        self.commit_journal = enabled;
+        self
+    }
+
+    /// Enable or disable tracking of the entries changed by each commit, as reported by
+    /// [`Database::last_commit_changes`]
+    ///
+    /// Each insertion and removal records the table name and serialized key, and keeps them until
+    /// the transaction commits, so this costs memory in proportion to the number of keys changed.
+    /// [`Table::delete_range`](crate::Table::delete_range) and
+    /// [`WriteTransaction::replace_table`](crate::WriteTransaction::replace_table) must also
+    /// visit every key which they remove
+    pub fn set_track_changes(&mut self, enabled: bool) -> &mut Self {
+        self.track_changes = enabled;
+        self
+    }
+
+    /// Mirror the database to a second file at `path`, for redundancy
+    ///
+    /// Every page written to the database file is also written to the mirror, and commits sync
+    /// both files. If a page fails checksum verification in [`Database::check_integrity`], it is
+    /// restored from its copy in the mirror, when that copy is intact. The mirror is created if it
+    /// does not exist, and is also restored from the database file, or vice versa, if either is
+    /// lost.
+    ///
+    /// A crash part way through a commit may leave the two files holding different commits. When
+    /// the database is opened, the file holding the older commit is copied over the other, so
+    /// that a commit which may never have been reported as complete is discarded
+    ///
+    /// The database and its mirror record a shared random id when the mirror is first populated.
+    /// Opening fails with [`DatabaseError::InvalidConfiguration`] if the mirror file is neither
+    /// empty nor a mirror of this database, and neither file is modified
+    ///
+    /// Mirroring is not supported for embedded databases
+    pub fn set_mirror(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        self.mirror = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    // Upgrades the file format of the database at `path`, if it exists and migrations are set
+    fn run_migrations(&self, path: &Path) -> Result<(), DatabaseError> {
+        if self.migrations.is_empty() {
+            return Ok(());
+        }
+        match Database::file_format_version(path) {
+            Ok(version) if version < FILE_FORMAT_VERSION => {
+                Database::migrate(path, &self.migrations)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    #[cfg(any(test, fuzzing))]
+    pub fn set_region_size(&mut self, size: u64) -> &mut Self {
+        assert!(size.is_power_of_two());
+        self.region_size = Some(size);
+        self
+    }
+
+    // Checks the options, both individually and in combination. Every way of opening a database
+    // calls this first, so that an invalid configuration is reported before the file is touched
+    fn validate(&self) -> Result<(), DatabaseError> {
+        self.growth_policy
+            .validate()
+            .map_err(DatabaseError::InvalidConfiguration)?;
+        if let Some(free_ratio) = self.auto_compaction_threshold {
+            if !(0.0..=1.0).contains(&free_ratio) {
+                return Err(DatabaseError::InvalidConfiguration(format!(
+                    "auto-compaction threshold must be between 0 and 1, found {free_ratio}"
+                )));
+            }
+        }
+        if self.transaction_page_limit == Some(0) {
+            return Err(DatabaseError::InvalidConfiguration(
+                "a transaction page limit of zero would prevent every write".to_string(),
+            ));
+        }
+        if self.max_value_pages == 0 {
+            return Err(DatabaseError::InvalidConfiguration(
+                "a maximum of zero pages per value would prevent every insert".to_string(),
+            ));
+        }
+        if !(0.1..=0.9).contains(&self.node_fill_target) {
+            return Err(DatabaseError::InvalidConfiguration(format!(
+                "node fill target must be between 0.1 and 0.9, found {}",
+                self.node_fill_target
+            )));
+        }
+        if let Some(threshold) = self.overflow_threshold {
+            if threshold <= self.page_size {
+                return Err(DatabaseError::InvalidConfiguration(format!(
+                    "overflow threshold {threshold} must be larger than the page size {}",
+                    self.page_size
+                )));
+            }
+        }
+        if let Some(region_size) = self.region_size {
+            if region_size < self.page_size as u64 {
+                return Err(DatabaseError::InvalidConfiguration(format!(
+                    "region size {region_size} is smaller than the page size {}",
+                    self.page_size
+                )));
+            }
+        }
+
+        Ok(())
     }
 
     /// Opens the specified file as a redb database.
     /// * if the file does not exist, or is an empty file, a new database will be initialized in it
     /// * if the file is a valid redb database, it will be opened
     /// * otherwise this function will return an error
+    ///
+    /// Returns [`DatabaseError::InvalidConfiguration`] if the options set on this builder are
+    /// invalid, or conflict with each other
     pub fn create(&self, path: impl AsRef<Path>) -> Result<Database, DatabaseError> {
+        self.validate()?;
+        self.run_migrations(path.as_ref())?;
         let file = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
             .open(path)?;
 
-        Database::new(
-            file,
-            self.page_size,
-            self.region_size,
-            self.read_cache_size_bytes,
-            self.write_cache_size_bytes,
-        )
-    }
+        Database::new(file, None, self.region_size, false, self)
+    }
+
+    /// Creates a new redb database at the specified path, which must not already exist
+    ///
+    /// The database is initialized in a temporary file in the same directory, which is hard linked
+    /// to `path` once it is complete, and then removed. If initialization fails, or the process
+    /// crashes part way through, nothing is left at `path`. A crash may leave the temporary file
+    /// behind. The link fails, rather than replacing the file, if one was created at `path` in the
+    /// meantime. The file system must support hard links
+    pub fn create_atomic(&self, path: impl AsRef<Path>) -> Result<Database, DatabaseError> {
+        self.validate()?;
+        let path = path.as_ref();
+        // Only saves the work of initializing the database, since the link checks this atomically
+        if path.exists() {
+            return Err(StorageError::Io(ErrorKind::AlreadyExists.into()).into());
+        }
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| StorageError::Io(ErrorKind::InvalidInput.into()))?;
+        let mut temp_name = OsString::from(".");
+        temp_name.push(file_name);
+        temp_name.push(format!(".{}.tmp", std::process::id()));
+        let temp_path = path.with_file_name(temp_name);
+
+        let result = self.create_and_link(&temp_path, path);
+        if result.is_err() {
+            let _ = std::fs::remove_file(&temp_path);
+        }
+        result
+    }
+
+    fn create_and_link(&self, temp_path: &Path, path: &Path) -> Result<Database, DatabaseError> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(temp_path)?;
+        let db = Database::new(file, None, self.region_size, false, self)?;
+        // Dropping the database writes out the allocator state and fsyncs the file
+        drop(db);
+
+        // Unlike a rename, the link never replaces a file which another process created at `path`
+        std::fs::hard_link(temp_path, path)?;
+        // The database is complete at `path` now, so a temporary file left behind is harmless
+        let _ = std::fs::remove_file(temp_path);
+        // Make the link itself durable
+        #[cfg(unix)]
+        {
+            let parent = match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            File::open(parent)?.sync_all()?;
+        }
+
+        self.open(path)
+    }
+
+    /// Creates a new redb database embedded in the existing file at `path`
+    ///
+    /// The database occupies the `len` bytes starting at `offset`, and the rest of the file is
+    /// never modified. Every byte of the window which lies within the file must be zero, so that
+    /// other data is never overwritten, and an error of kind [`ErrorKind::AlreadyExists`] is
+    /// returned otherwise. The file is extended if the window runs past its end. Use
+    /// [`Builder::open_embedded`] to open the database once it has been created.
+    ///
+    /// The database starts out small and grows as needed. Growing it past `len` bytes fails with
+    /// an I/O error. The whole file is locked while the database is open, so only one embedded
+    /// database per file can be open at a time
+    pub fn create_embedded(
+        &self,
+        path: impl AsRef<Path>,
+        offset: u64,
+        len: u64,
+    ) -> Result<Database, DatabaseError> {
+        let file = self.open_embedded_file(path.as_ref())?;
+        if !is_zeroed(&file, offset, len)? {
+            return Err(StorageError::Io(ErrorKind::AlreadyExists.into()).into());
+        }
+
+        Database::new(
+            file,
+            Some(EmbeddedRange { offset, len }),
+            self.region_size,
+            false,
+            self,
+        )
+    }
+
+    /// Opens an existing redb database embedded in the file at `path`, which was created with
+    /// [`Builder::create_embedded`] using the same `offset` and `len`
+    ///
+    /// Returns an error of kind [`ErrorKind::InvalidData`] if the window does not start with a
+    /// redb database
+    pub fn open_embedded(
+        &self,
+        path: impl AsRef<Path>,
+        offset: u64,
+        len: u64,
+    ) -> Result<Database, DatabaseError> {
+        let file = self.open_embedded_file(path.as_ref())?;
+        if !holds_database(&file, offset)? {
+            return Err(StorageError::Io(ErrorKind::InvalidData.into()).into());
+        }
+
+        Database::new(file, Some(EmbeddedRange { offset, len }), None, false, self)
+    }
+
+    fn open_embedded_file(&self, path: &Path) -> Result<File, DatabaseError> {
+        self.validate()?;
+        if self.mirror.is_some() {
+            return Err(DatabaseError::InvalidConfiguration(
+                "an embedded database cannot be mirrored".to_string(),
+            ));
+        }
+        Ok(OpenOptions::new().read(true).write(true).open(path)?)
+    }
+
+    /// Opens an existing redb database.
+    pub fn open(&self, path: impl AsRef<Path>) -> Result<Database, DatabaseError> {
+        self.open_impl(path.as_ref(), false)
+    }
+
+    /// Opens an existing redb database, discarding any commit which was never made durable
+    ///
+    /// After a crash part way through a commit, the secondary commit slot may hold a newer commit
+    /// than the primary, with a valid checksum. [`Builder::open`] recovers it, like any other
+    /// commit. This instead discards it, and restores the last commit which the header marked as
+    /// primary. The discarded commit is overwritten, so that opening the database again can't
+    /// recover it either.
+    ///
+    /// Returns the database, along with the contents of the discarded commit slot, if there was
+    /// one. [`Durability::None`] commits are never written to a commit slot, so those which were
+    /// not followed by a durable commit are lost on a crash either way
+    pub fn open_discarding_non_durable(
+        &self,
+        path: impl AsRef<Path>,
+    ) -> Result<(Database, Option<TransactionSlotDump>), DatabaseError> {
+        let db = self.open_impl(path.as_ref(), true)?;
+        let discarded = db.mem.discarded_commit();
+        Ok((db, discarded))
+    }
+
+    /// Opens an existing redb database for reading only
+    ///
+    /// The file is opened read-only and never written to, so the options of this builder which
+    /// only affect writes are ignored. The file is still locked, so this returns
+    /// [`DatabaseError::DatabaseAlreadyOpen`] if the database is open for writing
+    ///
+    /// `path` may also hold a full backup written by [`Database::backup`], which is read in place
+    /// without restoring it. It is independent of the database it was taken from, so it can be
+    /// opened while that database is still being written. A delta backup can't be opened, since
+    /// it lacks the pages which did not change
+    pub fn open_read_only(
+        &self,
+        path: impl AsRef<Path>,
+    ) -> Result<ReadOnlyDatabase, DatabaseError> {
+        self.validate()?;
+        let path = path.as_ref();
+        if !path.exists() {
+            Err(StorageError::Io(ErrorKind::NotFound.into()).into())
+        } else if File::open(path)?.metadata()?.len() > 0 {
+            ReadOnlyDatabase::new(
+                File::open(path)?,
+                self.page_size,
+                self.strict_page_size,
+                self.read_cache_size_bytes,
+            )
+        } else {
+            Err(StorageError::Io(io::Error::from(ErrorKind::InvalidData)).into())
+        }
+    }
+
+    fn open_impl(&self, path: &Path, discard_non_durable: bool) -> Result<Database, DatabaseError> {
+        self.validate()?;
+        if !path.exists() {
+            Err(StorageError::Io(ErrorKind::NotFound.into()).into())
+        } else if File::open(path)?.metadata()?.len() > 0 {
+            self.run_migrations(path)?;
+            let file = OpenOptions::new().read(true).write(true).open(path)?;
+            Database::new(file, None, None, discard_non_durable, self)
+        } else {
+            Err(StorageError::Io(io::Error::from(ErrorKind::InvalidData)).into())
+        }
+    }
+}
+
+// This just makes it easier to throw `dbg` etc statements on `Result<Database>`
+impl std::fmt::Debug for Database {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Database").finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::db::AtomicTransactionId;
+    use crate::transaction_tracker::TransactionId;
+    use crate::tree_store::{Page, PageNumber, RawLeafBuilder, PAGE_SIZE};
+    use crate::{
+        AllocationPolicy, Builder, CommitError, Database, DatabaseError, Durability, GrowthPolicy,
+        ReadableTable, StorageError, TableDefinition, TableError,
+    };
+    use std::mem::size_of;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+
+    #[test]
+    fn invalid_page_size() {
+        let tmpfile = crate::create_tempfile();
+
+        let result = Database::builder()
+            .set_page_size(4000)
+            .create(tmpfile.path());
+        assert!(matches!(
+            result,
+            Err(DatabaseError::InvalidPageSize { requested: 4000 })
+        ));
+
+        let result = Database::builder()
+            .set_page_size(256)
+            .create(tmpfile.path());
+        assert!(matches!(
+            result,
+            Err(DatabaseError::InvalidPageSize { requested: 256 })
+        ));
+
+        let result = Database::builder()
+            .set_page_size(2 * 1024 * 1024)
+            .create(tmpfile.path());
+        assert!(matches!(
+            result,
+            Err(DatabaseError::InvalidPageSize {
+                requested: 0x20_0000
+            })
+        ));
+
+        // The file must not have been modified
+        assert_eq!(tmpfile.as_file().metadata().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn invalid_configuration() {
+        let tmpfile = crate::create_tempfile();
+        let invalid = |builder: &Builder| {
+            assert!(matches!(
+                builder.create(tmpfile.path()),
+                Err(DatabaseError::InvalidConfiguration(_))
+            ));
+            assert!(matches!(
+                builder.open(tmpfile.path()),
+                Err(DatabaseError::InvalidConfiguration(_))
+            ));
+        };
+
+        invalid(Database::builder().set_growth_policy(GrowthPolicy {
+            factor: 0.5,
+            ..GrowthPolicy::default()
+        }));
+        invalid(Database::builder().set_growth_policy(GrowthPolicy {
+            factor: 2.0,
+            min_bytes: 1024 * 1024,
+            max_bytes: Some(1024),
+        }));
+        invalid(Database::builder().set_auto_compaction_threshold(1.5));
+        invalid(Database::builder().set_transaction_page_limit(0));
+        invalid(
+            Database::builder()
+                .set_page_size(16 * 1024)
+                .set_region_size(4096),
+        );
+        invalid(Database::builder().set_overflow_threshold(PAGE_SIZE));
+        invalid(Database::builder().set_max_value_pages(0));
+        invalid(Database::builder().set_node_fill_target(0.95));
+        // The file must not have been modified
+        assert_eq!(tmpfile.as_file().metadata().unwrap().len(), 0);
+
+        let err = Database::builder()
+            .set_transaction_page_limit(0)
+            .create(tmpfile.path())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid database configuration: a transaction page limit of zero would prevent every write"
+        );
+    }
+
+    #[test]
+    fn builder_defaults() {
+        let builder = Builder::new();
+        assert_eq!(builder.page_size, PAGE_SIZE);
+        assert!(!builder.strict_page_size);
+        assert_eq!(builder.region_size, None);
+        assert_eq!(builder.read_cache_size_bytes, 1024 * 1024 * 1024 / 10 * 9);
+        assert_eq!(builder.write_cache_size_bytes, 1024 * 1024 * 1024 / 10);
+        assert_eq!(builder.growth_policy, GrowthPolicy::default());
+        assert_eq!(builder.transaction_page_limit, None);
+        assert!(!builder.zero_on_free);
+        assert!(!builder.punch_holes_on_free);
+        assert_eq!(builder.auto_compaction_threshold, None);
+        assert!(!builder.repair_truncated);
+        assert_eq!(builder.allocation_policy, AllocationPolicy::SmallestFit);
+        assert_eq!(builder.overflow_threshold, None);
+        assert!(!builder.skip_init_fsync);
+        assert_eq!(builder.node_fill_target, 0.5);
+        assert!(!builder.verify_commits);
+        assert!(builder.growth_callback.is_none());
+        assert!(builder.migrations.is_empty());
+        assert_eq!(builder.transaction_epoch, None);
+        assert_eq!(builder.max_sync_interval, None);
+        assert_eq!(builder.mirror, None);
+        assert!(builder.cold_page_callback.is_none());
+        assert!(builder.commit_latency_budget.is_none());
+        assert!(!builder.commit_journal);
+        assert_eq!(builder.max_value_pages, u64::MAX);
+        assert!(!builder.track_changes);
+        assert_eq!(builder.deterministic_seed, None);
+        assert!(builder.validate().is_ok());
+
+        let tmpfile = crate::create_tempfile();
+        builder.create(tmpfile.path()).unwrap();
+        Database::open(tmpfile.path()).unwrap();
+    }
+
+    #[test]
+    fn deterministic_allocation() {
+        let table_def: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+        let run = |policy| {
+            let tmpfile = crate::create_tempfile();
+            let db = Database::builder()
+                .set_allocation_policy(policy)
+                .create(tmpfile.path())
+                .unwrap();
+            for round in 0..10u64 {
+                let txn = db.begin_write().unwrap();
+                {
+                    let mut table = txn.open_table(table_def).unwrap();
+                    for i in 0..200 {
+                        let value = vec![0xAB; ((i * 37 + round * 11) % 2000) as usize];
+                        table.insert(round * 100 + i, value.as_slice()).unwrap();
+                    }
+                    for i in (0..round * 100).step_by(3) {
+                        table.remove(i).unwrap();
+                    }
+                }
+                txn.commit().unwrap();
+            }
+            let allocators = db.get_memory().get_raw_allocator_states();
+            let roots: Vec<_> = db
+                .dump_metapage()
+                .unwrap()
+                .slots()
+                .iter()
+                .map(|slot| (slot.user_root(), slot.system_root(), slot.freed_root()))
+                .collect();
+            drop(db);
+            // The commit slots also record the wall-clock time of each commit, so only the pages
+            // after the super-header are compared byte for byte
+            let file = std::fs::read(tmpfile.path()).unwrap();
+            (allocators, roots, file[PAGE_SIZE..].to_vec())
+        };
+
+        // The same operations produce the same pages, and the same file
+        assert_eq!(
+            run(AllocationPolicy::LowestIndex),
+            run(AllocationPolicy::LowestIndex)
+        );
+        assert_eq!(
+            run(AllocationPolicy::SmallestFit),
+            run(AllocationPolicy::SmallestFit)
+        );
+        assert_ne!(
+            run(AllocationPolicy::LowestIndex).0,
+            run(AllocationPolicy::SmallestFit).0
+        );
+    }
+
+    #[test]
+    fn overflow_allocations() {
+        let tmpfile = crate::create_tempfile();
+        let table_def: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+        let open = || {
+            Database::builder()
+                .set_overflow_threshold(PAGE_SIZE + 1)
+                .create(tmpfile.path())
+                .unwrap()
+        };
+        let allocated_pages = |db: &Database| -> Vec<PageNumber> {
+            db.get_memory().iter_pages().map(|x| x.unwrap().0).collect()
+        };
+        // Every multi-page allocation must lie after every single page allocation. The pages
+        // allocated when the database is created hold allocator state, so are placed separately
+        let check_partitioned = |db: &Database, initial_pages: &[PageNumber]| {
+            let mut last_node = None;
+            let mut first_overflow = None;
+            for page_number in allocated_pages(db) {
+                if initial_pages.contains(&page_number) {
+                    continue;
+                }
+                let start = (
+                    page_number.region,
+                    page_number.page_index << page_number.page_order,
+                );
+                let end = (
+                    page_number.region,
+                    (page_number.page_index + 1) << page_number.page_order,
+                );
+                if page_number.page_order == 0 {
+                    last_node = last_node.max(Some(end));
+                } else {
+                    first_overflow =
+                        Some(first_overflow.map_or(start, |x: (u32, u32)| x.min(start)));
+                }
+            }
+            assert!(last_node.unwrap() <= first_overflow.unwrap());
+        };
+        let write = |db: &Database, round: u64| {
+            let txn = db.begin_write().unwrap();
+            {
+                let mut table = txn.open_table(table_def).unwrap();
+                for i in 0..50 {
+                    let key = round * 100 + i;
+                    if i % 25 == 0 {
+                        table
+                            .insert(key, vec![1; 3 * PAGE_SIZE].as_slice())
+                            .unwrap();
+                    } else {
+                        table.insert(key, [2; 8].as_slice()).unwrap();
+                    }
+                }
+                // Free some of both, so that later allocations reuse them
+                for i in (0..round * 100).step_by(7) {
+                    table.remove(i).unwrap();
+                }
+            }
+            txn.commit().unwrap();
+        };
+
+        let db = open();
+        let initial_pages = allocated_pages(&db);
+        let initial_len = tmpfile.as_file().metadata().unwrap().len();
+        for round in 0..5 {
+            write(&db, round);
+            check_partitioned(&db, &initial_pages);
+        }
+        drop(db);
+
+        let db = open();
+        check_partitioned(&db, &initial_pages);
+        for round in 5..10 {
+            write(&db, round);
+            check_partitioned(&db, &initial_pages);
+        }
+        // Space added by growing the file is shared by both kinds of allocation, so the workload
+        // must fit in the initial file
+        assert_eq!(tmpfile.as_file().metadata().unwrap().len(), initial_len);
+        let txn = db.begin_read().unwrap();
+        let table = txn.open_table(table_def).unwrap();
+        for round in 0..10u64 {
+            for i in 0..50 {
+                let key = round * 100 + i;
+                let removed = (round + 1..10).any(|r| r * 100 > key && key % 7 == 0);
+                let expected_len = if i % 25 == 0 { 3 * PAGE_SIZE } else { 8 };
+                match table.get(key).unwrap() {
+                    Some(value) => {
+                        assert!(!removed);
+                        assert_eq!(value.value().len(), expected_len);
+                    }
+                    None => assert!(removed),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn skip_init_fsync() {
+        let tmpfile = crate::create_tempfile();
+        let table_def: TableDefinition<u64, u64> = TableDefinition::new("x");
+        let db = Database::builder()
+            .set_skip_init_fsync(true)
+            .create(tmpfile.path())
+            .unwrap();
+        assert!(db.dump_metapage().unwrap().magic_number_valid());
+        let txn = db.begin_write().unwrap();
+        txn.open_table(table_def).unwrap().insert(1, 2).unwrap();
+        txn.commit().unwrap();
+        drop(db);
+
+        for skip in [true, false] {
+            let db = Database::builder()
+                .set_skip_init_fsync(skip)
+                .open(tmpfile.path())
+                .unwrap();
+            let txn = db.begin_read().unwrap();
+            let table = txn.open_table(table_def).unwrap();
+            assert_eq!(table.get(1).unwrap().unwrap().value(), 2);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn physical_size() {
+        // Skip the test if the file system does not support sparse files
+        let probe = crate::create_tempfile();
+        probe.as_file().set_len(16 * 1024 * 1024).unwrap();
+        if std::os::unix::fs::MetadataExt::blocks(&probe.as_file().metadata().unwrap()) > 0 {
+            return;
+        }
+
+        let tmpfile = crate::create_tempfile();
+        let db = Database::create(tmpfile.path()).unwrap();
+        let logical_size = db.logical_size().unwrap();
+        assert_eq!(logical_size, tmpfile.as_file().metadata().unwrap().len());
+        // Only the header and allocator state have been written
+        let initial_physical_size = db.physical_size().unwrap();
+        assert!(initial_physical_size < logical_size);
+
+        let table_def: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(table_def).unwrap();
+            for i in 0..100 {
+                table.insert(i, [0xAB; 1000].as_slice()).unwrap();
+            }
+        }
+        txn.commit().unwrap();
+        assert!(db.physical_size().unwrap() > initial_physical_size);
+    }
+
+    #[test]
+    fn transaction_epoch() {
+        let tmpfile = crate::create_tempfile();
+        let table_def: TableDefinition<u64, u64> = TableDefinition::new("x");
+        let last_commit = || {
+            let dump = Database::verify_header(tmpfile.path()).unwrap();
+            dump.slots()[dump.primary_slot()].clone()
+        };
+        let insert = |db: &Database, key: u64| {
+            let txn = db.begin_write().unwrap();
+            txn.open_table(table_def).unwrap().insert(key, key).unwrap();
+            txn.commit()
+        };
+        let contains = |db: &Database, key: u64| {
+            let txn = db.begin_read().unwrap();
+            let table = txn.open_table(table_def).unwrap();
+            let found = table.get(key).unwrap().is_some();
+            found
+        };
+
+        let db = Builder::new()
+            .set_transaction_epoch(2)
+            .create(tmpfile.path())
+            .unwrap();
+        insert(&db, 0).unwrap();
+        assert_eq!(last_commit().epoch(), 2);
+        assert!(last_commit().counter() < 10);
+        drop(db);
+
+        // Commits in an earlier epoch are refused
+        let db = Builder::new()
+            .set_transaction_epoch(1)
+            .open(tmpfile.path())
+            .unwrap();
+        assert!(matches!(
+            insert(&db, 1),
+            Err(CommitError::Storage(
+                StorageError::TransactionEpochMismatch { epoch: 1, .. }
+            ))
+        ));
+        assert!(!contains(&db, 1));
+        drop(db);
+        assert_eq!(last_commit().epoch(), 2);
+
+        // A later epoch starts its counter again
+        let mut db = Builder::new()
+            .set_transaction_epoch(3)
+            .open(tmpfile.path())
+            .unwrap();
+        insert(&db, 2).unwrap();
+        assert_eq!(last_commit().epoch(), 3);
+        assert!(last_commit().counter() < 10);
+
+        // The counter can't overflow into the next epoch
+        db.next_transaction_id =
+            AtomicTransactionId::new(TransactionId::first_in_epoch(4).parent().unwrap());
+        insert(&db, 3).unwrap();
+        assert_eq!(last_commit().counter(), u32::MAX);
+        assert!(matches!(
+            insert(&db, 4),
+            Err(CommitError::Storage(
+                StorageError::TransactionEpochMismatch { epoch: 3, .. }
+            ))
+        ));
+        assert!(contains(&db, 3));
+        assert!(!contains(&db, 4));
+    }
+
+    #[test]
+    fn free_ranges() {
+        let tmpfile = crate::create_tempfile();
+        let db = Database::create(tmpfile.path()).unwrap();
+        let initial = db.free_ranges();
+        let first = initial[0].clone();
+        assert!(first.end - first.start > 3);
+        assert!(initial.windows(2).all(|pair| pair[0].end < pair[1].start));
+
+        let pages: Vec<PageNumber> = (0..3)
+            .map(|_| {
+                db.get_memory()
+                    .allocate_lowest(PAGE_SIZE)
+                    .unwrap()
+                    .get_page_number()
+            })
+            .collect();
+        assert_eq!(db.free_ranges()[0], (first.start + 3)..first.end);
+        assert_eq!(db.free_ranges()[1..], initial[1..]);
+
+        // Freeing the middle page leaves it in a range of its own
+        db.get_memory().free(pages[1]).unwrap();
+        let mut expected = vec![
+            (first.start + 1)..(first.start + 2),
+            (first.start + 3)..first.end,
+        ];
+        expected.extend_from_slice(&initial[1..]);
+        assert_eq!(db.free_ranges(), expected);
+
+        // And freeing the others coalesces them again
+        db.get_memory().free(pages[0]).unwrap();
+        db.get_memory().free(pages[2]).unwrap();
+        assert_eq!(db.free_ranges(), initial);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn punch_holes_on_free() {
+        // Skip the test if the file system does not support sparse files
+        let probe = crate::create_tempfile();
+        probe.as_file().set_len(16 * 1024 * 1024).unwrap();
+        if std::os::unix::fs::MetadataExt::blocks(&probe.as_file().metadata().unwrap()) > 0 {
+            return;
+        }
+
+        let tmpfile = crate::create_tempfile();
+        let db = Database::builder()
+            .set_punch_holes_on_free(true)
+            .create(tmpfile.path())
+            .unwrap();
+        let table_def: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+        let value = vec![0xAB; 4 * 1024 * 1024];
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(table_def).unwrap();
+            table.insert(0, value.as_slice()).unwrap();
+            // Keeps the space of the first value in the middle of the file
+            table.insert(1, value.as_slice()).unwrap();
+        }
+        txn.commit().unwrap();
+        let logical_size = db.logical_size().unwrap();
+        let physical_size = db.physical_size().unwrap();
+
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(table_def).unwrap();
+            table.remove(0).unwrap();
+        }
+        txn.commit().unwrap();
+        // The pages are freed by the next commit, once no snapshot references them
+        db.begin_write().unwrap().commit().unwrap();
+
+        assert_eq!(db.logical_size().unwrap(), logical_size);
+        assert!(db.physical_size().unwrap() <= physical_size - value.len() as u64);
+        let txn = db.begin_read().unwrap();
+        let table = txn.open_table(table_def).unwrap();
+        assert_eq!(table.get(1).unwrap().unwrap().value(), value.as_slice());
+    }
+
+    #[test]
+    fn node_fill_target() {
+        let table_def: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+        let run = |fill_target: f64, sequential: bool| {
+            let tmpfile = crate::create_tempfile();
+            let db = Database::builder()
+                .set_node_fill_target(fill_target)
+                .create(tmpfile.path())
+                .unwrap();
+            let txn = db.begin_write().unwrap();
+            {
+                let mut table = txn.open_table(table_def).unwrap();
+                for i in 0..5000u64 {
+                    let key = if sequential { i } else { i * 7919 % 5000 };
+                    table.insert(key, [0xAB; 100].as_slice()).unwrap();
+                }
+            }
+            txn.commit().unwrap();
+            let txn = db.begin_read().unwrap();
+            let table = txn.open_table(table_def).unwrap();
+            assert_eq!(table.len().unwrap(), 5000);
+            for (i, entry) in table.iter().unwrap().enumerate() {
+                assert_eq!(entry.unwrap().0.value(), i as u64);
+            }
+            drop(table);
+            drop(txn);
+            let txn = db.begin_write().unwrap();
+            let leaf_pages = txn.stats().unwrap().leaf_pages();
+            txn.abort().unwrap();
+            leaf_pages
+        };
+
+        // Sequential inserts leave every node but the last filled to the target
+        let full = run(0.9, true);
+        let even = run(0.5, true);
+        let sparse = run(0.1, true);
+        assert!(full < even);
+        assert!(even < sparse);
+        for fill_target in [0.1, 0.5, 0.9] {
+            run(fill_target, false);
+        }
+    }
+
+    #[test]
+    fn rebuild_free_list() {
+        let tmpfile = crate::create_tempfile();
+        let table_def: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+        let mut db = Database::create(tmpfile.path()).unwrap();
+        for round in 0..5u64 {
+            let txn = db.begin_write().unwrap();
+            {
+                let mut table = txn.open_table(table_def).unwrap();
+                for i in 0..100 {
+                    table
+                        .insert(round * 100 + i, [0xAB; 500].as_slice())
+                        .unwrap();
+                }
+                for i in (0..round * 100).step_by(2) {
+                    table.remove(i).unwrap();
+                }
+            }
+            txn.commit().unwrap();
+        }
+        let read_keys = |db: &Database| -> Vec<u64> {
+            let txn = db.begin_read().unwrap();
+            let table = txn.open_table(table_def).unwrap();
+            table
+                .iter()
+                .unwrap()
+                .map(|x| x.unwrap().0.value())
+                .collect()
+        };
+        let keys = read_keys(&db);
+
+        // Rebuilding a consistent allocator state does not change it
+        db.rebuild_free_list().unwrap();
+        let allocators = db.get_memory().get_raw_allocator_states();
+        db.rebuild_free_list().unwrap();
+        assert_eq!(db.get_memory().get_raw_allocator_states(), allocators);
+        assert_eq!(read_keys(&db), keys);
+
+        // Leak some free pages, and free a page which is in use
+        let mem = db.get_memory();
+        let leaked = (200..210).map(|i| Ok(PageNumber::new(0, i, 0)));
+        mem.mark_pages_allocated(leaked, true).unwrap();
+        mem.free(mem.get_data_root().unwrap().0).unwrap();
+        assert_ne!(mem.get_raw_allocator_states(), allocators);
+
+        db.rebuild_free_list().unwrap();
+        assert_eq!(db.get_memory().get_raw_allocator_states(), allocators);
+        assert_eq!(read_keys(&db), keys);
+        // The full repair agrees with the rebuilt state
+        db.check_integrity().unwrap();
+        assert_eq!(db.get_memory().get_raw_allocator_states(), allocators);
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(table_def).unwrap();
+            table.insert(10_000, [0xCD; 500].as_slice()).unwrap();
+        }
+        txn.commit().unwrap();
+        drop(db);
+
+        let db = Database::open(tmpfile.path()).unwrap();
+        assert_eq!(read_keys(&db).len(), keys.len() + 1);
+    }
+
+    #[test]
+    fn mismatched_page_size() {
+        let tmpfile = crate::create_tempfile();
+        let table_definition: TableDefinition<u64, u64> = TableDefinition::new("x");
+
+        let db = Database::builder()
+            .set_page_size(1024)
+            .create(tmpfile.path())
+            .unwrap();
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(table_definition).unwrap();
+            table.insert(0, 1).unwrap();
+        }
+        txn.commit().unwrap();
+        drop(db);
+
+        // The stored page size is used for an existing database
+        let db = Database::builder()
+            .set_page_size(4096)
+            .open(tmpfile.path())
+            .unwrap();
+        let txn = db.begin_read().unwrap();
+        let table = txn.open_table(table_definition).unwrap();
+        assert_eq!(table.get(0).unwrap().unwrap().value(), 1);
+        drop(table);
+        drop(txn);
+        drop(db);
+
+        let result = Database::builder()
+            .set_page_size(4096)
+            .set_strict_page_size(true)
+            .open(tmpfile.path());
+        assert!(matches!(
+            result,
+            Err(DatabaseError::PageSizeMismatch {
+                requested: 4096,
+                stored: 1024
+            })
+        ));
+    }
+
+    #[test]
+    fn check_integrity_with_threads() {
+        let tmpfile = crate::create_tempfile();
+        let table_definition: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+
+        let db = Database::builder()
+            .set_page_size(1024)
+            .create(tmpfile.path())
+            .unwrap();
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(table_definition).unwrap();
+            for i in 0..2000u64 {
+                table.insert(i, [1u8; 100].as_slice()).unwrap();
+            }
+        }
+        txn.commit().unwrap();
+        drop(db);
+        let original = std::fs::read(tmpfile.path()).unwrap();
+
+        let serial = crate::create_tempfile();
+        let parallel = crate::create_tempfile();
+        // Corrupt the file in various places, and check that both walks reach the same conclusion
+        let offsets = (original.len() / 4..original.len()).step_by(original.len() / 16);
+        for offset in [None].into_iter().chain(offsets.map(Some)) {
+            let mut data = original.clone();
+            if let Some(offset) = offset {
+                data[offset] ^= 0xFF;
+            }
+            std::fs::write(serial.path(), &data).unwrap();
+            std::fs::write(parallel.path(), &data).unwrap();
+
+            let serial_result = Database::open(serial.path())
+                .unwrap()
+                .check_integrity()
+                .map_err(|e| e.to_string());
+            let parallel_result = Database::open(parallel.path())
+                .unwrap()
+                .check_integrity_with_threads(4)
+                .map_err(|e| e.to_string());
+            assert_eq!(serial_result, parallel_result);
+        }
+    }
+
+    #[test]
+    fn mirror() {
+        let tmpfile = crate::create_tempfile();
+        let mirror = crate::create_tempfile();
+        let table_definition: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+        let value = b"a value which appears once in the file".as_slice();
+
+        let db = Database::builder()
+            .set_mirror(mirror.path())
+            .create(tmpfile.path())
+            .unwrap();
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(table_definition).unwrap();
+            table.insert(0, value).unwrap();
+        }
+        txn.commit().unwrap();
+        drop(db);
+        let original = std::fs::read(tmpfile.path()).unwrap();
+        assert_eq!(original, std::fs::read(mirror.path()).unwrap());
+
+        // Corrupt the leaf holding the value, in the database file only
+        let mut corrupted = original.clone();
+        let offset = corrupted
+            .windows(value.len())
+            .position(|window| window == value)
+            .unwrap();
+        corrupted[offset] ^= 0xFF;
+
+        let unmirrored = crate::create_tempfile();
+        std::fs::write(unmirrored.path(), &corrupted).unwrap();
+        let mut db = Database::open(unmirrored.path()).unwrap();
+        // The repair rolls back to the commit before the table was created
+        assert!(!db.check_integrity().unwrap());
+        let txn = db.begin_read().unwrap();
+        assert!(matches!(
+            txn.open_table(table_definition),
+            Err(TableError::TableDoesNotExist(_))
+        ));
+        drop(txn);
+        drop(db);
+
+        std::fs::write(tmpfile.path(), &corrupted).unwrap();
+        let mut db = Database::builder()
+            .set_mirror(mirror.path())
+            .open(tmpfile.path())
+            .unwrap();
+        db.check_integrity().unwrap();
+        let txn = db.begin_read().unwrap();
+        let table = txn.open_table(table_definition).unwrap();
+        assert_eq!(table.get(0).unwrap().unwrap().value(), value);
+        drop(table);
+        drop(txn);
+        drop(db);
+        assert_eq!(
+            std::fs::read(tmpfile.path()).unwrap(),
+            std::fs::read(mirror.path()).unwrap()
+        );
+
+        // A lost database file is restored from the mirror
+        std::fs::remove_file(tmpfile.path()).unwrap();
+        let db = Database::builder()
+            .set_mirror(mirror.path())
+            .create(tmpfile.path())
+            .unwrap();
+        let txn = db.begin_read().unwrap();
+        let table = txn.open_table(table_definition).unwrap();
+        assert_eq!(table.get(0).unwrap().unwrap().value(), value);
+        drop(table);
+        drop(txn);
+        drop(db);
+
+        // A mirror of another database is never reconciled with this one
+        let other = crate::create_tempfile();
+        Database::create(other.path()).unwrap();
+        let other_contents = std::fs::read(other.path()).unwrap();
+        let mirror_contents = std::fs::read(mirror.path()).unwrap();
+        assert!(matches!(
+            Database::builder()
+                .set_mirror(mirror.path())
+                .open(other.path()),
+            Err(DatabaseError::InvalidConfiguration(_))
+        ));
+        assert_eq!(other_contents, std::fs::read(other.path()).unwrap());
+        assert_eq!(mirror_contents, std::fs::read(mirror.path()).unwrap());
+    }
+
+    #[test]
+    fn small_pages() {
+        let tmpfile = crate::create_tempfile();
+
+        let db = Database::builder()
+            .set_page_size(512)
+            .create(tmpfile.path())
+            .unwrap();
+
+        let table_definition: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+        let txn = db.begin_write().unwrap();
+        {
+            txn.open_table(table_definition).unwrap();
+        }
+        txn.commit().unwrap();
+    }
+
+    #[test]
+    fn small_pages2() {
+        let tmpfile = crate::create_tempfile();
+
+        let db = Database::builder()
+            .set_page_size(512)
+            .create(tmpfile.path())
+            .unwrap();
+
+        let table_def: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+
+        let mut tx = db.begin_write().unwrap();
+        tx.set_durability(Durability::Paranoid);
+        let savepoint0 = tx.ephemeral_savepoint().unwrap();
+        {
+            tx.open_table(table_def).unwrap();
+        }
+        tx.commit().unwrap();
+
+        let mut tx = db.begin_write().unwrap();
+        tx.set_durability(Durability::Paranoid);
+        let savepoint1 = tx.ephemeral_savepoint().unwrap();
+        tx.restore_savepoint(&savepoint0).unwrap();
+        tx.set_durability(Durability::None);
+        {
+            let mut t = tx.open_table(table_def).unwrap();
+            t.insert_reserve(&660503, 489).unwrap().as_mut().fill(0xFF);
+            assert!(t.remove(&291295).unwrap().is_none());
+        }
+        tx.commit().unwrap();
+
+        let mut tx = db.begin_write().unwrap();
+        tx.set_durability(Durability::Paranoid);
+        tx.restore_savepoint(&savepoint0).unwrap();
+        {
+            tx.open_table(table_def).unwrap();
+        }
+        tx.commit().unwrap();
+
+        let mut tx = db.begin_write().unwrap();
+        tx.set_durability(Durability::Paranoid);
+        let savepoint2 = tx.ephemeral_savepoint().unwrap();
+        drop(savepoint0);
+        tx.restore_savepoint(&savepoint2).unwrap();
+        {
+            let mut t = tx.open_table(table_def).unwrap();
+            assert!(t.get(&2059).unwrap().is_none());
+            assert!(t.remove(&145227).unwrap().is_none());
+            assert!(t.remove(&145227).unwrap().is_none());
+        }
+        tx.commit().unwrap();
+
+        let mut tx = db.begin_write().unwrap();
+        tx.set_durability(Durability::Paranoid);
+        let savepoint3 = tx.ephemeral_savepoint().unwrap();
+        drop(savepoint1);
+        tx.restore_savepoint(&savepoint3).unwrap();
+        {
+            tx.open_table(table_def).unwrap();
+        }
+        tx.commit().unwrap();
+
+        let mut tx = db.begin_write().unwrap();
+        tx.set_durability(Durability::Paranoid);
+        let savepoint4 = tx.ephemeral_savepoint().unwrap();
+        drop(savepoint2);
+        tx.restore_savepoint(&savepoint3).unwrap();
+        tx.set_durability(Durability::None);
+        {
+            let mut t = tx.open_table(table_def).unwrap();
+            assert!(t.remove(&207936).unwrap().is_none());
+        }
+        tx.abort().unwrap();
+
+        let mut tx = db.begin_write().unwrap();
+        tx.set_durability(Durability::Paranoid);
+        let savepoint5 = tx.ephemeral_savepoint().unwrap();
+        drop(savepoint3);
+        assert!(tx.restore_savepoint(&savepoint4).is_err());
+        {
+            tx.open_table(table_def).unwrap();
+        }
+        tx.commit().unwrap();
+
+        let mut tx = db.begin_write().unwrap();
+        tx.set_durability(Durability::Paranoid);
+        tx.restore_savepoint(&savepoint5).unwrap();
+        tx.set_durability(Durability::None);
+        {
+            tx.open_table(table_def).unwrap();
+        }
+        tx.commit().unwrap();
+    }
+
+    #[test]
+    fn small_pages3() {
+        let tmpfile = crate::create_tempfile();
+
+        let db = Database::builder()
+            .set_page_size(1024)
+            .create(tmpfile.path())
+            .unwrap();
+
+        let table_def: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+
+        let mut tx = db.begin_write().unwrap();
+        let _savepoint0 = tx.ephemeral_savepoint().unwrap();
+        tx.set_durability(Durability::None);
+        {
+            let mut t = tx.open_table(table_def).unwrap();
+            let value = vec![0; 306];
+            t.insert(&539717, value.as_slice()).unwrap();
+        }
+        tx.abort().unwrap();
+
+        let mut tx = db.begin_write().unwrap();
+        let savepoint1 = tx.ephemeral_savepoint().unwrap();
+        tx.restore_savepoint(&savepoint1).unwrap();
+        tx.set_durability(Durability::None);
+        {
+            let mut t = tx.open_table(table_def).unwrap();
+            let value = vec![0; 2008];
+            t.insert(&784384, value.as_slice()).unwrap();
+        }
+        tx.abort().unwrap();
+    }
+
+    #[test]
+    fn small_pages4() {
+        let tmpfile = crate::create_tempfile();
+
+        let db = Database::builder()
+            .set_cache_size(1024 * 1024)
+            .set_page_size(1024)
+            .create(tmpfile.path())
+            .unwrap();
+
+        let table_def: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
 
-    /// Opens an existing redb database.
-    pub fn open(&self, path: impl AsRef<Path>) -> Result<Database, DatabaseError> {
-        if !path.as_ref().exists() {
-            Err(StorageError::Io(ErrorKind::NotFound.into()).into())
-        } else if File::open(path.as_ref())?.metadata()?.len() > 0 {
-            let file = OpenOptions::new().read(true).write(true).open(path)?;
-            Database::new(
-                file,
-                self.page_size,
-                None,
-                self.read_cache_size_bytes,
-                self.write_cache_size_bytes,
-            )
-        } else {
-            Err(StorageError::Io(io::Error::from(ErrorKind::InvalidData)).into())
+        let tx = db.begin_write().unwrap();
+        {
+            tx.open_table(table_def).unwrap();
         }
-    }
-}
+        tx.commit().unwrap();
 
-// This just makes it easier to throw `dbg` etc statements on `Result<Database>`
-impl std::fmt::Debug for Database {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Database").finish()
+        let tx = db.begin_write().unwrap();
+        {
+            let mut t = tx.open_table(table_def).unwrap();
+            assert!(t.get(&131072).unwrap().is_none());
+            let value = vec![0xFF; 1130];
+            t.insert(&42394, value.as_slice()).unwrap();
+            t.insert_reserve(&744037, 3645).unwrap().as_mut().fill(0xFF);
+            assert!(t.get(&0).unwrap().is_none());
+        }
+        tx.abort().unwrap();
+
+        let tx = db.begin_write().unwrap();
+        {
+            let mut t = tx.open_table(table_def).unwrap();
+            t.insert_reserve(&118749, 734).unwrap().as_mut().fill(0xFF);
+        }
+        tx.abort().unwrap();
     }
-}
 
-#[cfg(test)]
-mod test {
-    use crate::{Database, Durability, ReadableTable, TableDefinition};
+    #[test]
+    fn crash_regression3() {
+        let tmpfile = crate::create_tempfile();
+
+        let db = Database::builder()
+            .set_cache_size(1024 * 1024)
+            .set_page_size(16 * 1024)
+            .set_region_size(32 * 4096)
+            .create(tmpfile.path())
+            .unwrap();
+
+        let tx = db.begin_write().unwrap();
+        let savepoint = tx.ephemeral_savepoint().unwrap();
+        tx.commit().unwrap();
+
+        let mut tx = db.begin_write().unwrap();
+        tx.restore_savepoint(&savepoint).unwrap();
+        tx.commit().unwrap();
+    }
 
     #[test]
-    fn small_pages() {
+    fn crash_regression4() {
         let tmpfile = crate::create_tempfile();
 
         let db = Database::builder()
-            .set_page_size(512)
+            .set_cache_size(12686)
+            .set_page_size(8 * 1024)
+            .set_region_size(32 * 4096)
             .create(tmpfile.path())
             .unwrap();
+        db.set_crash_countdown(10);
 
-        let table_definition: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+        let table_def: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+
+        let tx = db.begin_write().unwrap();
+        let _savepoint = tx.ephemeral_savepoint().unwrap();
+        let _persistent_savepoint = tx.persistent_savepoint().unwrap();
+        tx.commit().unwrap();
+        let tx = db.begin_write().unwrap();
+        {
+            let mut table = tx.open_table(table_def).unwrap();
+            let _ = table.insert_reserve(118821, 360);
+        }
+
+        drop(tx);
+        drop(db);
+        Database::builder()
+            .set_cache_size(1024 * 1024)
+            .set_page_size(8 * 1024)
+            .set_region_size(32 * 4096)
+            .create(tmpfile.path())
+            .unwrap();
+    }
+
+    #[test]
+    fn crash_in_durable_commit_after_non_durable() {
+        let table_def: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+        let value = vec![0xAB; 200];
+        let write = |db: &Database, durability, insert: std::ops::Range<u64>, remove| {
+            let mut txn = db.begin_write()?;
+            txn.set_durability(durability);
+            {
+                let mut table = txn.open_table(table_def)?;
+                for i in insert {
+                    table.insert(i, value.as_slice())?;
+                }
+                for i in remove {
+                    table.remove(i)?;
+                }
+            }
+            txn.commit()?;
+            Ok::<(), crate::Error>(())
+        };
+        let read_keys = |db: &Database| -> Vec<u64> {
+            let txn = db.begin_read().unwrap();
+            let table = txn.open_table(table_def).unwrap();
+            table
+                .iter()
+                .unwrap()
+                .map(|x| x.unwrap().0.value())
+                .collect()
+        };
+        let allocated_pages = |db: &Database| {
+            let txn = db.begin_write().unwrap();
+            let allocated = txn.stats().unwrap().allocated_pages();
+            txn.abort().unwrap();
+            allocated
+        };
+
+        let mut crashed = false;
+        let mut committed = false;
+        // Crash at each possible write in the durable commit, until it succeeds
+        for countdown in 0..1000 {
+            if committed {
+                break;
+            }
+            let tmpfile = crate::create_tempfile();
+            let db = Database::builder()
+                .set_cache_size(16 * 1024)
+                .create(tmpfile.path())
+                .unwrap();
+            write(&db, Durability::Immediate, 0..100, 0..0).unwrap();
+            let durable_keys = read_keys(&db);
+            // Free pages in the non-durable commits, which the durable commit then processes
+            for i in 0..3 {
+                write(
+                    &db,
+                    Durability::None,
+                    (100 + i * 50)..(150 + i * 50),
+                    (i * 30)..(i * 30 + 30),
+                )
+                .unwrap();
+            }
+
+            db.set_crash_countdown(countdown);
+            let result = write(&db, Durability::Immediate, 300..400, 90..110);
+            let expected_keys = if result.is_ok() {
+                committed = true;
+                db.set_crash_countdown(u64::MAX);
+                read_keys(&db)
+            } else {
+                crashed = true;
+                durable_keys
+            };
+            drop(db);
+
+            // Either the durable commit and all the non-durable ones before it are visible, or
+            // the database is rolled back to the previous durable commit
+            let db = Database::open(tmpfile.path()).unwrap();
+            assert_eq!(read_keys(&db), expected_keys);
+            write(&db, Durability::Immediate, 400..500, 0..0).unwrap();
+
+            // The allocator state must match the one rebuilt from the reachable pages by repair
+            let allocated = allocated_pages(&db);
+            // Simulate a crash, so that the allocator state is rebuilt on the next open
+            db.set_crash_countdown(0);
+            drop(db);
+            let db = Database::open(tmpfile.path()).unwrap();
+            assert_eq!(allocated_pages(&db), allocated);
+            assert_eq!(read_keys(&db).len(), expected_keys.len() + 100);
+        }
+        assert!(crashed && committed);
+    }
+
+    #[test]
+    fn noop_commit() {
+        let tmpfile = crate::create_tempfile();
+        let table_def: TableDefinition<u64, u64> = TableDefinition::new("x");
+        let db = Database::create(tmpfile.path()).unwrap();
+        let txn = db.begin_write().unwrap();
+        txn.open_table(table_def).unwrap().insert(1, 1).unwrap();
+        txn.commit().unwrap();
+
+        let contents = std::fs::read(tmpfile.path()).unwrap();
+        let fsyncs = db.mem.fsync_count();
+        let last_commit = db.mem.get_last_committed_transaction_id().unwrap();
+
+        // Only reads, through a table opened for writing
         let txn = db.begin_write().unwrap();
         {
-            txn.open_table(table_definition).unwrap();
+            let table = txn.open_table(table_def).unwrap();
+            assert_eq!(table.get(1).unwrap().unwrap().value(), 1);
         }
+        let stats = txn.commit_with_stats().unwrap();
+        assert_eq!((stats.allocated(), stats.freed()), (0, 0));
+        db.begin_write().unwrap().commit().unwrap();
+
+        assert_eq!(db.mem.fsync_count(), fsyncs);
+        assert_eq!(
+            db.mem.get_last_committed_transaction_id().unwrap(),
+            last_commit
+        );
+        assert_eq!(std::fs::read(tmpfile.path()).unwrap(), contents);
+
+        // A non-durable commit is made durable by the next commit, even if it changes nothing
+        let mut txn = db.begin_write().unwrap();
+        txn.set_durability(Durability::None);
+        txn.open_table(table_def).unwrap().insert(2, 2).unwrap();
         txn.commit().unwrap();
+        db.begin_write().unwrap().commit().unwrap();
+        assert!(db.mem.fsync_count() > fsyncs);
+        assert!(!db.is_reading_non_durable());
     }
 
     #[test]
-    fn small_pages2() {
+    fn max_sync_interval() {
         let tmpfile = crate::create_tempfile();
+        let table_def: TableDefinition<u64, u64> = TableDefinition::new("x");
+        let write = |db: &Database, key: u64| {
+            let mut txn = db.begin_write().unwrap();
+            txn.set_durability(Durability::NoSync);
+            txn.open_table(table_def).unwrap().insert(key, key).unwrap();
+            txn.commit().unwrap();
+        };
+
+        let db = Builder::new()
+            .set_max_sync_interval(Duration::from_millis(100))
+            .create(tmpfile.path())
+            .unwrap();
+        // Capture the contents of the file at the next fsync, which only the timer can make
+        db.set_power_loss_countdown(1).unwrap();
+        write(&db, 1);
+        std::thread::sleep(Duration::from_secs(1));
+        // Not yet synced when the power is lost
+        write(&db, 2);
+        drop(db);
 
+        let db = Database::open(tmpfile.path()).unwrap();
+        let txn = db.begin_read().unwrap();
+        let table = txn.open_table(table_def).unwrap();
+        assert!(table.get(1).unwrap().is_some());
+        assert!(table.get(2).unwrap().is_none());
+    }
+
+    #[test]
+    fn can_insert() {
+        let tmpfile = crate::create_tempfile();
+        let table_def: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+        let page_size = 1024;
         let db = Database::builder()
-            .set_page_size(512)
+            .set_region_size((8 * page_size).try_into().unwrap())
+            .set_page_size(page_size)
+            .create(tmpfile.path())
+            .unwrap();
+        let insert = |value: &[u8]| {
+            let txn = db.begin_write().unwrap();
+            let result = txn
+                .open_table(table_def)
+                .unwrap()
+                .insert(0, value)
+                .map(|_| ());
+            txn.abort().unwrap();
+            result
+        };
+
+        // Find the longest value which is accepted
+        let (mut low, mut high) = (0, 16 * page_size);
+        while low + 1 < high {
+            let mid = (low + high) / 2;
+            if db.can_insert(table_def, 0, vec![0; mid].as_slice()).is_ok() {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+        insert(vec![0; low].as_slice()).unwrap();
+        assert!(matches!(
+            db.can_insert(table_def, 0, vec![0; low + 1].as_slice()),
+            Err(crate::Error::AllocationTooLarge(_))
+        ));
+        assert!(matches!(
+            insert(vec![0; low + 1].as_slice()),
+            Err(StorageError::AllocationTooLarge(_))
+        ));
+
+        let fixed_def: TableDefinition<&[u8], u64> =
+            TableDefinition::new("fixed").with_fixed_key_width(8);
+        db.can_insert(fixed_def, [0; 8].as_slice(), 0).unwrap();
+        assert!(matches!(
+            db.can_insert(fixed_def, [0; 9].as_slice(), 0),
+            Err(crate::Error::KeyWrongSize {
+                expected: 8,
+                actual: 9
+            })
+        ));
+
+        // Once the table exists, the key width which it was created with is used
+        let txn = db.begin_write().unwrap();
+        txn.open_table(fixed_def).unwrap();
+        txn.commit().unwrap();
+        let unfixed_def: TableDefinition<&[u8], u64> = TableDefinition::new("fixed");
+        db.can_insert(unfixed_def, [0; 8].as_slice(), 0).unwrap();
+        assert!(matches!(
+            db.can_insert(unfixed_def, [0; 9].as_slice(), 0),
+            Err(crate::Error::KeyWrongSize {
+                expected: 8,
+                actual: 9
+            })
+        ));
+    }
+
+    #[test]
+    fn deterministic_writes() {
+        let run = |seed: u64| {
+            let tmpfile = crate::create_tempfile();
+            let db = Builder::new()
+                .set_deterministic(seed)
+                .create(tmpfile.path())
+                .unwrap();
+            let a: TableDefinition<u64, &[u8]> = TableDefinition::new("a");
+            let b: TableDefinition<&str, u64> = TableDefinition::new("b");
+            for i in 0..20u64 {
+                let mut txn = db.begin_write().unwrap();
+                if i % 5 == 0 {
+                    txn.set_durability(Durability::None);
+                }
+                {
+                    let mut a = txn.open_table(a).unwrap();
+                    let mut b = txn.open_table(b).unwrap();
+                    for j in 0..100 {
+                        let key = i * 100 + j;
+                        a.insert(
+                            key,
+                            vec![u8::try_from(i).unwrap(); (key % 700) as usize].as_slice(),
+                        )
+                        .unwrap();
+                        b.insert(format!("{key}").as_str(), key).unwrap();
+                    }
+                    a.remove(i * 50).unwrap();
+                }
+                txn.commit().unwrap();
+
+                // Aborted writes, including of savepoints, are rolled back the same way
+                let mut txn = db.begin_write().unwrap();
+                // Persistent savepoints can't follow a non-durable commit
+                if i % 5 != 0 {
+                    txn.persistent_savepoint().unwrap();
+                }
+                txn.open_table(b).unwrap().insert("aborted", i).unwrap();
+                txn.abort().unwrap();
+                txn = db.begin_write().unwrap();
+                txn.open_table(a).unwrap().remove(i * 30).unwrap();
+                txn.commit().unwrap();
+            }
+            drop(db);
+            std::fs::read(tmpfile.path()).unwrap()
+        };
+
+        let first = run(1);
+        assert!(first == run(1));
+        // The seed is recorded in the commit timestamps
+        assert!(first != run(2));
+    }
+
+    #[test]
+    fn max_value_pages() {
+        let tmpfile = crate::create_tempfile();
+        let table_def: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+        let db = Builder::new()
+            .set_max_value_pages(4)
             .create(tmpfile.path())
             .unwrap();
+        let txn = db.begin_write().unwrap();
+        let mut table = txn.open_table(table_def).unwrap();
+        table.insert(0, vec![0; PAGE_SIZE + 1].as_slice()).unwrap();
+
+        // The largest value whose leaf fits in the limit
+        let overhead = RawLeafBuilder::required_bytes(1, size_of::<u64>());
+        let largest = 4 * PAGE_SIZE - overhead;
+        table.insert(1, vec![0; largest].as_slice()).unwrap();
+        let allocated = db.mem.count_allocated_pages().unwrap();
+        // The value alone would fit, but not with its key, so their combined length is reported
+        assert!(matches!(
+            table.insert(2, vec![0; largest + 1].as_slice()),
+            Err(StorageError::ValueTooLarge(len)) if len == size_of::<u64>() + largest + 1
+        ));
+        assert!(matches!(
+            table.insert(2, vec![0; 4 * PAGE_SIZE].as_slice()),
+            Err(StorageError::ValueTooLarge(len)) if len == 4 * PAGE_SIZE
+        ));
+        assert!(matches!(
+            table.insert_reserve(2, (largest + 1).try_into().unwrap()),
+            Err(StorageError::ValueTooLarge(_))
+        ));
+        // A long key is included in the reported length, if the value alone would fit
+        let slice_def: TableDefinition<&[u8], &[u8]> = TableDefinition::new("y");
+        let mut slice_table = txn.open_table(slice_def).unwrap();
+        let key = vec![0; PAGE_SIZE];
+        assert!(matches!(
+            slice_table.insert(key.as_slice(), vec![0; largest].as_slice()),
+            Err(StorageError::ValueTooLarge(len)) if len == PAGE_SIZE + largest
+        ));
+        drop(slice_table);
+        assert_eq!(db.mem.count_allocated_pages().unwrap(), allocated);
+        assert!(table.get(2).unwrap().is_none());
+        drop(table);
+        txn.commit().unwrap();
+
+        assert!(db
+            .can_insert(table_def, 2, vec![0; largest].as_slice())
+            .is_ok());
+        assert!(matches!(
+            db.can_insert(table_def, 2, vec![0; largest + 1].as_slice()),
+            Err(crate::Error::ValueTooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn commit_latency_budget() {
+        let tmpfile = crate::create_tempfile();
+        let table_def: TableDefinition<u64, u64> = TableDefinition::new("x");
+        let write = |db: &Database, key: u64| {
+            let txn = db.begin_write().unwrap();
+            txn.open_table(table_def).unwrap().insert(key, key).unwrap();
+            txn.commit_with_stats().unwrap()
+        };
+
+        let db = Builder::new()
+            .set_commit_latency_budget(Duration::from_millis(100))
+            .create(tmpfile.path())
+            .unwrap();
+        // Fast fsyncs fit in the budget
+        assert!(!write(&db, 1).durability_degraded());
+
+        db.mem.set_sync_delay(Duration::from_millis(400));
+        // The estimate only reflects the slow disk once a slow fsync has completed
+        assert!(!write(&db, 3).durability_degraded());
+        let synced = db.mem.fsync_count();
+        assert!(write(&db, 4).durability_degraded());
+
+        // The degraded commit is synced in the background
+        while db.mem.fsync_count() == synced {
+            std::thread::yield_now();
+        }
+        drop(db);
+
+        let db = Database::open(tmpfile.path()).unwrap();
+        let txn = db.begin_read().unwrap();
+        let table = txn.open_table(table_def).unwrap();
+        assert_eq!(table.get(4).unwrap().unwrap().value(), 4);
+    }
+
+    #[test]
+    fn power_loss_at_each_fsync() {
+        #[derive(Copy, Clone, Debug)]
+        enum Operation {
+            DurableCommit,
+            NonDurableCommit,
+            NonDurableThenDurableCommit,
+            Repair,
+        }
 
         let table_def: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+        let value = vec![0xAB; 200];
+        let write = |db: &Database, durability, insert: std::ops::Range<u64>, remove| {
+            let mut txn = db.begin_write().unwrap();
+            txn.set_durability(durability);
+            {
+                let mut table = txn.open_table(table_def).unwrap();
+                for i in insert {
+                    table.insert(i, value.as_slice()).unwrap();
+                }
+                for i in remove {
+                    table.remove(i).unwrap();
+                }
+            }
+            txn.commit().unwrap();
+        };
+        let read_keys = |db: &Database| -> Vec<u64> {
+            let txn = db.begin_read().unwrap();
+            let table = txn.open_table(table_def).unwrap();
+            table
+                .iter()
+                .unwrap()
+                .map(|x| x.unwrap().0.value())
+                .collect()
+        };
 
-        let mut tx = db.begin_write().unwrap();
-        tx.set_durability(Durability::Paranoid);
-        let savepoint0 = tx.ephemeral_savepoint().unwrap();
+        for operation in [
+            Operation::DurableCommit,
+            Operation::NonDurableCommit,
+            Operation::NonDurableThenDurableCommit,
+            Operation::Repair,
+        ] {
+            let mut lost = false;
+            let mut survived = false;
+            for countdown in 0..10 {
+                let tmpfile = crate::create_tempfile();
+                let mut db = Database::create(tmpfile.path()).unwrap();
+                write(&db, Durability::Immediate, 0..100, 0..0);
+                let old_keys = read_keys(&db);
+
+                db.set_power_loss_countdown(countdown).unwrap();
+                match operation {
+                    Operation::DurableCommit => {
+                        write(&db, Durability::Immediate, 100..200, 0..50);
+                    }
+                    Operation::NonDurableCommit => {
+                        write(&db, Durability::None, 100..200, 0..50);
+                    }
+                    Operation::NonDurableThenDurableCommit => {
+                        write(&db, Durability::None, 100..200, 0..50);
+                        write(&db, Durability::Immediate, 200..300, 100..150);
+                    }
+                    Operation::Repair => {
+                        db.check_integrity().unwrap();
+                    }
+                }
+                let new_keys = read_keys(&db);
+                drop(db);
+
+                // The database is either entirely at the state before the operation, or after it
+                let mut db = Database::open(tmpfile.path()).unwrap();
+                let keys = read_keys(&db);
+                assert!(
+                    keys == old_keys || keys == new_keys,
+                    "{operation:?} with power loss after {countdown} fsyncs"
+                );
+                if keys == new_keys {
+                    survived = true;
+                } else {
+                    lost = true;
+                }
+                db.check_integrity().unwrap();
+                write(&db, Durability::Immediate, 1000..1100, 0..0);
+                assert_eq!(read_keys(&db).len(), keys.len() + 100);
+            }
+            // The power loss hit the operation for the first countdowns, and came too late for
+            // the last ones
+            assert!(survived, "{operation:?}");
+            if !matches!(operation, Operation::Repair) {
+                assert!(lost, "{operation:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn copy_on_write_preserves_snapshot() {
+        let tmpfile = crate::create_tempfile();
+        let table_def: TableDefinition<u64, u64> = TableDefinition::new("x");
+        let db = Database::create(tmpfile.path()).unwrap();
+        let txn = db.begin_write().unwrap();
         {
-            tx.open_table(table_def).unwrap();
+            let mut table = txn.open_table(table_def).unwrap();
+            for i in 0..10 {
+                table.insert(i, i).unwrap();
+            }
         }
-        tx.commit().unwrap();
+        txn.commit().unwrap();
 
-        let mut tx = db.begin_write().unwrap();
-        tx.set_durability(Durability::Paranoid);
-        let savepoint1 = tx.ephemeral_savepoint().unwrap();
-        tx.restore_savepoint(&savepoint0).unwrap();
-        tx.set_durability(Durability::None);
+        let read_txn = db.begin_read().unwrap();
+        let (root, _) = db.mem.get_data_root().unwrap();
+        let original = db.mem.get_page(root).unwrap().memory().to_vec();
+
+        let write_txn = db.begin_write().unwrap();
+        let mut freed = vec![];
+        let mut new_page = db.mem.copy_on_write(root, &mut freed).unwrap();
+        assert_ne!(new_page.get_page_number(), root);
+        assert_eq!(new_page.memory(), original.as_slice());
+        assert_eq!(freed, vec![root]);
+        new_page.memory_mut().fill(0xFF);
+        drop(new_page);
+
+        assert_eq!(db.mem.get_page(root).unwrap().memory(), original.as_slice());
+        let table = read_txn.open_table(table_def).unwrap();
+        for i in 0..10 {
+            assert_eq!(table.get(i).unwrap().unwrap().value(), i);
+        }
+        write_txn.abort().unwrap();
+    }
+
+    #[test]
+    fn abort_after_partial_failure() {
+        let tmpfile = crate::create_tempfile();
+        let table_def: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+        let value = vec![0xAB; 200];
+        let db = Database::builder()
+            .set_cache_size(16 * 1024)
+            .create(tmpfile.path())
+            .unwrap();
+        let txn = db.begin_write().unwrap();
         {
-            let mut t = tx.open_table(table_def).unwrap();
-            t.insert_reserve(&660503, 489).unwrap().as_mut().fill(0xFF);
-            assert!(t.remove(&291295).unwrap().is_none());
+            let mut table = txn.open_table(table_def).unwrap();
+            for i in 0..10 {
+                table.insert(i, value.as_slice()).unwrap();
+            }
+        }
+        txn.commit().unwrap();
+        let allocated_pages = |db: &Database| {
+            let txn = db.begin_write().unwrap();
+            let allocated = txn.stats().unwrap().allocated_pages();
+            txn.abort().unwrap();
+            allocated
+        };
+        let before = allocated_pages(&db);
+
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(table_def).unwrap();
+            for i in 10..200 {
+                table.insert(i, value.as_slice()).unwrap();
+            }
+        }
+        assert!(txn.stats().unwrap().allocated_pages() > before);
+        txn.abort().unwrap();
+        assert_eq!(allocated_pages(&db), before);
+
+        // Fail part way through an insert, once the write buffer fills up
+        db.set_crash_countdown(0);
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(table_def).unwrap();
+            let mut failed = false;
+            for i in 10..1000 {
+                if let Err(err) = table.insert(i, value.as_slice()) {
+                    assert!(matches!(err, StorageError::SimulatedIOFailure));
+                    failed = true;
+                    break;
+                }
+            }
+            assert!(failed);
+        }
+        txn.abort().unwrap();
+        db.set_crash_countdown(u64::MAX);
+        assert_eq!(allocated_pages(&db), before);
+
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(table_def).unwrap();
+            table.insert(10, value.as_slice()).unwrap();
+        }
+        txn.commit().unwrap();
+        let txn = db.begin_read().unwrap();
+        let table = txn.open_table(table_def).unwrap();
+        assert_eq!(table.len().unwrap(), 11);
+    }
+
+    #[test]
+    fn failed_scrub_does_not_poison() {
+        let tmpfile = crate::create_tempfile();
+        let table_def: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+        let value = vec![0xAB; 200];
+        let db = Database::builder()
+            .set_zero_on_free(true)
+            .create(tmpfile.path())
+            .unwrap();
+
+        // Removing the entries frees the uncommitted pages which held them
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(table_def).unwrap();
+            for i in 0..100 {
+                table.insert(i, value.as_slice()).unwrap();
+            }
+            db.set_crash_countdown(0);
+            let mut failed = false;
+            for i in 0..100 {
+                if let Err(err) = table.remove(i) {
+                    assert!(matches!(err, StorageError::SimulatedIOFailure));
+                    failed = true;
+                    break;
+                }
+            }
+            assert!(failed);
         }
-        tx.commit().unwrap();
+        db.set_crash_countdown(u64::MAX);
+        // Pages freed when a removed value's guard was dropped may have failed to be scrubbed too,
+        // which the abort reports instead
+        assert!(matches!(
+            txn.abort(),
+            Ok(()) | Err(StorageError::SimulatedIOFailure)
+        ));
 
-        let mut tx = db.begin_write().unwrap();
-        tx.set_durability(Durability::Paranoid);
-        tx.restore_savepoint(&savepoint0).unwrap();
+        let txn = db.begin_write().unwrap();
         {
-            tx.open_table(table_def).unwrap();
+            let mut table = txn.open_table(table_def).unwrap();
+            table.insert(0, value.as_slice()).unwrap();
         }
-        tx.commit().unwrap();
+        txn.commit().unwrap();
+        let txn = db.begin_read().unwrap();
+        let table = txn.open_table(table_def).unwrap();
+        assert_eq!(table.len().unwrap(), 1);
+    }
 
-        let mut tx = db.begin_write().unwrap();
-        tx.set_durability(Durability::Paranoid);
-        let savepoint2 = tx.ephemeral_savepoint().unwrap();
-        drop(savepoint0);
-        tx.restore_savepoint(&savepoint2).unwrap();
-        {
-            let mut t = tx.open_table(table_def).unwrap();
-            assert!(t.get(&2059).unwrap().is_none());
-            assert!(t.remove(&145227).unwrap().is_none());
-            assert!(t.remove(&145227).unwrap().is_none());
+    #[test]
+    fn no_sync_commit_survives_crash() {
+        let tmpfile = crate::create_tempfile();
+        let table_def: TableDefinition<u64, u64> = TableDefinition::new("x");
+        let db = Database::create(tmpfile.path()).unwrap();
+        for i in 0..3 {
+            let mut txn = db.begin_write().unwrap();
+            txn.set_durability(Durability::NoSync);
+            {
+                let mut table = txn.open_table(table_def).unwrap();
+                table.insert(i, i).unwrap();
+            }
+            txn.commit().unwrap();
         }
-        tx.commit().unwrap();
 
-        let mut tx = db.begin_write().unwrap();
-        tx.set_durability(Durability::Paranoid);
-        let savepoint3 = tx.ephemeral_savepoint().unwrap();
-        drop(savepoint1);
-        tx.restore_savepoint(&savepoint3).unwrap();
-        {
-            tx.open_table(table_def).unwrap();
+        // Simulate a crash, so that nothing is written on drop
+        db.set_crash_countdown(0);
+        drop(db);
+
+        let db = Database::open(tmpfile.path()).unwrap();
+        let txn = db.begin_read().unwrap();
+        let table = txn.open_table(table_def).unwrap();
+        assert_eq!(table.len().unwrap(), 3);
+    }
+
+    #[test]
+    fn flush_non_durable() {
+        let tmpfile = crate::create_tempfile();
+        let table_def: TableDefinition<u64, u64> = TableDefinition::new("x");
+        let db = Database::create(tmpfile.path()).unwrap();
+        assert!(!db.flush_non_durable().unwrap());
+
+        for i in 0..5 {
+            let mut txn = db.begin_write().unwrap();
+            txn.set_durability(Durability::None);
+            {
+                let mut table = txn.open_table(table_def).unwrap();
+                table.insert(i, i).unwrap();
+                if i > 0 {
+                    table.remove(i - 1).unwrap();
+                }
+            }
+            txn.commit().unwrap();
         }
-        tx.commit().unwrap();
+        assert!(db.is_reading_non_durable());
+        // A flush can't be made while a write transaction is in progress
+        let txn = db.begin_write().unwrap();
+        assert!(matches!(
+            db.flush_non_durable(),
+            Err(crate::Error::WriteTransactionInProgress)
+        ));
+        txn.abort().unwrap();
+        assert!(db.flush_non_durable().unwrap());
+        assert!(!db.is_reading_non_durable());
+        assert!(!db.flush_non_durable().unwrap());
+
+        // Simulate a crash, so that nothing is written on drop
+        db.set_crash_countdown(0);
+        drop(db);
 
-        let mut tx = db.begin_write().unwrap();
-        tx.set_durability(Durability::Paranoid);
-        let savepoint4 = tx.ephemeral_savepoint().unwrap();
-        drop(savepoint2);
-        tx.restore_savepoint(&savepoint3).unwrap();
-        tx.set_durability(Durability::None);
-        {
-            let mut t = tx.open_table(table_def).unwrap();
-            assert!(t.remove(&207936).unwrap().is_none());
+        let db = Database::open(tmpfile.path()).unwrap();
+        let txn = db.begin_read().unwrap();
+        let table = txn.open_table(table_def).unwrap();
+        let entries: Vec<(u64, u64)> = table
+            .iter()
+            .unwrap()
+            .map(|x| {
+                let (k, v) = x.unwrap();
+                (k.value(), v.value())
+            })
+            .collect();
+        assert_eq!(entries, vec![(4, 4)]);
+    }
+
+    #[test]
+    fn checkpoint() {
+        let tmpfile = crate::create_tempfile();
+        let table_def: TableDefinition<u64, u64> = TableDefinition::new("x");
+        let db = Database::create(tmpfile.path()).unwrap();
+        for i in 0..3 {
+            let mut txn = db.begin_write().unwrap();
+            if i == 2 {
+                txn.set_durability(Durability::None);
+            }
+            {
+                let mut table = txn.open_table(table_def).unwrap();
+                table.insert(i, i).unwrap();
+            }
+            txn.commit().unwrap();
         }
-        tx.abort().unwrap();
+        assert!(Database::verify_header(tmpfile.path())
+            .unwrap()
+            .recovery_required());
 
-        let mut tx = db.begin_write().unwrap();
-        tx.set_durability(Durability::Paranoid);
-        let savepoint5 = tx.ephemeral_savepoint().unwrap();
-        drop(savepoint3);
-        assert!(tx.restore_savepoint(&savepoint4).is_err());
+        db.checkpoint().unwrap();
+        assert!(!db.is_reading_non_durable());
+        assert!(!Database::verify_header(tmpfile.path())
+            .unwrap()
+            .recovery_required());
+        // The allocator state on disk is stale after the next commit, so it requires a repair again
+        let txn = db.begin_write().unwrap();
         {
-            tx.open_table(table_def).unwrap();
+            let mut table = txn.open_table(table_def).unwrap();
+            table.insert(3, 3).unwrap();
         }
-        tx.commit().unwrap();
+        txn.commit().unwrap();
+        assert!(Database::verify_header(tmpfile.path())
+            .unwrap()
+            .recovery_required());
 
-        let mut tx = db.begin_write().unwrap();
-        tx.set_durability(Durability::Paranoid);
-        tx.restore_savepoint(&savepoint5).unwrap();
-        tx.set_durability(Durability::None);
-        {
-            tx.open_table(table_def).unwrap();
+        // A checkpoint can't be taken while a write transaction is in progress
+        let txn = db.begin_write().unwrap();
+        assert!(matches!(
+            db.checkpoint(),
+            Err(crate::Error::WriteTransactionInProgress)
+        ));
+        txn.abort().unwrap();
+
+        // Simulate a crash right after a checkpoint, so that nothing is written on drop
+        db.checkpoint().unwrap();
+        db.set_crash_countdown(0);
+        drop(db);
+
+        assert!(!Database::verify_header(tmpfile.path())
+            .unwrap()
+            .recovery_required());
+        // The stored allocator state matches the pages which are reachable
+        let report = Database::corruption_report(tmpfile.path()).unwrap();
+        assert!(report.is_clean(), "{report:?}");
+        let db = Database::open(tmpfile.path()).unwrap();
+        let txn = db.begin_read().unwrap();
+        let table = txn.open_table(table_def).unwrap();
+        assert_eq!(table.len().unwrap(), 4);
+    }
+
+    #[test]
+    fn open_discarding_non_durable() {
+        let table_def: TableDefinition<u64, u64> = TableDefinition::new("x");
+        let write = |db: &Database, durability, keys: std::ops::Range<u64>| {
+            let mut txn = db.begin_write().unwrap();
+            txn.set_durability(durability);
+            {
+                let mut table = txn.open_table(table_def).unwrap();
+                for i in keys {
+                    table.insert(i, i).unwrap();
+                }
+            }
+            txn.commit().unwrap();
+        };
+        let read_keys = |db: &Database| -> Vec<u64> {
+            let txn = db.begin_read().unwrap();
+            let table = txn.open_table(table_def).unwrap();
+            table
+                .iter()
+                .unwrap()
+                .map(|x| x.unwrap().0.value())
+                .collect()
+        };
+
+        // Lose power after each fsync of a two-phase commit, which makes the preceding non-durable
+        // commits durable, until the new commit slot is written but never made primary
+        let mut found = false;
+        for countdown in 0..10 {
+            let tmpfile = crate::create_tempfile();
+            let db = Database::create(tmpfile.path()).unwrap();
+            write(&db, Durability::Immediate, 0..10);
+            let durable_keys = read_keys(&db);
+            write(&db, Durability::None, 10..20);
+            write(&db, Durability::None, 20..30);
+            db.set_power_loss_countdown(countdown).unwrap();
+            write(&db, Durability::Paranoid, 30..40);
+            let new_keys = read_keys(&db);
+            drop(db);
+
+            let dump = Database::verify_header(tmpfile.path()).unwrap();
+            let primary = &dump.slots()[dump.primary_slot()];
+            let secondary = &dump.slots()[dump.primary_slot() ^ 1];
+            if !secondary.checksum_valid() || secondary.transaction_id() <= primary.transaction_id()
+            {
+                continue;
+            }
+            found = true;
+            let copy = crate::create_tempfile();
+            std::fs::copy(tmpfile.path(), copy.path()).unwrap();
+
+            // Normal recovery uses the newest valid commit slot
+            let db = Database::open(copy.path()).unwrap();
+            assert_eq!(read_keys(&db), new_keys);
+            drop(db);
+
+            let (mut db, discarded) =
+                Database::open_discarding_non_durable(tmpfile.path()).unwrap();
+            assert_eq!(discarded.as_ref(), Some(secondary));
+            assert_eq!(read_keys(&db), durable_keys);
+            db.check_integrity().unwrap();
+            write(&db, Durability::Immediate, 100..110);
+            // Simulate a crash, so that the next open recovers again
+            db.set_crash_countdown(0);
+            drop(db);
+
+            // The discarded commit can't be recovered later
+            let db = Database::open(tmpfile.path()).unwrap();
+            let mut expected = durable_keys.clone();
+            expected.extend(100..110);
+            assert_eq!(read_keys(&db), expected);
+            drop(db);
+            let (_, discarded) = Database::open_discarding_non_durable(tmpfile.path()).unwrap();
+            assert!(discarded.is_none());
         }
-        tx.commit().unwrap();
+        assert!(found);
     }
 
     #[test]
-    fn small_pages3() {
+    fn cache_stays_within_budget() {
         let tmpfile = crate::create_tempfile();
+        let table_def: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+        let budget = 1024 * 1024;
+        let value = vec![0u8; 1000];
 
         let db = Database::builder()
-            .set_page_size(1024)
+            .set_cache_size(budget)
             .create(tmpfile.path())
             .unwrap();
-
-        let table_def: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
-
-        let mut tx = db.begin_write().unwrap();
-        let _savepoint0 = tx.ephemeral_savepoint().unwrap();
-        tx.set_durability(Durability::None);
+        let txn = db.begin_write().unwrap();
         {
-            let mut t = tx.open_table(table_def).unwrap();
-            let value = vec![0; 306];
-            t.insert(&539717, value.as_slice()).unwrap();
+            let mut table = txn.open_table(table_def).unwrap();
+            for i in 0..20_000u64 {
+                table.insert(i, value.as_slice()).unwrap();
+            }
         }
-        tx.abort().unwrap();
+        txn.commit().unwrap();
+        let mut max_cached = 0;
+        let txn = db.begin_read().unwrap();
+        let table = txn.open_table(table_def).unwrap();
+        for entry in table.iter().unwrap() {
+            entry.unwrap();
+            max_cached = max_cached.max(db.get_memory().cached_bytes());
+        }
+        assert!(max_cached <= budget);
+    }
 
-        let mut tx = db.begin_write().unwrap();
-        let savepoint1 = tx.ephemeral_savepoint().unwrap();
-        tx.restore_savepoint(&savepoint1).unwrap();
-        tx.set_durability(Durability::None);
+    #[test]
+    fn create_atomic() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("atomic.redb");
+
+        // An invalid page size makes initialization fail after the file is created
+        let result = Database::builder().set_page_size(3).create_atomic(&path);
+        assert!(matches!(result, Err(DatabaseError::InvalidPageSize { .. })));
+        assert!(!path.exists());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        let table_def: TableDefinition<u64, u64> = TableDefinition::new("x");
+        let db = Database::builder().create_atomic(&path).unwrap();
+        let txn = db.begin_write().unwrap();
+        txn.open_table(table_def).unwrap().insert(0, 1).unwrap();
+        txn.commit().unwrap();
+        drop(db);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        assert!(matches!(
+            Database::builder().create_atomic(&path),
+            Err(DatabaseError::Storage(StorageError::Io(_)))
+        ));
+        let db = Database::open(&path).unwrap();
+        let txn = db.begin_read().unwrap();
+        let table = txn.open_table(table_def).unwrap();
+        assert_eq!(table.get(0).unwrap().unwrap().value(), 1);
+        drop(table);
+        drop(txn);
+        drop(db);
+
+        // A file created at the path after the check is not replaced
+        let original = std::fs::read(&path).unwrap();
+        let temp_path = dir.path().join("atomic.tmp");
+        let result = Database::builder().create_and_link(&temp_path, &path);
+        assert!(matches!(
+            result,
+            Err(DatabaseError::Storage(StorageError::Io(ref err))) if err.kind() == std::io::ErrorKind::AlreadyExists
+        ));
+        assert_eq!(original, std::fs::read(&path).unwrap());
+    }
+
+    #[test]
+    fn transaction_ids_increase() {
+        let tmpfile = crate::create_tempfile();
+        let table_def: TableDefinition<u64, u64> = TableDefinition::new("x");
+        let db = Database::create(tmpfile.path()).unwrap();
+        let mem = db.get_memory();
+
+        let mut last = mem.get_last_committed_transaction_id().unwrap();
+        for (i, durability) in [
+            Durability::Immediate,
+            Durability::None,
+            Durability::None,
+            Durability::Eventual,
+            Durability::Immediate,
+        ]
+        .into_iter()
+        .enumerate()
         {
-            let mut t = tx.open_table(table_def).unwrap();
-            let value = vec![0; 2008];
-            t.insert(&784384, value.as_slice()).unwrap();
+            let mut txn = db.begin_write().unwrap();
+            txn.set_durability(durability);
+            txn.open_table(table_def)
+                .unwrap()
+                .insert(0, i as u64)
+                .unwrap();
+            txn.commit().unwrap();
+            let id = mem.get_last_committed_transaction_id().unwrap();
+            assert!(id > last);
+            assert_eq!(mem.next_transaction_id().unwrap(), id.next());
+            last = id;
         }
-        tx.abort().unwrap();
+        db.begin_write().unwrap().abort().unwrap();
+        assert_eq!(mem.get_last_committed_transaction_id().unwrap(), last);
     }
 
+    #[cfg(debug_assertions)]
     #[test]
-    fn small_pages4() {
+    #[should_panic(expected = "which was already used")]
+    fn regressed_transaction_id() {
+        let tmpfile = crate::create_tempfile();
+        let db = Database::create(tmpfile.path()).unwrap();
+        let mem = db.get_memory();
+        let id = mem.get_last_committed_transaction_id().unwrap();
+        mem.commit(
+            mem.get_data_root(),
+            mem.get_system_root(),
+            mem.get_freed_root(),
+            id,
+            mem.get_entry_count(),
+            false,
+            true,
+            true,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn auto_compaction() {
         let tmpfile = crate::create_tempfile();
+        let table_def: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+        let value = vec![0u8; 1000];
 
         let db = Database::builder()
-            .set_cache_size(1024 * 1024)
-            .set_page_size(1024)
+            .set_auto_compaction_threshold(0.5)
             .create(tmpfile.path())
             .unwrap();
-
-        let table_def: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
-
-        let tx = db.begin_write().unwrap();
+        let txn = db.begin_write().unwrap();
         {
-            tx.open_table(table_def).unwrap();
+            let mut table = txn.open_table(table_def).unwrap();
+            for i in 0..10_000u64 {
+                table.insert(i, value.as_slice()).unwrap();
+            }
         }
-        tx.commit().unwrap();
+        txn.commit().unwrap();
+        let file_len = tmpfile.as_file().metadata().unwrap().len();
 
-        let tx = db.begin_write().unwrap();
+        // Compaction is deferred while a read transaction is live
+        let read_txn = db.begin_read().unwrap();
+        let txn = db.begin_write().unwrap();
         {
-            let mut t = tx.open_table(table_def).unwrap();
-            assert!(t.get(&131072).unwrap().is_none());
-            let value = vec![0xFF; 1130];
-            t.insert(&42394, value.as_slice()).unwrap();
-            t.insert_reserve(&744037, 3645).unwrap().as_mut().fill(0xFF);
-            assert!(t.get(&0).unwrap().is_none());
+            let mut table = txn.open_table(table_def).unwrap();
+            for i in 100..10_000u64 {
+                table.remove(i).unwrap();
+            }
         }
-        tx.abort().unwrap();
+        txn.commit().unwrap();
+        drop(read_txn);
+        assert_eq!(tmpfile.as_file().metadata().unwrap().len(), file_len);
 
-        let tx = db.begin_write().unwrap();
+        // The next commit frees the removed pages, and then triggers compaction
+        let txn = db.begin_write().unwrap();
+        txn.open_table(table_def)
+            .unwrap()
+            .insert(0, value.as_slice())
+            .unwrap();
+        txn.commit().unwrap();
+        let compacted_len = tmpfile.as_file().metadata().unwrap().len();
+        assert!(compacted_len < file_len / 2);
         {
-            let mut t = tx.open_table(table_def).unwrap();
-            t.insert_reserve(&118749, 734).unwrap().as_mut().fill(0xFF);
+            let txn = db.begin_read().unwrap();
+            let table = txn.open_table(table_def).unwrap();
+            assert_eq!(table.len().unwrap(), 100);
         }
-        tx.abort().unwrap();
+
+        // Compaction can't release all of the free space in the file, but it is not run again
+        // until the free ratio falls below half of the threshold
+        assert!(db.free_ratio().unwrap() > 0.5);
+        assert!(!db.auto_compaction_armed.load(Ordering::Acquire));
+        let mut next_key = 10_000u64;
+        while !db.auto_compaction_armed.load(Ordering::Acquire) {
+            assert!(next_key < 20_000);
+            let txn = db.begin_write().unwrap();
+            {
+                let mut table = txn.open_table(table_def).unwrap();
+                for i in next_key..(next_key + 100) {
+                    table.insert(i, value.as_slice()).unwrap();
+                }
+            }
+            txn.commit().unwrap();
+            next_key += 100;
+        }
+        assert!(db.free_ratio().unwrap() < 0.25);
     }
 
     #[test]
-    fn crash_regression3() {
+    fn drop_caches() {
         let tmpfile = crate::create_tempfile();
+        let table_def: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+        let value = vec![0u8; 1000];
 
-        let db = Database::builder()
-            .set_cache_size(1024 * 1024)
-            .set_page_size(16 * 1024)
-            .set_region_size(32 * 4096)
-            .create(tmpfile.path())
-            .unwrap();
+        let db = Database::create(tmpfile.path()).unwrap();
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(table_def).unwrap();
+            for i in 0..1000u64 {
+                table.insert(i, value.as_slice()).unwrap();
+            }
+        }
+        txn.commit().unwrap();
 
-        let tx = db.begin_write().unwrap();
-        let savepoint = tx.ephemeral_savepoint().unwrap();
-        tx.commit().unwrap();
+        let txn = db.begin_read().unwrap();
+        let table = txn.open_table(table_def).unwrap();
+        for entry in table.iter().unwrap() {
+            entry.unwrap();
+        }
+        assert!(db.get_memory().cached_bytes() > 0);
+        db.drop_caches();
+        assert_eq!(db.get_memory().cached_bytes(), 0);
 
-        let mut tx = db.begin_write().unwrap();
-        tx.restore_savepoint(&savepoint).unwrap();
-        tx.commit().unwrap();
+        // Pages are read back from the file
+        assert_eq!(table.len().unwrap(), 1000);
+        assert_eq!(table.get(999).unwrap().unwrap().value(), value.as_slice());
     }
 
     #[test]
-    fn crash_regression4() {
+    fn growth_policy() {
         let tmpfile = crate::create_tempfile();
+        let table_definition: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+        let big_value = vec![0u8; 1024];
+        let min_growth = 16 * 1024 * 1024;
 
         let db = Database::builder()
-            .set_cache_size(12686)
-            .set_page_size(8 * 1024)
-            .set_region_size(32 * 4096)
+            .set_growth_policy(GrowthPolicy {
+                factor: 1.0,
+                min_bytes: min_growth,
+                max_bytes: None,
+            })
             .create(tmpfile.path())
             .unwrap();
-        db.set_crash_countdown(10);
-
-        let table_def: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+        let initial_size = tmpfile.as_file().metadata().unwrap().len();
+        assert!(initial_size < min_growth);
 
-        let tx = db.begin_write().unwrap();
-        let _savepoint = tx.ephemeral_savepoint().unwrap();
-        let _persistent_savepoint = tx.persistent_savepoint().unwrap();
-        tx.commit().unwrap();
-        let tx = db.begin_write().unwrap();
+        let txn = db.begin_write().unwrap();
         {
-            let mut table = tx.open_table(table_def).unwrap();
-            let _ = table.insert_reserve(118821, 360);
+            let mut table = txn.open_table(table_definition).unwrap();
+            for i in 0..2048 {
+                table.insert(&i, big_value.as_slice()).unwrap();
+            }
         }
+        txn.commit().unwrap();
 
-        drop(tx);
-        drop(db);
-        Database::builder()
-            .set_cache_size(1024 * 1024)
-            .set_page_size(8 * 1024)
-            .set_region_size(32 * 4096)
-            .create(tmpfile.path())
-            .unwrap();
+        let file_size = tmpfile.as_file().metadata().unwrap().len();
+        assert!(file_size >= initial_size + min_growth);
+        assert!(file_size < initial_size + 2 * min_growth);
     }
 
     #[test]
@@ -1109,4 +4534,76 @@ mod test {
         let final_file_size = tmpfile.as_file().metadata().unwrap().len();
         assert!(final_file_size < file_size);
     }
+
+    #[test]
+    fn interrupted_fsync_is_retried() {
+        let tmpfile = crate::create_tempfile();
+        let db = Database::create(tmpfile.path()).unwrap();
+        let table_def: TableDefinition<u64, u64> = TableDefinition::new("x");
+
+        // The first fsync of each commit is interrupted three times in a row, and is retried until
+        // the fourth attempt completes. The commit's later fsyncs are not interrupted
+        let durabilities = [
+            Durability::Immediate,
+            Durability::Eventual,
+            Durability::Paranoid,
+        ];
+        for (i, durability) in durabilities.into_iter().enumerate() {
+            db.get_memory().set_interrupted_fsyncs(3);
+            let mut txn = db.begin_write().unwrap();
+            txn.set_durability(durability);
+            {
+                let mut table = txn.open_table(table_def).unwrap();
+                table.insert(u64::try_from(i).unwrap(), 0).unwrap();
+            }
+            txn.commit().unwrap();
+        }
+        drop(db);
+
+        let db = Database::open(tmpfile.path()).unwrap();
+        let txn = db.begin_read().unwrap();
+        let table = txn.open_table(table_def).unwrap();
+        assert_eq!(table.len().unwrap(), 3);
+    }
+
+    #[test]
+    fn verify_commits() {
+        let table_def: TableDefinition<u64, u64> = TableDefinition::new("x");
+        let write = |db: &Database, key: u64| -> Result<(), CommitError> {
+            let txn = db.begin_write().unwrap();
+            {
+                let mut table = txn.open_table(table_def).unwrap();
+                table.insert(key, key).unwrap();
+            }
+            txn.commit()
+        };
+
+        let tmpfile = crate::create_tempfile();
+        let db = Database::builder()
+            .set_verify_commits(true)
+            .create(tmpfile.path())
+            .unwrap();
+        write(&db, 0).unwrap();
+
+        // The storage device acknowledges the header write, but never stores it
+        db.get_memory().set_dropped_write(0);
+        assert!(matches!(
+            write(&db, 1),
+            Err(CommitError::Storage(StorageError::WriteVerificationFailed))
+        ));
+        assert!(db.begin_write().is_err());
+        drop(db);
+
+        // The lost commit is not visible
+        let db = Database::open(tmpfile.path()).unwrap();
+        let txn = db.begin_read().unwrap();
+        let table = txn.open_table(table_def).unwrap();
+        assert_eq!(table.len().unwrap(), 1);
+        drop(table);
+        drop(txn);
+
+        // Without verification, the lost write goes unnoticed
+        db.get_memory().set_dropped_write(0);
+        write(&db, 1).unwrap();
+    }
 }
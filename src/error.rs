@@ -1,4 +1,4 @@
-use crate::tree_store::{FILE_FORMAT_VERSION, MAX_VALUE_LENGTH};
+use crate::tree_store::{FILE_FORMAT_VERSION, MAX_PAGE_SIZE, MAX_VALUE_LENGTH};
 use crate::TypeName;
 use std::fmt::{Display, Formatter};
 use std::sync::PoisonError;
@@ -13,8 +13,33 @@ pub enum StorageError {
     SimulatedIOFailure,
     /// The Database is corrupted
     Corrupted(String),
-    /// The value being inserted exceeds the maximum of 3GiB
+    /// The value being inserted exceeds the maximum of 3GiB, or would span more pages than allowed
+    /// by [`crate::Builder::set_max_value_pages`]. If the value alone would fit in those pages,
+    /// the combined length of the key and value is reported
     ValueTooLarge(usize),
+    /// The write transaction allocated more pages than the configured limit
+    TransactionTooLarge(usize),
+    /// An allocation of this many bytes is larger than a region of the database file, so no page
+    /// of that size can exist
+    AllocationTooLarge(usize),
+    /// The database file would have to grow beyond the largest size that can be addressed
+    DatabaseFull,
+    /// The key being inserted is not of the fixed width declared for its table, with
+    /// [`crate::TableDefinition::with_fixed_key_width`]
+    KeyWrongSize {
+        expected: usize,
+        actual: usize,
+    },
+    /// The header read back after a commit did not match what was written, so the storage device
+    /// silently lost the write
+    WriteVerificationFailed,
+    /// The id of the write transaction is not in the epoch set with
+    /// [`crate::Builder::set_transaction_epoch`], either because a later epoch has already
+    /// committed, or because the counter of the epoch is exhausted
+    TransactionEpochMismatch {
+        epoch: u32,
+        transaction_id: u64,
+    },
     Io(io::Error),
     LockPoisoned(&'static panic::Location<'static>),
 }
@@ -38,6 +63,20 @@ impl From<StorageError> for Error {
             StorageError::SimulatedIOFailure => Error::SimulatedIOFailure,
             StorageError::Corrupted(msg) => Error::Corrupted(msg),
             StorageError::ValueTooLarge(x) => Error::ValueTooLarge(x),
+            StorageError::TransactionTooLarge(x) => Error::TransactionTooLarge(x),
+            StorageError::AllocationTooLarge(x) => Error::AllocationTooLarge(x),
+            StorageError::DatabaseFull => Error::DatabaseFull,
+            StorageError::KeyWrongSize { expected, actual } => {
+                Error::KeyWrongSize { expected, actual }
+            }
+            StorageError::WriteVerificationFailed => Error::WriteVerificationFailed,
+            StorageError::TransactionEpochMismatch {
+                epoch,
+                transaction_id,
+            } => Error::TransactionEpochMismatch {
+                epoch,
+                transaction_id,
+            },
             StorageError::Io(x) => Error::Io(x),
             StorageError::LockPoisoned(location) => Error::LockPoisoned(location),
         }
@@ -57,10 +96,36 @@ impl Display for StorageError {
             StorageError::ValueTooLarge(len) => {
                 write!(
                     f,
-                    "The value (length={len}) being inserted exceeds the maximum of {}GiB",
+                    "The value (length={len}) being inserted exceeds the maximum of {}GiB, or the \
+                     maximum number of pages for a value",
                     MAX_VALUE_LENGTH / 1024 / 1024 / 1024
                 )
             }
+            StorageError::TransactionTooLarge(limit) => {
+                write!(
+                    f,
+                    "The transaction exceeded the limit of {limit} pages. It must be aborted"
+                )
+            }
+            StorageError::AllocationTooLarge(len) => {
+                write!(
+                    f,
+                    "An allocation of {len} bytes is larger than a region of the database"
+                )
+            }
+            StorageError::DatabaseFull => {
+                write!(f, "The database file can not grow any larger")
+            }
+            StorageError::KeyWrongSize { expected, actual } => {
+                fmt_key_wrong_size(f, *expected, *actual)
+            }
+            StorageError::WriteVerificationFailed => {
+                write!(f, "The committed header did not match what was written")
+            }
+            StorageError::TransactionEpochMismatch {
+                epoch,
+                transaction_id,
+            } => fmt_transaction_epoch_mismatch(f, *epoch, *transaction_id),
             StorageError::Io(err) => {
                 write!(f, "I/O error: {err}")
             }
@@ -94,6 +159,8 @@ pub enum TableError {
     },
     /// Table name does not match any table in database
     TableDoesNotExist(String),
+    /// A table with this name already exists
+    TableExists(String),
     // Tables cannot be opened for writing multiple times, since they could retrieve immutable &
     // mutable references to the same dirty pages, or multiple mutable references via insert_reserve()
     TableAlreadyOpen(String, &'static panic::Location<'static>),
@@ -109,6 +176,7 @@ impl TableError {
             | TableError::TableIsNotMultimap(_)
             | TableError::TypeDefinitionChanged { .. }
             | TableError::TableDoesNotExist(_)
+            | TableError::TableExists(_)
             | TableError::TableAlreadyOpen(_, _) => {
                 StorageError::Corrupted(format!("{}: {}", msg, &self))
             }
@@ -135,6 +203,7 @@ impl From<TableError> for Error {
             TableError::TableIsMultimap(table) => Error::TableIsMultimap(table),
             TableError::TableIsNotMultimap(table) => Error::TableIsNotMultimap(table),
             TableError::TableDoesNotExist(table) => Error::TableDoesNotExist(table),
+            TableError::TableExists(table) => Error::TableExists(table),
             TableError::TableAlreadyOpen(name, location) => Error::TableAlreadyOpen(name, location),
             TableError::Storage(storage) => storage.into(),
         }
@@ -180,6 +249,9 @@ impl Display for TableError {
             TableError::TableDoesNotExist(table) => {
                 write!(f, "Table '{table}' does not exist")
             }
+            TableError::TableExists(table) => {
+                write!(f, "Table '{table}' already exists")
+            }
             TableError::TableAlreadyOpen(name, location) => {
                 write!(f, "Table '{name}' already opened at: {location}")
             }
@@ -198,6 +270,21 @@ pub enum DatabaseError {
     DatabaseAlreadyOpen,
     /// The database file is in an old file format and must be manually upgraded
     UpgradeRequired(u8),
+    /// The database file is in a newer file format, written by a later version of redb
+    NewerFileFormat(u8),
+    /// The requested page size is not a power of two, is too small to hold the database header, or
+    /// is larger than 1MiB
+    InvalidPageSize { requested: usize },
+    /// A strict page size was requested, but the database was created with a different page size
+    PageSizeMismatch { requested: usize, stored: usize },
+    /// The database file is shorter than the length recorded in its header, for example because
+    /// it was only partially copied
+    FileTruncated { expected: u64, actual: u64 },
+    /// The length of the database file is not a whole number of pages, for example because it
+    /// was extended by something other than redb
+    InvalidFileSize { len: u64, page_size: usize },
+    /// The options set on the [`crate::Builder`] are invalid, or conflict with each other
+    InvalidConfiguration(String),
     /// Error from underlying storage
     Storage(StorageError),
 }
@@ -207,6 +294,18 @@ impl From<DatabaseError> for Error {
         match err {
             DatabaseError::DatabaseAlreadyOpen => Error::DatabaseAlreadyOpen,
             DatabaseError::UpgradeRequired(x) => Error::UpgradeRequired(x),
+            DatabaseError::NewerFileFormat(x) => Error::NewerFileFormat(x),
+            DatabaseError::InvalidPageSize { requested } => Error::InvalidPageSize { requested },
+            DatabaseError::PageSizeMismatch { requested, stored } => {
+                Error::PageSizeMismatch { requested, stored }
+            }
+            DatabaseError::FileTruncated { expected, actual } => {
+                Error::FileTruncated { expected, actual }
+            }
+            DatabaseError::InvalidFileSize { len, page_size } => {
+                Error::InvalidFileSize { len, page_size }
+            }
+            DatabaseError::InvalidConfiguration(reason) => Error::InvalidConfiguration(reason),
             DatabaseError::Storage(storage) => storage.into(),
         }
     }
@@ -224,15 +323,98 @@ impl From<StorageError> for DatabaseError {
     }
 }
 
+fn fmt_invalid_page_size(f: &mut Formatter<'_>, requested: usize) -> std::fmt::Result {
+    if requested.is_power_of_two() && requested > MAX_PAGE_SIZE {
+        write!(
+            f,
+            "Invalid page size {requested}: larger than the maximum of {}KiB",
+            MAX_PAGE_SIZE / 1024
+        )
+    } else if requested.is_power_of_two() {
+        write!(
+            f,
+            "Invalid page size {requested}: too small to hold the database header"
+        )
+    } else {
+        write!(f, "Invalid page size {requested}: not a power of two")
+    }
+}
+
+fn fmt_transaction_epoch_mismatch(
+    f: &mut Formatter<'_>,
+    epoch: u32,
+    transaction_id: u64,
+) -> std::fmt::Result {
+    write!(
+        f,
+        "Transaction id {transaction_id:#x} is outside of epoch {epoch}. A later epoch has committed, or the epoch is exhausted"
+    )
+}
+
+fn fmt_key_wrong_size(f: &mut Formatter<'_>, expected: usize, actual: usize) -> std::fmt::Result {
+    write!(
+        f,
+        "The key (length={actual}) is not of the fixed width {expected} declared for the table"
+    )
+}
+
+fn fmt_file_truncated(f: &mut Formatter<'_>, expected: u64, actual: u64) -> std::fmt::Result {
+    write!(
+        f,
+        "Database file is truncated. Expected length {expected}, found {actual}"
+    )
+}
+
+fn fmt_invalid_file_size(f: &mut Formatter<'_>, len: u64, page_size: usize) -> std::fmt::Result {
+    write!(
+        f,
+        "Database file length {len} is not a multiple of the page size {page_size}"
+    )
+}
+
+fn fmt_invalid_configuration(f: &mut Formatter<'_>, reason: &str) -> std::fmt::Result {
+    write!(f, "Invalid database configuration: {reason}")
+}
+
+fn fmt_newer_file_format(f: &mut Formatter<'_>, actual: u8) -> std::fmt::Result {
+    write!(
+        f,
+        "File format version {actual} is newer than version {FILE_FORMAT_VERSION}, the latest this version of redb supports"
+    )
+}
+
+fn fmt_page_size_mismatch(
+    f: &mut Formatter<'_>,
+    requested: usize,
+    stored: usize,
+) -> std::fmt::Result {
+    write!(
+        f,
+        "Requested page size {requested}, but database was created with page size {stored}"
+    )
+}
+
 impl Display for DatabaseError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
             DatabaseError::UpgradeRequired(actual) => {
                 write!(f, "Manual upgrade required. Expected file format version {FILE_FORMAT_VERSION}, but file is version {actual}")
             }
+            DatabaseError::NewerFileFormat(actual) => fmt_newer_file_format(f, *actual),
             DatabaseError::DatabaseAlreadyOpen => {
                 write!(f, "Database already open. Cannot acquire lock.")
             }
+            DatabaseError::InvalidPageSize { requested } => fmt_invalid_page_size(f, *requested),
+            DatabaseError::PageSizeMismatch { requested, stored } => {
+                fmt_page_size_mismatch(f, *requested, *stored)
+            }
+            DatabaseError::FileTruncated { expected, actual } => {
+                fmt_file_truncated(f, *expected, *actual)
+            }
+            DatabaseError::InvalidFileSize { len, page_size } => {
+                fmt_invalid_file_size(f, *len, *page_size)
+            }
+            DatabaseError::InvalidConfiguration(reason) => fmt_invalid_configuration(f, reason),
             DatabaseError::Storage(storage) => storage.fmt(f),
         }
     }
@@ -281,6 +463,44 @@ impl Display for SavepointError {
 
 impl std::error::Error for SavepointError {}
 
+/// Errors related to [`crate::Table::swap`]
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum SwapError {
+    /// One of the keys is absent, and [`crate::MissingKey::Fail`] was given
+    KeyNotFound,
+    /// Error from underlying storage
+    Storage(StorageError),
+}
+
+impl From<SwapError> for Error {
+    fn from(err: SwapError) -> Error {
+        match err {
+            SwapError::KeyNotFound => Error::KeyNotFound,
+            SwapError::Storage(storage) => storage.into(),
+        }
+    }
+}
+
+impl From<StorageError> for SwapError {
+    fn from(err: StorageError) -> SwapError {
+        SwapError::Storage(err)
+    }
+}
+
+impl Display for SwapError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SwapError::KeyNotFound => {
+                write!(f, "Key not found")
+            }
+            SwapError::Storage(storage) => storage.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for SwapError {}
+
 /// Errors related to compaction
 #[derive(Debug)]
 #[non_exhaustive]
@@ -415,12 +635,69 @@ pub enum Error {
     InvalidSavepoint,
     /// A persistent savepoint exists
     PersistentSavepointExists,
+    /// One of the keys passed to [`crate::Table::swap`] is absent
+    KeyNotFound,
     /// The Database is corrupted
     Corrupted(String),
     /// The database file is in an old file format and must be manually upgraded
     UpgradeRequired(u8),
-    /// The value being inserted exceeds the maximum of 3GiB
+    /// The database file is in a newer file format, written by a later version of redb
+    NewerFileFormat(u8),
+    /// The requested page size is not a power of two, is too small to hold the database header, or
+    /// is larger than 1MiB
+    InvalidPageSize {
+        requested: usize,
+    },
+    /// A strict page size was requested, but the database was created with a different page size
+    PageSizeMismatch {
+        requested: usize,
+        stored: usize,
+    },
+    /// The database file is shorter than the length recorded in its header, for example because
+    /// it was only partially copied
+    FileTruncated {
+        expected: u64,
+        actual: u64,
+    },
+    /// The length of the database file is not a whole number of pages, for example because it
+    /// was extended by something other than redb
+    InvalidFileSize {
+        len: u64,
+        page_size: usize,
+    },
+    /// The options set on the [`crate::Builder`] are invalid, or conflict with each other
+    InvalidConfiguration(String),
+    /// The value being inserted exceeds the maximum of 3GiB, or would span more pages than allowed
+    /// by [`crate::Builder::set_max_value_pages`]. If the value alone would fit in those pages,
+    /// the combined length of the key and value is reported
     ValueTooLarge(usize),
+    /// The write transaction allocated more pages than the configured limit
+    TransactionTooLarge(usize),
+    /// An allocation of this many bytes is larger than a region of the database file, so no page
+    /// of that size can exist
+    AllocationTooLarge(usize),
+    /// The database file would have to grow beyond the largest size that can be addressed
+    DatabaseFull,
+    /// The key being inserted is not of the fixed width declared for its table, with
+    /// [`crate::TableDefinition::with_fixed_key_width`]
+    KeyWrongSize {
+        expected: usize,
+        actual: usize,
+    },
+    /// The header read back after a commit did not match what was written, so the storage device
+    /// silently lost the write
+    WriteVerificationFailed,
+    /// The id of the write transaction is not in the epoch set with
+    /// [`crate::Builder::set_transaction_epoch`], either because a later epoch has already
+    /// committed, or because the counter of the epoch is exhausted
+    TransactionEpochMismatch {
+        epoch: u32,
+        transaction_id: u64,
+    },
+    /// The awaited commit was not made before the timeout expired
+    Timeout,
+    /// The operation can not be performed while a write transaction is in progress
+    WriteTransactionInProgress,
     /// Table types didn't match.
     TableTypeMismatch {
         table: String,
@@ -438,6 +715,8 @@ pub enum Error {
     },
     /// Table name does not match any table in database
     TableDoesNotExist(String),
+    /// A table with this name already exists
+    TableExists(String),
     // Tables cannot be opened for writing multiple times, since they could retrieve immutable &
     // mutable references to the same dirty pages, or multiple mutable references via insert_reserve()
     TableAlreadyOpen(String, &'static panic::Location<'static>),
@@ -470,13 +749,48 @@ impl Display for Error {
             Error::UpgradeRequired(actual) => {
                 write!(f, "Manual upgrade required. Expected file format version {FILE_FORMAT_VERSION}, but file is version {actual}")
             }
+            Error::NewerFileFormat(actual) => fmt_newer_file_format(f, *actual),
+            Error::InvalidPageSize { requested } => fmt_invalid_page_size(f, *requested),
+            Error::PageSizeMismatch { requested, stored } => {
+                fmt_page_size_mismatch(f, *requested, *stored)
+            }
+            Error::FileTruncated { expected, actual } => fmt_file_truncated(f, *expected, *actual),
+            Error::InvalidFileSize { len, page_size } => fmt_invalid_file_size(f, *len, *page_size),
+            Error::InvalidConfiguration(reason) => fmt_invalid_configuration(f, reason),
             Error::ValueTooLarge(len) => {
                 write!(
                     f,
-                    "The value (length={len}) being inserted exceeds the maximum of {}GiB",
+                    "The value (length={len}) being inserted exceeds the maximum of {}GiB, or the \
+                     maximum number of pages for a value",
                     MAX_VALUE_LENGTH / 1024 / 1024 / 1024
                 )
             }
+            Error::TransactionTooLarge(limit) => {
+                write!(
+                    f,
+                    "The transaction exceeded the limit of {limit} pages. It must be aborted"
+                )
+            }
+            Error::AllocationTooLarge(len) => {
+                write!(
+                    f,
+                    "An allocation of {len} bytes is larger than a region of the database"
+                )
+            }
+            Error::DatabaseFull => {
+                write!(f, "The database file can not grow any larger")
+            }
+            Error::KeyWrongSize { expected, actual } => fmt_key_wrong_size(f, *expected, *actual),
+            Error::WriteVerificationFailed => {
+                write!(f, "The committed header did not match what was written")
+            }
+            Error::TransactionEpochMismatch {
+                epoch,
+                transaction_id,
+            } => fmt_transaction_epoch_mismatch(f, *epoch, *transaction_id),
+            Error::Timeout => {
+                write!(f, "Timed out waiting for a commit")
+            }
             Error::TypeDefinitionChanged {
                 name,
                 alignment,
@@ -507,6 +821,9 @@ impl Display for Error {
             Error::TableDoesNotExist(table) => {
                 write!(f, "Table '{table}' does not exist")
             }
+            Error::TableExists(table) => {
+                write!(f, "Table '{table}' already exists")
+            }
             Error::TableAlreadyOpen(name, location) => {
                 write!(f, "Table '{name}' already opened at: {location}")
             }
@@ -531,8 +848,137 @@ impl Display for Error {
                     "Savepoint is invalid because an older savepoint was already restored."
                 )
             }
+            Error::KeyNotFound => {
+                write!(f, "Key not found")
+            }
+            Error::WriteTransactionInProgress => {
+                write!(f, "A write transaction is in progress")
+            }
         }
     }
 }
 
 impl std::error::Error for Error {}
+
+/// A problem found by [`Database::corruption_report`](crate::Database::corruption_report)
+///
+/// Page numbers are encoded the same way as
+/// [`TransactionSlotDump::user_root`](crate::TransactionSlotDump::user_root)
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CorruptionFinding {
+    /// The file does not start with the redb magic number
+    InvalidMagicNumber,
+    /// A commit slot records a file format version which this version of redb can not read
+    UnsupportedVersion(u8),
+    /// The checksum of a commit slot does not match its contents. The primary slot holds the
+    /// latest commit
+    CommitSlotCorrupted { primary: bool },
+    /// The header is invalid, for example because its layout can not be used
+    InvalidHeader(String),
+    /// The file is shorter than the layout recorded in its header
+    FileTruncated { expected: u64, actual: u64 },
+    /// The length of the file is not a whole number of pages
+    InvalidFileSize { len: u64, page_size: usize },
+    /// The allocator state was not saved by a clean shutdown, or does not match its checksum. It
+    /// is rebuilt from the tables when the database is opened
+    DirtyAllocator,
+    /// The checksum of the page does not match the one recorded in the page which references it
+    ChecksumMismatch { page: u64 },
+    /// The page is neither a branch nor a leaf page
+    InvalidPageType { page: u64 },
+    /// The page references a child page past the end of the database
+    DanglingChildPointer { page: u64, child: u64 },
+    /// The root of the table is past the end of the database
+    DanglingTableRoot { table: String, root: u64 },
+    /// The page is marked as allocated, but nothing references it
+    LeakedPage { page: u64 },
+}
+
+impl Display for CorruptionFinding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CorruptionFinding::InvalidMagicNumber => {
+                write!(f, "File is not a redb database: invalid magic number")
+            }
+            CorruptionFinding::UnsupportedVersion(version) => {
+                write!(f, "Unsupported file format version {version}")
+            }
+            CorruptionFinding::CommitSlotCorrupted { primary } => {
+                let slot = if *primary { "Primary" } else { "Secondary" };
+                write!(f, "{slot} commit slot does not match its checksum")
+            }
+            CorruptionFinding::InvalidHeader(reason) => write!(f, "Invalid header: {reason}"),
+            CorruptionFinding::FileTruncated { expected, actual } => {
+                fmt_file_truncated(f, *expected, *actual)
+            }
+            CorruptionFinding::InvalidFileSize { len, page_size } => {
+                fmt_invalid_file_size(f, *len, *page_size)
+            }
+            CorruptionFinding::DirtyAllocator => {
+                write!(f, "Allocator state is missing or damaged")
+            }
+            CorruptionFinding::ChecksumMismatch { page } => {
+                write!(f, "Page {page:#x} does not match its checksum")
+            }
+            CorruptionFinding::InvalidPageType { page } => {
+                write!(f, "Page {page:#x} is neither a branch nor a leaf")
+            }
+            CorruptionFinding::DanglingChildPointer { page, child } => {
+                write!(
+                    f,
+                    "Page {page:#x} references page {child:#x} past the end of the database"
+                )
+            }
+            CorruptionFinding::DanglingTableRoot { table, root } => {
+                write!(
+                    f,
+                    "Root {root:#x} of table {table} is past the end of the database"
+                )
+            }
+            CorruptionFinding::LeakedPage { page } => {
+                write!(f, "Page {page:#x} is allocated, but not referenced")
+            }
+        }
+    }
+}
+
+/// The problems found in a database file, as returned by
+/// [`Database::corruption_report`](crate::Database::corruption_report)
+///
+/// Findings are listed in the order in which they were found: first the header, then the pages
+/// of each tree in depth first order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CorruptionReport {
+    findings: Vec<CorruptionFinding>,
+}
+
+impl CorruptionReport {
+    pub(crate) fn new(findings: Vec<CorruptionFinding>) -> Self {
+        Self { findings }
+    }
+
+    /// Returns `true` if no problem was found
+    pub fn is_clean(&self) -> bool {
+        self.findings.is_empty()
+    }
+
+    /// The problems found
+    pub fn findings(&self) -> &[CorruptionFinding] {
+        &self.findings
+    }
+}
+
+impl Display for CorruptionReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.findings.len() {
+            0 => return write!(f, "No corruption found"),
+            1 => write!(f, "1 problem found:")?,
+            n => write!(f, "{n} problems found:")?,
+        }
+        for finding in &self.findings {
+            write!(f, "\n  {finding}")?;
+        }
+        Ok(())
+    }
+}
@@ -10,19 +10,28 @@
 #![cfg_attr(target_os = "wasi", feature(wasi_ext))]
 
 pub use db::{
-    Builder, Database, MultimapTableDefinition, MultimapTableHandle, TableDefinition, TableHandle,
-    UntypedMultimapTableHandle, UntypedTableHandle,
+    Builder, Database, MultimapTableDefinition, MultimapTableHandle, ReadOnlyDatabase,
+    TableDefinition, TableHandle, UntypedMultimapTableHandle, UntypedTableHandle,
 };
 pub use error::{
-    CommitError, CompactionError, DatabaseError, Error, SavepointError, StorageError, TableError,
-    TransactionError,
+    CommitError, CompactionError, CorruptionFinding, CorruptionReport, DatabaseError, Error,
+    SavepointError, StorageError, SwapError, TableError, TransactionError,
 };
 pub use multimap_table::{
     MultimapRange, MultimapTable, MultimapValue, ReadOnlyMultimapTable, ReadableMultimapTable,
 };
-pub use table::{Drain, DrainFilter, Range, ReadOnlyTable, ReadableTable, Table};
-pub use transactions::{DatabaseStats, Durability, ReadTransaction, WriteTransaction};
-pub use tree_store::{AccessGuard, AccessGuardMut, Savepoint};
+pub use table::{
+    Drain, DrainFilter, EtlScan, EtlScanToken, IteratorToken, JoinMode, JoinRow, Keys, MergeJoin,
+    MissingKey, Range, ReadOnlyTable, ReadableTable, Table,
+};
+pub use transactions::{
+    ChangeKind, DatabaseStats, Durability, ReadTransaction, SpaceUsage, TableInfo,
+    TransactionStats, WriteTransaction,
+};
+pub use tree_store::{
+    AccessGuard, AccessGuardMut, AllocationPolicy, Capacity, GrowthPolicy, HybridTimestamp,
+    MetapageDump, MigrationStep, Savepoint, TransactionSlotDump, FILE_FORMAT_VERSION,
+};
 pub use types::{RedbKey, RedbValue, TypeName};
 
 type Result<T = (), E = StorageError> = std::result::Result<T, E>;
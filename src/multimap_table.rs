@@ -1,12 +1,13 @@
 use crate::multimap_table::DynamicCollectionType::{Inline, Subtree};
 use crate::sealed::Sealed;
+use crate::table::check_entry_size;
 use crate::tree_store::{
-    AllPageNumbersBtreeIter, Btree, BtreeMut, BtreeRangeIter, Checksum, LeafAccessor, LeafMutator,
-    Page, PageHint, PageNumber, RawLeafBuilder, TransactionalMemory, UntypedBtreeMut, BRANCH, LEAF,
-    MAX_VALUE_LENGTH,
+    btree_len, AllPageNumbersBtreeIter, Btree, BtreeMut, BtreeRangeIter, Checksum, LeafAccessor,
+    LeafMutator, Page, PageHint, PageNumber, RawBtree, RawLeafBuilder, TransactionalMemory,
+    UntypedBtreeMut, BRANCH, LEAF,
 };
 use crate::types::{RedbKey, RedbValue, TypeName};
-use crate::{AccessGuard, Result, StorageError, WriteTransaction};
+use crate::{AccessGuard, CorruptionFinding, Result, WriteTransaction};
 use std::borrow::Borrow;
 use std::convert::TryInto;
 use std::marker::PhantomData;
@@ -77,6 +78,95 @@ pub(crate) fn finalize_tree_and_subtree_checksums(
     Ok(tree.get_root())
 }
 
+// Verify all the checksums in the tree, including any Dynamic collection subtrees
+pub(crate) fn verify_tree_and_subtree_checksums(
+    root: Option<(PageNumber, Checksum)>,
+    key_size: Option<usize>,
+    value_size: Option<usize>,
+    threads: usize,
+    mem: &TransactionalMemory,
+) -> Result<bool> {
+    let root_page = match root {
+        Some((page, _)) => page,
+        None => return Ok(true),
+    };
+    if !RawBtree::new(root, key_size, <&DynamicCollection>::fixed_width(), mem)
+        .verify_checksum(threads)?
+    {
+        return Ok(false);
+    }
+
+    let table_pages_iter = AllPageNumbersBtreeIter::new(
+        root_page,
+        key_size,
+        <&DynamicCollection>::fixed_width(),
+        mem,
+    )?;
+    for table_page in table_pages_iter {
+        let page = mem.get_page(table_page?)?;
+        if page.memory()[0] != LEAF {
+            continue;
+        }
+        let accessor =
+            LeafAccessor::new(page.memory(), key_size, <&DynamicCollection>::fixed_width());
+        for i in 0..accessor.num_pairs() {
+            let entry = accessor.entry(i).unwrap();
+            let collection = <&DynamicCollection>::from_bytes(entry.value());
+            if matches!(collection.collection_type(), DynamicCollectionType::Subtree) {
+                let sub_root = collection.as_subtree();
+                if !RawBtree::new(Some(sub_root), value_size, <()>::fixed_width(), mem)
+                    .verify_checksum(1)?
+                {
+                    return Ok(false);
+                }
+            }
+        }
+    }
+
+    Ok(true)
+}
+
+// Same as verify_tree_and_subtree_checksums(), but records every damaged page in `findings`.
+// Returns true if the tree and all of its subtrees are intact
+pub(crate) fn find_tree_and_subtree_corruption(
+    root: Option<(PageNumber, Checksum)>,
+    key_size: Option<usize>,
+    value_size: Option<usize>,
+    mem: &TransactionalMemory,
+    findings: &mut Vec<CorruptionFinding>,
+) -> Result<bool> {
+    let mut leaves = vec![];
+    let mut intact = RawBtree::new(root, key_size, <&DynamicCollection>::fixed_width(), mem)
+        .find_corruption(findings, &mut leaves)?;
+    for leaf in leaves {
+        let page = mem.get_page(leaf)?;
+        let accessor =
+            LeafAccessor::new(page.memory(), key_size, <&DynamicCollection>::fixed_width());
+        for i in 0..accessor.num_pairs() {
+            let entry = accessor.entry(i).unwrap();
+            let collection = <&DynamicCollection>::from_bytes(entry.value());
+            if matches!(collection.collection_type(), DynamicCollectionType::Subtree) {
+                let sub_root = collection.as_subtree();
+                if !mem.contains_page(sub_root.0) {
+                    findings.push(CorruptionFinding::DanglingChildPointer {
+                        page: u64::from_le_bytes(leaf.to_le_bytes()),
+                        child: u64::from_le_bytes(sub_root.0.to_le_bytes()),
+                    });
+                    intact = false;
+                    continue;
+                }
+                if !RawBtree::new(Some(sub_root), value_size, <()>::fixed_width(), mem)
+                    .find_corruption(findings, &mut vec![])?
+                {
+                    intact = false;
+                }
+            }
+        }
+    }
+
+    Ok(intact)
+}
+
 pub(crate) fn parse_subtree_roots<T: Page>(
     page: &T,
     fixed_key_size: Option<usize>,
@@ -106,6 +196,42 @@ pub(crate) fn parse_subtree_roots<T: Page>(
     }
 }
 
+// Count the key-value pairs in a multimap table, including those stored in subtrees
+pub(crate) fn multimap_btree_len(
+    root: Option<PageNumber>,
+    mem: &TransactionalMemory,
+    fixed_key_size: Option<usize>,
+    fixed_value_size: Option<usize>,
+) -> Result<u64> {
+    let mut count = 0;
+    if let Some(root) = root {
+        let all_pages = AllPageNumbersBtreeIter::new(
+            root,
+            fixed_key_size,
+            <&DynamicCollection>::fixed_width(),
+            mem,
+        )?;
+        for page_number in all_pages {
+            let page = mem.get_page(page_number?)?;
+            if page.memory()[0] != LEAF {
+                continue;
+            }
+            let accessor = LeafAccessor::new(
+                page.memory(),
+                fixed_key_size,
+                <&DynamicCollection>::fixed_width(),
+            );
+            for i in 0..accessor.num_pairs() {
+                let entry = accessor.entry(i).unwrap();
+                let collection = <&DynamicCollection>::from_bytes(entry.value());
+                count += collection.len(fixed_value_size, mem)?;
+            }
+        }
+    }
+
+    Ok(count)
+}
+
 pub(crate) struct LeafKeyIter<'a> {
     inline_collection: AccessGuard<'a, &'static DynamicCollection>,
     fixed_key_size: Option<usize>,
@@ -265,6 +391,23 @@ impl DynamicCollection {
         (page_number, checksum)
     }
 
+    // Number of values stored in this collection
+    fn len(&self, fixed_value_size: Option<usize>, mem: &TransactionalMemory) -> Result<u64> {
+        match self.collection_type() {
+            Inline => {
+                let accessor =
+                    LeafAccessor::new(self.as_inline(), fixed_value_size, <()>::fixed_width());
+                Ok(accessor.num_pairs() as u64)
+            }
+            Subtree => btree_len(
+                Some(self.as_subtree().0),
+                mem,
+                fixed_value_size,
+                <()>::fixed_width(),
+            ),
+        }
+    }
+
     fn iter<'a, V: RedbKey>(
         collection: AccessGuard<'a, &'static DynamicCollection>,
         mem: &'a TransactionalMemory,
@@ -420,8 +563,10 @@ impl<'a, V: RedbKey + 'static> Drop for MultimapValue<'a, V> {
         if !self.free_on_drop.is_empty() {
             let mut freed_pages = self.freed_pages.as_ref().unwrap().lock().unwrap();
             for page in self.free_on_drop.iter() {
-                if !self.mem.unwrap().free_if_uncommitted(*page) {
-                    freed_pages.push(*page);
+                match self.mem.unwrap().free_if_uncommitted(*page) {
+                    Ok(true) => {}
+                    Ok(false) => freed_pages.push(*page),
+                    Err(err) => self.mem.unwrap().defer_free_error(err),
                 }
             }
         }
@@ -533,13 +678,13 @@ impl<'db, 'txn, K: RedbKey + 'static, V: RedbKey + 'static> MultimapTable<'db, '
     {
         let value_bytes = V::as_bytes(value.borrow());
         let value_bytes_ref = value_bytes.as_ref();
-        if value_bytes_ref.len() > MAX_VALUE_LENGTH {
-            return Err(StorageError::ValueTooLarge(value_bytes_ref.len()));
-        }
         let key_bytes = K::as_bytes(key.borrow());
-        if key_bytes.as_ref().len() > MAX_VALUE_LENGTH {
-            return Err(StorageError::ValueTooLarge(key_bytes.as_ref().len()));
-        }
+        check_entry_size(
+            self.mem,
+            None,
+            key_bytes.as_ref().len(),
+            value_bytes_ref.len(),
+        )?;
         let get_result = self.tree.get(key.borrow())?;
         let existed = if get_result.is_some() {
             #[allow(clippy::unnecessary_unwrap)]
@@ -664,6 +809,10 @@ impl<'db, 'txn, K: RedbKey + 'static, V: RedbKey + 'static> MultimapTable<'db, '
             false
         };
 
+        if !self.system && !existed {
+            self.transaction.record_inserted_entries(1);
+        }
+
         Ok(existed)
     }
 
@@ -757,7 +906,7 @@ impl<'db, 'txn, K: RedbKey + 'static, V: RedbKey + 'static> MultimapTable<'db, '
                                 self.tree
                                     .insert(key.borrow(), &DynamicCollection::new(&inline_data))?;
                                 drop(page);
-                                if !self.mem.free_if_uncommitted(new_root) {
+                                if !self.mem.free_if_uncommitted(new_root)? {
                                     (*self.freed_pages).lock().unwrap().push(new_root);
                                 }
                             } else {
@@ -783,6 +932,10 @@ impl<'db, 'txn, K: RedbKey + 'static, V: RedbKey + 'static> MultimapTable<'db, '
             }
         };
 
+        if !self.system && existed {
+            self.transaction.record_removed_entries(1);
+        }
+
         Ok(existed)
     }
 
@@ -799,6 +952,10 @@ impl<'db, 'txn, K: RedbKey + 'static, V: RedbKey + 'static> MultimapTable<'db, '
         let iter = if let Some((collection, mut pages)) =
             self.tree.remove_retain_uncommitted(key.borrow())?
         {
+            if !self.system {
+                self.transaction
+                    .record_removed_entries(collection.value().len(V::fixed_width(), self.mem)?);
+            }
             if matches!(
                 collection.value().collection_type(),
                 DynamicCollectionType::Subtree
@@ -1,21 +1,79 @@
 use crate::sealed::Sealed;
 use crate::tree_store::{
     AccessGuardMut, Btree, BtreeDrain, BtreeDrainFilter, BtreeMut, BtreeRangeIter, Checksum,
-    PageHint, PageNumber, TransactionalMemory, MAX_VALUE_LENGTH,
+    PageHint, PageNumber, RawLeafBuilder, TransactionalMemory, MAX_VALUE_LENGTH,
 };
 use crate::types::{RedbKey, RedbValue, RedbValueMutInPlace};
 use crate::Result;
-use crate::{AccessGuard, StorageError, WriteTransaction};
+use crate::{AccessGuard, ReadTransaction, StorageError, SwapError, WriteTransaction};
 use std::borrow::Borrow;
-use std::ops::RangeBounds;
+use std::cmp::Ordering;
+use std::iter::Fuse;
+use std::ops::{Bound, RangeBounds};
 use std::sync::{Arc, Mutex};
 
+// Checks the lengths of the key and value of an entry against the limits of an insert into a table
+// whose keys have the fixed width `key_width`, if any. Nothing is allocated for an entry which is
+// rejected
+pub(crate) fn check_entry_size(
+    mem: &TransactionalMemory,
+    key_width: Option<usize>,
+    key_len: usize,
+    value_len: usize,
+) -> Result {
+    if value_len > MAX_VALUE_LENGTH {
+        return Err(StorageError::ValueTooLarge(value_len));
+    }
+    if key_len > MAX_VALUE_LENGTH {
+        return Err(StorageError::ValueTooLarge(key_len));
+    }
+    match key_width {
+        Some(width) if key_len != width => {
+            return Err(StorageError::KeyWrongSize {
+                expected: width,
+                actual: key_len,
+            });
+        }
+        _ => {}
+    }
+    // Matches the leaf which an insert builds for the entry on its own
+    let page_size = mem.get_page_size();
+    let leaf_pages = |len: usize| {
+        let leaf_len = RawLeafBuilder::required_bytes(1, len);
+        ((leaf_len + page_size - 1) / page_size) as u64
+    };
+    let max_pages = mem.get_max_value_pages();
+    if leaf_pages(key_len + value_len) > max_pages {
+        // Blame the key too, if the value alone would have fit
+        let len = if leaf_pages(value_len) > max_pages {
+            value_len
+        } else {
+            key_len + value_len
+        };
+        return Err(StorageError::ValueTooLarge(len));
+    }
+    Ok(())
+}
+
+// Checks a key read back from a token against the fixed width of the table's keys, if any, since
+// the token may have been taken from another table or corrupted in transit
+pub(crate) fn check_token_key(key_width: Option<usize>, key: &[u8]) -> Result {
+    match key_width {
+        Some(width) if key.len() != width => Err(StorageError::KeyWrongSize {
+            expected: width,
+            actual: key.len(),
+        }),
+        _ => Ok(()),
+    }
+}
+
 /// A table containing key-value mappings
 pub struct Table<'db, 'txn, K: RedbKey + 'static, V: RedbValue + 'static> {
     name: String,
     system: bool,
     transaction: &'txn WriteTransaction<'db>,
     tree: BtreeMut<'txn, K, V>,
+    mem: &'db TransactionalMemory,
 }
 
 impl<'db, 'txn, K: RedbKey + 'static, V: RedbValue + 'static> Table<'db, 'txn, K, V> {
@@ -23,6 +81,7 @@ impl<'db, 'txn, K: RedbKey + 'static, V: RedbValue + 'static> Table<'db, 'txn, K
         name: &str,
         system: bool,
         table_root: Option<(PageNumber, Checksum)>,
+        key_width: Option<usize>,
         freed_pages: Arc<Mutex<Vec<PageNumber>>>,
         mem: &'db TransactionalMemory,
         transaction: &'txn WriteTransaction<'db>,
@@ -31,10 +90,22 @@ impl<'db, 'txn, K: RedbKey + 'static, V: RedbValue + 'static> Table<'db, 'txn, K
             name: name.to_string(),
             system,
             transaction,
-            tree: BtreeMut::new(table_root, mem, freed_pages),
+            tree: BtreeMut::new(table_root, mem, freed_pages).with_key_width(key_width),
+            mem,
         }
     }
 
+    // Keys of a table with a fixed key width are stored without their lengths, so a key of any
+    // other width can't be stored
+    fn check_key_width(&self, key_len: usize) -> Result {
+        check_entry_size(self.mem, self.tree.key_width(), key_len, 0)
+    }
+
+    // The changes to system tables are never reported
+    fn tracks_changes(&self) -> bool {
+        !self.system && self.transaction.tracks_changes()
+    }
+
     #[allow(dead_code)]
     pub(crate) fn print_debug(&self, include_values: bool) -> Result {
         self.tree.print_debug(include_values)
@@ -83,7 +154,46 @@ impl<'db, 'txn, K: RedbKey + 'static, V: RedbValue + 'static> Table<'db, 'txn, K
         K: 'a,
         KR: Borrow<K::SelfType<'a>> + 'a,
     {
-        self.tree.drain(&range).map(Drain::new)
+        let mut removed_keys = vec![];
+        let (drain, removed) = self
+            .tree
+            .drain(&range, self.tracks_changes().then_some(&mut removed_keys))?;
+        if !self.system {
+            self.transaction.record_removed_entries(removed);
+            for key in removed_keys {
+                self.transaction
+                    .record_change(&self.name, &key, true, false);
+            }
+        }
+        Ok(Drain::new(drain))
+    }
+
+    /// Removes all entries in the specified range, and returns the number of entries removed
+    ///
+    /// Unlike [`Self::drain`], the removed values are not returned, which allows the tree to be
+    /// descended only once. Subtrees which lie entirely within the range are freed without being
+    /// rewritten, and the tree is rebalanced along the edges of the range. Only the number of
+    /// entries in each of their leaves is read, unless changes are tracked, as enabled with
+    /// [`Builder::set_track_changes`](crate::Builder::set_track_changes). This is much faster
+    /// than removing the keys one at a time, for example to expire old entries of a table keyed by
+    /// time
+    pub fn delete_range<'a, KR>(&mut self, range: impl RangeBounds<KR> + 'a) -> Result<u64>
+    where
+        K: 'a,
+        KR: Borrow<K::SelfType<'a>> + 'a,
+    {
+        let mut removed_keys = vec![];
+        let removed = self
+            .tree
+            .delete_range(&range, self.tracks_changes().then_some(&mut removed_keys))?;
+        if !self.system {
+            self.transaction.record_removed_entries(removed);
+            for key in removed_keys {
+                self.transaction
+                    .record_change(&self.name, &key, true, false);
+            }
+        }
+        Ok(removed)
     }
 
     /// Applies `predicate` to all key-value pairs in the specified range. All entries for which
@@ -97,9 +207,20 @@ impl<'db, 'txn, K: RedbKey + 'static, V: RedbValue + 'static> Table<'db, 'txn, K
         K: 'a,
         KR: Borrow<K::SelfType<'a>> + 'a,
     {
-        self.tree
-            .drain_filter(&range, predicate)
-            .map(DrainFilter::new)
+        let mut removed_keys = vec![];
+        let (drain, removed) = self.tree.drain_filter(
+            &range,
+            predicate,
+            self.tracks_changes().then_some(&mut removed_keys),
+        )?;
+        if !self.system {
+            self.transaction.record_removed_entries(removed);
+            for key in removed_keys {
+                self.transaction
+                    .record_change(&self.name, &key, true, false);
+            }
+        }
+        Ok(DrainFilter::new(drain))
     }
 
     /// Insert mapping of the given key to the given value
@@ -115,14 +236,57 @@ impl<'db, 'txn, K: RedbKey + 'static, V: RedbValue + 'static> Table<'db, 'txn, K
         V: 'a,
     {
         let value_len = V::as_bytes(value.borrow()).as_ref().len();
-        if value_len > MAX_VALUE_LENGTH {
-            return Err(StorageError::ValueTooLarge(value_len));
+        let key_len = K::as_bytes(key.borrow()).as_ref().len();
+        check_entry_size(self.mem, self.tree.key_width(), key_len, value_len)?;
+        let old_value = self.tree.insert(key.borrow(), value.borrow())?;
+        if !self.system {
+            if old_value.is_none() {
+                self.transaction.record_inserted_entries(1);
+            }
+            let key = K::as_bytes(key.borrow());
+            self.transaction
+                .record_change(&self.name, key.as_ref(), old_value.is_some(), true);
         }
+        Ok(old_value)
+    }
+
+    /// Returns the value of the given key, inserting the value returned by `f` if the key is absent
+    ///
+    /// `f` is only called if the key is absent, and the table is searched only once. The insert is
+    /// part of the transaction, so it is rolled back if the transaction is aborted
+    pub fn get_or_insert_with<'a, F, VR>(
+        &mut self,
+        key: impl Borrow<K::SelfType<'a>>,
+        f: F,
+    ) -> Result<AccessGuard<V>>
+    where
+        K: 'a,
+        F: FnOnce() -> VR,
+        VR: Borrow<V::SelfType<'a>>,
+    {
         let key_len = K::as_bytes(key.borrow()).as_ref().len();
-        if key_len > MAX_VALUE_LENGTH {
-            return Err(StorageError::ValueTooLarge(key_len));
+        self.check_key_width(key_len)?;
+        let mut f = Some(f);
+        let mut inserted = None;
+        let mem = self.mem;
+        let key_width = self.tree.key_width();
+        let mut produce = || {
+            let value = (f.take().unwrap())();
+            let bytes = V::as_bytes(value.borrow()).as_ref().to_vec();
+            check_entry_size(mem, key_width, key_len, bytes.len())?;
+            inserted = Some(bytes.clone());
+            Ok(bytes)
+        };
+        if let Some(existing) = self.tree.insert_if_absent(key.borrow(), &mut produce)? {
+            return Ok(existing);
+        }
+        if !self.system {
+            self.transaction.record_inserted_entries(1);
+            let key = K::as_bytes(key.borrow());
+            self.transaction
+                .record_change(&self.name, key.as_ref(), false, true);
         }
-        self.tree.insert(key.borrow(), value.borrow())
+        Ok(AccessGuard::with_owned_value(inserted.unwrap()))
     }
 
     /// Removes the given key
@@ -135,7 +299,79 @@ impl<'db, 'txn, K: RedbKey + 'static, V: RedbValue + 'static> Table<'db, 'txn, K
     where
         K: 'a,
     {
-        self.tree.remove(key.borrow())
+        let old_value = self.tree.remove(key.borrow())?;
+        if !self.system && old_value.is_some() {
+            self.transaction.record_removed_entries(1);
+            let key = K::as_bytes(key.borrow());
+            self.transaction
+                .record_change(&self.name, key.as_ref(), true, false);
+        }
+        Ok(old_value)
+    }
+
+    /// Exchanges the values of the two given keys
+    ///
+    /// If either key is absent, the table is left unchanged, and `missing` selects whether
+    /// [`SwapError::KeyNotFound`] is returned. Swapping a key with itself does nothing. If the swap
+    /// fails, neither key is changed, unless restoring `key_b` after a failed write of `key_a` also
+    /// fails, in which case the transaction must be aborted
+    pub fn swap<'a>(
+        &mut self,
+        key_a: impl Borrow<K::SelfType<'a>>,
+        key_b: impl Borrow<K::SelfType<'a>>,
+        missing: MissingKey,
+    ) -> std::result::Result<(), SwapError>
+    where
+        K: 'a,
+    {
+        let not_found = match missing {
+            MissingKey::Ignore => Ok(()),
+            MissingKey::Fail => Err(SwapError::KeyNotFound),
+        };
+        let value_a = match self.tree.get(key_a.borrow())? {
+            Some(guard) => V::as_bytes(&guard.value()).as_ref().to_vec(),
+            None => return not_found,
+        };
+        if K::compare(
+            K::as_bytes(key_a.borrow()).as_ref(),
+            K::as_bytes(key_b.borrow()).as_ref(),
+        )
+        .is_eq()
+        {
+            return Ok(());
+        }
+        // Both entries are checked before anything is written: key_b with the value of key_a here,
+        // and key_a with the value of key_b once the leaf of key_b is reached
+        let key_width = self.tree.key_width();
+        let key_a_len = K::as_bytes(key_a.borrow()).as_ref().len();
+        let key_b_len = K::as_bytes(key_b.borrow()).as_ref().len();
+        check_entry_size(self.mem, key_width, key_b_len, value_a.len())?;
+        let mem = self.mem;
+        let mut value_a = Some(value_a);
+        let mut produce = |value_b: &[u8]| {
+            check_entry_size(mem, key_width, key_a_len, value_b.len())?;
+            Ok(value_a.take().unwrap())
+        };
+        let value_b = match self.tree.replace_if_present(key_b.borrow(), &mut produce)? {
+            Some(guard) => V::as_bytes(&guard.value()).as_ref().to_vec(),
+            None => return not_found,
+        };
+        let result = self.tree.insert(key_a.borrow(), &V::from_bytes(&value_b));
+        if let Err(err) = result.map(drop) {
+            // Restore key_b, so that the table is unchanged. This must not be prevented by the page
+            // limit of the transaction, if that is what stopped the write of key_a
+            self.mem.without_transaction_page_limit(|| {
+                self.tree.insert(key_b.borrow(), &V::from_bytes(&value_b))
+            })?;
+            return Err(err.into());
+        }
+        if !self.system {
+            for key in [key_a.borrow(), key_b.borrow()] {
+                self.transaction
+                    .record_change(&self.name, K::as_bytes(key).as_ref(), true, true);
+            }
+        }
+        Ok(())
     }
 }
 
@@ -150,14 +386,23 @@ impl<'db, 'txn, K: RedbKey + 'static, V: RedbValueMutInPlace + 'static> Table<'d
     where
         K: 'a,
     {
-        if value_length as usize > MAX_VALUE_LENGTH {
-            return Err(StorageError::ValueTooLarge(value_length as usize));
-        }
         let key_len = K::as_bytes(key.borrow()).as_ref().len();
-        if key_len > MAX_VALUE_LENGTH {
-            return Err(StorageError::ValueTooLarge(key_len));
+        check_entry_size(
+            self.mem,
+            self.tree.key_width(),
+            key_len,
+            value_length as usize,
+        )?;
+        let (guard, existed) = self.tree.insert_reserve(key.borrow(), value_length)?;
+        if !self.system {
+            if !existed {
+                self.transaction.record_inserted_entries(1);
+            }
+            let key_bytes = K::as_bytes(key.borrow());
+            self.transaction
+                .record_change(&self.name, key_bytes.as_ref(), existed, true);
         }
-        self.tree.insert_reserve(key.borrow(), value_length)
+        Ok(guard)
     }
 }
 
@@ -171,6 +416,13 @@ impl<'db, 'txn, K: RedbKey + 'static, V: RedbValue + 'static> ReadableTable<K, V
         self.tree.get(key.borrow())
     }
 
+    fn get_many<'a>(&self, keys: &[K::SelfType<'a>]) -> Result<Vec<Option<AccessGuard<V>>>>
+    where
+        K: 'a,
+    {
+        self.tree.get_many(keys)
+    }
+
     fn range<'a, KR>(&self, range: impl RangeBounds<KR> + 'a) -> Result<Range<K, V>>
     where
         K: 'a,
@@ -179,6 +431,22 @@ impl<'db, 'txn, K: RedbKey + 'static, V: RedbValue + 'static> ReadableTable<K, V
         self.tree.range(&range).map(Range::new)
     }
 
+    fn estimate_range_len<'a, KR>(&self, range: impl RangeBounds<KR> + 'a) -> Result<u64>
+    where
+        K: 'a,
+        KR: Borrow<K::SelfType<'a>> + 'a,
+    {
+        self.tree.estimate_range_len(&range)
+    }
+
+    fn prefetch_range<'a, KR>(&self, range: impl RangeBounds<KR> + 'a) -> Result<u64>
+    where
+        K: 'a,
+        KR: Borrow<K::SelfType<'a>> + 'a,
+    {
+        self.tree.prefetch_range(&range)
+    }
+
     fn len(&self) -> Result<u64> {
         self.tree.len()
     }
@@ -203,6 +471,34 @@ pub trait ReadableTable<K: RedbKey + 'static, V: RedbValue + 'static>: Sealed {
     where
         K: 'a;
 
+    /// Returns the values corresponding to each of the given keys, in the same order as `keys`
+    ///
+    /// This is faster than calling [`Self::get()`] for each key, because the keys are looked up
+    /// in sorted order during a single walk of the table, so the pages on the paths to nearby keys
+    /// are only read once. Keys may be repeated, and `None` is returned for keys which do not exist
+    fn get_many<'a>(&self, keys: &[K::SelfType<'a>]) -> Result<Vec<Option<AccessGuard<V>>>>
+    where
+        K: 'a;
+
+    /// Copies the serialized value corresponding to the given key into `buffer`
+    ///
+    /// `buffer` is cleared, and grown if the value does not fit in its capacity, so reusing one
+    /// buffer across lookups avoids allocating for each of them. The value can be decoded with
+    /// [`RedbValue::from_bytes()`]. Returns `false`, leaving `buffer` empty, if the key does not
+    /// exist
+    fn get_into<'a>(&self, key: impl Borrow<K::SelfType<'a>>, buffer: &mut Vec<u8>) -> Result<bool>
+    where
+        K: 'a,
+    {
+        buffer.clear();
+        if let Some(guard) = self.get(key)? {
+            buffer.extend_from_slice(guard.raw_bytes());
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
     /// Returns a double-ended iterator over a range of elements in the table
     ///
     /// # Examples
@@ -240,6 +536,28 @@ pub trait ReadableTable<K: RedbKey + 'static, V: RedbValue + 'static>: Sealed {
         K: 'a,
         KR: Borrow<K::SelfType<'a>> + 'a;
 
+    /// Returns an estimate of the number of entries in a range, without iterating over it
+    ///
+    /// Only the pages on the paths to the two ends of the range are read, along with one sampled
+    /// path for the subtrees between them. Entries near the ends are counted exactly and the others
+    /// are extrapolated from the sampled subtree, so the estimate is only close when the table's
+    /// pages are evenly filled. Use [`Self::range()`] to get the exact count
+    fn estimate_range_len<'a, KR>(&self, range: impl RangeBounds<KR> + 'a) -> Result<u64>
+    where
+        K: 'a,
+        KR: Borrow<K::SelfType<'a>> + 'a;
+
+    /// Reads every page which holds entries in the range into the cache, so that a following scan
+    /// of it doesn't wait on the disk
+    ///
+    /// The pages are those of the snapshot seen by this table. Returns the number of pages read,
+    /// including the branch pages on the paths to the entries. The cache may evict some of them
+    /// before they are used, if the range is larger than the configured cache size
+    fn prefetch_range<'a, KR>(&self, range: impl RangeBounds<KR> + 'a) -> Result<u64>
+    where
+        K: 'a,
+        KR: Borrow<K::SelfType<'a>> + 'a;
+
     /// Returns the number of entries in the table
     fn len(&self) -> Result<u64>;
 
@@ -250,6 +568,205 @@ pub trait ReadableTable<K: RedbKey + 'static, V: RedbValue + 'static>: Sealed {
     fn iter(&self) -> Result<Range<K, V>> {
         self.range::<K::SelfType<'_>>(..)
     }
+
+    /// Returns a double-ended iterator over all keys in the table, in order
+    ///
+    /// No access guards are created for the values, which makes this cheaper than [`Self::iter()`]
+    /// when only the keys are needed. Values are stored in the same leaf pages as their keys, so
+    /// those pages are still read
+    fn keys(&self) -> Result<Keys<K, V>> {
+        self.iter().map(|range| Keys::new(range.inner))
+    }
+
+    /// Returns a double-ended iterator over the elements after the position recorded in `token`
+    ///
+    /// The token may come from an iterator over an older snapshot. Writes committed in between may
+    /// cause entries to be skipped or returned twice, since only the last key is recorded
+    ///
+    /// Returns [`StorageError::KeyWrongSize`] if `K` has a fixed width, and the key recorded in
+    /// the token does not, such as for a token taken from a table with a different key type
+    ///
+    /// # Examples
+    ///
+    /// Usage:
+    /// ```rust
+    /// use redb::*;
+    /// # use tempfile::NamedTempFile;
+    /// const TABLE: TableDefinition<u64, u64> = TableDefinition::new("my_data");
+    ///
+    /// # fn main() -> Result<(), Error> {
+    /// # let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    /// # let filename = tmpfile.path();
+    /// let db = Database::create(filename)?;
+    /// let write_txn = db.begin_write()?;
+    /// {
+    ///     let mut table = write_txn.open_table(TABLE)?;
+    ///     for i in 0..10 {
+    ///         table.insert(i, i)?;
+    ///     }
+    /// }
+    /// write_txn.commit()?;
+    ///
+    /// let token = {
+    ///     let read_txn = db.begin_read()?;
+    ///     let table = read_txn.open_table(TABLE)?;
+    ///     let mut iter = table.iter()?;
+    ///     iter.next().unwrap()?;
+    ///     iter.position_token().unwrap()
+    /// };
+    ///
+    /// let read_txn = db.begin_read()?;
+    /// let table = read_txn.open_table(TABLE)?;
+    /// let (key, _) = table.range_from_token(&token)?.next().unwrap()?;
+    /// assert_eq!(1, key.value());
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn range_from_token(&self, token: &IteratorToken) -> Result<Range<K, V>> {
+        check_token_key(K::fixed_width(), &token.key)?;
+        let key = K::from_bytes(&token.key);
+        self.range::<K::SelfType<'_>>((Bound::Excluded(key), Bound::Unbounded))
+    }
+}
+
+/// The position of a [`Range`] iterator, which can be used to resume iteration in a later transaction
+///
+/// See [`ReadableTable::range_from_token()`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct IteratorToken {
+    key: Vec<u8>,
+}
+
+impl IteratorToken {
+    /// Serialize the token, so that it can be handed to a client
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.key.clone()
+    }
+
+    /// Deserialize a token previously serialized with [`IteratorToken::to_bytes()`]
+    pub fn from_bytes(data: &[u8]) -> Self {
+        Self { key: data.to_vec() }
+    }
+}
+
+/// The position of an [`EtlScan`], which can be persisted to resume the scan after a restart
+///
+/// See [`Database::resume_etl_scan()`](crate::Database::resume_etl_scan)
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EtlScanToken {
+    savepoint: u64,
+    transaction_id: u64,
+    // The last key returned, or None if the scan has not returned any entries
+    key: Option<Vec<u8>>,
+}
+
+impl EtlScanToken {
+    /// The id of the persistent savepoint which holds the snapshot being scanned
+    pub fn savepoint(&self) -> u64 {
+        self.savepoint
+    }
+
+    /// The id of the commit which is being scanned
+    pub fn transaction_id(&self) -> u64 {
+        self.transaction_id
+    }
+
+    /// Serialize the token, so that it can be persisted
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = vec![];
+        result.extend_from_slice(&self.savepoint.to_le_bytes());
+        result.extend_from_slice(&self.transaction_id.to_le_bytes());
+        if let Some(key) = &self.key {
+            result.push(1);
+            result.extend_from_slice(key);
+        } else {
+            result.push(0);
+        }
+        result
+    }
+
+    /// Deserialize a token previously serialized with [`EtlScanToken::to_bytes()`]
+    ///
+    /// Returns `None` if `data` is not a serialized token
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() < 17 {
+            return None;
+        }
+        let savepoint = u64::from_le_bytes(data[..8].try_into().unwrap());
+        let transaction_id = u64::from_le_bytes(data[8..16].try_into().unwrap());
+        let key = match data[16] {
+            0 if data.len() == 17 => None,
+            1 => Some(data[17..].to_vec()),
+            _ => return None,
+        };
+        Some(Self {
+            savepoint,
+            transaction_id,
+            key,
+        })
+    }
+
+    pub(crate) fn key(&self) -> Option<&[u8]> {
+        self.key.as_deref()
+    }
+}
+
+/// Iterator over a table in a snapshot which survives restarts, as returned by
+/// [`Database::etl_scan()`](crate::Database::etl_scan)
+pub struct EtlScan<'db, K: RedbKey + 'static, V: RedbValue + 'static> {
+    range: Range<'db, K, V>,
+    // Keeps the pages of the snapshot from being freed while the scan is open
+    snapshot: ReadTransaction<'db>,
+    savepoint: u64,
+    // The key after which the scan was resumed
+    resumed_after: Option<Vec<u8>>,
+}
+
+impl<'db, K: RedbKey + 'static, V: RedbValue + 'static> EtlScan<'db, K, V> {
+    pub(crate) fn new(
+        snapshot: ReadTransaction<'db>,
+        range: Range<'db, K, V>,
+        savepoint: u64,
+        resumed_after: Option<Vec<u8>>,
+    ) -> Self {
+        Self {
+            range,
+            snapshot,
+            savepoint,
+            resumed_after,
+        }
+    }
+
+    /// The id of the persistent savepoint which holds the snapshot being scanned
+    ///
+    /// It should be deleted with
+    /// [`WriteTransaction::delete_persistent_savepoint()`](crate::WriteTransaction::delete_persistent_savepoint)
+    /// once the scan is complete
+    pub fn savepoint(&self) -> u64 {
+        self.savepoint
+    }
+
+    /// Returns a token recording the position of the scan, from which it can be resumed with
+    /// [`Database::resume_etl_scan()`](crate::Database::resume_etl_scan)
+    pub fn position_token(&self) -> EtlScanToken {
+        EtlScanToken {
+            savepoint: self.savepoint,
+            transaction_id: self.snapshot.transaction_id(),
+            key: self
+                .range
+                .position_token()
+                .map(|token| token.key)
+                .or_else(|| self.resumed_after.clone()),
+        }
+    }
+}
+
+impl<'db, K: RedbKey + 'static, V: RedbValue + 'static> Iterator for EtlScan<'db, K, V> {
+    type Item = Result<(AccessGuard<'db, K>, AccessGuard<'db, V>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.range.next()
+    }
 }
 
 /// A read-only table
@@ -260,11 +777,12 @@ pub struct ReadOnlyTable<'txn, K: RedbKey + 'static, V: RedbValue + 'static> {
 impl<'txn, K: RedbKey + 'static, V: RedbValue + 'static> ReadOnlyTable<'txn, K, V> {
     pub(crate) fn new(
         root_page: Option<(PageNumber, Checksum)>,
+        key_width: Option<usize>,
         hint: PageHint,
         mem: &'txn TransactionalMemory,
     ) -> Result<ReadOnlyTable<'txn, K, V>> {
         Ok(ReadOnlyTable {
-            tree: Btree::new(root_page, hint, mem)?,
+            tree: Btree::new(root_page, hint, mem)?.with_key_width(key_width),
         })
     }
 }
@@ -279,6 +797,13 @@ impl<'txn, K: RedbKey + 'static, V: RedbValue + 'static> ReadableTable<K, V>
         self.tree.get(key.borrow())
     }
 
+    fn get_many<'a>(&self, keys: &[K::SelfType<'a>]) -> Result<Vec<Option<AccessGuard<V>>>>
+    where
+        K: 'a,
+    {
+        self.tree.get_many(keys)
+    }
+
     fn range<'a, KR>(&self, range: impl RangeBounds<KR> + 'a) -> Result<Range<K, V>>
     where
         K: 'a,
@@ -287,6 +812,22 @@ impl<'txn, K: RedbKey + 'static, V: RedbValue + 'static> ReadableTable<K, V>
         self.tree.range(&range).map(Range::new)
     }
 
+    fn estimate_range_len<'a, KR>(&self, range: impl RangeBounds<KR> + 'a) -> Result<u64>
+    where
+        K: 'a,
+        KR: Borrow<K::SelfType<'a>> + 'a,
+    {
+        self.tree.estimate_range_len(&range)
+    }
+
+    fn prefetch_range<'a, KR>(&self, range: impl RangeBounds<KR> + 'a) -> Result<u64>
+    where
+        K: 'a,
+        KR: Borrow<K::SelfType<'a>> + 'a,
+    {
+        self.tree.prefetch_range(&range)
+    }
+
     fn len(&self) -> Result<u64> {
         self.tree.len()
     }
@@ -395,14 +936,221 @@ impl<
 
 pub struct Range<'a, K: RedbKey + 'static, V: RedbValue + 'static> {
     inner: BtreeRangeIter<'a, K, V>,
+    // The last key returned by next()
+    last_key: Option<AccessGuard<'a, K>>,
 }
 
 impl<'a, K: RedbKey + 'static, V: RedbValue + 'static> Range<'a, K, V> {
+    pub(crate) fn new(inner: BtreeRangeIter<'a, K, V>) -> Self {
+        Self {
+            inner,
+            last_key: None,
+        }
+    }
+
+    /// Merge-joins this range with `other` on their keys, advancing both in lockstep
+    ///
+    /// Both ranges must be in ascending order, and should come from tables in the same transaction
+    /// so that they read a consistent snapshot. Keys are unique within a table, so each key is
+    /// matched at most once
+    ///
+    /// # Examples
+    ///
+    /// Usage:
+    /// ```rust
+    /// use redb::*;
+    /// # use tempfile::NamedTempFile;
+    /// const USERS: TableDefinition<u64, &str> = TableDefinition::new("users");
+    /// const ORDERS: TableDefinition<u64, u64> = TableDefinition::new("orders");
+    ///
+    /// # fn main() -> Result<(), Error> {
+    /// # let tmpfile: NamedTempFile = NamedTempFile::new().unwrap();
+    /// # let filename = tmpfile.path();
+    /// let db = Database::create(filename)?;
+    /// let write_txn = db.begin_write()?;
+    /// {
+    ///     let mut users = write_txn.open_table(USERS)?;
+    ///     users.insert(1, "alice")?;
+    ///     users.insert(2, "bob")?;
+    ///     let mut orders = write_txn.open_table(ORDERS)?;
+    ///     orders.insert(2, 100)?;
+    ///     orders.insert(3, 200)?;
+    /// }
+    /// write_txn.commit()?;
+    ///
+    /// let read_txn = db.begin_read()?;
+    /// let users = read_txn.open_table(USERS)?;
+    /// let orders = read_txn.open_table(ORDERS)?;
+    /// let mut join = users.iter()?.merge_join(orders.iter()?, JoinMode::Inner);
+    /// let (key, user, order) = join.next().unwrap()?;
+    /// assert_eq!(2, key.value());
+    /// assert_eq!("bob", user.unwrap().value());
+    /// assert_eq!(100, order.unwrap().value());
+    /// assert!(join.next().is_none());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn merge_join<R: RedbValue + 'static>(
+        self,
+        other: Range<'a, K, R>,
+        mode: JoinMode,
+    ) -> MergeJoin<'a, K, V, R> {
+        MergeJoin {
+            left: self.fuse(),
+            right: other.fuse(),
+            mode,
+            left_next: None,
+            right_next: None,
+        }
+    }
+
+    /// Returns a token recording the last key returned by [`Iterator::next()`], or `None` if it has
+    /// not returned any entries yet
+    ///
+    /// Entries returned by [`DoubleEndedIterator::next_back()`] do not affect the token
+    pub fn position_token(&self) -> Option<IteratorToken> {
+        self.last_key.as_ref().map(|key| IteratorToken {
+            key: K::as_bytes(&key.value()).as_ref().to_vec(),
+        })
+    }
+}
+
+/// Selects what [`Table::swap`] does when either key is absent
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MissingKey {
+    /// Leave the table unchanged, and return `Ok`
+    Ignore,
+    /// Leave the table unchanged, and return [`SwapError::KeyNotFound`]
+    Fail,
+}
+
+/// Selects the rows yielded by [`Range::merge_join`]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum JoinMode {
+    /// Only keys which are present on both sides
+    Inner,
+    /// Every key of the left side, with the value of the right side if it has the key
+    Left,
+    /// Every key of either side
+    Outer,
+}
+
+/// A row yielded by [`MergeJoin`]: the key, and its value on each side. At least one is present
+pub type JoinRow<'a, K, L, R> = (
+    AccessGuard<'a, K>,
+    Option<AccessGuard<'a, L>>,
+    Option<AccessGuard<'a, R>>,
+);
+
+/// Iterator over the rows of two ranges joined on their keys, as returned by [`Range::merge_join`]
+pub struct MergeJoin<'a, K: RedbKey + 'static, L: RedbValue + 'static, R: RedbValue + 'static> {
+    left: Fuse<Range<'a, K, L>>,
+    right: Fuse<Range<'a, K, R>>,
+    mode: JoinMode,
+    // Entries which have been read, but not yet yielded or skipped
+    left_next: Option<(AccessGuard<'a, K>, AccessGuard<'a, L>)>,
+    right_next: Option<(AccessGuard<'a, K>, AccessGuard<'a, R>)>,
+}
+
+impl<'a, K: RedbKey + 'static, L: RedbValue + 'static, R: RedbValue + 'static> Iterator
+    for MergeJoin<'a, K, L, R>
+{
+    type Item = Result<JoinRow<'a, K, L, R>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.left_next.is_none() {
+                match self.left.next() {
+                    Some(Ok(entry)) => self.left_next = Some(entry),
+                    Some(Err(err)) => return Some(Err(err)),
+                    None => {}
+                }
+            }
+            if self.right_next.is_none() {
+                match self.right.next() {
+                    Some(Ok(entry)) => self.right_next = Some(entry),
+                    Some(Err(err)) => return Some(Err(err)),
+                    None => {}
+                }
+            }
+
+            match (self.left_next.take(), self.right_next.take()) {
+                (None, None) => return None,
+                (Some((key, left)), None) => {
+                    return if self.mode == JoinMode::Inner {
+                        None
+                    } else {
+                        Some(Ok((key, Some(left), None)))
+                    };
+                }
+                (None, Some((key, right))) => {
+                    return if self.mode == JoinMode::Outer {
+                        Some(Ok((key, None, Some(right))))
+                    } else {
+                        None
+                    };
+                }
+                (Some((left_key, left)), Some((right_key, right))) => {
+                    let ordering = K::compare(
+                        K::as_bytes(&left_key.value()).as_ref(),
+                        K::as_bytes(&right_key.value()).as_ref(),
+                    );
+                    match ordering {
+                        Ordering::Equal => return Some(Ok((left_key, Some(left), Some(right)))),
+                        Ordering::Less => {
+                            self.right_next = Some((right_key, right));
+                            if self.mode != JoinMode::Inner {
+                                return Some(Ok((left_key, Some(left), None)));
+                            }
+                        }
+                        Ordering::Greater => {
+                            self.left_next = Some((left_key, left));
+                            if self.mode == JoinMode::Outer {
+                                return Some(Ok((right_key, None, Some(right))));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Double-ended iterator over the keys of a table, as returned by [`ReadableTable::keys`]
+pub struct Keys<'a, K: RedbKey + 'static, V: RedbValue + 'static> {
+    inner: BtreeRangeIter<'a, K, V>,
+}
+
+impl<'a, K: RedbKey + 'static, V: RedbValue + 'static> Keys<'a, K, V> {
     fn new(inner: BtreeRangeIter<'a, K, V>) -> Self {
         Self { inner }
     }
 }
 
+impl<'a, K: RedbKey + 'static, V: RedbValue + 'static> Iterator for Keys<'a, K, V> {
+    type Item = Result<AccessGuard<'a, K>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|x| {
+            x.map(|entry| {
+                let (page, key_range, _) = entry.into_raw();
+                AccessGuard::with_page(page, key_range)
+            })
+        })
+    }
+}
+
+impl<'a, K: RedbKey + 'static, V: RedbValue + 'static> DoubleEndedIterator for Keys<'a, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|x| {
+            x.map(|entry| {
+                let (page, key_range, _) = entry.into_raw();
+                AccessGuard::with_page(page, key_range)
+            })
+        })
+    }
+}
+
 impl<'a, K: RedbKey + 'static, V: RedbValue + 'static> Iterator for Range<'a, K, V> {
     type Item = Result<(AccessGuard<'a, K>, AccessGuard<'a, V>)>;
 
@@ -410,6 +1158,7 @@ impl<'a, K: RedbKey + 'static, V: RedbValue + 'static> Iterator for Range<'a, K,
         self.inner.next().map(|x| {
             x.map(|entry| {
                 let (page, key_range, value_range) = entry.into_raw();
+                self.last_key = Some(AccessGuard::with_page(page.clone(), key_range.clone()));
                 let key = AccessGuard::with_page(page.clone(), key_range);
                 let value = AccessGuard::with_page(page, value_range);
                 (key, value)
@@ -4,10 +4,25 @@ use std::collections::btree_map::BTreeMap;
 use std::collections::btree_set::BTreeSet;
 use std::mem::size_of;
 
+// Transaction ids are split into an epoch, in the high bits, and a counter within the epoch
+const EPOCH_SHIFT: u32 = 32;
+
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
 pub(crate) struct TransactionId(pub u64);
 
 impl TransactionId {
+    pub(crate) fn first_in_epoch(epoch: u32) -> TransactionId {
+        TransactionId(u64::from(epoch) << EPOCH_SHIFT)
+    }
+
+    pub(crate) fn epoch(&self) -> u32 {
+        (self.0 >> EPOCH_SHIFT).try_into().unwrap()
+    }
+
+    pub(crate) fn counter(&self) -> u32 {
+        (self.0 & u64::from(u32::MAX)).try_into().unwrap()
+    }
+
     pub(crate) fn next(&self) -> TransactionId {
         TransactionId(self.0 + 1)
     }
@@ -97,6 +112,10 @@ impl TransactionTracker {
         }
     }
 
+    pub(crate) fn any_pending_non_durable_commit(&self) -> bool {
+        !self.pending_non_durable_commits.is_empty()
+    }
+
     pub(crate) fn register_non_durable_commit(&mut self, id: TransactionId) {
         if let Some(parent) = id.parent() {
             self.live_read_transactions
@@ -124,6 +143,17 @@ impl TransactionTracker {
             .or_insert(1);
     }
 
+    // Registers a read of the snapshot held by the given savepoint, unless the savepoint has been
+    // deleted or restored over. The check and the registration are made under the same lock, so
+    // that the pages of the snapshot can't be freed in between
+    pub(crate) fn register_savepoint_read(&mut self, savepoint: &Savepoint) -> bool {
+        if !self.is_valid_savepoint(savepoint.get_id()) {
+            return false;
+        }
+        self.register_read_transaction(savepoint.get_transaction_id());
+        true
+    }
+
     pub(crate) fn deallocate_read_transaction(&mut self, id: TransactionId) {
         let ref_count = self.live_read_transactions.get_mut(&id).unwrap();
         *ref_count -= 1;
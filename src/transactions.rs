@@ -1,20 +1,23 @@
 use crate::error::CommitError;
 use crate::sealed::Sealed;
+use crate::table::check_entry_size;
 use crate::transaction_tracker::{SavepointId, TransactionId, TransactionTracker};
 use crate::tree_store::{
-    Btree, BtreeMut, FreedPageList, FreedTableKey, InternalTableDefinition, PageHint, PageNumber,
-    SerializedSavepoint, TableTree, TableType, TransactionalMemory,
+    Btree, BtreeMut, Checksum, FreedPageList, FreedTableKey, HybridTimestamp,
+    InternalTableDefinition, PageHint, PageNumber, SerializedSavepoint, TableTree, TableType,
+    TransactionalMemory,
 };
-use crate::types::{RedbKey, RedbValue};
+use crate::types::{RedbKey, RedbValue, TypeName};
 use crate::{
     Database, MultimapTable, MultimapTableDefinition, MultimapTableHandle, ReadOnlyMultimapTable,
-    ReadOnlyTable, ReadableTable, Result, Savepoint, SavepointError, Table, TableDefinition,
-    TableError, TableHandle, UntypedMultimapTableHandle, UntypedTableHandle,
+    ReadOnlyTable, ReadableTable, Result, Savepoint, SavepointError, StorageError, Table,
+    TableDefinition, TableError, TableHandle, UntypedMultimapTableHandle, UntypedTableHandle,
 };
 #[cfg(feature = "logging")]
 use log::{info, warn};
+use std::borrow::Borrow;
 use std::cmp::min;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt::{Display, Formatter};
 use std::marker::PhantomData;
 use std::ops::RangeFull;
@@ -26,6 +29,12 @@ const NEXT_SAVEPOINT_TABLE: SystemTableDefinition<(), SavepointId> =
     SystemTableDefinition::new("next_savepoint_id");
 pub(crate) const SAVEPOINT_TABLE: SystemTableDefinition<SavepointId, SerializedSavepoint> =
     SystemTableDefinition::new("persistent_savepoints");
+const COMMIT_METADATA_TABLE: SystemTableDefinition<(), &[u8]> =
+    SystemTableDefinition::new("commit_metadata");
+// Maximum length of the metadata attached to a commit
+const MAX_COMMIT_METADATA_LENGTH: usize = 64;
+
+type ChangeSet = BTreeMap<(String, Vec<u8>), (bool, bool)>;
 
 pub struct SystemTableDefinition<'a, K: RedbKey + 'static, V: RedbValue + 'static> {
     name: &'a str,
@@ -130,6 +139,199 @@ impl DatabaseStats {
     }
 }
 
+/// Page allocation counts of a committed write transaction, as returned by
+/// [`WriteTransaction::commit_with_stats`]
+///
+/// Pages which were both allocated and freed by the transaction are not counted, nor are the
+/// pages of the list of freed pages which the commit itself maintains
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransactionStats {
+    pub(crate) allocated: usize,
+    pub(crate) freed: usize,
+    pub(crate) durability_degraded: bool,
+}
+
+impl TransactionStats {
+    /// Number of pages allocated by the transaction
+    pub fn allocated(&self) -> usize {
+        self.allocated
+    }
+
+    /// Number of pages, written by earlier transactions, which the transaction freed. They become
+    /// available for reuse once no read transaction or savepoint references them
+    pub fn freed(&self) -> usize {
+        self.freed
+    }
+
+    /// Change in the number of pages in use, as a result of the transaction
+    pub fn net(&self) -> isize {
+        // A transaction can't allocate or free more than isize::MAX pages
+        #[allow(clippy::cast_possible_wrap)]
+        let net = self.allocated as isize - self.freed as isize;
+        net
+    }
+
+    /// Returns true if the commit was not synced before returning, despite its durability, because
+    /// the sync was expected to exceed the budget set with
+    /// [`Builder::set_commit_latency_budget`](crate::Builder::set_commit_latency_budget). The
+    /// commit has been handed to the operating system, and is synced in the background
+    pub fn durability_degraded(&self) -> bool {
+        self.durability_degraded
+    }
+}
+
+/// An entry changed by the most recent commit, as returned by [`Database::last_commit_changes`]
+///
+/// Keys are in their serialized form, as produced by [`RedbKey::as_bytes`], and can be decoded
+/// with [`RedbKey::from_bytes`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChangeKind {
+    /// The key was not present before the commit
+    Inserted { table: String, key: Vec<u8> },
+    /// The key was present before the commit, and its value was overwritten
+    Updated { table: String, key: Vec<u8> },
+    /// The key was present before the commit, and was removed
+    Deleted { table: String, key: Vec<u8> },
+}
+
+impl ChangeKind {
+    /// Name of the table containing the key
+    pub fn table(&self) -> &str {
+        match self {
+            ChangeKind::Inserted { table, .. }
+            | ChangeKind::Updated { table, .. }
+            | ChangeKind::Deleted { table, .. } => table,
+        }
+    }
+
+    /// Serialized key of the entry
+    pub fn key(&self) -> &[u8] {
+        match self {
+            ChangeKind::Inserted { key, .. }
+            | ChangeKind::Updated { key, .. }
+            | ChangeKind::Deleted { key, .. } => key,
+        }
+    }
+}
+
+/// Information about a table, as returned by [`ReadTransaction::list_table_info`]
+#[derive(Debug, Clone)]
+pub struct TableInfo {
+    pub(crate) name: String,
+    pub(crate) multimap: bool,
+    pub(crate) key_type: TypeName,
+    pub(crate) value_type: TypeName,
+    pub(crate) entry_count: u64,
+    pub(crate) page_count: u64,
+}
+
+impl TableInfo {
+    /// Name of the table
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Whether this is a multimap table
+    pub fn is_multimap(&self) -> bool {
+        self.multimap
+    }
+
+    /// Type of the keys, as it was declared when the table was created
+    pub fn key_type(&self) -> &TypeName {
+        &self.key_type
+    }
+
+    /// Type of the values, as it was declared when the table was created
+    pub fn value_type(&self) -> &TypeName {
+        &self.value_type
+    }
+
+    /// Number of key-value pairs in the table
+    pub fn entry_count(&self) -> u64 {
+        self.entry_count
+    }
+
+    /// Number of btree pages used by the table, including the subtrees of a multimap table. A table
+    /// which has never been written to has none
+    ///
+    /// Pages are never shared between tables, so the counts of different tables are independent
+    pub fn page_count(&self) -> u64 {
+        self.page_count
+    }
+}
+
+/// Breakdown of the space occupied by the btree pages of a database, and how much of it is in use
+///
+/// Bytes in a page that are not used to store data or metadata are wasted due to fragmentation.
+/// The table of tables is counted in the branch pages, since it indexes the data tables.
+#[derive(Debug)]
+pub struct SpaceUsage {
+    pub(crate) leaf_pages: u64,
+    pub(crate) leaf_page_bytes: u64,
+    pub(crate) leaf_stored_bytes: u64,
+    pub(crate) leaf_metadata_bytes: u64,
+    pub(crate) branch_pages: u64,
+    pub(crate) branch_page_bytes: u64,
+    pub(crate) branch_metadata_bytes: u64,
+}
+
+impl SpaceUsage {
+    /// Number of leaf pages that store user data
+    pub fn leaf_pages(&self) -> u64 {
+        self.leaf_pages
+    }
+
+    /// Number of bytes occupied by leaf pages
+    pub fn leaf_page_bytes(&self) -> u64 {
+        self.leaf_page_bytes
+    }
+
+    /// Number of bytes in leaf pages consumed by keys and values
+    pub fn leaf_stored_bytes(&self) -> u64 {
+        self.leaf_stored_bytes
+    }
+
+    /// Number of bytes in leaf pages consumed by page headers and offset tables
+    pub fn leaf_metadata_bytes(&self) -> u64 {
+        self.leaf_metadata_bytes
+    }
+
+    /// Number of bytes in leaf pages that are unused
+    pub fn leaf_fragmented_bytes(&self) -> u64 {
+        self.leaf_page_bytes - self.leaf_stored_bytes - self.leaf_metadata_bytes
+    }
+
+    /// Number of branch pages
+    pub fn branch_pages(&self) -> u64 {
+        self.branch_pages
+    }
+
+    /// Number of bytes occupied by branch pages
+    pub fn branch_page_bytes(&self) -> u64 {
+        self.branch_page_bytes
+    }
+
+    /// Number of bytes in branch pages consumed by keys, child pointers, and other metadata
+    pub fn branch_metadata_bytes(&self) -> u64 {
+        self.branch_metadata_bytes
+    }
+
+    /// Number of bytes in branch pages that are unused
+    pub fn branch_fragmented_bytes(&self) -> u64 {
+        self.branch_page_bytes - self.branch_metadata_bytes
+    }
+
+    /// Number of bytes consumed by keys and values that have been inserted
+    pub fn stored_bytes(&self) -> u64 {
+        self.leaf_stored_bytes
+    }
+
+    /// Total number of bytes occupied by all pages
+    pub fn page_bytes(&self) -> u64 {
+        self.leaf_page_bytes + self.branch_page_bytes
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 #[non_exhaustive]
 pub enum Durability {
@@ -139,6 +341,17 @@ pub enum Durability {
     /// Note: Pages are only freed during commits with higher durability levels. Exclusively using
     /// this function may result in Error::OutOfSpace.
     None,
+    /// Commits with this durability level become the primary commit, like
+    /// [Durability::Immediate], but `fsync` is never called. The commit is handed to the operating
+    /// system before [WriteTransaction::commit] returns, so it survives the process crashing or
+    /// the database being closed and reopened.
+    ///
+    /// Warning: a power loss or operating system crash loses this commit, and every later commit,
+    /// until the next commit which is fsync'ed. The database reopens at the last commit which was
+    /// fsync'ed. Only use this where the OS page cache is itself durable, such as on
+    /// battery-backed storage, or where losing those commits is acceptable. See
+    /// [`crate::Builder::set_max_sync_interval`] to bound how many commits can be lost.
+    NoSync,
     /// Commits with this durability level have been queued for persitance to disk, and should be
     /// persistent some time after [WriteTransaction::commit] returns.
     Eventual,
@@ -208,8 +421,14 @@ pub struct WriteTransaction<'db> {
     dirty: AtomicBool,
     durability: Durability,
     // Persistent savepoints created during this transaction
-    created_persistent_savepoints: Mutex<HashSet<SavepointId>>,
+    // Ordered, so that an abort deletes them in the same order every run
+    created_persistent_savepoints: Mutex<BTreeSet<SavepointId>>,
     deleted_persistent_savepoints: Mutex<Vec<(SavepointId, TransactionId)>>,
+    // Number of key-value pairs in all user tables. None if it must be recomputed before commit
+    entry_count: Mutex<Option<u64>>,
+    // Keys changed in user tables, mapped to whether they were present before this transaction,
+    // and whether they are present now
+    changes: Mutex<ChangeSet>,
     live_write_transaction: MutexGuard<'db, Option<TransactionId>>,
 }
 
@@ -218,16 +437,20 @@ impl<'db> WriteTransaction<'db> {
         db: &'db Database,
         transaction_tracker: Arc<Mutex<TransactionTracker>>,
     ) -> Result<Self> {
+        db.get_memory().check_io_errors()?;
         let mut live_write_transaction = db.live_write_transaction.lock().unwrap();
         assert!(live_write_transaction.is_none());
         let transaction_id = db.increment_transaction_id();
         #[cfg(feature = "logging")]
         info!("Beginning write transaction id={:?}", transaction_id);
         *live_write_transaction = Some(transaction_id);
+        db.get_memory().begin_write_transaction(transaction_id);
+        db.clear_last_commit_changes();
 
         let root_page = db.get_memory().get_data_root();
         let system_page = db.get_memory().get_system_root();
         let freed_root = db.get_memory().get_freed_root();
+        let entry_count = db.get_memory().get_entry_count();
         let freed_pages = Arc::new(Mutex::new(vec![]));
         let post_commit_frees = Arc::new(Mutex::new(vec![]));
         Ok(Self {
@@ -259,6 +482,8 @@ impl<'db> WriteTransaction<'db> {
             durability: Durability::Immediate,
             created_persistent_savepoints: Mutex::new(Default::default()),
             deleted_persistent_savepoints: Mutex::new(vec![]),
+            entry_count: Mutex::new(entry_count),
+            changes: Mutex::new(Default::default()),
             live_write_transaction,
         })
     }
@@ -301,6 +526,7 @@ impl<'db> WriteTransaction<'db> {
             definition.name(),
             true,
             internal_table.get_root(),
+            internal_table.get_fixed_key_size(),
             self.freed_pages.clone(),
             self.mem,
             self,
@@ -484,7 +710,7 @@ impl<'db> WriteTransaction<'db> {
                 continue;
             }
             if self.mem.uncommitted(page) {
-                self.mem.free(page);
+                self.mem.free(page)?;
             } else {
                 freed_pages.push(page);
             }
@@ -529,6 +755,9 @@ impl<'db> WriteTransaction<'db> {
 
         *self.freed_tree.lock().unwrap() = freed_tree;
 
+        // The savepoint does not record the number of entries, so recompute it during commit
+        *self.entry_count.lock().unwrap() = None;
+
         // Invalidate all savepoints that are newer than the one being applied to prevent the user
         // from later trying to restore a savepoint "on another timeline"
         self.transaction_tracker
@@ -545,6 +774,12 @@ impl<'db> WriteTransaction<'db> {
         Ok(())
     }
 
+    /// Returns the id of this transaction, which becomes the id of the commit if it is committed.
+    /// See [`Database::await_transaction`]
+    pub fn transaction_id(&self) -> u64 {
+        self.transaction_id.0
+    }
+
     /// Set the desired durability level for writes made in this transaction
     /// Defaults to [`Durability::Immediate`]
     ///
@@ -586,7 +821,11 @@ impl<'db> WriteTransaction<'db> {
             .table_tree
             .write()
             .unwrap()
-            .get_or_create_table::<K, V>(definition.name(), TableType::Normal)?;
+            .get_or_create_table_with_key_width::<K, V>(
+                definition.name(),
+                TableType::Normal,
+                definition.key_width(),
+            )?;
         self.open_tables
             .lock()
             .unwrap()
@@ -596,6 +835,7 @@ impl<'db> WriteTransaction<'db> {
             definition.name(),
             false,
             internal_table.get_root(),
+            internal_table.get_fixed_key_size(),
             self.freed_pages.clone(),
             self.mem,
             self,
@@ -665,6 +905,66 @@ impl<'db> WriteTransaction<'db> {
         }
     }
 
+    pub(crate) fn reserve_pages(&self, pages: usize) {
+        self.mem.reserve_uncommitted_pages(pages);
+        self.freed_pages.lock().unwrap().reserve(pages);
+    }
+
+    pub(crate) fn record_inserted_entries(&self, count: u64) {
+        if let Some(entry_count) = self.entry_count.lock().unwrap().as_mut() {
+            *entry_count += count;
+        }
+    }
+
+    pub(crate) fn record_removed_entries(&self, count: u64) {
+        let mut entry_count = self.entry_count.lock().unwrap();
+        if let Some(current) = *entry_count {
+            // If the count has drifted, recompute it during commit rather than persisting a
+            // wrapped value
+            *entry_count = current.checked_sub(count);
+        }
+    }
+
+    pub(crate) fn tracks_changes(&self) -> bool {
+        self.db.tracks_changes()
+    }
+
+    // existed is whether the key was present before the operation, and exists whether it is after
+    pub(crate) fn record_change(&self, table: &str, key: &[u8], existed: bool, exists: bool) {
+        if !self.tracks_changes() {
+            return;
+        }
+        self.changes
+            .lock()
+            .unwrap()
+            .entry((table.to_string(), key.to_vec()))
+            .and_modify(|(_, now)| *now = exists)
+            .or_insert((existed, exists));
+    }
+
+    fn take_changes(&self) -> Vec<ChangeKind> {
+        let changes = std::mem::take(&mut *self.changes.lock().unwrap());
+        changes
+            .into_iter()
+            .filter_map(|((table, key), change)| match change {
+                (false, true) => Some(ChangeKind::Inserted { table, key }),
+                (true, true) => Some(ChangeKind::Updated { table, key }),
+                (true, false) => Some(ChangeKind::Deleted { table, key }),
+                (false, false) => None,
+            })
+            .collect()
+    }
+
+    fn delete_table_inner(&self, name: &str, table_type: TableType) -> Result<bool, TableError> {
+        let mut table_tree = self.table_tree.write().unwrap();
+        let entries = table_tree.count_table_entries(name, table_type)?;
+        let found = table_tree.delete_table(name, table_type)?;
+        if found {
+            self.record_removed_entries(entries);
+        }
+        Ok(found)
+    }
+
     /// Delete the given table
     ///
     /// Returns a bool indicating whether the table existed
@@ -672,10 +972,7 @@ impl<'db> WriteTransaction<'db> {
         #[cfg(feature = "logging")]
         info!("Deleting table: {}", definition.name());
         self.dirty.store(true, Ordering::Release);
-        self.table_tree
-            .write()
-            .unwrap()
-            .delete_table(definition.name(), TableType::Normal)
+        self.delete_table_inner(definition.name(), TableType::Normal)
     }
 
     /// Delete the given table
@@ -688,10 +985,156 @@ impl<'db> WriteTransaction<'db> {
         #[cfg(feature = "logging")]
         info!("Deleting multimap table: {}", definition.name());
         self.dirty.store(true, Ordering::Release);
+        self.delete_table_inner(definition.name(), TableType::Multimap)
+    }
+
+    fn rename_table_inner(
+        &self,
+        name: &str,
+        new_name: &str,
+        table_type: TableType,
+    ) -> Result<(), TableError> {
+        let open_tables = self.open_tables.lock().unwrap();
+        for open_name in [name, new_name] {
+            if let Some(location) = open_tables.get(open_name) {
+                return Err(TableError::TableAlreadyOpen(
+                    open_name.to_string(),
+                    location,
+                ));
+            }
+        }
+        drop(open_tables);
+        self.dirty.store(true, Ordering::Release);
         self.table_tree
             .write()
             .unwrap()
-            .delete_table(definition.name(), TableType::Multimap)
+            .rename_table(name, new_name, table_type)
+    }
+
+    /// Rename the given table, without copying its contents
+    ///
+    /// Returns [`TableError::TableDoesNotExist`] if the table does not exist, and
+    /// [`TableError::TableExists`] if a table named `new_name` already exists. Neither table may be
+    /// open in this transaction
+    pub fn rename_table(
+        &self,
+        definition: impl TableHandle,
+        new_name: impl TableHandle,
+    ) -> Result<(), TableError> {
+        #[cfg(feature = "logging")]
+        info!(
+            "Renaming table: {} to {}",
+            definition.name(),
+            new_name.name()
+        );
+        self.rename_table_inner(definition.name(), new_name.name(), TableType::Normal)
+    }
+
+    /// Rename the given multimap table, without copying its contents
+    ///
+    /// Returns [`TableError::TableDoesNotExist`] if the table does not exist, and
+    /// [`TableError::TableExists`] if a table named `new_name` already exists. Neither table may be
+    /// open in this transaction
+    pub fn rename_multimap_table(
+        &self,
+        definition: impl MultimapTableHandle,
+        new_name: impl MultimapTableHandle,
+    ) -> Result<(), TableError> {
+        #[cfg(feature = "logging")]
+        info!(
+            "Renaming multimap table: {} to {}",
+            definition.name(),
+            new_name.name()
+        );
+        self.rename_table_inner(definition.name(), new_name.name(), TableType::Multimap)
+    }
+
+    /// Rebuild the given table as a dense tree, and free the pages of its old tree
+    ///
+    /// Only this table is rewritten, which is much cheaper than [`crate::Database::compact`]
+    /// when a single table has become fragmented, for example after removing many of its entries.
+    /// The new tree becomes visible when this transaction commits. Returns
+    /// [`TableError::TableDoesNotExist`] if the table does not exist. The table may not be open in
+    /// this transaction
+    pub fn compact_table(&self, definition: impl TableHandle) -> Result<(), TableError> {
+        #[cfg(feature = "logging")]
+        info!("Compacting table: {}", definition.name());
+        if let Some(location) = self.open_tables.lock().unwrap().get(definition.name()) {
+            return Err(TableError::TableAlreadyOpen(
+                definition.name().to_string(),
+                location,
+            ));
+        }
+        self.dirty.store(true, Ordering::Release);
+        self.table_tree
+            .write()
+            .unwrap()
+            .compact_table(definition.name())
+    }
+
+    /// Replace the entire contents of the given table with `entries`, creating the table if it does
+    /// not exist
+    ///
+    /// A new tree is bulk loaded from the entries, filling each page as full as the page size
+    /// allows, and the pages of the old tree are freed. The replacement becomes visible when this
+    /// transaction commits, and read transactions which began earlier keep seeing the old contents,
+    /// whose pages are only reused once they are dropped. The entries need not be sorted, although
+    /// sorted entries are cheapest to load. If a key occurs more than once, its last value is kept.
+    /// The table may not be open in this transaction
+    pub fn replace_table<'a, K: RedbKey + 'static, V: RedbValue + 'static, KR, VR>(
+        &self,
+        definition: TableDefinition<K, V>,
+        entries: impl IntoIterator<Item = (KR, VR)>,
+    ) -> Result<(), TableError>
+    where
+        KR: Borrow<K::SelfType<'a>>,
+        VR: Borrow<V::SelfType<'a>>,
+    {
+        #[cfg(feature = "logging")]
+        info!("Replacing table: {}", definition);
+        if let Some(location) = self.open_tables.lock().unwrap().get(definition.name()) {
+            return Err(TableError::TableAlreadyOpen(
+                definition.name().to_string(),
+                location,
+            ));
+        }
+
+        let key_width = definition.key_width();
+        let mut pairs = vec![];
+        for (key, value) in entries {
+            let key = K::as_bytes(key.borrow()).as_ref().to_vec();
+            let value = V::as_bytes(value.borrow()).as_ref().to_vec();
+            check_entry_size(self.mem, key_width, key.len(), value.len())?;
+            pairs.push((key, value));
+        }
+        // The sort is stable, so the values of a duplicated key stay in the order given
+        pairs.sort_by(|a, b| K::compare(&a.0, &b.0));
+        let mut entries: Vec<(Vec<u8>, Vec<u8>)> = Vec::with_capacity(pairs.len());
+        for pair in pairs {
+            match entries.last_mut() {
+                Some(last) if K::compare(&last.0, &pair.0).is_eq() => *last = pair,
+                _ => entries.push(pair),
+            }
+        }
+
+        self.dirty.store(true, Ordering::Release);
+        let mut table_tree = self.table_tree.write().unwrap();
+        let removed = table_tree.count_table_entries(definition.name(), TableType::Normal)?;
+        if self.tracks_changes() {
+            // The old keys must be read before their pages are freed. A key which is also in
+            // entries is then reported as updated
+            for key in table_tree.table_keys::<K, V>(definition.name(), key_width)? {
+                self.record_change(definition.name(), &key, true, false);
+            }
+        }
+        table_tree.replace_table::<K, V>(definition.name(), key_width, &entries)?;
+        self.record_removed_entries(removed);
+        self.record_inserted_entries(entries.len() as u64);
+        for (key, _) in entries.iter() {
+            self.record_change(definition.name(), key, false, true);
+        }
+
+        Ok(())
     }
 
     /// List all the tables
@@ -717,25 +1160,242 @@ impl<'db> WriteTransaction<'db> {
     /// Commit the transaction
     ///
     /// All writes performed in this transaction will be visible to future transactions, and are
-    /// durable as consistent with the [`Durability`] level set by [`Self::set_durability`]
-    pub fn commit(mut self) -> Result<(), CommitError> {
+    /// durable as consistent with the [`Durability`] level set by [`Self::set_durability`].
+    /// If [`Builder::set_auto_compaction_threshold`](crate::Builder::set_auto_compaction_threshold)
+    /// is set, a commit which crosses the threshold also compacts the database before returning
+    pub fn commit(self) -> Result<(), CommitError> {
+        self.commit_with_stats()?;
+        Ok(())
+    }
+
+    /// Commit the transaction, as with [`Self::commit`], and return the number of pages it
+    /// allocated and freed
+    pub fn commit_with_stats(mut self) -> Result<TransactionStats, CommitError> {
+        self.check_transaction_epoch()?;
+        // A page this transaction freed wasn't scrubbed. Fail before the commit starts, so that the
+        // transaction is aborted on drop
+        self.mem.take_deferred_free_error()?;
         // Set completed flag first, so that we don't go through the abort() path on drop, if this fails
         self.completed = true;
-        self.commit_inner()
+        self.store_commit_metadata(None)?;
+        self.commit_and_maybe_compact()
     }
 
-    fn commit_inner(&mut self) -> Result<(), CommitError> {
+    /// Commit the transaction, and attach `metadata` to the commit
+    ///
+    /// The metadata is opaque to redb, and can be used to record things such as a logical timestamp
+    /// or replication sequence number. It is returned by [`ReadTransaction::commit_metadata`] for
+    /// read transactions which observe this commit, and is cleared by the next commit unless that
+    /// commit also attaches metadata
+    ///
+    /// The metadata may be at most 64 bytes long. Longer metadata returns
+    /// [`StorageError::ValueTooLarge`], and the transaction is aborted
+    pub fn commit_with_metadata(mut self, metadata: &[u8]) -> Result<(), CommitError> {
+        if metadata.len() > MAX_COMMIT_METADATA_LENGTH {
+            return Err(StorageError::ValueTooLarge(metadata.len()).into());
+        }
+        self.check_transaction_epoch()?;
+        // A page this transaction freed wasn't scrubbed. Fail before the commit starts, so that the
+        // transaction is aborted on drop
+        self.mem.take_deferred_free_error()?;
+        // Set completed flag first, so that we don't go through the abort() path on drop, if this fails
+        self.completed = true;
+        self.store_commit_metadata(Some(metadata))?;
+        self.commit_and_maybe_compact()?;
+        Ok(())
+    }
+
+    // The transaction is aborted on drop, if its id is not in the configured epoch
+    fn check_transaction_epoch(&self) -> Result {
+        match self.db.transaction_epoch() {
+            Some(epoch) if self.transaction_id.epoch() != epoch => {
+                Err(StorageError::TransactionEpochMismatch {
+                    epoch,
+                    transaction_id: self.transaction_id.0,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Compute the [`TransactionStats`] which [`Self::commit_with_stats`] would return, and then
+    /// abort the transaction
+    ///
+    /// The commit is prepared as usual, including storing the list of freed pages, but the
+    /// database is left unchanged. Pages freed by earlier transactions, which a real commit might
+    /// also release, are not processed, so the stats can differ slightly if any are pending
+    pub fn commit_dry_run(mut self) -> Result<TransactionStats, CommitError> {
+        // Set completed flag first, so that we don't go through the abort() path on drop
+        self.completed = true;
+        let stats = self
+            .store_commit_metadata(None)
+            .map_err(CommitError::from)
+            .and_then(|()| self.dry_run_commit_inner());
+        self.abort_inner()?;
+        stats
+    }
+
+    fn dry_run_commit_inner(&mut self) -> Result<TransactionStats, CommitError> {
+        self.mem.lift_transaction_page_limit();
+        self.table_tree
+            .write()
+            .unwrap()
+            .flush_table_root_updates()?;
+        self.system_table_tree
+            .write()
+            .unwrap()
+            .flush_table_root_updates()?;
+
+        // Matches the handling of the pages freed from the freed-tree by each kind of commit
+        let include_post_commit_free = matches!(self.durability, Durability::None)
+            || self
+                .transaction_tracker
+                .lock()
+                .unwrap()
+                .any_savepoint_exists();
+        let (freed, allocated_and_freed) = self.count_freed_pages();
+        self.store_freed_pages(include_post_commit_free)?;
+        self.freed_tree.lock().unwrap().finalize_dirty_checksums()?;
+
+        Ok(TransactionStats {
+            allocated: self.mem.uncommitted_page_count() - allocated_and_freed,
+            freed,
+            durability_degraded: false,
+        })
+    }
+
+    fn commit_and_maybe_compact(mut self) -> Result<TransactionStats, CommitError> {
+        let db = self.db;
+        let durable = !matches!(self.durability, Durability::None);
+        let stats = self.commit_inner()?;
+        // Release the write lock, since compaction runs its own write transactions
+        drop(self);
+        if durable {
+            db.maybe_auto_compact();
+        }
+        Ok(stats)
+    }
+
+    fn store_commit_metadata(&self, metadata: Option<&[u8]>) -> Result {
+        if let Some(metadata) = metadata {
+            let mut table = self.open_system_table(COMMIT_METADATA_TABLE)?;
+            table.insert((), metadata)?;
+        } else {
+            // Avoid creating the table, if no commit has ever attached metadata
+            let existing = self
+                .system_table_tree
+                .read()
+                .unwrap()
+                .get_table::<(), &[u8]>(COMMIT_METADATA_TABLE.name(), TableType::Normal)
+                .map_err(|e| {
+                    e.into_storage_error_or_corrupted("Internal error. System table is corrupted")
+                })?;
+            if existing.map_or(false, |definition| definition.get_root().is_some()) {
+                let mut table = self.open_system_table(COMMIT_METADATA_TABLE)?;
+                table.remove(())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // True if committing would leave the database unchanged: no table root changed, and no page was
+    // allocated or freed. Nor is there an earlier non-durable commit for it to make durable, pages
+    // freed by earlier commits for it to release, or surplus file space for it to trim. Flushes the
+    // pending updates to the table roots
+    fn is_noop(&self) -> Result<bool> {
+        let user_root = self
+            .table_tree
+            .write()
+            .unwrap()
+            .flush_table_root_updates()?;
+        let system_root = self
+            .system_table_tree
+            .write()
+            .unwrap()
+            .flush_table_root_updates()?;
+        Ok(self.mem.uncommitted_page_count() == 0
+            && self.freed_pages.lock().unwrap().is_empty()
+            && self.post_commit_frees.lock().unwrap().is_empty()
+            && user_root == self.mem.get_data_root()
+            && system_root == self.mem.get_system_root()
+            && self.freed_tree.lock().unwrap().get_root() == self.mem.get_freed_root()
+            && !self
+                .transaction_tracker
+                .lock()
+                .unwrap()
+                .any_pending_non_durable_commit()
+            && !self.any_freed_pages_to_process()?
+            && !self.mem.can_shrink())
+    }
+
+    // True if process_freed_pages() would release any pages
+    fn any_freed_pages_to_process(&self) -> Result<bool> {
+        let oldest_live_read = self
+            .transaction_tracker
+            .lock()
+            .unwrap()
+            .oldest_live_read_transaction()
+            .unwrap_or(self.transaction_id);
+        let lookup_key = FreedTableKey {
+            transaction_id: oldest_live_read.0,
+            pagination_id: 0,
+        };
+        let freed_tree = self.freed_tree.lock().unwrap();
+        let mut iter = freed_tree.range(&(..lookup_key))?;
+        Ok(iter.next().transpose()?.is_some())
+    }
+
+    fn commit_inner(&mut self) -> Result<TransactionStats, CommitError> {
         #[cfg(feature = "logging")]
         info!(
             "Committing transaction id={:?} with durability={:?}",
             self.transaction_id, self.durability
         );
-        match self.durability {
-            Durability::None => self.non_durable_commit()?,
-            Durability::Eventual => self.durable_commit(true, false)?,
-            Durability::Immediate => self.durable_commit(false, false)?,
-            Durability::Paranoid => self.durable_commit(false, true)?,
+        self.mem.lift_transaction_page_limit();
+        if self.is_noop()? {
+            // Nothing is written, so the last commit, and its transaction id, remain the latest
+            #[cfg(feature = "logging")]
+            info!(
+                "Transaction id={:?} made no changes. Skipping commit",
+                self.transaction_id
+            );
+            self.mem.rollback_uncommitted_writes()?;
+            self.db.set_last_commit_changes(self.take_changes());
+            return Ok(TransactionStats {
+                allocated: 0,
+                freed: 0,
+                durability_degraded: false,
+            });
         }
+        let paranoid = matches!(self.durability, Durability::Paranoid);
+        let degraded = matches!(
+            self.durability,
+            Durability::Eventual | Durability::Immediate | Durability::Paranoid
+        ) && self.mem.exceeds_commit_latency_budget(paranoid);
+        let stats = if degraded {
+            #[cfg(feature = "logging")]
+            warn!(
+                "Sync of transaction id={:?} is expected to exceed the commit latency budget. Deferring it",
+                self.transaction_id
+            );
+            // The commit is written like a durable one, so it survives a process crash, and the
+            // sync thread makes it durable shortly after
+            TransactionStats {
+                durability_degraded: true,
+                ..self.durable_commit(false, paranoid, false)?
+            }
+        } else {
+            match self.durability {
+                Durability::None => self.non_durable_commit()?,
+                Durability::NoSync => self.durable_commit(false, false, false)?,
+                Durability::Eventual => self.durable_commit(true, false, true)?,
+                Durability::Immediate => self.durable_commit(false, false, true)?,
+                Durability::Paranoid => self.durable_commit(false, true, true)?,
+            }
+        };
+        self.db.set_last_commit_changes(self.take_changes());
+        self.db.notify_commit();
 
         for (savepoint, transaction) in self.deleted_persistent_savepoints.lock().unwrap().iter() {
             self.transaction_tracker
@@ -750,12 +1410,14 @@ impl<'db> WriteTransaction<'db> {
             self.transaction_id
         );
 
-        Ok(())
+        Ok(stats)
     }
 
     /// Abort the transaction
     ///
-    /// All writes performed in this transaction will be rolled back
+    /// All writes performed in this transaction will be rolled back, and the pages it allocated
+    /// are returned to the allocator. This may be called after an operation in this transaction
+    /// has returned an error. Once it returns, the next call to [`Database::begin_write()`] can proceed
     pub fn abort(mut self) -> Result {
         // Set completed flag first, so that we don't go through the abort() path on drop, if this fails
         self.completed = true;
@@ -785,7 +1447,12 @@ impl<'db> WriteTransaction<'db> {
         Ok(())
     }
 
-    pub(crate) fn durable_commit(&mut self, eventual: bool, two_phase: bool) -> Result {
+    pub(crate) fn durable_commit(
+        &mut self,
+        eventual: bool,
+        two_phase: bool,
+        fsync: bool,
+    ) -> Result<TransactionStats> {
         let oldest_live_read = self
             .transaction_tracker
             .lock()
@@ -815,6 +1482,7 @@ impl<'db> WriteTransaction<'db> {
             .lock()
             .unwrap()
             .any_savepoint_exists();
+        let (freed, allocated_and_freed) = self.count_freed_pages();
         self.store_freed_pages(savepoint_exists)?;
 
         // Finalize freed table checksums, before doing the final commit
@@ -822,14 +1490,18 @@ impl<'db> WriteTransaction<'db> {
         self.freed_tree.lock().unwrap().finalize_dirty_checksums()?;
 
         let freed_root = self.freed_tree.lock().unwrap().get_root();
+        let entry_count = self.final_entry_count()?;
+        let allocated = self.mem.uncommitted_page_count() - allocated_and_freed;
 
         self.mem.commit(
             user_root,
             system_root,
             freed_root,
             self.transaction_id,
+            Some(entry_count),
             eventual,
             two_phase,
+            fsync,
         )?;
 
         // Mark any pending non-durable commits as fully committed.
@@ -841,14 +1513,18 @@ impl<'db> WriteTransaction<'db> {
         // Immediately free the pages that were freed from the freed-tree itself. These are only
         // accessed by write transactions, so it's safe to free them as soon as the commit is done.
         for page in self.post_commit_frees.lock().unwrap().drain(..) {
-            self.mem.free(page);
+            self.mem.free(page)?;
         }
 
-        Ok(())
+        Ok(TransactionStats {
+            allocated,
+            freed,
+            durability_degraded: false,
+        })
     }
 
     // Commit without a durability guarantee
-    pub(crate) fn non_durable_commit(&mut self) -> Result {
+    pub(crate) fn non_durable_commit(&mut self) -> Result<TransactionStats> {
         let user_root = self
             .table_tree
             .write()
@@ -863,22 +1539,44 @@ impl<'db> WriteTransaction<'db> {
 
         // Store all freed pages for a future commit(), since we can't free pages during a
         // non-durable commit (it's non-durable, so could be rolled back anytime in the future)
+        let (freed, allocated_and_freed) = self.count_freed_pages();
         self.store_freed_pages(true)?;
 
         // Finalize all checksums, before doing the final commit
         self.freed_tree.lock().unwrap().finalize_dirty_checksums()?;
 
         let freed_root = self.freed_tree.lock().unwrap().get_root();
+        let entry_count = self.final_entry_count()?;
+        let allocated = self.mem.uncommitted_page_count() - allocated_and_freed;
 
-        self.mem
-            .non_durable_commit(user_root, system_root, freed_root, self.transaction_id)?;
+        self.mem.non_durable_commit(
+            user_root,
+            system_root,
+            freed_root,
+            self.transaction_id,
+            Some(entry_count),
+        )?;
         // Register this as a non-durable transaction to ensure that the freed pages we just pushed
         // are only processed after this has been persisted
         self.transaction_tracker
             .lock()
             .unwrap()
             .register_non_durable_commit(self.transaction_id);
-        Ok(())
+        Ok(TransactionStats {
+            allocated,
+            freed,
+            durability_degraded: false,
+        })
+    }
+
+    // The number of entries to persist in the commit slot. Walks all the tables, if it is not
+    // known because the file was written by an older version, or a savepoint was restored
+    fn final_entry_count(&self) -> Result<u64> {
+        let mut entry_count = self.entry_count.lock().unwrap();
+        if entry_count.is_none() {
+            *entry_count = Some(self.table_tree.read().unwrap().count_entries()?);
+        }
+        Ok(entry_count.unwrap())
     }
 
     // Relocate pages to lower number regions/pages
@@ -916,7 +1614,7 @@ impl<'db> WriteTransaction<'db> {
             to_remove.push(entry.key());
             let value = entry.value();
             for i in 0..value.len() {
-                self.mem.free(value.get(i));
+                self.mem.free(value.get(i))?;
             }
         }
 
@@ -928,6 +1626,18 @@ impl<'db> WriteTransaction<'db> {
         Ok(())
     }
 
+    // Returns the number of pages freed by this transaction which were written by earlier
+    // transactions, and the number which it allocated itself. Must be called before
+    // store_freed_pages(), which may add the pages freed from the freed-tree
+    fn count_freed_pages(&self) -> (usize, usize) {
+        let freed_pages = self.freed_pages.lock().unwrap();
+        let allocated_and_freed = freed_pages
+            .iter()
+            .filter(|&&page| self.mem.uncommitted(page))
+            .count();
+        (freed_pages.len() - allocated_and_freed, allocated_and_freed)
+    }
+
     fn store_freed_pages(&mut self, include_post_commit_free: bool) -> Result {
         assert_eq!(PageNumber::serialized_size(), 8); // We assume below that PageNumber is length 8
 
@@ -948,8 +1658,9 @@ impl<'db> WriteTransaction<'db> {
                 transaction_id: self.transaction_id.0,
                 pagination_id: pagination_counter,
             };
-            let mut access_guard =
-                freed_tree.insert_reserve(&key, buffer_size.try_into().unwrap())?;
+            let mut access_guard = freed_tree
+                .insert_reserve(&key, buffer_size.try_into().unwrap())?
+                .0;
 
             let mut freed_pages = self.freed_pages.lock().unwrap();
             let len = freed_pages.len();
@@ -1029,12 +1740,19 @@ impl<'a> Drop for WriteTransaction<'a> {
 
 /// A read-only transaction
 ///
-/// Read-only transactions may exist concurrently with writes
+/// Read-only transactions may exist concurrently with writes, and provide snapshot isolation: the
+/// transaction sees the database as of the last commit before it began, for its entire lifetime.
+/// Pages freed by later commits are not reused while it is alive, so its view is never modified
 pub struct ReadTransaction<'a> {
     transaction_tracker: Arc<Mutex<TransactionTracker>>,
     mem: &'a TransactionalMemory,
     tree: TableTree<'a>,
+    root: Option<(PageNumber, Checksum)>,
+    system_root: Option<(PageNumber, Checksum)>,
     transaction_id: TransactionId,
+    entry_count: Option<u64>,
+    commit_timestamp: Option<HybridTimestamp>,
+    non_durable: bool,
 }
 
 impl<'db> ReadTransaction<'db> {
@@ -1043,15 +1761,87 @@ impl<'db> ReadTransaction<'db> {
         transaction_tracker: Arc<Mutex<TransactionTracker>>,
         transaction_id: TransactionId,
     ) -> Self {
-        let root_page = mem.get_data_root();
+        let (root_page, system_root, entry_count, commit_timestamp, non_durable) =
+            mem.get_read_snapshot();
         Self {
             transaction_tracker,
             mem,
             tree: TableTree::new(root_page, mem, Default::default()),
+            root: root_page,
+            system_root,
             transaction_id,
+            entry_count,
+            commit_timestamp,
+            non_durable,
         }
     }
 
+    // Reads the snapshot of a persistent savepoint. The snapshot is registered as a live read, so
+    // its pages are not freed while this transaction exists, even if the savepoint is deleted
+    // Returns None if the savepoint is no longer valid
+    pub(crate) fn at_savepoint(
+        mem: &'db TransactionalMemory,
+        transaction_tracker: Arc<Mutex<TransactionTracker>>,
+        savepoint: &Savepoint,
+    ) -> Option<Self> {
+        let transaction_id = savepoint.get_transaction_id();
+        if !transaction_tracker
+            .lock()
+            .unwrap()
+            .register_savepoint_read(savepoint)
+        {
+            return None;
+        }
+        let root = savepoint.get_user_root();
+        Some(Self {
+            transaction_tracker,
+            mem,
+            tree: TableTree::new(root, mem, Default::default()),
+            root,
+            // The savepoint does not record the system tree, or the details of the commit
+            system_root: None,
+            transaction_id,
+            entry_count: None,
+            commit_timestamp: None,
+            non_durable: false,
+        })
+    }
+
+    // Returns the root of the given table, for iterators which must not borrow the transaction
+    pub(crate) fn table_root<K: RedbKey + 'static, V: RedbValue + 'static>(
+        &self,
+        definition: TableDefinition<K, V>,
+    ) -> Result<Option<(PageNumber, Checksum)>, TableError> {
+        let header = self
+            .tree
+            .get_table_with_key_width::<K, V>(
+                definition.name(),
+                TableType::Normal,
+                definition.key_width(),
+            )?
+            .ok_or_else(|| TableError::TableDoesNotExist(definition.name().to_string()))?;
+
+        Ok(header.get_root())
+    }
+
+    /// Returns `true` if this transaction is reading the state of a [`Durability::None`] commit
+    ///
+    /// Such a commit has been written to the file, but not fsync'ed, and will be lost if the
+    /// process or operating system crashes before a later durable commit. If `false`, this
+    /// transaction is reading the most recent durable commit: it has been fsync'ed, unless it
+    /// was made with [`Durability::Eventual`] or [`Durability::NoSync`], in which case it has been
+    /// handed to the operating system and will be persisted some time later
+    ///
+    /// The value is fixed when the transaction begins
+    pub fn is_reading_non_durable(&self) -> bool {
+        self.non_durable
+    }
+
+    /// Returns the id of the commit which this transaction reads
+    pub fn transaction_id(&self) -> u64 {
+        self.transaction_id.0
+    }
+
     /// Open the given table
     pub fn open_table<K: RedbKey + 'static, V: RedbValue + 'static>(
         &self,
@@ -1059,16 +1849,29 @@ impl<'db> ReadTransaction<'db> {
     ) -> Result<ReadOnlyTable<K, V>, TableError> {
         let header = self
             .tree
-            .get_table::<K, V>(definition.name(), TableType::Normal)?
+            .get_table_with_key_width::<K, V>(
+                definition.name(),
+                TableType::Normal,
+                definition.key_width(),
+            )?
             .ok_or_else(|| TableError::TableDoesNotExist(definition.name().to_string()))?;
 
         Ok(ReadOnlyTable::new(
             header.get_root(),
+            header.get_fixed_key_size(),
             PageHint::Clean,
             self.mem,
         )?)
     }
 
+    // The fixed key width stored in the catalog for the named table, if the table exists
+    pub(crate) fn stored_key_width(&self, name: &str) -> Result<Option<Option<usize>>, TableError> {
+        Ok(self
+            .tree
+            .get_table_untyped(name, TableType::Normal)?
+            .map(|definition| definition.get_fixed_key_size()))
+    }
+
     /// Open the given table
     pub fn open_multimap_table<K: RedbKey + 'static, V: RedbKey + 'static>(
         &self,
@@ -1099,6 +1902,125 @@ impl<'db> ReadTransaction<'db> {
             .list_tables(TableType::Multimap)
             .map(|x| x.into_iter().map(UntypedMultimapTableHandle::new))
     }
+
+    /// Lists all the tables, including multimap tables, with their types and sizes
+    ///
+    /// Note: this reads every page of every table, and is intended for occasional analysis
+    pub fn list_table_info(&self) -> Result<Vec<TableInfo>> {
+        self.tree.table_info()
+    }
+
+    /// Returns the number of pages used by the table of tables, which records the name, type and
+    /// root of every table. These pages are not included in the [`TableInfo::page_count`] of any
+    /// table
+    pub fn catalog_page_count(&self) -> Result<u64> {
+        self.tree.catalog_page_count()
+    }
+
+    /// Returns the total number of key-value pairs in all tables, including multimap tables
+    ///
+    /// This is read from the commit record, so is cheap to call. Databases last written by a version
+    /// of redb which did not record it fall back to counting the entries of every table
+    pub fn entry_count(&self) -> Result<u64> {
+        if let Some(entry_count) = self.entry_count {
+            Ok(entry_count)
+        } else {
+            self.tree.count_entries()
+        }
+    }
+
+    /// Returns the timestamp of the commit which this transaction reads from
+    ///
+    /// Every commit records a [`HybridTimestamp`], which is strictly greater than that of the
+    /// commit before it. Returns `None` for a database which has never been written to, or which
+    /// was last written by a version of redb which did not record timestamps
+    pub fn commit_timestamp(&self) -> Option<HybridTimestamp> {
+        self.commit_timestamp
+    }
+
+    /// Returns the metadata attached to the commit which this transaction reads from, if it was
+    /// committed with [`WriteTransaction::commit_with_metadata`]
+    pub fn commit_metadata(&self) -> Result<Option<Vec<u8>>> {
+        let system_tree = TableTree::new(self.system_root, self.mem, Default::default());
+        let definition = system_tree
+            .get_table::<(), &[u8]>(COMMIT_METADATA_TABLE.name(), TableType::Normal)
+            .map_err(|e| e.into_storage_error_or_corrupted("Commit metadata table corrupted"))?;
+        if let Some(definition) = definition {
+            let table: ReadOnlyTable<(), &[u8]> = ReadOnlyTable::new(
+                definition.get_root(),
+                definition.get_fixed_key_size(),
+                PageHint::Clean,
+                self.mem,
+            )?;
+            let metadata = table.get(())?.map(|x| x.value().to_vec());
+            Ok(metadata)
+        } else {
+            Ok(None)
+        }
+    }
+
+    // Returns the persistent savepoint with the given id, if it exists in this snapshot
+    pub(crate) fn persistent_savepoint(&self, id: u64) -> Result<Option<Savepoint>> {
+        let system_tree = TableTree::new(self.system_root, self.mem, Default::default());
+        let definition = system_tree
+            .get_table::<SavepointId, SerializedSavepoint>(
+                SAVEPOINT_TABLE.name(),
+                TableType::Normal,
+            )
+            .map_err(|e| e.into_storage_error_or_corrupted("Savepoint table corrupted"))?;
+        if let Some(definition) = definition {
+            let table: ReadOnlyTable<SavepointId, SerializedSavepoint> = ReadOnlyTable::new(
+                definition.get_root(),
+                definition.get_fixed_key_size(),
+                PageHint::Clean,
+                self.mem,
+            )?;
+            let savepoint = table
+                .get(SavepointId(id))?
+                .map(|x| x.value().to_savepoint(self.transaction_tracker.clone()));
+            Ok(savepoint)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Retrieves a breakdown of the space used by the tables in this snapshot of the database
+    ///
+    /// Note: this reads every page of every table, and is intended for occasional analysis
+    pub fn space_usage(&self) -> Result<SpaceUsage> {
+        self.tree.space_usage()
+    }
+
+    /// Returns the page number of the root of the table of tables in this snapshot, or `None` if
+    /// no table has been created
+    ///
+    /// This is intended for tools which inspect the file format, such as visualizers, and is
+    /// encoded the same way as [`TransactionSlotDump::user_root`](crate::TransactionSlotDump::user_root).
+    /// The page number is only valid while this transaction is alive: once it is dropped, the page
+    /// may be freed and overwritten by a later write transaction
+    pub fn raw_root(&self) -> Option<u64> {
+        self.root
+            .map(|(page_number, _)| u64::from_le_bytes(page_number.to_le_bytes()))
+    }
+
+    /// Returns a copy of the page with the given number, such as one returned by
+    /// [`Self::raw_root`] or one referenced by a branch page reachable from it
+    ///
+    /// The contents are returned as stored, without verifying their checksum, and can not be
+    /// modified through this transaction. Only pages reachable from [`Self::raw_root`] are
+    /// guaranteed to belong to this snapshot. Returns an error if the page number is not a valid
+    /// page of the database, for example because it is past the end of the database
+    pub fn read_raw_page(&self, page_number: u64) -> Result<Vec<u8>> {
+        let page = PageNumber::from_le_bytes(page_number.to_le_bytes());
+        // Bits which are not part of the encoding must be clear
+        if u64::from_le_bytes(page.to_le_bytes()) != page_number {
+            return Err(StorageError::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{page_number:#x} is not a page number"),
+            )));
+        }
+        self.mem.read_page_direct(page)
+    }
 }
 
 impl<'a> Drop for ReadTransaction<'a> {
@@ -1133,4 +2055,24 @@ mod test {
         let write_txn = db2.begin_write().unwrap();
         assert!(write_txn.transaction_id > first_txn_id);
     }
+
+    #[test]
+    fn entry_count_underflow() {
+        let tmpfile = crate::create_tempfile();
+        let db = Database::create(tmpfile.path()).unwrap();
+        let write_txn = db.begin_write().unwrap();
+        {
+            let mut table = write_txn.open_table(X).unwrap();
+            table.insert("hello", "world").unwrap();
+            table.insert("hello2", "world2").unwrap();
+        }
+        write_txn.commit().unwrap();
+
+        let write_txn = db.begin_write().unwrap();
+        write_txn.record_removed_entries(3);
+        write_txn.commit().unwrap();
+
+        let read_txn = db.begin_read().unwrap();
+        assert_eq!(read_txn.entry_count().unwrap(), 2);
+    }
 }
@@ -1,6 +1,6 @@
 use crate::tree_store::btree_base::{
-    branch_checksum, leaf_checksum, BranchAccessor, BranchMutator, Checksum, FreePolicy,
-    LeafAccessor, BRANCH, DEFERRED, LEAF,
+    branch_checksum, leaf_checksum, BranchAccessor, BranchBuilder, BranchMutator, Checksum,
+    FreePolicy, LeafAccessor, LeafBuilder, RawBranchBuilder, BRANCH, DEFERRED, LEAF,
 };
 use crate::tree_store::btree_iters::BtreeDrain;
 use crate::tree_store::btree_mutator::MutateHelper;
@@ -9,14 +9,36 @@ use crate::tree_store::{
     AccessGuardMut, AllPageNumbersBtreeIter, BtreeDrainFilter, BtreeRangeIter, PageHint, PageNumber,
 };
 use crate::types::{RedbKey, RedbValue, RedbValueMutInPlace};
-use crate::{AccessGuard, Result};
+use crate::{AccessGuard, CorruptionFinding, Result};
 #[cfg(feature = "logging")]
 use log::trace;
 use std::borrow::Borrow;
 use std::cmp::max;
 use std::marker::PhantomData;
-use std::ops::{RangeBounds, RangeFull};
+use std::ops::{Bound, RangeBounds, RangeFull};
 use std::sync::{Arc, Mutex};
+use std::thread;
+
+// Serialized keys of the entries removed by a drain
+type RemovedKeys = Vec<Vec<u8>>;
+
+fn key_bound<'a, K: RedbKey + 'a, KR: Borrow<K::SelfType<'a>>>(
+    bound: Bound<&KR>,
+) -> Bound<Vec<u8>> {
+    match bound {
+        Bound::Included(key) => Bound::Included(K::as_bytes(key.borrow()).as_ref().to_vec()),
+        Bound::Excluded(key) => Bound::Excluded(K::as_bytes(key.borrow()).as_ref().to_vec()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+fn as_slice_bound(bound: &Bound<Vec<u8>>) -> Bound<&[u8]> {
+    match bound {
+        Bound::Included(key) => Bound::Included(key),
+        Bound::Excluded(key) => Bound::Excluded(key),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
 
 pub(crate) struct BtreeStats {
     pub(crate) tree_height: u32,
@@ -25,6 +47,12 @@ pub(crate) struct BtreeStats {
     pub(crate) stored_leaf_bytes: u64,
     pub(crate) metadata_bytes: u64,
     pub(crate) fragmented_bytes: u64,
+    // Total size of all the leaf pages
+    pub(crate) leaf_page_bytes: u64,
+    // Total size of all the branch pages
+    pub(crate) branch_page_bytes: u64,
+    // The portion of metadata_bytes which is stored in branch pages
+    pub(crate) branch_metadata_bytes: u64,
 }
 
 pub(crate) struct UntypedBtreeMut<'a> {
@@ -182,7 +210,7 @@ impl<'a> UntypedBtreeMut<'a> {
         let new_page_number = new_page.get_page_number();
         if !new_page_number.is_before(page_number) {
             drop(new_page);
-            self.mem.free(new_page_number);
+            self.mem.free(new_page_number)?;
             return Ok(None);
         }
 
@@ -207,16 +235,218 @@ impl<'a> UntypedBtreeMut<'a> {
         }
 
         let mut freed_pages = self.freed_pages.lock().unwrap();
-        FreePolicy::Uncommitted.conditional_free(page_number, &mut freed_pages, self.mem);
+        FreePolicy::Uncommitted.conditional_free(page_number, &mut freed_pages, self.mem)?;
 
         Ok(Some((new_page_number, DEFERRED)))
     }
+
+    // Rebuilds the btree bottom-up from its current contents, filling each page as full as the
+    // page size allows, and frees the pages of the old tree
+    pub(crate) fn rebuild_dense(&mut self) -> Result {
+        let old_root = match self.get_root() {
+            Some((page_number, _)) => page_number,
+            None => return Ok(()),
+        };
+        let mut leaves = vec![];
+        self.collect_leaves(old_root, &mut leaves)?;
+
+        let mut builder = DenseTreeBuilder::new(self.mem, self.key_width, self.value_width);
+        for leaf in leaves {
+            let page = self.mem.get_page(leaf)?;
+            let accessor = LeafAccessor::new(page.memory(), self.key_width, self.value_width);
+            for i in 0..accessor.num_pairs() {
+                let entry = accessor.entry(i).unwrap();
+                builder.push(entry.key(), entry.value())?;
+            }
+        }
+        let new_root = builder.finish()?;
+        self.free_tree(old_root)?;
+        *self.root.lock().unwrap() = new_root.map(|page_number| (page_number, DEFERRED));
+
+        Ok(())
+    }
+
+    // Replaces the contents of the btree with a dense tree of the given entries, which must be in
+    // key order without duplicates, and frees the pages of the old tree
+    pub(crate) fn replace_dense<'e>(
+        &mut self,
+        entries: impl Iterator<Item = (&'e [u8], &'e [u8])>,
+    ) -> Result {
+        let mut builder = DenseTreeBuilder::new(self.mem, self.key_width, self.value_width);
+        for (key, value) in entries {
+            builder.push(key, value)?;
+        }
+        let new_root = builder.finish()?;
+        if let Some((old_root, _)) = self.get_root() {
+            self.free_tree(old_root)?;
+        }
+        *self.root.lock().unwrap() = new_root.map(|page_number| (page_number, DEFERRED));
+
+        Ok(())
+    }
+
+    // Frees every page of the tree under the given root. Committed pages are only released once
+    // no reader can see them
+    fn free_tree(&self, root: PageNumber) -> Result {
+        let old_pages =
+            AllPageNumbersBtreeIter::new(root, self.key_width, self.value_width, self.mem)?
+                .collect::<Result<Vec<_>>>()?;
+        let mut freed_pages = self.freed_pages.lock().unwrap();
+        for page_number in old_pages {
+            FreePolicy::Uncommitted.conditional_free(page_number, &mut freed_pages, self.mem)?;
+        }
+
+        Ok(())
+    }
+
+    // Appends the leaves under the given page, in key order
+    fn collect_leaves(&self, page_number: PageNumber, leaves: &mut Vec<PageNumber>) -> Result {
+        let page = self.mem.get_page(page_number)?;
+        match page.memory()[0] {
+            LEAF => {
+                leaves.push(page_number);
+            }
+            BRANCH => {
+                let accessor = BranchAccessor::new(&page, self.key_width);
+                for i in 0..accessor.count_children() {
+                    self.collect_leaves(accessor.child_page(i).unwrap(), leaves)?;
+                }
+            }
+            _ => unreachable!(),
+        }
+
+        Ok(())
+    }
+}
+
+// Builds a btree bottom-up from entries pushed in key order, filling each page as full as the page
+// size allows
+struct DenseTreeBuilder<'a> {
+    mem: &'a TransactionalMemory,
+    key_width: Option<usize>,
+    value_width: Option<usize>,
+    // Each leaf built so far, along with the last key stored in it
+    leaves: Vec<(PageNumber, Vec<u8>)>,
+    // Entries of the leaf being filled
+    pairs: Vec<(Vec<u8>, Vec<u8>)>,
+    pair_bytes: usize,
+}
+
+impl<'a> DenseTreeBuilder<'a> {
+    fn new(
+        mem: &'a TransactionalMemory,
+        key_width: Option<usize>,
+        value_width: Option<usize>,
+    ) -> Self {
+        Self {
+            mem,
+            key_width,
+            value_width,
+            leaves: vec![],
+            pairs: vec![],
+            pair_bytes: 0,
+        }
+    }
+
+    fn push(&mut self, key: &[u8], value: &[u8]) -> Result {
+        let len = key.len() + value.len();
+        if !self.pairs.is_empty()
+            && LeafBuilder::required_bytes(self.pairs.len() + 1, self.pair_bytes + len)
+                > self.mem.get_page_size()
+        {
+            let leaf = self.build_leaf()?;
+            self.leaves.push(leaf);
+            self.pairs.clear();
+            self.pair_bytes = 0;
+        }
+        self.pairs.push((key.to_vec(), value.to_vec()));
+        self.pair_bytes += len;
+
+        Ok(())
+    }
+
+    // Returns the root of the tree, or None if no entries were pushed
+    fn finish(mut self) -> Result<Option<PageNumber>> {
+        if !self.pairs.is_empty() {
+            let leaf = self.build_leaf()?;
+            self.leaves.push(leaf);
+        }
+
+        let page_size = self.mem.get_page_size();
+        // Each node of the level being built, along with the last key stored under it
+        let mut level = self.leaves;
+        while level.len() > 1 {
+            let mut next_level = vec![];
+            let mut start = 0;
+            while start < level.len() {
+                // Every branch gets at least two children
+                let mut end = start + 1;
+                let mut key_bytes = 0;
+                while end < level.len() {
+                    let key_len = level[end - 1].1.len();
+                    if end - start >= 2
+                        && RawBranchBuilder::required_bytes(
+                            end - start,
+                            key_bytes + key_len,
+                            self.key_width,
+                        ) > page_size
+                    {
+                        break;
+                    }
+                    key_bytes += key_len;
+                    end += 1;
+                }
+                // Don't leave a single child behind for the last branch. Hand it one of this
+                // branch's children instead, so that neither branch grows past the page size,
+                // unless this branch has only two children to begin with
+                if level.len() - end == 1 {
+                    if end - start > 2 {
+                        end -= 1;
+                    } else {
+                        end += 1;
+                    }
+                }
+
+                let children = &level[start..end];
+                let mut builder = BranchBuilder::new(self.mem, children.len(), self.key_width);
+                for (child, _) in children {
+                    builder.push_child(*child, DEFERRED);
+                }
+                for (_, last_key) in &children[..children.len() - 1] {
+                    builder.push_key(last_key);
+                }
+                let page = builder.build()?;
+                next_level.push((
+                    page.get_page_number(),
+                    children[children.len() - 1].1.clone(),
+                ));
+                start = end;
+            }
+            level = next_level;
+        }
+
+        Ok(level.pop().map(|(page_number, _)| page_number))
+    }
+
+    fn build_leaf(&self) -> Result<(PageNumber, Vec<u8>)> {
+        let mut builder =
+            LeafBuilder::new(self.mem, self.pairs.len(), self.key_width, self.value_width);
+        for (key, value) in &self.pairs {
+            builder.push(key, value);
+        }
+        let page = builder.build()?;
+        Ok((
+            page.get_page_number(),
+            self.pairs[self.pairs.len() - 1].0.clone(),
+        ))
+    }
 }
 
 pub(crate) struct BtreeMut<'a, K: RedbKey, V: RedbValue> {
     mem: &'a TransactionalMemory,
     root: Arc<Mutex<Option<(PageNumber, Checksum)>>>,
     freed_pages: Arc<Mutex<Vec<PageNumber>>>,
+    key_width: Option<usize>,
     _key_type: PhantomData<K>,
     _value_type: PhantomData<V>,
 }
@@ -231,17 +461,28 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> BtreeMut<'a, K, V> {
             mem,
             root: Arc::new(Mutex::new(root)),
             freed_pages,
+            key_width: K::fixed_width(),
             _key_type: Default::default(),
             _value_type: Default::default(),
         }
     }
 
+    // For a table whose keys were declared to have a fixed width, which K does not
+    pub(crate) fn with_key_width(mut self, key_width: Option<usize>) -> Self {
+        self.key_width = key_width;
+        self
+    }
+
+    pub(crate) fn key_width(&self) -> Option<usize> {
+        self.key_width
+    }
+
     pub(crate) fn finalize_dirty_checksums(&mut self) -> Result {
         let mut tree = UntypedBtreeMut::new(
             self.get_root(),
             self.mem,
             self.freed_pages.clone(),
-            K::fixed_width(),
+            self.key_width,
             V::fixed_width(),
         );
         tree.finalize_dirty_checksums()?;
@@ -253,7 +494,7 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> BtreeMut<'a, K, V> {
         if let Some((root, _)) = *self.root.lock().unwrap() {
             Ok(Some(AllPageNumbersBtreeIter::new(
                 root,
-                K::fixed_width(),
+                self.key_width,
                 V::fixed_width(),
                 self.mem,
             )?))
@@ -271,7 +512,7 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> BtreeMut<'a, K, V> {
             self.get_root(),
             self.mem,
             self.freed_pages.clone(),
-            K::fixed_width(),
+            self.key_width,
             V::fixed_width(),
         );
         if tree.relocate()? {
@@ -301,11 +542,62 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> BtreeMut<'a, K, V> {
             FreePolicy::Uncommitted,
             self.mem,
             freed_pages.as_mut(),
+            self.key_width,
         );
         let (old_value, _) = operation.insert(key, value)?;
         Ok(old_value)
     }
 
+    // Returns the value of `key`, if it is present. Otherwise, inserts the value produced by
+    // `value`, and returns None
+    pub(crate) fn insert_if_absent(
+        &mut self,
+        key: &K::SelfType<'_>,
+        value: &mut dyn FnMut() -> Result<Vec<u8>>,
+    ) -> Result<Option<AccessGuard<V>>> {
+        #[cfg(feature = "logging")]
+        trace!(
+            "Btree(root={:?}): Inserting {:?} if absent",
+            &self.root,
+            key
+        );
+        let mut freed_pages = self.freed_pages.lock().unwrap();
+        let mut root = self.root.lock().unwrap();
+        let mut operation: MutateHelper<'_, '_, K, V> = MutateHelper::new(
+            &mut root,
+            FreePolicy::Uncommitted,
+            self.mem,
+            freed_pages.as_mut(),
+            self.key_width,
+        );
+        operation.insert_if_absent(key, value)
+    }
+
+    // Replaces the value of `key`, if it is present, with the value which `value` produces from
+    // it, and returns the old value
+    pub(crate) fn replace_if_present(
+        &mut self,
+        key: &K::SelfType<'_>,
+        value: &mut dyn FnMut(&[u8]) -> Result<Vec<u8>>,
+    ) -> Result<Option<AccessGuard<V>>> {
+        #[cfg(feature = "logging")]
+        trace!(
+            "Btree(root={:?}): Replacing {:?} if present",
+            &self.root,
+            key
+        );
+        let mut freed_pages = self.freed_pages.lock().unwrap();
+        let mut root = self.root.lock().unwrap();
+        let mut operation: MutateHelper<'_, '_, K, V> = MutateHelper::new(
+            &mut root,
+            FreePolicy::Uncommitted,
+            self.mem,
+            freed_pages.as_mut(),
+            self.key_width,
+        );
+        operation.replace_if_present(key, value)
+    }
+
     pub(crate) fn remove(&mut self, key: &K::SelfType<'_>) -> Result<Option<AccessGuard<V>>> {
         #[cfg(feature = "logging")]
         trace!("Btree(root={:?}): Deleting {:?}", &self.root, key);
@@ -316,6 +608,7 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> BtreeMut<'a, K, V> {
             FreePolicy::Uncommitted,
             self.mem,
             freed_pages.as_mut(),
+            self.key_width,
         );
         let result = operation.delete(key)?;
         Ok(result)
@@ -328,8 +621,13 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> BtreeMut<'a, K, V> {
     ) -> Result<Option<(AccessGuard<V>, Vec<PageNumber>)>> {
         let mut freed_pages = vec![];
         let mut root = self.root.lock().unwrap();
-        let mut operation: MutateHelper<'_, '_, K, V> =
-            MutateHelper::new(&mut root, FreePolicy::Never, self.mem, &mut freed_pages);
+        let mut operation: MutateHelper<'_, '_, K, V> = MutateHelper::new(
+            &mut root,
+            FreePolicy::Never,
+            self.mem,
+            &mut freed_pages,
+            self.key_width,
+        );
         let result = operation.safe_delete(key)?;
         Ok(result.map(|x| (x, freed_pages)))
     }
@@ -343,19 +641,26 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> BtreeMut<'a, K, V> {
         btree_stats(
             self.get_root().map(|(p, _)| p),
             self.mem,
-            K::fixed_width(),
+            self.key_width,
             V::fixed_width(),
         )
     }
 
     fn read_tree(&self) -> Result<Btree<'a, K, V>> {
-        Btree::new(self.get_root(), PageHint::None, self.mem)
+        Ok(Btree::new(self.get_root(), PageHint::None, self.mem)?.with_key_width(self.key_width))
     }
 
     pub(crate) fn get(&self, key: &K::SelfType<'_>) -> Result<Option<AccessGuard<'_, V>>> {
         self.read_tree()?.get(key)
     }
 
+    pub(crate) fn get_many(
+        &self,
+        keys: &[K::SelfType<'_>],
+    ) -> Result<Vec<Option<AccessGuard<'_, V>>>> {
+        self.read_tree()?.get_many(keys)
+    }
+
     pub(crate) fn range<'a0, T: RangeBounds<KR> + 'a0, KR: Borrow<K::SelfType<'a0>> + 'a0>(
         &self,
         range: &'_ T,
@@ -366,10 +671,12 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> BtreeMut<'a, K, V> {
         self.read_tree()?.range(range)
     }
 
+    // Returns the number of entries removed, and also records their keys in removed_keys, if given
     pub(crate) fn drain<'a0, T: RangeBounds<KR> + 'a0, KR: Borrow<K::SelfType<'a0>> + 'a0>(
         &mut self,
         range: &'_ T,
-    ) -> Result<BtreeDrain<'a, K, V>>
+        mut removed_keys: Option<&mut RemovedKeys>,
+    ) -> Result<(BtreeDrain<'a, K, V>, u64)>
     where
         K: 'a0,
     {
@@ -377,11 +684,22 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> BtreeMut<'a, K, V> {
         let return_iter = self.range(range)?;
         let mut free_on_drop = vec![];
         let mut root = self.root.lock().unwrap();
-        let mut operation: MutateHelper<'_, '_, K, V> =
-            MutateHelper::new(&mut root, FreePolicy::Never, self.mem, &mut free_on_drop);
+        let mut operation: MutateHelper<'_, '_, K, V> = MutateHelper::new(
+            &mut root,
+            FreePolicy::Never,
+            self.mem,
+            &mut free_on_drop,
+            self.key_width,
+        );
+        let mut removed = 0;
         for entry in iter {
             // TODO: optimize so that we don't have to call safe_delete in a loop
-            assert!(operation.safe_delete(&entry?.key())?.is_some());
+            let entry = entry?;
+            assert!(operation.safe_delete(&entry.key())?.is_some());
+            removed += 1;
+            if let Some(keys) = removed_keys.as_mut() {
+                keys.push(entry.key_data());
+            }
         }
 
         let result = BtreeDrain::new(
@@ -391,7 +709,30 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> BtreeMut<'a, K, V> {
             self.mem,
         );
 
-        Ok(result)
+        Ok((result, removed))
+    }
+
+    // Returns the number of entries removed, and also records their keys in removed_keys, if given
+    pub(crate) fn delete_range<'a0, T: RangeBounds<KR> + 'a0, KR: Borrow<K::SelfType<'a0>> + 'a0>(
+        &mut self,
+        range: &'_ T,
+        removed_keys: Option<&mut RemovedKeys>,
+    ) -> Result<u64>
+    where
+        K: 'a0,
+    {
+        let start = key_bound::<K, KR>(range.start_bound());
+        let end = key_bound::<K, KR>(range.end_bound());
+        let mut root = self.root.lock().unwrap();
+        let mut freed_pages = self.freed_pages.lock().unwrap();
+        let mut operation: MutateHelper<'_, '_, K, V> = MutateHelper::new(
+            &mut root,
+            FreePolicy::Uncommitted,
+            self.mem,
+            freed_pages.as_mut(),
+            self.key_width,
+        );
+        operation.delete_range(as_slice_bound(&start), as_slice_bound(&end), removed_keys)
     }
 
     pub(crate) fn drain_filter<
@@ -403,7 +744,8 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> BtreeMut<'a, K, V> {
         &mut self,
         range: &'_ T,
         predicate: F,
-    ) -> Result<BtreeDrainFilter<'a, K, V, F>>
+        mut removed_keys: Option<&mut RemovedKeys>,
+    ) -> Result<(BtreeDrainFilter<'a, K, V, F>, u64)>
     where
         K: 'a0,
     {
@@ -411,13 +753,23 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> BtreeMut<'a, K, V> {
         let return_iter = self.range(range)?;
         let mut free_on_drop = vec![];
         let mut root = self.root.lock().unwrap();
-        let mut operation: MutateHelper<'_, '_, K, V> =
-            MutateHelper::new(&mut root, FreePolicy::Never, self.mem, &mut free_on_drop);
+        let mut operation: MutateHelper<'_, '_, K, V> = MutateHelper::new(
+            &mut root,
+            FreePolicy::Never,
+            self.mem,
+            &mut free_on_drop,
+            self.key_width,
+        );
+        let mut removed = 0;
         for entry in iter {
             // TODO: optimize so that we don't have to call safe_delete in a loop
             let entry = entry?;
             if predicate(entry.key(), entry.value()) {
                 assert!(operation.safe_delete(&entry.key())?.is_some());
+                removed += 1;
+                if let Some(keys) = removed_keys.as_mut() {
+                    keys.push(entry.key_data());
+                }
             }
         }
 
@@ -429,23 +781,52 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> BtreeMut<'a, K, V> {
             self.mem,
         );
 
-        Ok(result)
+        Ok((result, removed))
     }
 
     pub(crate) fn len(&self) -> Result<u64> {
         self.read_tree()?.len()
     }
+
+    pub(crate) fn estimate_range_len<
+        'a0,
+        T: RangeBounds<KR> + 'a0,
+        KR: Borrow<K::SelfType<'a0>> + 'a0,
+    >(
+        &self,
+        range: &'_ T,
+    ) -> Result<u64>
+    where
+        K: 'a0,
+    {
+        self.read_tree()?.estimate_range_len(range)
+    }
+
+    pub(crate) fn prefetch_range<
+        'a0,
+        T: RangeBounds<KR> + 'a0,
+        KR: Borrow<K::SelfType<'a0>> + 'a0,
+    >(
+        &self,
+        range: &'_ T,
+    ) -> Result<u64>
+    where
+        K: 'a0,
+    {
+        self.read_tree()?.prefetch_range(range)
+    }
 }
 
 impl<'a, K: RedbKey + 'a, V: RedbValueMutInPlace + 'a> BtreeMut<'a, K, V> {
     /// Reserve space to insert a key-value pair
     /// The returned reference will have length equal to value_length
     // Return type has the same lifetime as &self, because the tree must not be modified until the mutable guard is dropped
+    // Also returns whether the key was already present
     pub(crate) fn insert_reserve(
         &mut self,
         key: &K::SelfType<'_>,
         value_length: u32,
-    ) -> Result<AccessGuardMut<V>> {
+    ) -> Result<(AccessGuardMut<V>, bool)> {
         #[cfg(feature = "logging")]
         trace!(
             "Btree(root={:?}): Inserting {:?} with {} reserved bytes for the value",
@@ -462,10 +843,13 @@ impl<'a, K: RedbKey + 'a, V: RedbValueMutInPlace + 'a> BtreeMut<'a, K, V> {
             FreePolicy::Uncommitted,
             self.mem,
             freed_pages.as_mut(),
+            self.key_width,
         );
-        let (_, guard) = operation.insert(key, &V::from_bytes(&value))?;
+        let (old_value, guard) = operation.insert(key, &V::from_bytes(&value))?;
+        let existed = old_value.is_some();
+        drop(old_value);
         drop(root);
-        Ok(guard)
+        Ok((guard, existed))
     }
 }
 
@@ -491,12 +875,56 @@ impl<'a> RawBtree<'a> {
         }
     }
 
-    pub(crate) fn verify_checksum(&self) -> Result<bool> {
-        if let Some((root, checksum)) = self.root {
-            self.verify_checksum_helper(root, checksum)
-        } else {
-            Ok(true)
+    // The subtrees below the root are divided between up to `threads` threads. The result does not
+    // depend on the number of threads
+    pub(crate) fn verify_checksum(&self, threads: usize) -> Result<bool> {
+        let (root, checksum) = match self.root {
+            Some(root) => root,
+            None => return Ok(true),
+        };
+        let page = self.mem.get_page(root)?;
+        // A damaged root is left to the serial walk, which may restore it from the mirror
+        if threads <= 1 || page.memory()[0] != BRANCH || !self.page_checksum_valid(&page, checksum)
+        {
+            drop(page);
+            return self.verify_checksum_helper(root, checksum);
         }
+        let accessor = BranchAccessor::new(&page, self.fixed_key_size);
+        let children: Vec<(PageNumber, Checksum)> = (0..accessor.count_children())
+            .map(|i| {
+                (
+                    accessor.child_page(i).unwrap(),
+                    accessor.child_checksum(i).unwrap(),
+                )
+            })
+            .collect();
+        drop(page);
+
+        let chunk_size = (children.len() + threads - 1) / threads;
+        thread::scope(|scope| {
+            let handles: Vec<_> = children
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        for &(child, child_checksum) in chunk {
+                            if !self.verify_checksum_helper(child, child_checksum)? {
+                                return Ok(false);
+                            }
+                        }
+                        Ok(true)
+                    })
+                })
+                .collect();
+            // Merge in order, so that the result is the same as that of the serial walk
+            let mut result = Ok(true);
+            for handle in handles {
+                let chunk_result = handle.join().unwrap();
+                if matches!(result, Ok(true)) {
+                    result = chunk_result;
+                }
+            }
+            result
+        })
     }
 
     fn verify_checksum_helper(
@@ -504,17 +932,21 @@ impl<'a> RawBtree<'a> {
         page_number: PageNumber,
         expected_checksum: Checksum,
     ) -> Result<bool> {
-        let page = self.mem.get_page(page_number)?;
-        let node_mem = page.memory();
-        Ok(match node_mem[0] {
-            LEAF => {
-                expected_checksum
-                    == leaf_checksum(&page, self.fixed_key_size, self.fixed_value_size)
+        let mut page = self.mem.get_page(page_number)?;
+        if !self.page_checksum_valid(&page, expected_checksum) {
+            drop(page);
+            // The copy of the page in the mirror may be intact
+            if !self.mem.restore_page_from_mirror(page_number)? {
+                return Ok(false);
+            }
+            page = self.mem.get_page(page_number)?;
+            if !self.page_checksum_valid(&page, expected_checksum) {
+                return Ok(false);
             }
+        }
+        Ok(match page.memory()[0] {
+            LEAF => true,
             BRANCH => {
-                if expected_checksum != branch_checksum(&page, self.fixed_key_size) {
-                    return Ok(false);
-                }
                 let accessor = BranchAccessor::new(&page, self.fixed_key_size);
                 for i in 0..accessor.count_children() {
                     if !self.verify_checksum_helper(
@@ -526,9 +958,94 @@ impl<'a> RawBtree<'a> {
                 }
                 true
             }
-            _ => unreachable!(),
+            // A corrupted page type
+            _ => false,
         })
     }
+
+    fn page_checksum_valid(&self, page: &PageImpl, expected_checksum: Checksum) -> bool {
+        match page.memory()[0] {
+            LEAF => {
+                expected_checksum == leaf_checksum(page, self.fixed_key_size, self.fixed_value_size)
+            }
+            BRANCH => expected_checksum == branch_checksum(page, self.fixed_key_size),
+            // A corrupted page type
+            _ => false,
+        }
+    }
+
+    // Same as verify_checksum(), but each damaged page is recorded in `findings`, instead of
+    // stopping at the first one. Pages below a damaged page are skipped, since its child pointers
+    // can't be trusted. The leaves which pass verification are appended to `leaves`.
+    // Returns true if the whole tree is intact
+    pub(crate) fn find_corruption(
+        &self,
+        findings: &mut Vec<CorruptionFinding>,
+        leaves: &mut Vec<PageNumber>,
+    ) -> Result<bool> {
+        if let Some((root, checksum)) = self.root {
+            self.find_corruption_helper(root, checksum, findings, leaves)
+        } else {
+            Ok(true)
+        }
+    }
+
+    fn find_corruption_helper(
+        &self,
+        page_number: PageNumber,
+        expected_checksum: Checksum,
+        findings: &mut Vec<CorruptionFinding>,
+        leaves: &mut Vec<PageNumber>,
+    ) -> Result<bool> {
+        let raw = |page: PageNumber| u64::from_le_bytes(page.to_le_bytes());
+        let page = self.mem.get_page(page_number)?;
+        match page.memory()[0] {
+            LEAF => {
+                if expected_checksum
+                    != leaf_checksum(&page, self.fixed_key_size, self.fixed_value_size)
+                {
+                    findings.push(CorruptionFinding::ChecksumMismatch {
+                        page: raw(page_number),
+                    });
+                    return Ok(false);
+                }
+                leaves.push(page_number);
+                Ok(true)
+            }
+            BRANCH => {
+                if expected_checksum != branch_checksum(&page, self.fixed_key_size) {
+                    findings.push(CorruptionFinding::ChecksumMismatch {
+                        page: raw(page_number),
+                    });
+                    return Ok(false);
+                }
+                let accessor = BranchAccessor::new(&page, self.fixed_key_size);
+                let mut intact = true;
+                for i in 0..accessor.count_children() {
+                    let child = accessor.child_page(i).unwrap();
+                    if !self.mem.contains_page(child) {
+                        findings.push(CorruptionFinding::DanglingChildPointer {
+                            page: raw(page_number),
+                            child: raw(child),
+                        });
+                        intact = false;
+                        continue;
+                    }
+                    let child_checksum = accessor.child_checksum(i).unwrap();
+                    if !self.find_corruption_helper(child, child_checksum, findings, leaves)? {
+                        intact = false;
+                    }
+                }
+                Ok(intact)
+            }
+            _ => {
+                findings.push(CorruptionFinding::InvalidPageType {
+                    page: raw(page_number),
+                });
+                Ok(false)
+            }
+        }
+    }
 }
 
 pub(crate) struct Btree<'a, K: RedbKey, V: RedbValue> {
@@ -537,6 +1054,7 @@ pub(crate) struct Btree<'a, K: RedbKey, V: RedbValue> {
     cached_root: Option<PageImpl<'a>>,
     root: Option<(PageNumber, Checksum)>,
     hint: PageHint,
+    key_width: Option<usize>,
     _key_type: PhantomData<K>,
     _value_type: PhantomData<V>,
 }
@@ -557,11 +1075,18 @@ impl<'a, K: RedbKey, V: RedbValue> Btree<'a, K, V> {
             cached_root,
             root,
             hint,
+            key_width: K::fixed_width(),
             _key_type: Default::default(),
             _value_type: Default::default(),
         })
     }
 
+    // For a table whose keys were declared to have a fixed width, which K does not
+    pub(crate) fn with_key_width(mut self, key_width: Option<usize>) -> Self {
+        self.key_width = key_width;
+        self
+    }
+
     pub(crate) fn get(&self, key: &K::SelfType<'_>) -> Result<Option<AccessGuard<'a, V>>> {
         if let Some(ref root_page) = self.cached_root {
             self.get_helper(root_page.clone(), K::as_bytes(key).as_ref())
@@ -570,12 +1095,92 @@ impl<'a, K: RedbKey, V: RedbValue> Btree<'a, K, V> {
         }
     }
 
+    // Looks up all of the keys in a single walk of the tree. The keys are visited in sorted order, so
+    // each page on the paths to them is read only once, and the results are returned in the order
+    // of `keys`
+    pub(crate) fn get_many(
+        &self,
+        keys: &[K::SelfType<'_>],
+    ) -> Result<Vec<Option<AccessGuard<'a, V>>>> {
+        let mut results: Vec<Option<AccessGuard<'a, V>>> = keys.iter().map(|_| None).collect();
+        if let Some(ref root_page) = self.cached_root {
+            let key_bytes: Vec<_> = keys.iter().map(|key| K::as_bytes(key)).collect();
+            let mut queries: Vec<(usize, &[u8])> = key_bytes
+                .iter()
+                .enumerate()
+                .map(|(i, key)| (i, key.as_ref()))
+                .collect();
+            queries.sort_by(|(_, a), (_, b)| K::compare(a, b));
+            self.get_many_helper(root_page.clone(), &queries, &mut results)?;
+        }
+        Ok(results)
+    }
+
+    // `queries` must be sorted by key. Stores the value of each query in `results`, at the index
+    // which is paired with its key
+    fn get_many_helper(
+        &self,
+        page: PageImpl<'a>,
+        queries: &[(usize, &[u8])],
+        results: &mut [Option<AccessGuard<'a, V>>],
+    ) -> Result {
+        let node_mem = page.memory();
+        match node_mem[0] {
+            LEAF => {
+                let accessor = LeafAccessor::new(page.memory(), self.key_width, V::fixed_width());
+                let mut previous: Option<(&[u8], Option<usize>)> = None;
+                for &(result_index, query) in queries {
+                    // Duplicate keys are adjacent, so each distinct key is only searched for once
+                    let entry_index = match previous {
+                        Some((key, entry_index)) if K::compare(key, query).is_eq() => entry_index,
+                        _ => accessor.find_key::<K>(query),
+                    };
+                    previous = Some((query, entry_index));
+                    if let Some(entry_index) = entry_index {
+                        let (start, end) = accessor.value_range(entry_index).unwrap();
+                        // Safety: free_on_drop is false
+                        results[result_index] = Some(AccessGuard::new(
+                            page.clone(),
+                            start,
+                            end - start,
+                            false,
+                            self.mem,
+                        ));
+                    }
+                }
+            }
+            BRANCH => {
+                let accessor = BranchAccessor::new(&page, self.key_width);
+                let mut start = 0;
+                while start < queries.len() {
+                    // Descend once for each run of queries which fall in the same child
+                    let (child_index, child_page) = accessor.child_for_key::<K>(queries[start].1);
+                    let mut end = start + 1;
+                    while end < queries.len()
+                        && accessor.child_for_key::<K>(queries[end].1).0 == child_index
+                    {
+                        end += 1;
+                    }
+                    self.get_many_helper(
+                        self.mem.get_page_extended(child_page, self.hint)?,
+                        &queries[start..end],
+                        results,
+                    )?;
+                    start = end;
+                }
+            }
+            _ => unreachable!(),
+        }
+
+        Ok(())
+    }
+
     // Returns the value for the queried key, if present
     fn get_helper(&self, page: PageImpl<'a>, query: &[u8]) -> Result<Option<AccessGuard<'a, V>>> {
         let node_mem = page.memory();
         match node_mem[0] {
             LEAF => {
-                let accessor = LeafAccessor::new(page.memory(), K::fixed_width(), V::fixed_width());
+                let accessor = LeafAccessor::new(page.memory(), self.key_width, V::fixed_width());
                 if let Some(entry_index) = accessor.find_key::<K>(query) {
                     let (start, end) = accessor.value_range(entry_index).unwrap();
                     // Safety: free_on_drop is false
@@ -586,7 +1191,7 @@ impl<'a, K: RedbKey, V: RedbValue> Btree<'a, K, V> {
                 }
             }
             BRANCH => {
-                let accessor = BranchAccessor::new(&page, K::fixed_width());
+                let accessor = BranchAccessor::new(&page, self.key_width);
                 let (_, child_page) = accessor.child_for_key::<K>(query);
                 self.get_helper(self.mem.get_page_extended(child_page, self.hint)?, query)
             }
@@ -601,15 +1206,17 @@ impl<'a, K: RedbKey, V: RedbValue> Btree<'a, K, V> {
     where
         K: 'a0,
     {
-        BtreeRangeIter::new(range, self.root.map(|(p, _)| p), self.mem)
+        BtreeRangeIter::with_key_width(range, self.root.map(|(p, _)| p), self.key_width, self.mem)
     }
 
     pub(crate) fn len(&self) -> Result<u64> {
-        let iter: BtreeRangeIter<K, V> = BtreeRangeIter::new::<RangeFull, K::SelfType<'_>>(
-            &(..),
-            self.root.map(|(p, _)| p),
-            self.mem,
-        )?;
+        let iter: BtreeRangeIter<K, V> =
+            BtreeRangeIter::with_key_width::<RangeFull, K::SelfType<'_>>(
+                &(..),
+                self.root.map(|(p, _)| p),
+                self.key_width,
+                self.mem,
+            )?;
         let mut count = 0;
         for v in iter {
             v?;
@@ -618,6 +1225,140 @@ impl<'a, K: RedbKey, V: RedbValue> Btree<'a, K, V> {
         Ok(count)
     }
 
+    // Estimates the number of entries in the range, by descending only to its two ends. Entries in
+    // the leaves at the ends are counted exactly, and every subtree between them is assumed to hold
+    // as many entries as one sampled subtree of the same level
+    pub(crate) fn estimate_range_len<
+        'a0,
+        T: RangeBounds<KR> + 'a0,
+        KR: Borrow<K::SelfType<'a0>> + 'a0,
+    >(
+        &self,
+        range: &'_ T,
+    ) -> Result<u64>
+    where
+        K: 'a0,
+    {
+        let root = if let Some((p, _)) = self.root {
+            p
+        } else {
+            return Ok(0);
+        };
+        // An unbounded end is replaced with the first or last key, so that the pages along that
+        // edge of the tree are counted exactly, rather than estimated
+        let (start, start_inclusive) = match range.start_bound() {
+            Bound::Included(k) => (K::as_bytes(k.borrow()).as_ref().to_vec(), true),
+            Bound::Excluded(k) => (K::as_bytes(k.borrow()).as_ref().to_vec(), false),
+            Bound::Unbounded => (self.edge_key(root, false)?, true),
+        };
+        let (end, end_inclusive) = match range.end_bound() {
+            Bound::Included(k) => (K::as_bytes(k.borrow()).as_ref().to_vec(), true),
+            Bound::Excluded(k) => (K::as_bytes(k.borrow()).as_ref().to_vec(), false),
+            Bound::Unbounded => (self.edge_key(root, true)?, true),
+        };
+        estimate_range_len_helper::<K>(
+            root,
+            self.mem,
+            self.key_width,
+            V::fixed_width(),
+            Some((&start, start_inclusive)),
+            Some((&end, end_inclusive)),
+        )
+    }
+
+    // Reads every page on the paths to the entries in the range, so that they are in the read
+    // cache. Returns the number of pages read
+    pub(crate) fn prefetch_range<
+        'a0,
+        T: RangeBounds<KR> + 'a0,
+        KR: Borrow<K::SelfType<'a0>> + 'a0,
+    >(
+        &self,
+        range: &'_ T,
+    ) -> Result<u64>
+    where
+        K: 'a0,
+    {
+        let root_page = if let Some(ref root_page) = self.cached_root {
+            root_page.clone()
+        } else {
+            return Ok(0);
+        };
+        // Whether a bound is inclusive doesn't matter, since the child holding the bound key is
+        // read either way
+        let start = match range.start_bound() {
+            Bound::Included(k) | Bound::Excluded(k) => Some(K::as_bytes(k.borrow())),
+            Bound::Unbounded => None,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(k) | Bound::Excluded(k) => Some(K::as_bytes(k.borrow())),
+            Bound::Unbounded => None,
+        };
+        self.prefetch_helper(
+            root_page,
+            start.as_ref().map(|k| k.as_ref()),
+            end.as_ref().map(|k| k.as_ref()),
+        )
+    }
+
+    fn prefetch_helper(
+        &self,
+        page: PageImpl<'a>,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+    ) -> Result<u64> {
+        match page.memory()[0] {
+            LEAF => Ok(1),
+            BRANCH => {
+                let accessor = BranchAccessor::new(&page, self.key_width);
+                let first = start.map_or(0, |key| accessor.child_for_key::<K>(key).0);
+                let last = end.map_or(accessor.count_children() - 1, |key| {
+                    accessor.child_for_key::<K>(key).0
+                });
+                let mut pages = 1;
+                for i in first..=last {
+                    let child = self
+                        .mem
+                        .get_page_extended(accessor.child_page(i).unwrap(), self.hint)?;
+                    // Only the children at the two ends of the range can hold keys outside it
+                    pages += self.prefetch_helper(
+                        child,
+                        if i == first { start } else { None },
+                        if i == last { end } else { None },
+                    )?;
+                }
+                Ok(pages)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // Returns the first, or last, key in the tree
+    fn edge_key(&self, root: PageNumber, last: bool) -> Result<Vec<u8>> {
+        let mut page = self.mem.get_page(root)?;
+        loop {
+            match page.memory()[0] {
+                LEAF => {
+                    let accessor =
+                        LeafAccessor::new(page.memory(), self.key_width, V::fixed_width());
+                    let index = if last { accessor.num_pairs() - 1 } else { 0 };
+                    return Ok(accessor.entry(index).unwrap().key().to_vec());
+                }
+                BRANCH => {
+                    let accessor = BranchAccessor::new(&page, self.key_width);
+                    let index = if last {
+                        accessor.count_children() - 1
+                    } else {
+                        0
+                    };
+                    let child = accessor.child_page(index).unwrap();
+                    page = self.mem.get_page(child)?;
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
     #[allow(dead_code)]
     pub(crate) fn print_debug(&self, include_values: bool) -> Result {
         if let Some((p, _)) = self.root {
@@ -629,12 +1370,12 @@ impl<'a, K: RedbKey, V: RedbValue> Btree<'a, K, V> {
                     match node_mem[0] {
                         LEAF => {
                             eprint!("Leaf[ (page={:?})", page.get_page_number());
-                            LeafAccessor::new(page.memory(), K::fixed_width(), V::fixed_width())
+                            LeafAccessor::new(page.memory(), self.key_width, V::fixed_width())
                                 .print_node::<K, V>(include_values);
                             eprint!("]");
                         }
                         BRANCH => {
-                            let accessor = BranchAccessor::new(&page, K::fixed_width());
+                            let accessor = BranchAccessor::new(&page, self.key_width);
                             for i in 0..accessor.count_children() {
                                 let child = accessor.child_page(i).unwrap();
                                 next_children.push(self.mem.get_page(child)?);
@@ -671,6 +1412,9 @@ pub(crate) fn btree_stats(
             stored_leaf_bytes: 0,
             metadata_bytes: 0,
             fragmented_bytes: 0,
+            leaf_page_bytes: 0,
+            branch_page_bytes: 0,
+            branch_metadata_bytes: 0,
         })
     }
 }
@@ -696,6 +1440,9 @@ fn stats_helper(
                 stored_leaf_bytes: leaf_bytes.try_into().unwrap(),
                 metadata_bytes: overhead_bytes.try_into().unwrap(),
                 fragmented_bytes,
+                leaf_page_bytes: page.memory().len() as u64,
+                branch_page_bytes: 0,
+                branch_metadata_bytes: 0,
             })
         }
         BRANCH => {
@@ -706,6 +1453,9 @@ fn stats_helper(
             let mut stored_leaf_bytes = 0;
             let mut metadata_bytes = accessor.total_length() as u64;
             let mut fragmented_bytes = (page.memory().len() - accessor.total_length()) as u64;
+            let mut leaf_page_bytes = 0;
+            let mut branch_page_bytes = page.memory().len() as u64;
+            let mut branch_metadata_bytes = accessor.total_length() as u64;
             for i in 0..accessor.count_children() {
                 if let Some(child) = accessor.child_page(i) {
                     let stats = stats_helper(child, mem, fixed_key_size, fixed_value_size)?;
@@ -715,6 +1465,9 @@ fn stats_helper(
                     stored_leaf_bytes += stats.stored_leaf_bytes;
                     metadata_bytes += stats.metadata_bytes;
                     fragmented_bytes += stats.fragmented_bytes;
+                    leaf_page_bytes += stats.leaf_page_bytes;
+                    branch_page_bytes += stats.branch_page_bytes;
+                    branch_metadata_bytes += stats.branch_metadata_bytes;
                 }
             }
 
@@ -725,8 +1478,168 @@ fn stats_helper(
                 stored_leaf_bytes,
                 metadata_bytes,
                 fragmented_bytes,
+                leaf_page_bytes,
+                branch_page_bytes,
+                branch_metadata_bytes,
             })
         }
         _ => unreachable!(),
     }
 }
+
+// Count the key-value pairs in the tree by walking its pages, without decoding any entries
+pub(crate) fn btree_len(
+    root: Option<PageNumber>,
+    mem: &TransactionalMemory,
+    fixed_key_size: Option<usize>,
+    fixed_value_size: Option<usize>,
+) -> Result<u64> {
+    if let Some(root) = root {
+        len_helper(root, mem, fixed_key_size, fixed_value_size)
+    } else {
+        Ok(0)
+    }
+}
+
+// Estimates the number of entries in a subtree, assuming that the entries in each node are spread
+// evenly over its children
+fn estimate_subtree_len(
+    page_number: PageNumber,
+    mem: &TransactionalMemory,
+    fixed_key_size: Option<usize>,
+    fixed_value_size: Option<usize>,
+) -> Result<u64> {
+    let page = mem.get_page(page_number)?;
+    let node_mem = page.memory();
+    match node_mem[0] {
+        LEAF => {
+            let accessor = LeafAccessor::new(page.memory(), fixed_key_size, fixed_value_size);
+            Ok(accessor.num_pairs() as u64)
+        }
+        BRANCH => {
+            let accessor = BranchAccessor::new(&page, fixed_key_size);
+            let children = accessor.count_children();
+            let sample = accessor.child_page(children / 2).unwrap();
+            let sample_len = estimate_subtree_len(sample, mem, fixed_key_size, fixed_value_size)?;
+            Ok(children as u64 * sample_len)
+        }
+        _ => unreachable!(),
+    }
+}
+
+// `start` and `end` are the bounds of the range within the subtree, as a key and whether it is
+// included. A bound of None means that the range extends past that side of the subtree
+fn estimate_range_len_helper<K: RedbKey>(
+    page_number: PageNumber,
+    mem: &TransactionalMemory,
+    fixed_key_size: Option<usize>,
+    fixed_value_size: Option<usize>,
+    start: Option<(&[u8], bool)>,
+    end: Option<(&[u8], bool)>,
+) -> Result<u64> {
+    let page = mem.get_page(page_number)?;
+    let node_mem = page.memory();
+    match node_mem[0] {
+        LEAF => {
+            let accessor = LeafAccessor::new(page.memory(), fixed_key_size, fixed_value_size);
+            let first = match start {
+                Some((key, inclusive)) => match accessor.position::<K>(key) {
+                    (position, true) if !inclusive => position + 1,
+                    (position, _) => position,
+                },
+                None => 0,
+            };
+            let last = match end {
+                Some((key, inclusive)) => match accessor.position::<K>(key) {
+                    (position, true) if inclusive => position + 1,
+                    (position, _) => position,
+                },
+                None => accessor.num_pairs(),
+            };
+            Ok(last.saturating_sub(first) as u64)
+        }
+        BRANCH => {
+            let accessor = BranchAccessor::new(&page, fixed_key_size);
+            let first = start.map_or(0, |(key, _)| accessor.child_for_key::<K>(key).0);
+            let last = end.map_or(accessor.count_children() - 1, |(key, _)| {
+                accessor.child_for_key::<K>(key).0
+            });
+            if first == last {
+                return estimate_range_len_helper::<K>(
+                    accessor.child_page(first).unwrap(),
+                    mem,
+                    fixed_key_size,
+                    fixed_value_size,
+                    start,
+                    end,
+                );
+            }
+            if first > last {
+                return Ok(0);
+            }
+
+            let mut count = 0;
+            // Children which lie entirely within the range
+            let mut full_children = last - first - 1;
+            if start.is_some() {
+                count += estimate_range_len_helper::<K>(
+                    accessor.child_page(first).unwrap(),
+                    mem,
+                    fixed_key_size,
+                    fixed_value_size,
+                    start,
+                    None,
+                )?;
+            } else {
+                full_children += 1;
+            }
+            if end.is_some() {
+                count += estimate_range_len_helper::<K>(
+                    accessor.child_page(last).unwrap(),
+                    mem,
+                    fixed_key_size,
+                    fixed_value_size,
+                    None,
+                    end,
+                )?;
+            } else {
+                full_children += 1;
+            }
+            if full_children > 0 {
+                let sample = accessor.child_page((first + last) / 2).unwrap();
+                let sample_len =
+                    estimate_subtree_len(sample, mem, fixed_key_size, fixed_value_size)?;
+                count += full_children as u64 * sample_len;
+            }
+            Ok(count)
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn len_helper(
+    page_number: PageNumber,
+    mem: &TransactionalMemory,
+    fixed_key_size: Option<usize>,
+    fixed_value_size: Option<usize>,
+) -> Result<u64> {
+    let page = mem.get_page(page_number)?;
+    let node_mem = page.memory();
+    match node_mem[0] {
+        LEAF => {
+            let accessor = LeafAccessor::new(page.memory(), fixed_key_size, fixed_value_size);
+            Ok(accessor.num_pairs() as u64)
+        }
+        BRANCH => {
+            let accessor = BranchAccessor::new(&page, fixed_key_size);
+            let mut count = 0;
+            for i in 0..accessor.count_children() {
+                if let Some(child) = accessor.child_page(i) {
+                    count += len_helper(child, mem, fixed_key_size, fixed_value_size)?;
+                }
+            }
+            Ok(count)
+        }
+        _ => unreachable!(),
+    }
+}
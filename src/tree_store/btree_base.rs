@@ -49,17 +49,19 @@ impl FreePolicy {
         page: PageNumber,
         freed: &mut Vec<PageNumber>,
         mem: &TransactionalMemory,
-    ) {
+    ) -> Result {
         match self {
             FreePolicy::Never => {
                 freed.push(page);
             }
             FreePolicy::Uncommitted => {
-                if !mem.free_if_uncommitted(page) {
+                if !mem.free_if_uncommitted(page)? {
                     freed.push(page);
                 }
             }
         }
+
+        Ok(())
     }
 
     pub(crate) fn free_on_drop(&self, page: PageNumber, mem: &TransactionalMemory) -> bool {
@@ -172,7 +174,16 @@ impl<'a, V: RedbValue> AccessGuard<'a, V> {
     }
 
     pub fn value(&self) -> V::SelfType<'_> {
-        V::from_bytes(&self.page.memory()[self.offset..(self.offset + self.len)])
+        V::from_bytes(self.raw_bytes())
+    }
+
+    /// Returns the serialized value, borrowed from the page which stores it
+    ///
+    /// Nothing is copied or decoded. The page is held by this guard, so the bytes remain valid
+    /// until it is dropped, even if a later transaction overwrites or removes the value and its
+    /// page is reused
+    pub fn raw_bytes(&self) -> &[u8] {
+        &self.page.memory()[self.offset..(self.offset + self.len)]
     }
 }
 
@@ -185,7 +196,9 @@ impl<'a, V: RedbValue> Drop for AccessGuard<'a, V> {
                 let mut dummy = EitherPage::OwnedMemory(vec![]);
                 mem::swap(&mut self.page, &mut dummy);
                 drop(dummy);
-                self.mem.unwrap().free(page_number);
+                if let Err(err) = self.mem.unwrap().free(page_number) {
+                    self.mem.unwrap().defer_free_error(err);
+                }
             }
             OnDrop::RemoveEntry {
                 position,
@@ -509,14 +522,22 @@ impl<'a, 'b> LeafBuilder<'a, 'b> {
 
     pub(super) fn build_split(self) -> Result<(PageMut<'b>, &'a [u8], PageMut<'b>)> {
         let total_size = self.total_key_bytes + self.total_value_bytes;
+        // The fill target is a fraction, and page contents are far smaller than 2^52 bytes
+        #[allow(
+            clippy::cast_possible_truncation,
+            clippy::cast_sign_loss,
+            clippy::cast_precision_loss
+        )]
+        let first_page_target = (total_size as f64 * self.mem.get_node_fill_target()) as usize;
         let mut division = 0;
         let mut first_split_key_bytes = 0;
         let mut first_split_value_bytes = 0;
+        // Each page gets at least one pair
         for (key, value) in self.pairs.iter().take(self.pairs.len() - 1) {
             first_split_key_bytes += key.len();
             first_split_value_bytes += value.len();
             division += 1;
-            if first_split_key_bytes + first_split_value_bytes >= total_size / 2 {
+            if first_split_key_bytes + first_split_value_bytes >= first_page_target {
                 break;
             }
         }
@@ -1275,7 +1296,15 @@ impl<'a, 'b> BranchBuilder<'a, 'b> {
     pub(super) fn build_split(self) -> Result<(PageMut<'b>, &'a [u8], PageMut<'b>)> {
         assert_eq!(self.children.len(), self.keys.len() + 1);
         assert!(self.keys.len() >= 3);
-        let division = self.keys.len() / 2;
+        // Each page gets at least one key. The fill target is a fraction, and the number of keys is
+        // far smaller than 2^52
+        #[allow(
+            clippy::cast_possible_truncation,
+            clippy::cast_sign_loss,
+            clippy::cast_precision_loss
+        )]
+        let division = ((self.keys.len() as f64 * self.mem.get_node_fill_target()) as usize)
+            .clamp(1, self.keys.len() - 2);
         let first_split_key_len: usize = self.keys.iter().take(division).map(|k| k.len()).sum();
         let division_key = self.keys[division];
         let second_split_key_len = self.total_key_bytes - first_split_key_len - division_key.len();
@@ -294,8 +294,10 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> Drop for BtreeDrain<'a, K, V> {
 
         let mut master_free_list = self.master_free_list.lock().unwrap();
         for page in self.free_on_drop.drain(..) {
-            if !self.mem.free_if_uncommitted(page) {
-                master_free_list.push(page);
+            match self.mem.free_if_uncommitted(page) {
+                Ok(true) => {}
+                Ok(false) => master_free_list.push(page),
+                Err(err) => self.mem.defer_free_error(err),
             }
         }
     }
@@ -394,8 +396,10 @@ impl<
 
         let mut master_free_list = self.master_free_list.lock().unwrap();
         for page in self.free_on_drop.drain(..) {
-            if !self.mem.free_if_uncommitted(page) {
-                master_free_list.push(page);
+            match self.mem.free_if_uncommitted(page) {
+                Ok(true) => {}
+                Ok(false) => master_free_list.push(page),
+                Err(err) => self.mem.defer_free_error(err),
             }
         }
     }
@@ -417,6 +421,23 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> BtreeRangeIter<'a, K, V> {
         table_root: Option<PageNumber>,
         manager: &'a TransactionalMemory,
     ) -> Result<Self>
+    where
+        K: 'a0,
+    {
+        Self::with_key_width(query_range, table_root, K::fixed_width(), manager)
+    }
+
+    // For a table whose keys were declared to have a fixed width, which K does not
+    pub(crate) fn with_key_width<
+        'a0,
+        T: RangeBounds<KR> + 'a0,
+        KR: Borrow<K::SelfType<'a0>> + 'a0,
+    >(
+        query_range: &'_ T,
+        table_root: Option<PageNumber>,
+        key_width: Option<usize>,
+        manager: &'a TransactionalMemory,
+    ) -> Result<Self>
     where
         K: 'a0,
     {
@@ -425,6 +446,7 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> BtreeRangeIter<'a, K, V> {
                 Bound::Included(k) => find_iter_left::<K, V>(
                     manager.get_page(root)?,
                     None,
+                    key_width,
                     K::as_bytes(k.borrow()).as_ref(),
                     true,
                     manager,
@@ -432,13 +454,19 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> BtreeRangeIter<'a, K, V> {
                 Bound::Excluded(k) => find_iter_left::<K, V>(
                     manager.get_page(root)?,
                     None,
+                    key_width,
                     K::as_bytes(k.borrow()).as_ref(),
                     false,
                     manager,
                 )?,
                 Bound::Unbounded => {
-                    let state =
-                        find_iter_unbounded::<K, V>(manager.get_page(root)?, None, false, manager)?;
+                    let state = find_iter_unbounded::<K, V>(
+                        manager.get_page(root)?,
+                        None,
+                        key_width,
+                        false,
+                        manager,
+                    )?;
                     (true, state)
                 }
             };
@@ -446,6 +474,7 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> BtreeRangeIter<'a, K, V> {
                 Bound::Included(k) => find_iter_right::<K, V>(
                     manager.get_page(root)?,
                     None,
+                    key_width,
                     K::as_bytes(k.borrow()).as_ref(),
                     true,
                     manager,
@@ -453,13 +482,19 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> BtreeRangeIter<'a, K, V> {
                 Bound::Excluded(k) => find_iter_right::<K, V>(
                     manager.get_page(root)?,
                     None,
+                    key_width,
                     K::as_bytes(k.borrow()).as_ref(),
                     false,
                     manager,
                 )?,
                 Bound::Unbounded => {
-                    let state =
-                        find_iter_unbounded::<K, V>(manager.get_page(root)?, None, true, manager)?;
+                    let state = find_iter_unbounded::<K, V>(
+                        manager.get_page(root)?,
+                        None,
+                        key_width,
+                        true,
+                        manager,
+                    )?;
                     (true, state)
                 }
             };
@@ -623,24 +658,25 @@ impl<'a, K: RedbKey + 'a, V: RedbValue + 'a> DoubleEndedIterator for BtreeRangeI
 fn find_iter_unbounded<'a, K: RedbKey, V: RedbValue>(
     page: PageImpl<'a>,
     mut parent: Option<Box<RangeIterState<'a>>>,
+    fixed_key_size: Option<usize>,
     reverse: bool,
     manager: &'a TransactionalMemory,
 ) -> Result<Option<RangeIterState<'a>>> {
     let node_mem = page.memory();
     match node_mem[0] {
         LEAF => {
-            let accessor = LeafAccessor::new(page.memory(), K::fixed_width(), V::fixed_width());
+            let accessor = LeafAccessor::new(page.memory(), fixed_key_size, V::fixed_width());
             let entry = if reverse { accessor.num_pairs() - 1 } else { 0 };
             Ok(Some(Leaf {
                 page,
-                fixed_key_size: K::fixed_width(),
+                fixed_key_size,
                 fixed_value_size: V::fixed_width(),
                 entry,
                 parent,
             }))
         }
         BRANCH => {
-            let accessor = BranchAccessor::new(&page, K::fixed_width());
+            let accessor = BranchAccessor::new(&page, fixed_key_size);
             let child_index = if reverse {
                 accessor.count_children() - 1
             } else {
@@ -651,14 +687,14 @@ fn find_iter_unbounded<'a, K: RedbKey, V: RedbValue>(
             let direction = if reverse { -1isize } else { 1 };
             parent = Some(Box::new(Internal {
                 page,
-                fixed_key_size: K::fixed_width(),
+                fixed_key_size,
                 fixed_value_size: V::fixed_width(),
                 child: (isize::try_from(child_index).unwrap() + direction)
                     .try_into()
                     .unwrap(),
                 parent,
             }));
-            find_iter_unbounded::<K, V>(child_page, parent, reverse, manager)
+            find_iter_unbounded::<K, V>(child_page, parent, fixed_key_size, reverse, manager)
         }
         _ => unreachable!(),
     }
@@ -669,6 +705,7 @@ fn find_iter_unbounded<'a, K: RedbKey, V: RedbValue>(
 fn find_iter_left<'a, K: RedbKey, V: RedbValue>(
     page: PageImpl<'a>,
     mut parent: Option<Box<RangeIterState<'a>>>,
+    fixed_key_size: Option<usize>,
     query: &[u8],
     include_query: bool,
     manager: &'a TransactionalMemory,
@@ -676,7 +713,7 @@ fn find_iter_left<'a, K: RedbKey, V: RedbValue>(
     let node_mem = page.memory();
     match node_mem[0] {
         LEAF => {
-            let accessor = LeafAccessor::new(page.memory(), K::fixed_width(), V::fixed_width());
+            let accessor = LeafAccessor::new(page.memory(), fixed_key_size, V::fixed_width());
             let (mut position, found) = accessor.position::<K>(query);
             let include = if position < accessor.num_pairs() {
                 include_query || !found
@@ -688,7 +725,7 @@ fn find_iter_left<'a, K: RedbKey, V: RedbValue>(
             };
             let result = Leaf {
                 page,
-                fixed_key_size: K::fixed_width(),
+                fixed_key_size,
                 fixed_value_size: V::fixed_width(),
                 entry: position,
                 parent,
@@ -696,19 +733,26 @@ fn find_iter_left<'a, K: RedbKey, V: RedbValue>(
             Ok((include, Some(result)))
         }
         BRANCH => {
-            let accessor = BranchAccessor::new(&page, K::fixed_width());
+            let accessor = BranchAccessor::new(&page, fixed_key_size);
             let (child_index, child_page_number) = accessor.child_for_key::<K>(query);
             let child_page = manager.get_page(child_page_number)?;
             if child_index < accessor.count_children() - 1 {
                 parent = Some(Box::new(Internal {
                     page,
-                    fixed_key_size: K::fixed_width(),
+                    fixed_key_size,
                     fixed_value_size: V::fixed_width(),
                     child: child_index + 1,
                     parent,
                 }));
             }
-            find_iter_left::<K, V>(child_page, parent, query, include_query, manager)
+            find_iter_left::<K, V>(
+                child_page,
+                parent,
+                fixed_key_size,
+                query,
+                include_query,
+                manager,
+            )
         }
         _ => unreachable!(),
     }
@@ -717,6 +761,7 @@ fn find_iter_left<'a, K: RedbKey, V: RedbValue>(
 fn find_iter_right<'a, K: RedbKey, V: RedbValue>(
     page: PageImpl<'a>,
     mut parent: Option<Box<RangeIterState<'a>>>,
+    fixed_key_size: Option<usize>,
     query: &[u8],
     include_query: bool,
     manager: &'a TransactionalMemory,
@@ -724,7 +769,7 @@ fn find_iter_right<'a, K: RedbKey, V: RedbValue>(
     let node_mem = page.memory();
     match node_mem[0] {
         LEAF => {
-            let accessor = LeafAccessor::new(page.memory(), K::fixed_width(), V::fixed_width());
+            let accessor = LeafAccessor::new(page.memory(), fixed_key_size, V::fixed_width());
             let (mut position, found) = accessor.position::<K>(query);
             let include = if position < accessor.num_pairs() {
                 include_query && found
@@ -736,7 +781,7 @@ fn find_iter_right<'a, K: RedbKey, V: RedbValue>(
             };
             let result = Leaf {
                 page,
-                fixed_key_size: K::fixed_width(),
+                fixed_key_size,
                 fixed_value_size: V::fixed_width(),
                 entry: position,
                 parent,
@@ -744,19 +789,26 @@ fn find_iter_right<'a, K: RedbKey, V: RedbValue>(
             Ok((include, Some(result)))
         }
         BRANCH => {
-            let accessor = BranchAccessor::new(&page, K::fixed_width());
+            let accessor = BranchAccessor::new(&page, fixed_key_size);
             let (child_index, child_page_number) = accessor.child_for_key::<K>(query);
             let child_page = manager.get_page(child_page_number)?;
             if child_index > 0 && accessor.child_page(child_index - 1).is_some() {
                 parent = Some(Box::new(Internal {
                     page,
-                    fixed_key_size: K::fixed_width(),
+                    fixed_key_size,
                     fixed_value_size: V::fixed_width(),
                     child: child_index - 1,
                     parent,
                 }));
             }
-            find_iter_right::<K, V>(child_page, parent, query, include_query, manager)
+            find_iter_right::<K, V>(
+                child_page,
+                parent,
+                fixed_key_size,
+                query,
+                include_query,
+                manager,
+            )
         }
         _ => unreachable!(),
     }
@@ -11,6 +11,7 @@ use crate::types::{RedbKey, RedbValue};
 use crate::{AccessGuard, Result};
 use std::cmp::{max, min};
 use std::marker::PhantomData;
+use std::ops::Bound;
 
 // TODO: it seems like Checksum can be removed from most/all of these, now that we're using deferred checksums
 #[derive(Debug)]
@@ -27,6 +28,24 @@ enum DeletionResult {
     DeletedBranch(PageNumber, Checksum),
 }
 
+// A subtree which remains after a range was deleted, and takes the place of part of the original
+// tree
+struct RangeRemainder {
+    page: PageNumber,
+    checksum: Checksum,
+    // Number of levels, including the leaves
+    height: usize,
+    // A key which is greater than or equal to every key in this subtree, and less than every key in
+    // the subtree which follows it. None if no subtree follows it within its parent
+    separator: Option<Vec<u8>>,
+}
+
+// The entries removed by a range deletion: their number, and their keys if those were requested
+struct RangeRemoval<'k> {
+    count: u64,
+    keys: Option<&'k mut Vec<Vec<u8>>>,
+}
+
 struct InsertionResult<'a, V: RedbValue> {
     // the new root page
     new_root: PageNumber,
@@ -40,11 +59,28 @@ struct InsertionResult<'a, V: RedbValue> {
     old_value: Option<AccessGuard<'a, V>>,
 }
 
+// The value to insert. A lazy value is only produced once the leaf is reached, and only if the key
+// is absent from it, or for IfPresent, only from the existing value of the key
+enum InsertValue<'v> {
+    Bytes(&'v [u8]),
+    IfAbsent(&'v mut dyn FnMut() -> Result<Vec<u8>>),
+    IfPresent(&'v mut dyn FnMut(&[u8]) -> Result<Vec<u8>>),
+}
+
+enum InsertionOutcome<'a, V: RedbValue> {
+    Inserted(InsertionResult<'a, V>),
+    // The key was present, and the tree was not modified
+    Present(AccessGuard<'a, V>),
+    // The key was absent, and the tree was not modified
+    Absent,
+}
+
 pub(crate) struct MutateHelper<'a, 'b, K: RedbKey, V: RedbValue> {
     root: &'b mut Option<(PageNumber, Checksum)>,
     free_policy: FreePolicy,
     mem: &'a TransactionalMemory,
     freed: &'b mut Vec<PageNumber>,
+    key_width: Option<usize>,
     _key_type: PhantomData<K>,
     _value_type: PhantomData<V>,
 }
@@ -55,12 +91,14 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
         free_policy: FreePolicy,
         mem: &'a TransactionalMemory,
         freed: &'b mut Vec<PageNumber>,
+        key_width: Option<usize>,
     ) -> Self {
         Self {
             root,
             free_policy,
             mem,
             freed,
+            key_width,
             _key_type: Default::default(),
             _value_type: Default::default(),
         }
@@ -85,11 +123,11 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                 PartialLeaf { deleted_pair } => {
                     let page = self.mem.get_page(p)?;
                     let accessor =
-                        LeafAccessor::new(page.memory(), K::fixed_width(), V::fixed_width());
+                        LeafAccessor::new(page.memory(), self.key_width, V::fixed_width());
                     let mut builder = LeafBuilder::new(
                         self.mem,
                         accessor.num_pairs() - 1,
-                        K::fixed_width(),
+                        self.key_width,
                         V::fixed_width(),
                     );
                     builder.push_all_except(&accessor, Some(deleted_pair));
@@ -106,22 +144,107 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
         }
     }
 
+    // Removes all entries in the given range, and returns their number. Their keys are also
+    // recorded in removed_keys, if given. The tree is descended once: subtrees which lie entirely
+    // within the range are freed without being rewritten, and the subtrees which remain on either
+    // side of it are joined back together, keeping the tree balanced
+    pub(crate) fn delete_range(
+        &mut self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        removed_keys: Option<&mut Vec<Vec<u8>>>,
+    ) -> Result<u64> {
+        let mut removed = RangeRemoval {
+            count: 0,
+            keys: removed_keys,
+        };
+        if let Some((p, checksum)) = *self.root {
+            let (remainders, _) = self.delete_range_helper(
+                self.mem.get_page(p)?,
+                checksum,
+                start,
+                end,
+                &mut removed,
+            )?;
+            *self.root = match remainders.as_slice() {
+                [] => None,
+                [only] => Some((only.page, only.checksum)),
+                [first, second] => {
+                    let mut builder = BranchBuilder::new(self.mem, 2, self.key_width);
+                    builder.push_child(first.page, first.checksum);
+                    builder.push_key(first.separator.as_ref().unwrap());
+                    builder.push_child(second.page, second.checksum);
+                    let new_page = builder.build()?;
+                    Some((new_page.get_page_number(), DEFERRED))
+                }
+                _ => unreachable!(),
+            };
+        }
+
+        Ok(removed.count)
+    }
+
     #[allow(clippy::type_complexity)]
     pub(crate) fn insert(
         &mut self,
         key: &K::SelfType<'_>,
         value: &V::SelfType<'_>,
     ) -> Result<(Option<AccessGuard<'a, V>>, AccessGuardMut<'a, V>)> {
+        let value_bytes = V::as_bytes(value);
+        match self.insert_root(key, InsertValue::Bytes(value_bytes.as_ref()))? {
+            InsertionOutcome::Inserted(result) => Ok((result.old_value, result.inserted_value)),
+            InsertionOutcome::Present(_) | InsertionOutcome::Absent => unreachable!(),
+        }
+    }
+
+    // Returns the value of `key`, if it is present. Otherwise, the value produced by `value` is
+    // inserted, and None is returned. The tree is descended only once
+    pub(crate) fn insert_if_absent(
+        &mut self,
+        key: &K::SelfType<'_>,
+        value: &mut dyn FnMut() -> Result<Vec<u8>>,
+    ) -> Result<Option<AccessGuard<'a, V>>> {
+        match self.insert_root(key, InsertValue::IfAbsent(value))? {
+            InsertionOutcome::Inserted(_) => Ok(None),
+            InsertionOutcome::Present(existing) => Ok(Some(existing)),
+            InsertionOutcome::Absent => unreachable!(),
+        }
+    }
+
+    // Replaces the value of `key` with the value which `value` produces from it, and returns the
+    // old value. If the key is absent, or `value` fails, the tree is not modified. The tree is
+    // descended only once
+    pub(crate) fn replace_if_present(
+        &mut self,
+        key: &K::SelfType<'_>,
+        value: &mut dyn FnMut(&[u8]) -> Result<Vec<u8>>,
+    ) -> Result<Option<AccessGuard<'a, V>>> {
+        match self.insert_root(key, InsertValue::IfPresent(value))? {
+            InsertionOutcome::Inserted(result) => Ok(result.old_value),
+            InsertionOutcome::Absent => Ok(None),
+            InsertionOutcome::Present(_) => unreachable!(),
+        }
+    }
+
+    // The returned InsertionResult describes the new root, which has already been stored
+    fn insert_root(
+        &mut self,
+        key: &K::SelfType<'_>,
+        mut value: InsertValue<'_>,
+    ) -> Result<InsertionOutcome<'a, V>> {
         let (new_root, old_value, guard) = if let Some((p, checksum)) = *self.root {
-            let result = self.insert_helper(
+            let result = match self.insert_helper(
                 self.mem.get_page(p)?,
                 checksum,
                 K::as_bytes(key).as_ref(),
-                V::as_bytes(value).as_ref(),
-            )?;
+                &mut value,
+            )? {
+                InsertionOutcome::Inserted(result) => result,
+                present => return Ok(present),
+            };
 
             let new_root = if let Some((key, page2, page2_checksum)) = result.additional_sibling {
-                let mut builder = BranchBuilder::new(self.mem, 2, K::fixed_width());
+                let mut builder = BranchBuilder::new(self.mem, 2, self.key_width);
                 builder.push_child(result.new_root, result.root_checksum);
                 builder.push_key(&key);
                 builder.push_child(page2, page2_checksum);
@@ -133,14 +256,21 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
             (new_root, result.old_value, result.inserted_value)
         } else {
             let key_bytes = K::as_bytes(key);
-            let value_bytes = V::as_bytes(value);
             let key_bytes = key_bytes.as_ref();
-            let value_bytes = value_bytes.as_ref();
-            let mut builder = LeafBuilder::new(self.mem, 1, K::fixed_width(), V::fixed_width());
+            let produced;
+            let value_bytes = match value {
+                InsertValue::Bytes(bytes) => bytes,
+                InsertValue::IfAbsent(produce) => {
+                    produced = produce()?;
+                    produced.as_slice()
+                }
+                InsertValue::IfPresent(_) => return Ok(InsertionOutcome::Absent),
+            };
+            let mut builder = LeafBuilder::new(self.mem, 1, self.key_width, V::fixed_width());
             builder.push(key_bytes, value_bytes);
             let page = builder.build()?;
 
-            let accessor = LeafAccessor::new(page.memory(), K::fixed_width(), V::fixed_width());
+            let accessor = LeafAccessor::new(page.memory(), self.key_width, V::fixed_width());
             let offset = accessor.offset_of_first_value();
             let page_num = page.get_page_number();
             let guard = AccessGuardMut::new(page, offset, value_bytes.len());
@@ -148,7 +278,13 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
             ((page_num, DEFERRED), None, guard)
         };
         *self.root = Some(new_root);
-        Ok((old_value, guard))
+        Ok(InsertionOutcome::Inserted(InsertionResult {
+            new_root: new_root.0,
+            root_checksum: new_root.1,
+            additional_sibling: None,
+            inserted_value: guard,
+            old_value,
+        }))
     }
 
     fn insert_helper(
@@ -156,30 +292,53 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
         page: PageImpl<'a>,
         page_checksum: Checksum,
         key: &[u8],
-        value: &[u8],
-    ) -> Result<InsertionResult<'a, V>> {
+        value: &mut InsertValue<'_>,
+    ) -> Result<InsertionOutcome<'a, V>> {
         let node_mem = page.memory();
-        Ok(match node_mem[0] {
+        Ok(InsertionOutcome::Inserted(match node_mem[0] {
             LEAF => {
-                let accessor = LeafAccessor::new(page.memory(), K::fixed_width(), V::fixed_width());
+                let accessor = LeafAccessor::new(page.memory(), self.key_width, V::fixed_width());
                 let (position, found) = accessor.position::<K>(key);
+                let produced;
+                let value = match value {
+                    InsertValue::Bytes(bytes) => *bytes,
+                    InsertValue::IfAbsent(produce) => {
+                        if found {
+                            let (start, end) = accessor.value_range(position).unwrap();
+                            drop(accessor);
+                            return Ok(InsertionOutcome::Present(AccessGuard::with_page(
+                                page,
+                                start..end,
+                            )));
+                        }
+                        produced = produce()?;
+                        produced.as_slice()
+                    }
+                    InsertValue::IfPresent(produce) => {
+                        if !found {
+                            return Ok(InsertionOutcome::Absent);
+                        }
+                        produced = produce(accessor.entry(position).unwrap().value())?;
+                        produced.as_slice()
+                    }
+                };
 
                 // Fast-path to avoid re-building and splitting pages with a single large value
                 let single_large_value = accessor.num_pairs() == 1
                     && accessor.total_length() >= self.mem.get_page_size();
                 if !found && single_large_value {
                     let mut builder =
-                        LeafBuilder::new(self.mem, 1, K::fixed_width(), V::fixed_width());
+                        LeafBuilder::new(self.mem, 1, self.key_width, V::fixed_width());
                     builder.push(key, value);
                     let new_page = builder.build()?;
                     let new_page_number = new_page.get_page_number();
                     let new_page_accessor =
-                        LeafAccessor::new(new_page.memory(), K::fixed_width(), V::fixed_width());
+                        LeafAccessor::new(new_page.memory(), self.key_width, V::fixed_width());
                     let offset = new_page_accessor.offset_of_first_value();
                     drop(new_page_accessor);
                     let guard = AccessGuardMut::new(new_page, offset, value.len());
                     return if position == 0 {
-                        Ok(InsertionResult {
+                        Ok(InsertionOutcome::Inserted(InsertionResult {
                             new_root: new_page_number,
                             root_checksum: DEFERRED,
                             additional_sibling: Some((
@@ -189,16 +348,16 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                             )),
                             inserted_value: guard,
                             old_value: None,
-                        })
+                        }))
                     } else {
                         let split_key = accessor.last_entry().key().to_vec();
-                        Ok(InsertionResult {
+                        Ok(InsertionOutcome::Inserted(InsertionResult {
                             new_root: page.get_page_number(),
                             root_checksum: page_checksum,
                             additional_sibling: Some((split_key, new_page_number, DEFERRED)),
                             inserted_value: guard,
                             old_value: None,
-                        })
+                        }))
                     };
                 }
 
@@ -208,7 +367,7 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                         &page,
                         position,
                         found,
-                        K::fixed_width(),
+                        self.key_width,
                         V::fixed_width(),
                         key,
                         value,
@@ -224,26 +383,26 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                     drop(page);
                     let mut page_mut = self.mem.get_page_mut(page_number)?;
                     let mut mutator =
-                        LeafMutator::new(&mut page_mut, K::fixed_width(), V::fixed_width());
+                        LeafMutator::new(&mut page_mut, self.key_width, V::fixed_width());
                     mutator.insert(position, found, key, value);
                     let new_page_accessor =
-                        LeafAccessor::new(page_mut.memory(), K::fixed_width(), V::fixed_width());
+                        LeafAccessor::new(page_mut.memory(), self.key_width, V::fixed_width());
                     let offset = new_page_accessor.offset_of_value(position).unwrap();
                     drop(new_page_accessor);
                     let guard = AccessGuardMut::new(page_mut, offset, value.len());
-                    return Ok(InsertionResult {
+                    return Ok(InsertionOutcome::Inserted(InsertionResult {
                         new_root: page_number,
                         root_checksum: DEFERRED,
                         additional_sibling: None,
                         inserted_value: guard,
                         old_value: existing_value,
-                    });
+                    }));
                 }
 
                 let mut builder = LeafBuilder::new(
                     self.mem,
                     accessor.num_pairs() + 1,
-                    K::fixed_width(),
+                    self.key_width,
                     V::fixed_width(),
                 );
                 for i in 0..accessor.num_pairs() {
@@ -278,13 +437,13 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                     } else {
                         drop(page);
                         self.free_policy
-                            .conditional_free(page_number, self.freed, self.mem);
+                            .conditional_free(page_number, self.freed, self.mem)?;
                         None
                     };
 
                     let new_page_number = new_page.get_page_number();
                     let accessor =
-                        LeafAccessor::new(new_page.memory(), K::fixed_width(), V::fixed_width());
+                        LeafAccessor::new(new_page.memory(), self.key_width, V::fixed_width());
                     let offset = accessor.offset_of_value(position).unwrap();
                     let guard = AccessGuardMut::new(new_page, offset, value.len());
 
@@ -315,29 +474,23 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                     } else {
                         drop(page);
                         self.free_policy
-                            .conditional_free(page_number, self.freed, self.mem);
+                            .conditional_free(page_number, self.freed, self.mem)?;
                         None
                     };
 
                     let new_page_number = new_page1.get_page_number();
                     let new_page_number2 = new_page2.get_page_number();
                     let accessor =
-                        LeafAccessor::new(new_page1.memory(), K::fixed_width(), V::fixed_width());
+                        LeafAccessor::new(new_page1.memory(), self.key_width, V::fixed_width());
                     let division = accessor.num_pairs();
                     let guard = if position < division {
-                        let accessor = LeafAccessor::new(
-                            new_page1.memory(),
-                            K::fixed_width(),
-                            V::fixed_width(),
-                        );
+                        let accessor =
+                            LeafAccessor::new(new_page1.memory(), self.key_width, V::fixed_width());
                         let offset = accessor.offset_of_value(position).unwrap();
                         AccessGuardMut::new(new_page1, offset, value.len())
                     } else {
-                        let accessor = LeafAccessor::new(
-                            new_page2.memory(),
-                            K::fixed_width(),
-                            V::fixed_width(),
-                        );
+                        let accessor =
+                            LeafAccessor::new(new_page2.memory(), self.key_width, V::fixed_width());
                         let offset = accessor.offset_of_value(position - division).unwrap();
                         AccessGuardMut::new(new_page2, offset, value.len())
                     };
@@ -352,36 +505,45 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                 }
             }
             BRANCH => {
-                let accessor = BranchAccessor::new(&page, K::fixed_width());
+                let accessor = BranchAccessor::new(&page, self.key_width);
                 let (child_index, child_page) = accessor.child_for_key::<K>(key);
                 let child_checksum = accessor.child_checksum(child_index).unwrap();
-                let sub_result =
-                    self.insert_helper(self.mem.get_page(child_page)?, child_checksum, key, value)?;
+                let sub_result = match self.insert_helper(
+                    self.mem.get_page(child_page)?,
+                    child_checksum,
+                    key,
+                    value,
+                )? {
+                    InsertionOutcome::Inserted(result) => result,
+                    present => return Ok(present),
+                };
 
-                if sub_result.additional_sibling.is_none()
-                    && self.mem.uncommitted(page.get_page_number())
-                {
+                if sub_result.additional_sibling.is_none() {
                     let page_number = page.get_page_number();
                     drop(page);
-                    let mut mutpage = self.mem.get_page_mut(page_number)?;
+                    let mut mutpage = if self.mem.uncommitted(page_number) {
+                        self.mem.get_page_mut(page_number)?
+                    } else {
+                        self.mem.copy_on_write(page_number, self.freed)?
+                    };
                     let mut mutator = BranchMutator::new(&mut mutpage);
                     mutator.write_child_page(
                         child_index,
                         sub_result.new_root,
                         sub_result.root_checksum,
                     );
-                    return Ok(InsertionResult {
+                    return Ok(InsertionOutcome::Inserted(InsertionResult {
                         new_root: mutpage.get_page_number(),
                         root_checksum: DEFERRED,
                         additional_sibling: None,
                         inserted_value: sub_result.inserted_value,
                         old_value: sub_result.old_value,
-                    });
+                    }));
                 }
 
                 // A child was added, or we couldn't use the fast-path above
                 let mut builder =
-                    BranchBuilder::new(self.mem, accessor.count_children() + 1, K::fixed_width());
+                    BranchBuilder::new(self.mem, accessor.count_children() + 1, self.key_width);
                 if child_index == 0 {
                     builder.push_child(sub_result.new_root, sub_result.root_checksum);
                     if let Some((ref index_key2, page2, page2_checksum)) =
@@ -445,12 +607,12 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                 let page_number = page.get_page_number();
                 drop(page);
                 self.free_policy
-                    .conditional_free(page_number, self.freed, self.mem);
+                    .conditional_free(page_number, self.freed, self.mem)?;
 
                 result
             }
             _ => unreachable!(),
-        })
+        }))
     }
 
     fn delete_leaf_helper(
@@ -459,7 +621,7 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
         checksum: Checksum,
         key: &[u8],
     ) -> Result<(DeletionResult, Option<AccessGuard<'a, V>>)> {
-        let accessor = LeafAccessor::new(page.memory(), K::fixed_width(), V::fixed_width());
+        let accessor = LeafAccessor::new(page.memory(), self.key_width, V::fixed_width());
         let (position, found) = accessor.position::<K>(key);
         if !found {
             return Ok((Subtree(page.get_page_number(), checksum), None));
@@ -485,7 +647,7 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                 start,
                 end - start,
                 position,
-                K::fixed_width(),
+                self.key_width,
                 self.mem,
             );
             return Ok((Subtree(page_number, DEFERRED), Some(guard)));
@@ -503,7 +665,7 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
             let mut builder = LeafBuilder::new(
                 self.mem,
                 accessor.num_pairs() - 1,
-                K::fixed_width(),
+                self.key_width,
                 V::fixed_width(),
             );
             for i in 0..accessor.num_pairs() {
@@ -542,7 +704,7 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
             // TODO: can we optimize away this page allocation?
             // The PartialInternal gets returned, and then the caller has to merge it immediately
             let new_page = builder.build()?;
-            let accessor = BranchAccessor::new(&new_page, K::fixed_width());
+            let accessor = BranchAccessor::new(&new_page, self.key_width);
             // Merge when less than 33% full. Splits occur when a page is full and produce two 50%
             // full pages, so we use 33% instead of 50% to avoid oscillating
             if accessor.total_length() < self.mem.get_page_size() / 3 {
@@ -560,7 +722,7 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
         checksum: Checksum,
         key: &[u8],
     ) -> Result<(DeletionResult, Option<AccessGuard<'a, V>>)> {
-        let accessor = BranchAccessor::new(&page, K::fixed_width());
+        let accessor = BranchAccessor::new(&page, self.key_width);
         let original_page_number = page.get_page_number();
         let (child_index, child_page_number) = accessor.child_for_key::<K>(key);
         let child_checksum = accessor.child_checksum(child_index).unwrap();
@@ -570,27 +732,19 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
             return Ok((Subtree(original_page_number, checksum), None));
         }
         if let Subtree(new_child, new_child_checksum) = result {
-            let result_page = if self.mem.uncommitted(original_page_number) {
-                drop(page);
-                let mut mutpage = self.mem.get_page_mut(original_page_number)?;
-                let mut mutator = BranchMutator::new(&mut mutpage);
-                mutator.write_child_page(child_index, new_child, new_child_checksum);
-                original_page_number
+            drop(page);
+            let mut mutpage = if self.mem.uncommitted(original_page_number) {
+                self.mem.get_page_mut(original_page_number)?
             } else {
-                let mut builder =
-                    BranchBuilder::new(self.mem, accessor.count_children(), K::fixed_width());
-                builder.push_all(&accessor);
-                builder.replace_child(child_index, new_child, new_child_checksum);
-                let new_page = builder.build()?;
-                self.free_policy
-                    .conditional_free(original_page_number, self.freed, self.mem);
-                new_page.get_page_number()
+                self.mem.copy_on_write(original_page_number, self.freed)?
             };
-            return Ok((Subtree(result_page, DEFERRED), found));
+            let mut mutator = BranchMutator::new(&mut mutpage);
+            mutator.write_child_page(child_index, new_child, new_child_checksum);
+            return Ok((Subtree(mutpage.get_page_number(), DEFERRED), found));
         }
 
         // Child is requesting to be merged with a sibling
-        let mut builder = BranchBuilder::new(self.mem, accessor.count_children(), K::fixed_width());
+        let mut builder = BranchBuilder::new(self.mem, accessor.count_children(), self.key_width);
 
         let final_result = match result {
             Subtree(_, _) => {
@@ -625,7 +779,7 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                 let partial_child_page = self.mem.get_page(child_page_number)?;
                 let partial_child_accessor = LeafAccessor::new(
                     partial_child_page.memory(),
-                    K::fixed_width(),
+                    self.key_width,
                     V::fixed_width(),
                 );
                 debug_assert!(partial_child_accessor.num_pairs() > 1);
@@ -636,7 +790,7 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                     .mem
                     .get_page(accessor.child_page(merge_with).unwrap())?;
                 let merge_with_accessor =
-                    LeafAccessor::new(merge_with_page.memory(), K::fixed_width(), V::fixed_width());
+                    LeafAccessor::new(merge_with_page.memory(), self.key_width, V::fixed_width());
 
                 let single_large_value = merge_with_accessor.num_pairs() == 1
                     && merge_with_accessor.total_length() >= self.mem.get_page_size();
@@ -645,7 +799,7 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                     let mut child_builder = LeafBuilder::new(
                         self.mem,
                         partial_child_accessor.num_pairs() - 1,
-                        K::fixed_width(),
+                        self.key_width,
                         V::fixed_width(),
                     );
                     child_builder.push_all_except(&partial_child_accessor, Some(deleted_pair));
@@ -656,8 +810,11 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                     let result = self.finalize_branch_builder(builder)?;
 
                     drop(page);
-                    self.free_policy
-                        .conditional_free(original_page_number, self.freed, self.mem);
+                    self.free_policy.conditional_free(
+                        original_page_number,
+                        self.freed,
+                        self.mem,
+                    )?;
                     // child_page_number does not need to be freed, because it's a leaf and the
                     // MutAccessGuard will free it
 
@@ -675,7 +832,7 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                             self.mem,
                             partial_child_accessor.num_pairs() - 1
                                 + merge_with_accessor.num_pairs(),
-                            K::fixed_width(),
+                            self.key_width,
                             V::fixed_width(),
                         );
                         if child_index < merge_with {
@@ -714,7 +871,7 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                 let page_number = merge_with_page.get_page_number();
                 drop(merge_with_page);
                 self.free_policy
-                    .conditional_free(page_number, self.freed, self.mem);
+                    .conditional_free(page_number, self.freed, self.mem)?;
                 // child_page_number does not need to be freed, because it's a leaf and the
                 // MutAccessGuard will free it
 
@@ -725,7 +882,7 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                 let merge_with_page = self
                     .mem
                     .get_page(accessor.child_page(merge_with).unwrap())?;
-                let merge_with_accessor = BranchAccessor::new(&merge_with_page, K::fixed_width());
+                let merge_with_accessor = BranchAccessor::new(&merge_with_page, self.key_width);
                 debug_assert!(merge_with < accessor.count_children());
                 for i in 0..accessor.count_children() {
                     if i == child_index {
@@ -737,7 +894,7 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                         let mut child_builder = BranchBuilder::new(
                             self.mem,
                             merge_with_accessor.count_children() + 1,
-                            K::fixed_width(),
+                            self.key_width,
                         );
                         let separator_key = accessor.key(min(child_index, merge_with)).unwrap();
                         if child_index < merge_with {
@@ -775,19 +932,19 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                 let page_number = merge_with_page.get_page_number();
                 drop(merge_with_page);
                 self.free_policy
-                    .conditional_free(page_number, self.freed, self.mem);
+                    .conditional_free(page_number, self.freed, self.mem)?;
 
                 result
             }
             PartialBranch(partial_child, ..) => {
                 let partial_child_page = self.mem.get_page(partial_child)?;
                 let partial_child_accessor =
-                    BranchAccessor::new(&partial_child_page, K::fixed_width());
+                    BranchAccessor::new(&partial_child_page, self.key_width);
                 let merge_with = if child_index == 0 { 1 } else { child_index - 1 };
                 let merge_with_page = self
                     .mem
                     .get_page(accessor.child_page(merge_with).unwrap())?;
-                let merge_with_accessor = BranchAccessor::new(&merge_with_page, K::fixed_width());
+                let merge_with_accessor = BranchAccessor::new(&merge_with_page, self.key_width);
                 debug_assert!(merge_with < accessor.count_children());
                 for i in 0..accessor.count_children() {
                     if i == child_index {
@@ -800,7 +957,7 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                             self.mem,
                             merge_with_accessor.count_children()
                                 + partial_child_accessor.count_children(),
-                            K::fixed_width(),
+                            self.key_width,
                         );
                         let separator_key = accessor.key(min(child_index, merge_with)).unwrap();
                         if child_index < merge_with {
@@ -838,10 +995,10 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
                 let page_number = merge_with_page.get_page_number();
                 drop(merge_with_page);
                 self.free_policy
-                    .conditional_free(page_number, self.freed, self.mem);
+                    .conditional_free(page_number, self.freed, self.mem)?;
                 drop(partial_child_page);
                 self.free_policy
-                    .conditional_free(partial_child, self.freed, self.mem);
+                    .conditional_free(partial_child, self.freed, self.mem)?;
 
                 result
             }
@@ -849,11 +1006,468 @@ impl<'a, 'b, K: RedbKey, V: RedbValue> MutateHelper<'a, 'b, K, V> {
 
         drop(page);
         self.free_policy
-            .conditional_free(original_page_number, self.freed, self.mem);
+            .conditional_free(original_page_number, self.freed, self.mem)?;
 
         Ok((final_result, found))
     }
 
+    // Returns the subtrees which remain of the subtree at `page`, in key order, along with the
+    // height of the original subtree. They all have the same height, which may be less than the
+    // original. If nothing was deleted, the original subtree is returned
+    fn delete_range_helper(
+        &mut self,
+        page: PageImpl<'a>,
+        checksum: Checksum,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        removed: &mut RangeRemoval,
+    ) -> Result<(Vec<RangeRemainder>, usize)> {
+        match page.memory()[0] {
+            LEAF => Ok((
+                self.delete_range_leaf_helper(page, checksum, start, end, removed)?,
+                1,
+            )),
+            BRANCH => self.delete_range_branch_helper(page, checksum, start, end, removed),
+            _ => unreachable!(),
+        }
+    }
+
+    fn delete_range_leaf_helper(
+        &mut self,
+        page: PageImpl<'a>,
+        checksum: Checksum,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        removed: &mut RangeRemoval,
+    ) -> Result<Vec<RangeRemainder>> {
+        let page_number = page.get_page_number();
+        let accessor = LeafAccessor::new(page.memory(), self.key_width, V::fixed_width());
+        let first = match start {
+            Bound::Included(key) => accessor.position::<K>(key).0,
+            Bound::Excluded(key) => {
+                let (position, found) = accessor.position::<K>(key);
+                if found {
+                    position + 1
+                } else {
+                    position
+                }
+            }
+            Bound::Unbounded => 0,
+        };
+        let last = match end {
+            Bound::Included(key) => {
+                let (position, found) = accessor.position::<K>(key);
+                if found {
+                    position + 1
+                } else {
+                    position
+                }
+            }
+            Bound::Excluded(key) => accessor.position::<K>(key).0,
+            Bound::Unbounded => accessor.num_pairs(),
+        };
+        // The range is empty if its start is after its end
+        let last = max(first, last);
+        if first == last {
+            return Ok(vec![RangeRemainder {
+                page: page_number,
+                checksum,
+                height: 1,
+                separator: None,
+            }]);
+        }
+
+        removed.count += (last - first) as u64;
+        if let Some(keys) = removed.keys.as_mut() {
+            for i in first..last {
+                keys.push(accessor.entry(i).unwrap().key().to_vec());
+            }
+        }
+        let remaining = accessor.num_pairs() - (last - first);
+        let result = if remaining == 0 {
+            vec![]
+        } else {
+            let mut builder =
+                LeafBuilder::new(self.mem, remaining, self.key_width, V::fixed_width());
+            for i in (0..first).chain(last..accessor.num_pairs()) {
+                let entry = accessor.entry(i).unwrap();
+                builder.push(entry.key(), entry.value());
+            }
+            let new_page = builder.build()?;
+            vec![RangeRemainder {
+                page: new_page.get_page_number(),
+                checksum: DEFERRED,
+                height: 1,
+                separator: None,
+            }]
+        };
+        drop(page);
+        self.free_policy
+            .conditional_free(page_number, self.freed, self.mem)?;
+
+        Ok(result)
+    }
+
+    fn delete_range_branch_helper(
+        &mut self,
+        page: PageImpl<'a>,
+        checksum: Checksum,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        removed: &mut RangeRemoval,
+    ) -> Result<(Vec<RangeRemainder>, usize)> {
+        let page_number = page.get_page_number();
+        let accessor = BranchAccessor::new(&page, self.key_width);
+        let num_children = accessor.count_children();
+        let first = match start {
+            Bound::Included(key) | Bound::Excluded(key) => accessor.child_for_key::<K>(key).0,
+            Bound::Unbounded => 0,
+        };
+        let last = match end {
+            Bound::Included(key) | Bound::Excluded(key) => accessor.child_for_key::<K>(key).0,
+            Bound::Unbounded => num_children - 1,
+        };
+        // The range is empty if its start is after its end
+        let last = max(first, last);
+        let removed_before = removed.count;
+
+        let (mut first_remainders, child_height) = self.delete_range_helper(
+            self.mem.get_page(accessor.child_page(first).unwrap())?,
+            accessor.child_checksum(first).unwrap(),
+            start,
+            end,
+            removed,
+        )?;
+        let height = child_height + 1;
+        let mut last_remainders = vec![];
+        if last > first {
+            // The children in between lie entirely within the range
+            for i in (first + 1)..last {
+                self.free_subtree(accessor.child_page(i).unwrap(), child_height, removed)?;
+            }
+            last_remainders = self
+                .delete_range_helper(
+                    self.mem.get_page(accessor.child_page(last).unwrap())?,
+                    accessor.child_checksum(last).unwrap(),
+                    start,
+                    end,
+                    removed,
+                )?
+                .0;
+        }
+        if removed.count == removed_before {
+            return Ok((
+                vec![RangeRemainder {
+                    page: page_number,
+                    checksum,
+                    height,
+                    separator: None,
+                }],
+                height,
+            ));
+        }
+
+        let separator = |i: usize| -> Option<Vec<u8>> {
+            if i < num_children - 1 {
+                Some(accessor.key(i).unwrap().to_vec())
+            } else {
+                None
+            }
+        };
+        let child = |i: usize| RangeRemainder {
+            page: accessor.child_page(i).unwrap(),
+            checksum: accessor.child_checksum(i).unwrap(),
+            height: child_height,
+            separator: separator(i),
+        };
+        if let Some(remainder) = first_remainders.last_mut() {
+            remainder.separator = separator(first);
+        }
+        if let Some(remainder) = last_remainders.last_mut() {
+            remainder.separator = separator(last);
+        }
+        // Whether each subtree was rewritten by the deletion, and so may need to be joined with a
+        // neighbor
+        let subtrees: Vec<(RangeRemainder, bool)> = (0..first)
+            .map(|i| (child(i), false))
+            .chain(first_remainders.into_iter().map(|x| (x, true)))
+            .chain(last_remainders.into_iter().map(|x| (x, true)))
+            .chain(((last + 1)..num_children).map(|i| (child(i), false)))
+            .collect();
+        drop(accessor);
+        drop(page);
+        self.free_policy
+            .conditional_free(page_number, self.freed, self.mem)?;
+
+        // Join subtrees which are shorter than their siblings, or underfull, with their left
+        // neighbor, or with their right neighbor if they come first
+        let mut joined: Vec<(RangeRemainder, bool)> = vec![];
+        for (subtree, rewritten) in subtrees {
+            let deficient = rewritten && self.is_deficient(&subtree, child_height)?;
+            let join_previous = deficient || matches!(joined.last(), Some((_, true)));
+            match joined.pop() {
+                Some((previous, _)) if join_previous => {
+                    let mut result = self.join(previous, subtree)?;
+                    let last = result.pop().unwrap();
+                    joined.extend(result.into_iter().map(|x| (x, false)));
+                    let deficient = self.is_deficient(&last, child_height)?;
+                    joined.push((last, deficient));
+                }
+                previous => {
+                    joined.extend(previous);
+                    joined.push((subtree, deficient));
+                }
+            }
+        }
+
+        let mut remainders: Vec<RangeRemainder> = joined.into_iter().map(|(x, _)| x).collect();
+        if remainders.len() <= 1 {
+            // The node collapsed into its only remaining subtree, if any
+            return Ok((remainders, height));
+        }
+        let separator = remainders.last_mut().unwrap().separator.take();
+        let mut builder = BranchBuilder::new(self.mem, remainders.len(), self.key_width);
+        for (i, remainder) in remainders.iter().enumerate() {
+            builder.push_child(remainder.page, remainder.checksum);
+            if i < remainders.len() - 1 {
+                builder.push_key(remainder.separator.as_ref().unwrap());
+            }
+        }
+
+        Ok((self.build_range_branch(builder, height, separator)?, height))
+    }
+
+    // Frees every page of the subtree, whose height is given, and records the entries which it
+    // held. Only the number of entries in each leaf is read, unless their keys were requested
+    fn free_subtree(
+        &mut self,
+        page_number: PageNumber,
+        height: usize,
+        removed: &mut RangeRemoval,
+    ) -> Result {
+        let page = self.mem.get_page(page_number)?;
+        if height == 1 {
+            let accessor = LeafAccessor::new(page.memory(), self.key_width, V::fixed_width());
+            removed.count += accessor.num_pairs() as u64;
+            if let Some(keys) = removed.keys.as_mut() {
+                for i in 0..accessor.num_pairs() {
+                    keys.push(accessor.entry(i).unwrap().key().to_vec());
+                }
+            }
+        } else {
+            let accessor = BranchAccessor::new(&page, self.key_width);
+            for i in 0..accessor.count_children() {
+                self.free_subtree(accessor.child_page(i).unwrap(), height - 1, removed)?;
+            }
+        }
+        drop(page);
+        self.free_policy
+            .conditional_free(page_number, self.freed, self.mem)?;
+
+        Ok(())
+    }
+
+    // A subtree is deficient if it is shorter than its siblings, or its root is less than a third
+    // full, which is when deletions merge pages
+    fn is_deficient(&self, subtree: &RangeRemainder, height: usize) -> Result<bool> {
+        if subtree.height < height {
+            return Ok(true);
+        }
+        let page = self.mem.get_page(subtree.page)?;
+        let length = match page.memory()[0] {
+            LEAF => {
+                LeafAccessor::new(page.memory(), self.key_width, V::fixed_width()).total_length()
+            }
+            BRANCH => BranchAccessor::new(&page, self.key_width).total_length(),
+            _ => unreachable!(),
+        };
+        Ok(length < self.mem.get_page_size() / 3)
+    }
+
+    // Joins two adjacent subtrees, which may have different heights. The shorter one is merged
+    // into the edge of the taller one, at the same height. Returns one or two subtrees, with the
+    // height of the taller one
+    fn join(&mut self, left: RangeRemainder, right: RangeRemainder) -> Result<Vec<RangeRemainder>> {
+        if left.height == right.height {
+            return self.merge(left, right);
+        }
+
+        let taller = if left.height > right.height {
+            left.page
+        } else {
+            right.page
+        };
+        let result = if left.height > right.height {
+            let page = self.mem.get_page(left.page)?;
+            let accessor = BranchAccessor::new(&page, self.key_width);
+            let last = accessor.count_children() - 1;
+            let last_child = RangeRemainder {
+                page: accessor.child_page(last).unwrap(),
+                checksum: accessor.child_checksum(last).unwrap(),
+                height: left.height - 1,
+                separator: left.separator,
+            };
+            let joined = self.join(last_child, right)?;
+            let mut builder = BranchBuilder::new(self.mem, last + joined.len(), self.key_width);
+            for i in 0..last {
+                builder.push_child(
+                    accessor.child_page(i).unwrap(),
+                    accessor.child_checksum(i).unwrap(),
+                );
+                builder.push_key(accessor.key(i).unwrap());
+            }
+            for (i, subtree) in joined.iter().enumerate() {
+                builder.push_child(subtree.page, subtree.checksum);
+                if i < joined.len() - 1 {
+                    builder.push_key(subtree.separator.as_ref().unwrap());
+                }
+            }
+            let separator = joined.last().unwrap().separator.clone();
+            self.build_range_branch(builder, left.height, separator)?
+        } else {
+            let page = self.mem.get_page(right.page)?;
+            let accessor = BranchAccessor::new(&page, self.key_width);
+            let first_child = RangeRemainder {
+                page: accessor.child_page(0).unwrap(),
+                checksum: accessor.child_checksum(0).unwrap(),
+                height: right.height - 1,
+                separator: Some(accessor.key(0).unwrap().to_vec()),
+            };
+            let joined = self.join(left, first_child)?;
+            let mut builder = BranchBuilder::new(
+                self.mem,
+                accessor.count_children() - 1 + joined.len(),
+                self.key_width,
+            );
+            for subtree in joined.iter() {
+                builder.push_child(subtree.page, subtree.checksum);
+                builder.push_key(subtree.separator.as_ref().unwrap());
+            }
+            for i in 1..accessor.count_children() {
+                builder.push_child(
+                    accessor.child_page(i).unwrap(),
+                    accessor.child_checksum(i).unwrap(),
+                );
+                if i < accessor.count_children() - 1 {
+                    builder.push_key(accessor.key(i).unwrap());
+                }
+            }
+            self.build_range_branch(builder, right.height, right.separator)?
+        };
+        self.free_policy
+            .conditional_free(taller, self.freed, self.mem)?;
+
+        Ok(result)
+    }
+
+    // Merges the roots of two adjacent subtrees of the same height, unless neither is underfull
+    fn merge(
+        &mut self,
+        left: RangeRemainder,
+        right: RangeRemainder,
+    ) -> Result<Vec<RangeRemainder>> {
+        if !self.is_deficient(&left, left.height)? && !self.is_deficient(&right, right.height)? {
+            return Ok(vec![left, right]);
+        }
+        let height = left.height;
+        let left_page = self.mem.get_page(left.page)?;
+        let right_page = self.mem.get_page(right.page)?;
+        let result = if left_page.memory()[0] == LEAF {
+            let left_accessor =
+                LeafAccessor::new(left_page.memory(), self.key_width, V::fixed_width());
+            let right_accessor =
+                LeafAccessor::new(right_page.memory(), self.key_width, V::fixed_width());
+            let mut builder = LeafBuilder::new(
+                self.mem,
+                left_accessor.num_pairs() + right_accessor.num_pairs(),
+                self.key_width,
+                V::fixed_width(),
+            );
+            builder.push_all_except(&left_accessor, None);
+            builder.push_all_except(&right_accessor, None);
+            if builder.should_split() {
+                let (new_page1, split_key, new_page2) = builder.build_split()?;
+                vec![
+                    RangeRemainder {
+                        page: new_page1.get_page_number(),
+                        checksum: DEFERRED,
+                        height,
+                        separator: Some(split_key.to_vec()),
+                    },
+                    RangeRemainder {
+                        page: new_page2.get_page_number(),
+                        checksum: DEFERRED,
+                        height,
+                        separator: right.separator,
+                    },
+                ]
+            } else {
+                let new_page = builder.build()?;
+                vec![RangeRemainder {
+                    page: new_page.get_page_number(),
+                    checksum: DEFERRED,
+                    height,
+                    separator: right.separator,
+                }]
+            }
+        } else {
+            let left_accessor = BranchAccessor::new(&left_page, self.key_width);
+            let right_accessor = BranchAccessor::new(&right_page, self.key_width);
+            let mut builder = BranchBuilder::new(
+                self.mem,
+                left_accessor.count_children() + right_accessor.count_children(),
+                self.key_width,
+            );
+            builder.push_all(&left_accessor);
+            builder.push_key(left.separator.as_ref().unwrap());
+            builder.push_all(&right_accessor);
+            self.build_range_branch(builder, height, right.separator)?
+        };
+        drop(left_page);
+        drop(right_page);
+        self.free_policy
+            .conditional_free(left.page, self.freed, self.mem)?;
+        self.free_policy
+            .conditional_free(right.page, self.freed, self.mem)?;
+
+        Ok(result)
+    }
+
+    // Builds the branch, splitting it in two if it is too large. The last subtree is followed by
+    // `separator`
+    fn build_range_branch(
+        &self,
+        builder: BranchBuilder<'_, '_>,
+        height: usize,
+        separator: Option<Vec<u8>>,
+    ) -> Result<Vec<RangeRemainder>> {
+        if builder.should_split() {
+            let (new_page1, split_key, new_page2) = builder.build_split()?;
+            Ok(vec![
+                RangeRemainder {
+                    page: new_page1.get_page_number(),
+                    checksum: DEFERRED,
+                    height,
+                    separator: Some(split_key.to_vec()),
+                },
+                RangeRemainder {
+                    page: new_page2.get_page_number(),
+                    checksum: DEFERRED,
+                    height,
+                    separator,
+                },
+            ])
+        } else {
+            let new_page = builder.build()?;
+            Ok(vec![RangeRemainder {
+                page: new_page.get_page_number(),
+                checksum: DEFERRED,
+                height,
+                separator,
+            }])
+        }
+    }
+
     // Returns the page number of the sub-tree with this key deleted, or None if the sub-tree is empty.
     // If key is not found, guaranteed not to modify the tree
     fn delete_helper(
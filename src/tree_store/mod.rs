@@ -5,18 +5,23 @@ mod btree_mutator;
 mod page_store;
 mod table_tree;
 
-pub(crate) use btree::{Btree, BtreeMut, RawBtree, UntypedBtreeMut};
+pub(crate) use btree::{btree_len, Btree, BtreeMut, RawBtree, UntypedBtreeMut};
 pub(crate) use btree_base::Checksum;
 pub use btree_base::{AccessGuard, AccessGuardMut};
 pub(crate) use btree_base::{LeafAccessor, LeafMutator, RawLeafBuilder, BRANCH, LEAF};
 pub(crate) use btree_iters::{
     AllPageNumbersBtreeIter, BtreeDrain, BtreeDrainFilter, BtreeRangeIter,
 };
-pub use page_store::Savepoint;
 pub(crate) use page_store::{
-    Page, PageHint, PageNumber, SerializedSavepoint, TransactionalMemory, FILE_FORMAT_VERSION,
+    holds_database, is_zeroed, migrate, reconcile_mirror, restore_backup, stored_format_version,
+    write_backup, ColdPageCallback, EmbeddedRange, GrowthCallback, Page, PageHint, PageNumber,
+    SerializedSavepoint, TransactionalMemory, DEFAULT_NODE_FILL_TARGET, MAX_PAGE_SIZE,
     MAX_VALUE_LENGTH, PAGE_SIZE,
 };
+pub use page_store::{
+    AllocationPolicy, Capacity, GrowthPolicy, HybridTimestamp, MetapageDump, MigrationStep,
+    Savepoint, TransactionSlotDump, FILE_FORMAT_VERSION,
+};
 pub(crate) use table_tree::{
     FreedPageList, FreedTableKey, InternalTableDefinition, TableTree, TableType,
 };
@@ -0,0 +1,298 @@
+use crate::tree_store::page_store::file_lock::LockedFile;
+use crate::tree_store::page_store::header::{DatabaseHeader, DB_HEADER_PREFIX_SIZE};
+use crate::tree_store::page_store::layout::DatabaseLayout;
+use crate::tree_store::page_store::TransactionalMemory;
+use crate::tree_store::{Page, PageNumber};
+use crate::{DatabaseError, Result, StorageError};
+use std::cmp::min;
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fs::File;
+use std::io;
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::mem::size_of;
+use std::ops::Bound::Excluded;
+use std::ops::Range;
+
+const BACKUP_MAGIC: [u8; 8] = *b"redbbkup";
+// Stored in place of the base watermark of a full backup
+const FULL_BACKUP: u64 = u64::MAX;
+// Offset of the database header in a backup
+pub(crate) const BACKUP_HEADER_OFFSET: u64 = 4 * size_of::<u64>() as u64;
+
+// Backup format:
+// 8 bytes: magic number
+// 8 bytes: watermark of this backup
+// 8 bytes: watermark of the backup which this is a delta from, or FULL_BACKUP
+// 8 bytes: length of the database file
+// database header, including both commit slots. Its length is recorded in its first bytes
+// 8 bytes: number of pages
+// For each page: 8 bytes page number, followed by the contents of the page
+pub(crate) fn write_backup<W: Write>(
+    mem: &TransactionalMemory,
+    header: &[u8],
+    file_len: u64,
+    base: Option<u64>,
+    watermark: u64,
+    pages: &[PageNumber],
+    out: &mut W,
+) -> Result {
+    assert_eq!(DatabaseHeader::stored_size(header)?, header.len());
+    out.write_all(&BACKUP_MAGIC)?;
+    out.write_all(&watermark.to_le_bytes())?;
+    out.write_all(&base.unwrap_or(FULL_BACKUP).to_le_bytes())?;
+    out.write_all(&file_len.to_le_bytes())?;
+    out.write_all(header)?;
+    out.write_all(&(pages.len() as u64).to_le_bytes())?;
+    for page_number in pages {
+        out.write_all(&page_number.to_le_bytes())?;
+        out.write_all(mem.get_page(*page_number)?.memory())?;
+    }
+
+    Ok(())
+}
+
+fn read_u64<R: Read>(input: &mut R) -> Result<u64> {
+    let mut buffer = [0; size_of::<u64>()];
+    input.read_exact(&mut buffer)?;
+    Ok(u64::from_le_bytes(buffer))
+}
+
+fn invalid_backup(msg: &str) -> StorageError {
+    StorageError::Corrupted(format!("Invalid backup: {msg}"))
+}
+
+struct BackupPreamble {
+    watermark: u64,
+    base: u64,
+    header_bytes: Vec<u8>,
+    layout: DatabaseLayout,
+    page_size: u32,
+}
+
+// Reads and validates everything which precedes the pages of a backup
+fn read_preamble<R: Read>(input: &mut R) -> Result<BackupPreamble, DatabaseError> {
+    let mut magic = [0; BACKUP_MAGIC.len()];
+    input.read_exact(&mut magic)?;
+    if magic != BACKUP_MAGIC {
+        return Err(invalid_backup("magic number").into());
+    }
+    let watermark = read_u64(input)?;
+    let base = read_u64(input)?;
+    let file_len = read_u64(input)?;
+    let mut header_bytes = vec![0; DB_HEADER_PREFIX_SIZE];
+    input.read_exact(&mut header_bytes)?;
+    let header_len = DatabaseHeader::stored_size(&header_bytes)?;
+    header_bytes.resize(header_len, 0);
+    input.read_exact(&mut header_bytes[DB_HEADER_PREFIX_SIZE..])?;
+    let (header, repair_info) = DatabaseHeader::from_bytes(&header_bytes);
+    if repair_info.invalid_magic_number {
+        return Err(invalid_backup("database header").into());
+    }
+    header.validate_layout()?;
+    let layout = header.layout();
+    if layout.len() != file_len {
+        return Err(invalid_backup("database length").into());
+    }
+
+    Ok(BackupPreamble {
+        watermark,
+        base,
+        header_bytes,
+        layout,
+        page_size: header.page_size(),
+    })
+}
+
+// Reads the number of the next page of a backup, and returns the range of the database file which
+// its contents are restored to
+fn read_page_range<R: Read>(input: &mut R, preamble: &BackupPreamble) -> Result<Range<u64>> {
+    let page_number = PageNumber::from_le_bytes(read_u64(input)?.to_le_bytes());
+    if !preamble.layout.contains_page(page_number) {
+        return Err(invalid_backup("page number"));
+    }
+    let region_layout = preamble.layout.full_region_layout();
+    Ok(page_number.address_range(
+        preamble.page_size.into(),
+        region_layout.len(),
+        region_layout.data_section().start,
+        preamble.page_size,
+    ))
+}
+
+// Writes a full backup followed by a chain of deltas into `file`. The database header is written
+// last, so that the file is only recognized as a database once all pages are in place
+pub(crate) fn restore_backup<R: Read>(
+    mut file: &File,
+    backups: impl IntoIterator<Item = R>,
+) -> Result<(), DatabaseError> {
+    let mut last_header = None;
+    let mut last_watermark = None;
+    for mut input in backups {
+        let preamble = read_preamble(&mut input)?;
+        match last_watermark {
+            None if preamble.base != FULL_BACKUP => {
+                return Err(invalid_backup("chain must start with a full backup").into());
+            }
+            Some(previous) if preamble.base != previous => {
+                return Err(invalid_backup("delta does not follow the previous backup").into());
+            }
+            _ => {}
+        }
+        file.set_len(preamble.layout.len())?;
+
+        let num_pages = read_u64(&mut input)?;
+        for _ in 0..num_pages {
+            let range = read_page_range(&mut input, &preamble)?;
+            let mut contents = vec![0; (range.end - range.start).try_into().unwrap()];
+            input.read_exact(&mut contents)?;
+            file.seek(SeekFrom::Start(range.start))?;
+            file.write_all(&contents)?;
+        }
+
+        last_watermark = Some(preamble.watermark);
+        last_header = Some(preamble.header_bytes);
+    }
+
+    if let Some(header_bytes) = last_header {
+        file.sync_all()?;
+        file.rewind()?;
+        file.write_all(&header_bytes)?;
+        file.sync_all()?;
+        Ok(())
+    } else {
+        Err(StorageError::Io(io::Error::from(ErrorKind::InvalidInput)).into())
+    }
+}
+
+// Locations of the pages of a full backup, so that it can be read in place like a database file
+pub(crate) struct BackupIndex {
+    // Length of the database file which the backup was taken from
+    len: u64,
+    // Start of each range of the database file which is stored in the backup, mapped to the
+    // offset of its contents in the backup and its length
+    ranges: BTreeMap<u64, (u64, u64)>,
+}
+
+impl BackupIndex {
+    pub(crate) fn len(&self) -> u64 {
+        self.len
+    }
+
+    // Reads `len` bytes at `offset` in the database file. Pages which are not in the backup are
+    // not reachable from its commit, and read as zeros. A read may span several adjacent stored
+    // ranges, but a read which is only partly stored is an error, since it can't be a page
+    pub(crate) fn read(&self, file: &LockedFile, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        if len == 0 {
+            return Ok(vec![]);
+        }
+        let end = offset + len as u64;
+        // The range which starts at or before offset, if it extends past it, followed by those
+        // which start within the read
+        let preceding = self
+            .ranges
+            .range(..=offset)
+            .next_back()
+            .filter(|(start, (_, range_len))| **start + range_len > offset);
+        let following = self.ranges.range((Excluded(offset), Excluded(end)));
+        let mut overlapping = preceding.into_iter().chain(following).peekable();
+        if overlapping.peek().is_none() {
+            return Ok(vec![0; len]);
+        }
+        let mut result = Vec::with_capacity(len);
+        let mut position = offset;
+        for (start, (backup_offset, range_len)) in overlapping {
+            if *start > position {
+                break;
+            }
+            let read_end = min(start + range_len, end);
+            let data_len = (read_end - position).try_into().unwrap();
+            result.extend(file.read(backup_offset + (position - start), data_len)?);
+            position = read_end;
+        }
+        if position < end {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("read of {len} bytes at {offset} is only partly stored in the backup"),
+            ));
+        }
+
+        Ok(result)
+    }
+}
+
+// Indexes the pages of the backup in `file`, or returns None if it does not hold a backup. Only a
+// full backup can be read in place, since a delta lacks the pages which did not change
+pub(crate) fn index_backup(mut file: &File) -> Result<Option<BackupIndex>, DatabaseError> {
+    let file_len = file.metadata()?.len();
+    let mut magic = [0; BACKUP_MAGIC.len()];
+    file.rewind()?;
+    if file_len < magic.len() as u64 {
+        return Ok(None);
+    }
+    file.read_exact(&mut magic)?;
+    if magic != BACKUP_MAGIC {
+        return Ok(None);
+    }
+    file.rewind()?;
+    let preamble = read_preamble(&mut file)?;
+    if preamble.base != FULL_BACKUP {
+        return Err(invalid_backup("only a full backup can be opened").into());
+    }
+
+    let mut ranges = BTreeMap::new();
+    let header_len = preamble.header_bytes.len() as u64;
+    ranges.insert(0, (BACKUP_HEADER_OFFSET, header_len));
+    let num_pages = read_u64(&mut file)?;
+    for _ in 0..num_pages {
+        let range = read_page_range(&mut file, &preamble)?;
+        let contents_offset = file.stream_position()?;
+        let range_len = range.end - range.start;
+        if contents_offset + range_len > file_len {
+            return Err(invalid_backup("truncated").into());
+        }
+        ranges.insert(range.start, (contents_offset, range_len));
+        file.seek(SeekFrom::Current(range_len.try_into().unwrap()))?;
+    }
+
+    Ok(Some(BackupIndex {
+        len: preamble.layout.len(),
+        ranges,
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::tree_store::page_store::backup::BackupIndex;
+    use crate::tree_store::page_store::file_lock::LockedFile;
+    use std::collections::BTreeMap;
+    use std::io::ErrorKind;
+
+    #[test]
+    fn read_backup_index() {
+        let tmpfile = crate::create_tempfile();
+        let contents: Vec<u8> = (0..=255).collect();
+        std::fs::write(tmpfile.path(), &contents).unwrap();
+        let file = LockedFile::new(tmpfile.into_file()).unwrap();
+        // Two adjacent ranges of 100 bytes, and another after a gap of 100 bytes
+        let mut ranges = BTreeMap::new();
+        ranges.insert(100, (10, 100));
+        ranges.insert(200, (110, 100));
+        ranges.insert(400, (0, 10));
+        let index = BackupIndex { len: 500, ranges };
+
+        assert_eq!(index.read(&file, 120, 10).unwrap(), contents[30..40]);
+        // Reads may straddle adjacent ranges
+        assert_eq!(index.read(&file, 150, 100).unwrap(), contents[60..160]);
+        assert_eq!(index.read(&file, 100, 200).unwrap(), contents[10..210]);
+        // Unstored pages read as zeros
+        assert_eq!(index.read(&file, 0, 100).unwrap(), vec![0; 100]);
+        assert_eq!(index.read(&file, 300, 100).unwrap(), vec![0; 100]);
+        assert_eq!(index.read(&file, 410, 90).unwrap(), vec![0; 90]);
+        // A read which is only partly stored is an error
+        for (offset, len) in [(50, 100), (250, 100), (350, 100), (405, 10), (0, 500)] {
+            let err = index.read(&file, offset, len).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+    }
+}
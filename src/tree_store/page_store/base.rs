@@ -77,6 +77,14 @@ impl PageNumber {
         self_order0 < other_order0
     }
 
+    // Returns true if the two pages share any part of the file
+    #[cfg(debug_assertions)]
+    pub(crate) fn overlaps(&self, other: PageNumber) -> bool {
+        let start = |x: &PageNumber| u64::from(x.page_index) << x.page_order;
+        let end = |x: &PageNumber| (u64::from(x.page_index) + 1) << x.page_order;
+        self.region == other.region && start(self) < end(&other) && start(&other) < end(self)
+    }
+
     #[cfg(test)]
     pub(crate) fn to_order0(self) -> Vec<PageNumber> {
         let mut pages = vec![self];
@@ -142,6 +150,8 @@ impl Debug for PageNumber {
 }
 
 pub(crate) trait Page {
+    // The whole page is usable by the btree. Pages have no trailer: the checksum of a page is
+    // stored in its parent branch page, or in the commit slot for a root
     fn memory(&self) -> &[u8];
 
     fn get_page_number(&self) -> PageNumber;
@@ -264,4 +274,19 @@ mod test {
             page_size.try_into().unwrap(),
         );
     }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn overlaps() {
+        // Pages 4 and 5 of region 0
+        let page = PageNumber::new(0, 2, 1);
+        assert!(page.overlaps(page));
+        assert!(page.overlaps(PageNumber::new(0, 4, 0)));
+        assert!(page.overlaps(PageNumber::new(0, 5, 0)));
+        assert!(page.overlaps(PageNumber::new(0, 0, 3)));
+        assert!(!page.overlaps(PageNumber::new(0, 3, 0)));
+        assert!(!page.overlaps(PageNumber::new(0, 6, 0)));
+        assert!(!page.overlaps(PageNumber::new(0, 1, 1)));
+        assert!(!page.overlaps(PageNumber::new(1, 4, 0)));
+    }
 }
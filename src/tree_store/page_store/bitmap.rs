@@ -1,8 +1,13 @@
+use crate::{Result, StorageError};
 use std::mem::size_of;
 
 const HEIGHT_OFFSET: usize = 0;
 const END_OFFSETS: usize = HEIGHT_OFFSET + size_of::<u32>();
 
+fn corrupted_bitmap() -> StorageError {
+    StorageError::Corrupted("Invalid allocator bitmap".to_string())
+}
+
 pub(crate) struct BtreeBitmap {
     heights: Vec<U64GroupedBitmap>,
 }
@@ -54,6 +59,25 @@ impl BtreeBitmap {
         }
     }
 
+    pub(crate) fn find_last_unset(&self) -> Option<u32> {
+        if let Some(mut entry) = self.get_level(0).last_unset(0, 64) {
+            let mut height = 0;
+
+            while height < self.get_height() - 1 {
+                height += 1;
+                entry *= 64;
+                entry = self
+                    .get_level(height)
+                    .last_unset(entry, entry + 64)
+                    .unwrap();
+            }
+
+            Some(entry)
+        } else {
+            None
+        }
+    }
+
     fn get_level(&self, i: u32) -> &U64GroupedBitmap {
         assert!(i < self.get_height());
         &self.heights[i as usize]
@@ -85,7 +109,10 @@ impl BtreeBitmap {
         result
     }
 
-    pub(crate) fn from_bytes(data: &[u8]) -> Self {
+    pub(crate) fn from_bytes(data: &[u8]) -> Result<Self> {
+        if data.len() < END_OFFSETS {
+            return Err(corrupted_bitmap());
+        }
         let height = u32::from_le_bytes(
             data[HEIGHT_OFFSET..(HEIGHT_OFFSET + size_of::<u32>())]
                 .try_into()
@@ -93,21 +120,47 @@ impl BtreeBitmap {
         );
 
         let mut metadata = END_OFFSETS;
-        let mut data_start = END_OFFSETS + (height as usize) * size_of::<u32>();
+        let mut data_start = (height as usize)
+            .checked_mul(size_of::<u32>())
+            .and_then(|x| x.checked_add(END_OFFSETS))
+            .ok_or_else(corrupted_bitmap)?;
+        if height == 0 || data_start > data.len() {
+            return Err(corrupted_bitmap());
+        }
 
-        let mut heights = vec![];
+        let mut heights: Vec<U64GroupedBitmap> = vec![];
         for _ in 0..height {
             let data_end = u32::from_le_bytes(
                 data[metadata..(metadata + size_of::<u32>())]
                     .try_into()
                     .unwrap(),
             ) as usize;
-            heights.push(U64GroupedBitmap::from_bytes(&data[data_start..data_end]));
+            if data_end < data_start || data_end > data.len() {
+                return Err(corrupted_bitmap());
+            }
+            let level = U64GroupedBitmap::from_bytes(&data[data_start..data_end])?;
+            // Each bit of the parent level must cover a full group of 64 bits in this level
+            let parent_len = heights.last().map(|x| x.len() as u64).unwrap_or(1);
+            if (level.capacity() as u64) < parent_len * 64
+                || (level.len() as u64) > parent_len * 64
+                || !level.padding_is_set()
+            {
+                return Err(corrupted_bitmap());
+            }
+            // If a parent bit is clear, there must be a free id in the group below it
+            if let Some(parent) = heights.last() {
+                for i in 0..parent.len() {
+                    if !parent.get(i) && level.data[i as usize] == u64::MAX {
+                        return Err(corrupted_bitmap());
+                    }
+                }
+            }
+            heights.push(level);
             data_start = data_end;
             metadata += size_of::<u32>();
         }
 
-        Self { heights }
+        Ok(Self { heights })
     }
 
     // Initializes a new allocator, with no ids free
@@ -309,8 +362,12 @@ impl U64GroupedBitmap {
         result
     }
 
-    pub fn from_bytes(serialized: &[u8]) -> Self {
-        assert_eq!(0, (serialized.len() - size_of::<u32>()) % size_of::<u64>());
+    pub fn from_bytes(serialized: &[u8]) -> Result<Self> {
+        if serialized.len() < size_of::<u32>()
+            || (serialized.len() - size_of::<u32>()) % size_of::<u64>() != 0
+        {
+            return Err(corrupted_bitmap());
+        }
         let mut data = vec![];
         let len = u32::from_le_bytes(serialized[..size_of::<u32>()].try_into().unwrap());
         let words = (serialized.len() - size_of::<u32>()) / size_of::<u64>();
@@ -323,8 +380,19 @@ impl U64GroupedBitmap {
             );
             data.push(value);
         }
+        if len as u64 > words as u64 * u64::BITS as u64 {
+            return Err(corrupted_bitmap());
+        }
 
-        Self { len, data }
+        Ok(Self { len, data })
+    }
+
+    // Returns true if every bit past the end of the bitmap is set
+    fn padding_is_set(&self) -> bool {
+        (self.len..self.capacity()).all(|bit| {
+            let (index, bit_index) = self.data_index_of(bit);
+            self.data[index] & Self::select_mask(bit_index) != 0
+        })
     }
 
     fn data_index_of(&self, bit: u32) -> (usize, usize) {
@@ -372,6 +440,18 @@ impl U64GroupedBitmap {
         }
     }
 
+    // Padding past the end of the bitmap is always set, so it is never returned
+    fn last_unset(&self, start_bit: u32, end_bit: u32) -> Option<u32> {
+        assert_eq!(start_bit % 64, 0);
+        assert_eq!(end_bit, start_bit + 64);
+
+        let (index, _) = self.data_index_of(start_bit);
+        match self.data[index].leading_ones() {
+            64 => None,
+            x => Some(start_bit + 63 - x),
+        }
+    }
+
     pub fn len(&self) -> u32 {
         self.len
     }
@@ -497,6 +577,21 @@ mod test {
         assert_eq!(allocator.find_first_unset().unwrap(), 0);
     }
 
+    #[test]
+    fn find_last_unset() {
+        let num_pages = 129;
+        let mut allocator = BtreeBitmap::new(num_pages);
+        assert!(allocator.find_last_unset().is_none());
+        allocator.clear(0);
+        assert_eq!(allocator.find_last_unset().unwrap(), 0);
+        allocator.clear(65);
+        assert_eq!(allocator.find_last_unset().unwrap(), 65);
+        allocator.clear(128);
+        assert_eq!(allocator.find_last_unset().unwrap(), 128);
+        allocator.set(128);
+        assert_eq!(allocator.find_last_unset().unwrap(), 65);
+    }
+
     #[test]
     fn iter() {
         let num_pages = 129;
@@ -1,16 +1,22 @@
 use crate::tree_store::page_store::bitmap::{BtreeBitmap, U64GroupedBitmap};
 use crate::tree_store::page_store::page_manager::MAX_MAX_PAGE_ORDER;
 use crate::tree_store::PageNumber;
+use crate::{Result, StorageError};
 use std::cmp::min;
 #[cfg(test)]
 use std::collections::HashSet;
 use std::mem::size_of;
+use std::ops::Range;
 
 const MAX_ORDER_OFFSET: usize = 0;
 const PADDING: usize = 3;
 const NUM_PAGES_OFFSET: usize = MAX_ORDER_OFFSET + size_of::<u8>() + PADDING;
 const FREE_END_OFFSETS: usize = NUM_PAGES_OFFSET + size_of::<u32>();
 
+fn corrupted_allocator() -> StorageError {
+    StorageError::Corrupted("Invalid region allocator state".to_string())
+}
+
 fn calculate_usable_order(pages: u32) -> u8 {
     let max_order = (32 - pages.leading_zeros() - 1).try_into().unwrap();
     min(MAX_MAX_PAGE_ORDER, max_order)
@@ -108,7 +114,10 @@ impl BuddyAllocator {
         result
     }
 
-    pub(crate) fn from_bytes(data: &[u8]) -> Self {
+    pub(crate) fn from_bytes(data: &[u8]) -> Result<Self> {
+        if data.len() < FREE_END_OFFSETS || data[MAX_ORDER_OFFSET] > MAX_MAX_PAGE_ORDER {
+            return Err(corrupted_allocator());
+        }
         let max_order = data[MAX_ORDER_OFFSET];
         let num_pages = u32::from_le_bytes(
             data[NUM_PAGES_OFFSET..(NUM_PAGES_OFFSET + size_of::<u32>())]
@@ -118,6 +127,9 @@ impl BuddyAllocator {
 
         let mut metadata = FREE_END_OFFSETS;
         let mut data_start = FREE_END_OFFSETS + (max_order as usize + 1) * 2 * size_of::<u32>();
+        if data_start > data.len() {
+            return Err(corrupted_allocator());
+        }
 
         let mut free = vec![];
         for _ in 0..=max_order {
@@ -126,7 +138,10 @@ impl BuddyAllocator {
                     .try_into()
                     .unwrap(),
             ) as usize;
-            free.push(BtreeBitmap::from_bytes(&data[data_start..data_end]));
+            if data_end < data_start || data_end > data.len() {
+                return Err(corrupted_allocator());
+            }
+            free.push(BtreeBitmap::from_bytes(&data[data_start..data_end])?);
             data_start = data_end;
             metadata += size_of::<u32>();
         }
@@ -137,17 +152,34 @@ impl BuddyAllocator {
                     .try_into()
                     .unwrap(),
             ) as usize;
-            allocated.push(U64GroupedBitmap::from_bytes(&data[data_start..data_end]));
+            if data_end < data_start || data_end > data.len() {
+                return Err(corrupted_allocator());
+            }
+            allocated.push(U64GroupedBitmap::from_bytes(&data[data_start..data_end])?);
             data_start = data_end;
             metadata += size_of::<u32>();
         }
 
-        Self {
+        // Each order tracks half as many pages as the one below it
+        let capacity = free[0].len();
+        for order in 0..=max_order {
+            let pages_for_order = capacity >> order;
+            if free[order as usize].len() != pages_for_order
+                || allocated[order as usize].len() != pages_for_order
+            {
+                return Err(corrupted_allocator());
+            }
+        }
+        if num_pages > capacity {
+            return Err(corrupted_allocator());
+        }
+
+        Ok(Self {
             allocated,
             free,
             len: num_pages,
             max_order,
-        }
+        })
     }
 
     #[inline]
@@ -187,6 +219,29 @@ impl BuddyAllocator {
         None
     }
 
+    // Ranges of consecutive free pages, in increasing order
+    pub(crate) fn free_ranges(&self) -> Vec<Range<u32>> {
+        let mut result: Vec<Range<u32>> = vec![];
+        let mut page = 0;
+        while page < self.len() {
+            let order = if let Some(order) = self.find_free_order(page) {
+                order
+            } else {
+                page += 1;
+                continue;
+            };
+            // Skip to the end of the free block which contains the page
+            let end = min(((page >> order) + 1) << order, self.len());
+            match result.last_mut() {
+                Some(last) if last.end == page => last.end = end,
+                _ => result.push(page..end),
+            }
+            page = end;
+        }
+
+        result
+    }
+
     pub(crate) fn trailing_free_pages(&self) -> u32 {
         let mut free_pages = 0;
         let mut next_page = self.len() - 1;
@@ -244,7 +299,7 @@ impl BuddyAllocator {
                     .unwrap(),
             ) as usize;
             let bytes = &state[data_start..data_end];
-            let savepoint_allocated = U64GroupedBitmap::from_bytes(bytes);
+            let savepoint_allocated = U64GroupedBitmap::from_bytes(bytes).unwrap();
             let self_allocated = self.get_order_allocated(order);
             for i in self_allocated.difference(&savepoint_allocated) {
                 if i >= num_pages {
@@ -444,6 +499,53 @@ impl BuddyAllocator {
         Some(best.0)
     }
 
+    pub(crate) fn alloc_highest(&mut self, order: u8) -> Option<u32> {
+        let page = self.alloc_highest_inner(order);
+        if let Some(page_number) = page {
+            debug_assert!(!self.get_order_allocated(order).get(page_number));
+            self.get_order_allocated_mut(order).set(page_number);
+        }
+        page
+    }
+
+    pub(crate) fn alloc_highest_inner(&mut self, order: u8) -> Option<u32> {
+        if order > self.max_order {
+            return None;
+        }
+        // Best (index, order) found, and the end of that block in pages of the requested order
+        let mut best: Option<(u32, u8)> = None;
+        let mut best_end = 0;
+
+        // Find the free block which ends at the highest index, across all orders
+        let mut multiplier = 1;
+        for i in order..=self.max_order {
+            if let Some(index) = self.get_order_free(i).find_last_unset() {
+                let end = (index + 1) * multiplier;
+                if end > best_end {
+                    best_end = end;
+                    best = Some((index, i));
+                }
+            }
+            multiplier *= 2;
+        }
+        let (mut best_index, mut best_order) = best?;
+        self.get_order_free_mut(best_order).set(best_index);
+
+        // Split the page, keeping the upper half, until we get to the requested order
+        while best_order > order {
+            let (free1, free2) = (best_index * 2, best_index * 2 + 1);
+            let allocator = self.get_order_free_mut(best_order - 1);
+            debug_assert!(allocator.get(free1));
+            debug_assert!(allocator.get(free2));
+            allocator.clear(free1);
+            best_index = free2;
+            best_order -= 1;
+        }
+        assert_eq!(best_index + 1, best_end);
+
+        Some(best_index)
+    }
+
     pub(crate) fn alloc(&mut self, order: u8) -> Option<u32> {
         let page = self.alloc_inner(order);
         if let Some(page_number) = page {
@@ -475,6 +577,7 @@ impl BuddyAllocator {
 
     /// data must have been initialized by Self::init_new(), and page_number must be free
     pub(crate) fn record_alloc(&mut self, page_number: u32, order: u8) {
+        // Kept in release builds: repair calls this with page orders read from the file
         assert!(order <= self.max_order);
         // Only record the allocation for the actual page
         self.get_order_allocated_mut(order).set(page_number);
@@ -531,6 +634,20 @@ impl BuddyAllocator {
         }
     }
 
+    // Returns true if the page is entirely free, either by itself or as part of a larger free page
+    pub(crate) fn is_free(&self, mut page_number: u32, order: u8) -> bool {
+        if order > self.max_order || (u64::from(page_number) + 1) << order > u64::from(self.len()) {
+            return false;
+        }
+        for order in order..=self.max_order {
+            if !self.get_order_free(order).get(page_number) {
+                return true;
+            }
+            page_number = next_higher_order(page_number);
+        }
+        false
+    }
+
     pub(crate) fn is_allocated(&self, page_number: u32, order: u8) -> bool {
         self.get_order_allocated(order).get(page_number)
     }
@@ -595,6 +712,30 @@ mod test {
         }
     }
 
+    #[test]
+    fn alloc_highest() {
+        let num_pages = 100;
+        let mut allocator = BuddyAllocator::new(num_pages, 128);
+        assert_eq!(allocator.alloc_highest(0), Some(99));
+        assert_eq!(allocator.alloc_highest(0), Some(98));
+        // Pages 96..98
+        assert_eq!(allocator.alloc_highest(1), Some(48));
+        // Pages 92..96
+        assert_eq!(allocator.alloc_highest(2), Some(23));
+        // Splitting 64..96 for it left 88..92 free, but the block at 80..88 is the highest to fit
+        assert_eq!(allocator.alloc_highest(3), Some(10));
+        assert_eq!(allocator.alloc_lowest(0), Some(0));
+        assert_eq!(allocator.count_allocated_pages(), 17);
+
+        allocator.free(99, 0);
+        allocator.free(98, 0);
+        allocator.free(48, 1);
+        allocator.free(23, 2);
+        allocator.free(10, 3);
+        allocator.free(0, 0);
+        assert_eq!(allocator.count_allocated_pages(), 0);
+    }
+
     #[test]
     fn alloc_large() {
         let num_pages = 256;
@@ -618,6 +759,31 @@ mod test {
         assert_eq!(allocator.count_allocated_pages(), 0);
     }
 
+    #[test]
+    fn free_ranges() {
+        let num_pages = 100;
+        let mut allocator = BuddyAllocator::new(num_pages, 128);
+        assert_eq!(allocator.free_ranges(), vec![0..num_pages]);
+
+        for _ in 0..num_pages {
+            allocator.alloc_lowest(0).unwrap();
+        }
+        assert!(allocator.free_ranges().is_empty());
+
+        for page in [3, 4, 5, 10, 64, 65, 66, 67, 99] {
+            allocator.free(page, 0);
+        }
+        assert_eq!(allocator.free_ranges(), [3..6, 10..11, 64..68, 99..100]);
+
+        let allocated: Vec<u32> = (0..num_pages)
+            .filter(|page| !allocator.is_free(*page, 0))
+            .collect();
+        for page in allocated {
+            allocator.free(page, 0);
+        }
+        assert_eq!(allocator.free_ranges(), vec![0..num_pages]);
+    }
+
     #[test]
     fn serialized_size() {
         // Check that serialized size is as expected for a full region
@@ -1,19 +1,28 @@
+use crate::tree_store::page_store::backup::BackupIndex;
 use crate::tree_store::page_store::base::PageHint;
 use crate::tree_store::page_store::file_lock::LockedFile;
+use crate::tree_store::page_store::mirror::{copy_file, same_header};
 use crate::tree_store::LEAF;
 use crate::{DatabaseError, Result, StorageError};
 use std::collections::BTreeMap;
 use std::fs::File;
 use std::io;
+#[cfg(all(target_os = "macos", not(fuzzing)))]
+use std::iter;
 use std::mem;
 use std::ops::{Index, IndexMut};
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
 #[cfg(any(target_os = "linux", all(unix, not(fuzzing))))]
 use std::os::unix::io::AsRawFd;
 use std::slice::SliceIndex;
-#[cfg(any(fuzzing, test, feature = "cache_metrics"))]
-use std::sync::atomic::AtomicU64;
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex, RwLock};
+#[cfg(test)]
+use std::thread;
+use std::time::Duration;
+#[cfg(not(fuzzing))]
+use std::time::Instant;
 
 pub(super) struct WritablePage<'a> {
     buffer: &'a Mutex<PrioritizedCache>,
@@ -36,12 +45,14 @@ impl<'a> Drop for WritablePage<'a> {
         let data = mem::take(&mut self.data);
         // TODO: it's quite a hack to check the leaf/branch byte here
         let low_pri = data[0] == LEAF;
-        assert!(self
+        let existing = self
             .buffer
             .lock()
             .unwrap()
-            .insert(self.offset, Arc::new(data), low_pri)
-            .is_none());
+            .insert(self.offset, Arc::new(data), low_pri);
+        // write() removed this offset from the buffer while holding the lock, and the page manager
+        // never hands out two WritablePages for the same page
+        debug_assert!(existing.is_none());
     }
 }
 
@@ -111,8 +122,26 @@ impl PrioritizedCache {
     }
 }
 
+// A window of a larger file which holds the database. Offsets within the database are relative to
+// the start of the window, and the database may grow until it fills the window
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct EmbeddedRange {
+    pub(crate) offset: u64,
+    pub(crate) len: u64,
+}
+
 pub(super) struct PagedCachedFile {
     file: LockedFile,
+    // Second copy of the file, which receives every write made to it. Never set for an embedded
+    // database
+    mirror: Option<LockedFile>,
+    // Offset of the database within the file. Zero, unless the database is embedded
+    base_offset: u64,
+    // Length of the window and the current length of an embedded database. The length of the
+    // database is tracked here, since it is not the length of the file
+    embedded_len: Option<(u64, AtomicU64)>,
+    // Set if the file holds a full backup, which is read in place. The file is never written then
+    backup: Option<BackupIndex>,
     page_size: u64,
     max_read_cache_bytes: usize,
     read_cache_bytes: AtomicUsize,
@@ -123,16 +152,38 @@ pub(super) struct PagedCachedFile {
     #[cfg(feature = "cache_metrics")]
     reads_hits: AtomicU64,
     fsync_failed: AtomicBool,
+    // Moving average of the duration of recent fsyncs, in nanoseconds, or zero if none has completed
+    sync_nanos: AtomicU64,
     read_cache: Vec<RwLock<PrioritizedCache>>,
     // TODO: maybe move this cache to WriteTransaction?
     write_buffer: Mutex<PrioritizedCache>,
     #[cfg(any(fuzzing, test))]
     crash_countdown: AtomicU64,
+    // Number of fsyncs after which the contents of the file are captured. They are written back
+    // when the file is dropped, which loses every write made after that fsync, as in a power loss
+    #[cfg(any(fuzzing, test))]
+    power_loss_countdown: AtomicU64,
+    #[cfg(any(fuzzing, test))]
+    power_loss_image: Mutex<Option<Vec<u8>>>,
+    // Number of following fsyncs which are interrupted by a simulated signal, before being retried
+    #[cfg(test)]
+    interrupted_fsyncs: AtomicU64,
+    // Number of fsyncs which have completed
+    #[cfg(test)]
+    fsync_count: AtomicU64,
+    // Offset of the next write which is silently discarded, as by a faulty storage device
+    #[cfg(test)]
+    dropped_write: Mutex<Option<u64>>,
+    // Added to the duration of every fsync, to simulate a slow storage device
+    #[cfg(test)]
+    sync_delay_nanos: AtomicU64,
 }
 
 impl PagedCachedFile {
     pub(super) fn new(
         file: File,
+        embedded: Option<EmbeddedRange>,
+        backup: Option<BackupIndex>,
         page_size: u64,
         max_read_cache_bytes: usize,
         max_write_buffer_bytes: usize,
@@ -151,8 +202,20 @@ impl PagedCachedFile {
             libc::posix_fadvise64(lock.file().as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED);
         }
 
+        let embedded_len = match embedded {
+            Some(range) => {
+                let available = lock.file().metadata()?.len().saturating_sub(range.offset);
+                Some((range.len, AtomicU64::new(available.min(range.len))))
+            }
+            None => None,
+        };
+
         Ok(Self {
             file: lock,
+            mirror: None,
+            base_offset: embedded.map_or(0, |range| range.offset),
+            embedded_len,
+            backup,
             page_size,
             max_read_cache_bytes,
             read_cache_bytes: AtomicUsize::new(0),
@@ -163,15 +226,75 @@ impl PagedCachedFile {
             #[cfg(feature = "cache_metrics")]
             reads_hits: Default::default(),
             fsync_failed: Default::default(),
+            sync_nanos: AtomicU64::new(0),
             read_cache,
             write_buffer: Mutex::new(PrioritizedCache::new()),
             #[cfg(any(fuzzing, test))]
             crash_countdown: AtomicU64::new(u64::MAX),
+            #[cfg(any(fuzzing, test))]
+            power_loss_countdown: AtomicU64::new(u64::MAX),
+            #[cfg(any(fuzzing, test))]
+            power_loss_image: Mutex::new(None),
+            #[cfg(test)]
+            interrupted_fsyncs: AtomicU64::new(0),
+            #[cfg(test)]
+            fsync_count: AtomicU64::new(0),
+            #[cfg(test)]
+            dropped_write: Mutex::new(None),
+            #[cfg(test)]
+            sync_delay_nanos: AtomicU64::new(0),
         })
     }
 
+    // Starts mirroring writes to `mirror`, which is first overwritten with the contents of the file,
+    // unless they already match
+    pub(super) fn set_mirror(&mut self, mirror: File) -> Result<(), DatabaseError> {
+        assert!(!self.is_embedded());
+        let mirror = LockedFile::new(mirror)?;
+        self.flush()?;
+        if !same_header(self.file.file(), mirror.file())? {
+            copy_file(self.file.file(), mirror.file())?;
+        }
+        self.mirror = Some(mirror);
+        Ok(())
+    }
+
+    // Writes directly to the file, and to the mirror
+    fn write_file(&self, offset: u64, data: &[u8]) -> io::Result<()> {
+        self.file.write(self.base_offset + offset, data)?;
+        if let Some(mirror) = &self.mirror {
+            mirror.write(offset, data)?;
+        }
+        Ok(())
+    }
+
     pub(crate) fn raw_file_len(&self) -> Result<u64> {
-        Ok(self.file.file().metadata()?.len())
+        if let Some((_, len)) = &self.embedded_len {
+            Ok(len.load(Ordering::Acquire))
+        } else if let Some(backup) = &self.backup {
+            Ok(backup.len())
+        } else {
+            Ok(self.file.file().metadata()?.len())
+        }
+    }
+
+    // Bytes of storage allocated to the file, which is less than its length if it is sparse. Only
+    // Unix reports this, so elsewhere the length is returned
+    pub(crate) fn physical_file_len(&self) -> Result<u64> {
+        let metadata = self.file.file().metadata()?;
+        #[cfg(unix)]
+        {
+            // st_blocks is always in units of 512 bytes, whatever the block size of the file system
+            Ok(metadata.blocks() * 512)
+        }
+        #[cfg(not(unix))]
+        {
+            Ok(metadata.len())
+        }
+    }
+
+    pub(super) fn is_embedded(&self) -> bool {
+        self.embedded_len.is_some()
     }
 
     #[cfg(any(fuzzing, test))]
@@ -179,12 +302,61 @@ impl PagedCachedFile {
         self.crash_countdown.store(value, Ordering::Release);
     }
 
+    // Simulate a power loss immediately after the `value`th following fsync, or immediately if
+    // `value` is zero. u64::MAX disables it
+    #[cfg(any(fuzzing, test))]
+    pub(crate) fn set_power_loss_countdown(&self, value: u64) -> Result {
+        *self.power_loss_image.lock().unwrap() = None;
+        self.power_loss_countdown.store(value, Ordering::Release);
+        if value == 0 {
+            self.capture_power_loss_image()?;
+        }
+        Ok(())
+    }
+
+    #[cfg(test)]
+    pub(crate) fn set_interrupted_fsyncs(&self, value: u64) {
+        self.interrupted_fsyncs.store(value, Ordering::Release);
+    }
+
+    #[cfg(test)]
+    pub(crate) fn set_sync_delay(&self, delay: Duration) {
+        self.sync_delay_nanos
+            .store(delay.as_nanos().try_into().unwrap(), Ordering::Release);
+    }
+
+    #[cfg(test)]
+    pub(crate) fn fsync_count(&self) -> u64 {
+        self.fsync_count.load(Ordering::Acquire)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn set_dropped_write(&self, offset: u64) {
+        *self.dropped_write.lock().unwrap() = Some(offset);
+    }
+
+    #[cfg(any(fuzzing, test))]
+    fn capture_power_loss_image(&self) -> Result {
+        self.power_loss_countdown.store(u64::MAX, Ordering::Release);
+        let len = self.raw_file_len()?.try_into().unwrap();
+        let image = self.file.read(self.base_offset, len)?;
+        *self.power_loss_image.lock().unwrap() = Some(image);
+        Ok(())
+    }
+
+    // Bytes held by the read cache and write buffer
+    #[cfg(test)]
+    pub(super) fn cached_bytes(&self) -> usize {
+        self.read_cache_bytes.load(Ordering::Acquire)
+            + self.write_buffer_bytes.load(Ordering::Acquire)
+    }
+
     const fn lock_stripes() -> usize {
         131
     }
 
     #[inline]
-    fn check_fsync_failure(&self) -> Result<()> {
+    pub(super) fn check_fsync_failure(&self) -> Result<()> {
         if self.fsync_failed.load(Ordering::Acquire) {
             Err(StorageError::Io(io::Error::from(io::ErrorKind::Other)))
         } else {
@@ -198,6 +370,32 @@ impl PagedCachedFile {
         self.fsync_failed.store(failed, Ordering::Release);
     }
 
+    // Retries `sync` for as long as it is interrupted by a signal. An interrupted fsync has not
+    // failed, so it must not be treated as one. Any other error is sticky, since the kernel may have
+    // already dropped the dirty pages it failed to write (EIO), or have written only some of them
+    // (ENOSPC), and retrying the fsync could then report success without the data being durable
+    #[cfg(not(fuzzing))]
+    fn retry_interrupted(&self, mut sync: impl FnMut() -> io::Result<()>) -> io::Result<()> {
+        loop {
+            #[cfg(test)]
+            let result = if self
+                .interrupted_fsyncs
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |x| x.checked_sub(1))
+                .is_ok()
+            {
+                Err(io::Error::from(io::ErrorKind::Interrupted))
+            } else {
+                sync()
+            };
+            #[cfg(not(test))]
+            let result = sync();
+            match result {
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                result => return result,
+            }
+        }
+    }
+
     fn flush_write_buffer(&self) -> Result {
         #[cfg(any(fuzzing, test))]
         {
@@ -208,11 +406,20 @@ impl PagedCachedFile {
         self.check_fsync_failure()?;
         let mut write_buffer = self.write_buffer.lock().unwrap();
 
-        for (offset, buffer) in write_buffer.cache.iter() {
-            self.file.write(*offset, buffer)?;
-        }
-        for (offset, buffer) in write_buffer.low_pri_cache.iter() {
-            self.file.write(*offset, buffer)?;
+        for (offset, buffer) in write_buffer
+            .cache
+            .iter()
+            .chain(write_buffer.low_pri_cache.iter())
+        {
+            #[cfg(test)]
+            {
+                let mut dropped_write = self.dropped_write.lock().unwrap();
+                if *dropped_write == Some(*offset) {
+                    *dropped_write = None;
+                    continue;
+                }
+            }
+            self.write_file(*offset, buffer)?;
         }
         self.write_buffer_bytes.store(0, Ordering::Release);
         write_buffer.clear();
@@ -225,16 +432,77 @@ impl PagedCachedFile {
         // TODO: be more fine-grained about this invalidation
         self.invalidate_cache_all();
 
-        self.file.file().set_len(len).map_err(StorageError::from)
+        if let Some((max_len, current_len)) = &self.embedded_len {
+            if len > *max_len {
+                return Err(StorageError::Io(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("Embedded database cannot grow beyond its {max_len} byte window"),
+                )));
+            }
+            // The file is only ever extended, since it may hold other data after the window
+            if self.base_offset + len > self.file.file().metadata()?.len() {
+                self.file.file().set_len(self.base_offset + len)?;
+            }
+            current_len.store(len, Ordering::Release);
+            Ok(())
+        } else {
+            self.file.file().set_len(len)?;
+            if let Some(mirror) = &self.mirror {
+                mirror.file().set_len(len)?;
+            }
+            Ok(())
+        }
     }
 
     pub(super) fn flush(&self) -> Result {
         self.check_fsync_failure()?;
         self.flush_write_buffer()?;
+        self.sync_data()
+    }
+
+    // Folds the duration of a completed fsync into the moving average, weighting it by a quarter
+    #[cfg(not(fuzzing))]
+    fn record_sync_time(&self, elapsed: Duration) {
+        let sample = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX).max(1);
+        let _ = self
+            .sync_nanos
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |old| {
+                Some(if old == 0 {
+                    sample
+                } else {
+                    old / 4 * 3 + sample / 4
+                })
+            });
+    }
+
+    // Estimate of how long the next fsync will take, or None if no fsync has completed yet
+    pub(super) fn estimated_sync_time(&self) -> Option<Duration> {
+        match self.sync_nanos.load(Ordering::Acquire) {
+            0 => None,
+            nanos => Some(Duration::from_nanos(nanos)),
+        }
+    }
+
+    // Syncs the writes which have already been handed to the OS, without flushing the write buffer
+    pub(super) fn sync_data(&self) -> Result {
+        self.check_fsync_failure()?;
         // Disable fsync when fuzzing, since it doesn't test crash consistency
         #[cfg(not(fuzzing))]
         {
-            let res = self.file.file().sync_data().map_err(StorageError::from);
+            let start = Instant::now();
+            #[cfg(test)]
+            thread::sleep(Duration::from_nanos(
+                self.sync_delay_nanos.load(Ordering::Acquire),
+            ));
+            // The mirror is synced second, so if a crash comes between the two, it holds the older
+            // commit and is authoritative when the files are reconciled
+            let res = self
+                .retry_interrupted(|| self.file.file().sync_data())
+                .and_then(|()| match &self.mirror {
+                    Some(mirror) => self.retry_interrupted(|| mirror.file().sync_data()),
+                    None => Ok(()),
+                })
+                .map_err(StorageError::from);
             if res.is_err() {
                 self.set_fsync_failed(true);
                 // Try to flush any pages in the page cache that are out of sync with disk.
@@ -250,6 +518,19 @@ impl PagedCachedFile {
                 }
                 return res;
             }
+            self.record_sync_time(start.elapsed());
+            #[cfg(test)]
+            self.fsync_count.fetch_add(1, Ordering::AcqRel);
+        }
+        #[cfg(any(fuzzing, test))]
+        {
+            let countdown = self.power_loss_countdown.load(Ordering::Acquire);
+            if countdown == 1 {
+                self.capture_power_loss_image()?;
+            } else if countdown != u64::MAX {
+                self.power_loss_countdown
+                    .store(countdown - 1, Ordering::Release);
+            }
         }
 
         Ok(())
@@ -265,10 +546,19 @@ impl PagedCachedFile {
         #[cfg(all(target_os = "macos", not(fuzzing)))]
         {
             self.flush_write_buffer()?;
-            let code = unsafe { libc::fcntl(self.file.file().as_raw_fd(), libc::F_BARRIERFSYNC) };
-            if code == -1 {
+            let res = self.retry_interrupted(|| {
+                for file in iter::once(&self.file).chain(&self.mirror) {
+                    let code =
+                        unsafe { libc::fcntl(file.file().as_raw_fd(), libc::F_BARRIERFSYNC) };
+                    if code == -1 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+            if let Err(err) = res {
                 self.set_fsync_failed(true);
-                return Err(io::Error::last_os_error().into());
+                return Err(err.into());
             }
         }
 
@@ -289,7 +579,63 @@ impl PagedCachedFile {
             }
         }
         self.check_fsync_failure()?;
-        Ok(self.file.read(offset, len)?)
+        if let Some(backup) = &self.backup {
+            return Ok(backup.read(&self.file, offset, len)?);
+        }
+        Ok(self.file.read(self.base_offset + offset, len)?)
+    }
+
+    // Reads the given range back from the storage device, once it has been synced, rather than
+    // from the OS page cache, which returns what was written even if the device lost it. The page
+    // cache can only be dropped on Linux, so elsewhere this checks the write path alone
+    pub(super) fn read_synced(&self, offset: u64, len: usize) -> Result<Vec<u8>> {
+        #[cfg(target_os = "linux")]
+        unsafe {
+            libc::posix_fadvise64(
+                self.file.file().as_raw_fd(),
+                i64::try_from(self.base_offset + offset).unwrap(),
+                i64::try_from(len).unwrap(),
+                libc::POSIX_FADV_DONTNEED,
+            );
+        }
+        self.read_direct(offset, len)
+    }
+
+    // Fails all following I/O, as after an fsync failure, once the file is known not to hold what
+    // was written to it
+    pub(super) fn poison(&self) {
+        self.fsync_failed.store(true, Ordering::Release);
+    }
+
+    // Reads the given range from the mirror, or returns None if there is no mirror
+    pub(super) fn read_mirror(&self, offset: u64, len: usize) -> Result<Option<Vec<u8>>> {
+        match &self.mirror {
+            Some(mirror) => Ok(Some(mirror.read(offset, len)?)),
+            None => Ok(None),
+        }
+    }
+
+    // Writes to the file and the mirror, bypassing the write buffer. Only for ranges which are
+    // never read through the cache, such as the commit journal
+    pub(super) fn write_unbuffered(&self, offset: u64, data: &[u8]) -> Result {
+        #[cfg(any(fuzzing, test))]
+        {
+            if self.crash_countdown.load(Ordering::Acquire) == 0 {
+                return Err(StorageError::SimulatedIOFailure);
+            }
+        }
+        self.check_fsync_failure()?;
+        self.write_file(offset, data)?;
+        Ok(())
+    }
+
+    // Overwrites the given range of the file, but not of the mirror, bypassing the write buffer.
+    // Used to repair the file from the mirror
+    pub(super) fn write_primary(&self, offset: u64, data: &[u8]) -> Result {
+        self.check_fsync_failure()?;
+        self.invalidate_cache(offset, data.len());
+        self.file.write(self.base_offset + offset, data)?;
+        Ok(())
     }
 
     // Read with caching. Caller must not read overlapping ranges without first calling invalidate_cache().
@@ -345,13 +691,58 @@ impl PagedCachedFile {
 
     // Discard pending writes to the given range
     pub(super) fn cancel_pending_write(&self, offset: u64, _len: usize) {
-        assert_eq!(0, offset % self.page_size);
+        debug_assert_eq!(0, offset % self.page_size);
         if let Some(removed) = self.write_buffer.lock().unwrap().remove(&offset) {
             self.write_buffer_bytes
                 .fetch_sub(removed.len(), Ordering::Release);
         }
     }
 
+    // Overwrite the given range with zeros. This bypasses the write buffer, so that a pending scrub
+    // can never be flushed over a later allocation of the range
+    pub(super) fn scrub(&self, offset: u64, len: usize) -> Result<()> {
+        self.cancel_pending_write(offset, len);
+        #[cfg(any(fuzzing, test))]
+        {
+            if self.crash_countdown.load(Ordering::Acquire) == 0 {
+                return Err(StorageError::SimulatedIOFailure);
+            }
+        }
+        self.write_file(offset, &vec![0; len])?;
+        Ok(())
+    }
+
+    // Deallocates the storage of the given range, so that it reads back as zeros without occupying
+    // space in the file system. As with scrub(), this bypasses the write buffer. Returns false if
+    // the file system, or platform, does not support it, in which case the range is left as is
+    pub(super) fn punch_hole(&self, offset: u64, len: usize) -> bool {
+        self.cancel_pending_write(offset, len);
+        #[cfg(target_os = "linux")]
+        {
+            let punch = |file: &LockedFile, offset: u64| {
+                let code = unsafe {
+                    libc::fallocate(
+                        file.file().as_raw_fd(),
+                        libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+                        offset.try_into().unwrap(),
+                        len.try_into().unwrap(),
+                    )
+                };
+                code == 0
+            };
+            // If the mirror can't punch the range, the caller falls back to scrubbing both files
+            punch(&self.file, self.base_offset + offset)
+                && self
+                    .mirror
+                    .as_ref()
+                    .map_or(true, |mirror| punch(mirror, offset))
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            false
+        }
+    }
+
     // Invalidate any caching of the given range. After this call overlapping reads of the range are allowed
     //
     // NOTE: Invalidating a cached region in subsections is permitted, as long as all subsections are invalidated
@@ -359,6 +750,8 @@ impl PagedCachedFile {
         let cache_slot: usize = (offset % self.read_cache.len() as u64).try_into().unwrap();
         let mut lock = self.read_cache[cache_slot].write().unwrap();
         if let Some(removed) = lock.remove(&offset) {
+            // Kept in release builds: a mismatch means the cache is out of sync with the allocator,
+            // and this function has no way to report it
             assert_eq!(len, removed.len());
             self.read_cache_bytes
                 .fetch_sub(removed.len(), Ordering::AcqRel);
@@ -378,7 +771,8 @@ impl PagedCachedFile {
     // If overwrite is true, the page is initialized to zero
     pub(super) fn write(&self, offset: u64, len: usize, overwrite: bool) -> Result<WritablePage> {
         self.check_fsync_failure()?;
-        assert_eq!(0, offset % self.page_size);
+        // Offsets always come from PageNumber::address_range(), which is page aligned
+        debug_assert_eq!(0, offset % self.page_size);
         let mut lock = self.write_buffer.lock().unwrap();
 
         // TODO: allow hint that page is known to be dirty and will not be in the read cache
@@ -386,14 +780,16 @@ impl PagedCachedFile {
         let existing = {
             let mut lock = self.read_cache[cache_slot].write().unwrap();
             if let Some(removed) = lock.remove(&offset) {
-                assert_eq!(
-                    len,
-                    removed.len(),
-                    "cache inconsistency {len} != {} for offset {offset}",
-                    removed.len()
-                );
                 self.read_cache_bytes
                     .fetch_sub(removed.len(), Ordering::AcqRel);
+                // The length depends on the page order, which is read from the file, so a corrupted
+                // database can make two pages of different orders alias
+                if len != removed.len() {
+                    return Err(StorageError::Corrupted(format!(
+                        "Cache inconsistency {len} != {} for offset {offset}",
+                        removed.len()
+                    )));
+                }
                 Some(Arc::try_unwrap(removed).unwrap())
             } else {
                 None
@@ -404,40 +800,48 @@ impl PagedCachedFile {
             Arc::try_unwrap(removed).unwrap()
         } else {
             let previous = self.write_buffer_bytes.fetch_add(len, Ordering::AcqRel);
-            if previous + len > self.max_write_buffer_bytes {
-                #[cfg(any(fuzzing, test))]
-                {
-                    if self.crash_countdown.load(Ordering::Acquire) == 0 {
-                        return Err(StorageError::SimulatedIOFailure);
+            let make_room_and_load = || -> Result<Vec<u8>> {
+                if previous + len > self.max_write_buffer_bytes {
+                    #[cfg(any(fuzzing, test))]
+                    {
+                        if self.crash_countdown.load(Ordering::Acquire) == 0 {
+                            return Err(StorageError::SimulatedIOFailure);
+                        }
+                        self.crash_countdown.fetch_sub(1, Ordering::AcqRel);
                     }
-                    self.crash_countdown.fetch_sub(1, Ordering::AcqRel);
-                }
-                let mut removed_bytes = 0;
-                while removed_bytes < len {
-                    if let Some((offset, buffer)) = lock.pop_lowest_priority() {
-                        let removed_len = buffer.len();
-                        let result = self.file.write(offset, &buffer);
-                        if result.is_err() {
-                            let low_pri = buffer[0] == LEAF;
-                            // TODO: it's quite a hack to check the leaf/branch byte here
-                            lock.insert(offset, buffer, low_pri);
+                    let mut removed_bytes = 0;
+                    while removed_bytes < len {
+                        if let Some((offset, buffer)) = lock.pop_lowest_priority() {
+                            let removed_len = buffer.len();
+                            let result = self.write_file(offset, &buffer);
+                            if result.is_err() {
+                                let low_pri = buffer[0] == LEAF;
+                                // TODO: it's quite a hack to check the leaf/branch byte here
+                                lock.insert(offset, buffer, low_pri);
+                            }
+                            result?;
+                            self.write_buffer_bytes
+                                .fetch_sub(removed_len, Ordering::Release);
+                            removed_bytes += removed_len;
+                        } else {
+                            break;
                         }
-                        result?;
-                        self.write_buffer_bytes
-                            .fetch_sub(removed_len, Ordering::Release);
-                        removed_bytes += removed_len;
-                    } else {
-                        break;
                     }
                 }
+                if let Some(data) = existing {
+                    Ok(data)
+                } else if overwrite {
+                    Ok(vec![0; len])
+                } else {
+                    self.read_direct(offset, len)
+                }
+            };
+            let result = make_room_and_load();
+            if result.is_err() {
+                // Nothing was added to the write buffer
+                self.write_buffer_bytes.fetch_sub(len, Ordering::Release);
             }
-            if let Some(data) = existing {
-                data
-            } else if overwrite {
-                vec![0; len]
-            } else {
-                self.read_direct(offset, len)?
-            }
+            result?
         };
         Ok(WritablePage {
             buffer: &self.write_buffer,
@@ -446,3 +850,17 @@ impl PagedCachedFile {
         })
     }
 }
+
+#[cfg(any(fuzzing, test))]
+impl Drop for PagedCachedFile {
+    fn drop(&mut self) {
+        // Complete a simulated power loss, by reverting the file to its captured contents
+        if let Some(image) = self.power_loss_image.get_mut().unwrap().take() {
+            if !self.is_embedded() {
+                let _ = self.file.file().set_len(image.len() as u64);
+            }
+            let _ = self.file.write(self.base_offset, &image);
+            let _ = self.file.file().sync_data();
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+// Called with the offset in the file of a page which was read without having been read recently
+pub(crate) type ColdPageCallback = Arc<dyn Fn(u64) + Send + Sync>;
+
+// Must be a power of two
+const RECENT_SLOTS: usize = 4096;
+
+// Detects reads of pages which were not read recently. The recently read pages are approximated
+// by a direct mapped table, like a CPU cache: each page maps to a single slot, which holds the
+// last page read that maps to it. A page is cold if its slot holds another page, so a page may be
+// reported again after a read of a colliding page, but the table is lock free and never grows
+pub(super) struct ColdPageTracker {
+    callback: ColdPageCallback,
+    // Offset of the page plus one, or zero if the slot is empty
+    recent: Vec<AtomicU64>,
+}
+
+impl ColdPageTracker {
+    pub(super) fn new(callback: ColdPageCallback) -> Self {
+        Self {
+            callback,
+            recent: (0..RECENT_SLOTS).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    pub(super) fn record_read(&self, offset: u64) {
+        // Fibonacci hashing, since page offsets share their low bits
+        let hash = offset.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        let slot: u64 = hash >> (64 - RECENT_SLOTS.trailing_zeros());
+        let slot: usize = slot.try_into().unwrap();
+        let key = offset + 1;
+        if self.recent[slot].swap(key, Ordering::Relaxed) != key {
+            (self.callback)(offset);
+        }
+    }
+}
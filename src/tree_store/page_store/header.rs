@@ -1,8 +1,14 @@
 use crate::transaction_tracker::TransactionId;
+use crate::tree_store::page_store::base::MAX_PAGE_INDEX;
 use crate::tree_store::page_store::layout::{DatabaseLayout, RegionLayout};
-use crate::tree_store::page_store::page_manager::{xxh3_checksum, FILE_FORMAT_VERSION};
+use crate::tree_store::page_store::page_manager::{
+    xxh3_checksum, FILE_FORMAT_VERSION, MAX_USABLE_REGION_SPACE,
+};
+use crate::tree_store::page_store::region::RegionHeader;
 use crate::tree_store::{Checksum, PageNumber};
+use crate::StorageError;
 use std::mem::size_of;
+use std::ops::Range;
 
 // Database layout:
 //
@@ -12,46 +18,81 @@ use std::mem::size_of;
 // Header (first 64 bytes):
 // 9 bytes: magic number
 // 1 byte: god byte
-// 2 byte: padding
+// 2 bytes: size of each commit slot. Files written by older versions store 0, meaning 128
 // 4 bytes: page size
 // Definition of region
 // 4 bytes: region header pages
 // 4 bytes: region max data pages
+// 4 bytes: number of full regions
+// 4 bytes: data pages in partial trailing region
+// 8 bytes: region tracker page number
+// 16 bytes: checksum of the allocator state. Only valid if the recovery required bit is unset
+// 8 bytes: random id shared with the mirror of the database, or zero if it was never mirrored
 //
-// Commit slot 0 (next 128 bytes):
+// Commit slot 0 (next 128 bytes, or as many as the commit slot size):
 // 1 byte: version
 // 1 byte: != 0 if root page is non-null
+// 1 byte: != 0 if system root page is non-null
 // 1 byte: != 0 if freed table root page is non-null
-// 5 bytes: padding
+// 1 byte: != 0 if entry count is present
+// 1 byte: != 0 if commit timestamp is present
+// 2 bytes: padding
 // 8 bytes: root page
 // 16 bytes: root checksum
+// 8 bytes: system root page
+// 16 bytes: system root checksum
 // 8 bytes: freed table root page
 // 16 bytes: freed table root checksum
 // 8 bytes: last committed transaction id
-// 4 bytes: number of full regions
-// 4 bytes: data pages in partial trailing region
-// 8 bytes: region tracker page number
-// 16 bytes: slot checksum
+// 8 bytes: number of entries in all tables
+// 8 bytes: commit timestamp, physical milliseconds since the UNIX epoch
+// 4 bytes: commit timestamp, logical counter
+// 4 bytes: padding
+// Any further bytes, up to the slot checksum, are reserved for fields added by later versions.
+// They are zero when written by this version, so later fields must treat zero as absent
+// 16 bytes: slot checksum, in the last 16 bytes of the slot
 //
-// Commit slot 1 (next 128 bytes):
+// Commit slot 1 (next 128 bytes, or as many as the commit slot size):
 // Same layout as slot 0
+//
+// The header and both commit slots must fit in the first page
+//
+// Commit journal (rest of the first page):
+// Copies of the commit slots written by recent commits, each the size of a commit slot. The commit
+// with transaction id N is written to entry N modulo the number of entries which fit. Only written
+// if enabled, and zero otherwise, which fails the checksum of every entry
 
 // Inspired by PNG's magic number
 pub(super) const MAGICNUMBER: [u8; 9] = [b'r', b'e', b'd', b'b', 0x1A, 0x0A, 0xA9, 0x0D, 0x0A];
 const GOD_BYTE_OFFSET: usize = MAGICNUMBER.len();
-const PAGE_SIZE_OFFSET: usize = GOD_BYTE_OFFSET + size_of::<u8>() + 2; // +2 for padding
+const TRANSACTION_SIZE_OFFSET: usize = GOD_BYTE_OFFSET + size_of::<u8>();
+const PAGE_SIZE_OFFSET: usize = TRANSACTION_SIZE_OFFSET + size_of::<u16>();
 const REGION_HEADER_PAGES_OFFSET: usize = PAGE_SIZE_OFFSET + size_of::<u32>();
 const REGION_MAX_DATA_PAGES_OFFSET: usize = REGION_HEADER_PAGES_OFFSET + size_of::<u32>();
 const NUM_FULL_REGIONS_OFFSET: usize = REGION_MAX_DATA_PAGES_OFFSET + size_of::<u32>();
 const TRAILING_REGION_DATA_PAGES_OFFSET: usize = NUM_FULL_REGIONS_OFFSET + size_of::<u32>();
 const REGION_TRACKER_PAGE_NUMBER_OFFSET: usize =
     TRAILING_REGION_DATA_PAGES_OFFSET + size_of::<u32>();
-const TRANSACTION_SIZE: usize = 128;
+const ALLOCATOR_CHECKSUM_OFFSET: usize = REGION_TRACKER_PAGE_NUMBER_OFFSET + size_of::<u64>();
+const MIRROR_ID_OFFSET: usize = ALLOCATOR_CHECKSUM_OFFSET + size_of::<Checksum>();
+// Size of the commit slots written by this version. Slots may be larger in files written by later
+// versions, but never smaller
+const DEFAULT_TRANSACTION_SIZE: usize = 128;
 const TRANSACTION_0_OFFSET: usize = 64;
-const TRANSACTION_1_OFFSET: usize = TRANSACTION_0_OFFSET + TRANSACTION_SIZE;
-pub(super) const DB_HEADER_SIZE: usize = TRANSACTION_1_OFFSET + TRANSACTION_SIZE;
+// The part of the header which precedes the commit slots, and records their size
+pub(super) const DB_HEADER_PREFIX_SIZE: usize = TRANSACTION_0_OFFSET;
+// Size of the header of a database with the default commit slot size. No page can be smaller
+pub(super) const MIN_DB_HEADER_SIZE: usize = db_header_size(DEFAULT_TRANSACTION_SIZE);
+
+// Size of the header, including both commit slots
+const fn db_header_size(transaction_size: usize) -> usize {
+    TRANSACTION_0_OFFSET + 2 * transaction_size
+}
 
 // God byte flags
+// Region numbers are stored in 20 bits of a PageNumber
+pub(super) const MAX_REGIONS: u64 = 0x0010_0000;
+
 const PRIMARY_BIT: u8 = 1;
 const RECOVERY_REQUIRED: u8 = 2;
 
@@ -60,23 +101,32 @@ const VERSION_OFFSET: usize = 0;
 const USER_ROOT_NON_NULL_OFFSET: usize = size_of::<u8>();
 const SYSTEM_ROOT_NON_NULL_OFFSET: usize = USER_ROOT_NON_NULL_OFFSET + size_of::<u8>();
 const FREED_ROOT_NON_NULL_OFFSET: usize = SYSTEM_ROOT_NON_NULL_OFFSET + size_of::<u8>();
-const PADDING: usize = 4;
-const USER_ROOT_PAGE_OFFSET: usize = FREED_ROOT_NON_NULL_OFFSET + size_of::<u8>() + PADDING;
+const ENTRY_COUNT_NON_NULL_OFFSET: usize = FREED_ROOT_NON_NULL_OFFSET + size_of::<u8>();
+const TIMESTAMP_NON_NULL_OFFSET: usize = ENTRY_COUNT_NON_NULL_OFFSET + size_of::<u8>();
+const PADDING: usize = 2;
+const USER_ROOT_PAGE_OFFSET: usize = TIMESTAMP_NON_NULL_OFFSET + size_of::<u8>() + PADDING;
 const USER_ROOT_CHECKSUM_OFFSET: usize = USER_ROOT_PAGE_OFFSET + size_of::<u64>();
 const SYSTEM_ROOT_PAGE_OFFSET: usize = USER_ROOT_CHECKSUM_OFFSET + size_of::<u128>();
 const SYSTEM_ROOT_CHECKSUM_OFFSET: usize = SYSTEM_ROOT_PAGE_OFFSET + size_of::<u64>();
 const FREED_ROOT_OFFSET: usize = SYSTEM_ROOT_CHECKSUM_OFFSET + size_of::<u128>();
 const FREED_ROOT_CHECKSUM_OFFSET: usize = FREED_ROOT_OFFSET + size_of::<u64>();
 const TRANSACTION_ID_OFFSET: usize = FREED_ROOT_CHECKSUM_OFFSET + size_of::<u128>();
-const TRANSACTION_LAST_FIELD: usize = TRANSACTION_ID_OFFSET + size_of::<u64>();
-const SLOT_CHECKSUM_OFFSET: usize = TRANSACTION_SIZE - size_of::<Checksum>();
+const ENTRY_COUNT_OFFSET: usize = TRANSACTION_ID_OFFSET + size_of::<u64>();
+const TIMESTAMP_PHYSICAL_OFFSET: usize = ENTRY_COUNT_OFFSET + size_of::<u64>();
+const TIMESTAMP_LOGICAL_OFFSET: usize = TIMESTAMP_PHYSICAL_OFFSET + size_of::<u64>();
+const TRANSACTION_LAST_FIELD: usize = TIMESTAMP_LOGICAL_OFFSET + size_of::<u32>();
 
 pub(crate) const PAGE_SIZE: usize = 4096;
+pub(crate) const MAX_PAGE_SIZE: usize = 1024 * 1024;
 
 fn get_u32(data: &[u8]) -> u32 {
     u32::from_le_bytes(data[..size_of::<u32>()].try_into().unwrap())
 }
 
+fn get_u16(data: &[u8]) -> u16 {
+    u16::from_le_bytes(data[..size_of::<u16>()].try_into().unwrap())
+}
+
 fn get_u64(data: &[u8]) -> u64 {
     u64::from_le_bytes(data[..size_of::<u64>()].try_into().unwrap())
 }
@@ -92,15 +142,22 @@ pub(super) struct HeaderRepairInfo {
 pub(super) struct DatabaseHeader {
     primary_slot: usize,
     pub(super) recovery_required: bool,
+    transaction_size: usize,
     page_size: u32,
     region_header_pages: u32,
     region_max_data_pages: u32,
     full_regions: u32,
     trailing_partial_region_pages: u32,
     region_tracker: PageNumber,
+    allocator_checksum: Checksum,
+    mirror_id: u64,
     transaction_slots: [TransactionHeader; 2],
 }
 
+fn corrupted_header(field: &str) -> StorageError {
+    StorageError::Corrupted(format!("Invalid {field} in database header"))
+}
+
 impl DatabaseHeader {
     pub(super) fn new(
         layout: DatabaseLayout,
@@ -109,13 +166,15 @@ impl DatabaseHeader {
     ) -> Self {
         #[allow(clippy::assertions_on_constants)]
         {
-            assert!(TRANSACTION_LAST_FIELD <= SLOT_CHECKSUM_OFFSET);
+            assert!(TRANSACTION_LAST_FIELD + size_of::<Checksum>() <= DEFAULT_TRANSACTION_SIZE);
+            assert!(MIRROR_ID_OFFSET + size_of::<u64>() <= TRANSACTION_0_OFFSET);
         }
 
         let slot = TransactionHeader::new(transaction_id);
         Self {
             primary_slot: 0,
             recovery_required: true,
+            transaction_size: DEFAULT_TRANSACTION_SIZE,
             page_size: layout.full_region_layout().page_size(),
             region_header_pages: layout.full_region_layout().get_header_pages(),
             region_max_data_pages: layout.full_region_layout().num_pages(),
@@ -125,6 +184,8 @@ impl DatabaseHeader {
                 .map(|x| x.num_pages())
                 .unwrap_or_default(),
             region_tracker,
+            allocator_checksum: 0,
+            mirror_id: 0,
             transaction_slots: [slot.clone(), slot],
         }
     }
@@ -133,6 +194,145 @@ impl DatabaseHeader {
         self.page_size
     }
 
+    pub(super) fn mirror_id(&self) -> u64 {
+        self.mirror_id
+    }
+
+    pub(super) fn set_mirror_id(&mut self, id: u64) {
+        self.mirror_id = id;
+    }
+
+    // Size of the header, including both commit slots
+    pub(super) fn db_header_size(&self) -> usize {
+        db_header_size(self.transaction_size)
+    }
+
+    // The range of the file which holds the commit journal, following the header in the first page
+    pub(super) fn journal_range(&self) -> Range<u64> {
+        (self.db_header_size() as u64)..(self.page_size as u64)
+    }
+
+    // Returns the offset and contents of the journal entry which records the secondary slot, or
+    // None if no entry fits in the first page
+    pub(super) fn secondary_journal_entry(&self) -> Option<(u64, Vec<u8>)> {
+        let range = self.journal_range();
+        let entries = (range.end - range.start) / self.transaction_size as u64;
+        if entries == 0 {
+            return None;
+        }
+        let slot = self.secondary_slot();
+        let offset = range.start + (slot.transaction_id.0 % entries) * self.transaction_size as u64;
+        let mut entry = vec![0; self.transaction_size];
+        slot.write_to(&mut entry);
+        Some((offset, entry))
+    }
+
+    // Returns the latest commit recorded in `journal`, the contents of journal_range(), whose
+    // checksum is valid
+    pub(super) fn latest_journal_entry(&self, journal: &[u8]) -> Option<TransactionHeader> {
+        journal
+            .chunks_exact(self.transaction_size)
+            .filter_map(|entry| match TransactionHeader::from_bytes(entry) {
+                (slot, false) => Some(slot),
+                (_, true) => None,
+            })
+            .max_by_key(|slot| slot.transaction_id)
+    }
+
+    // Returns the size of the header which starts with `prefix`, the first DB_HEADER_PREFIX_SIZE
+    // bytes of the file. A file without the magic number is assumed to use the default size, since
+    // it will be initialized with it
+    pub(super) fn stored_size(prefix: &[u8]) -> Result<usize, StorageError> {
+        if prefix[..MAGICNUMBER.len()] != MAGICNUMBER {
+            return Ok(MIN_DB_HEADER_SIZE);
+        }
+        let transaction_size = Self::stored_transaction_size(prefix);
+        if transaction_size < DEFAULT_TRANSACTION_SIZE {
+            return Err(corrupted_header("commit slot size"));
+        }
+        Ok(db_header_size(transaction_size))
+    }
+
+    // Returns the version of the primary commit slot of the header in `data`. Only the prefix and
+    // the version byte are read, since older versions may lay out the rest of the header differently
+    pub(super) fn stored_version(data: &[u8]) -> u8 {
+        let primary_slot = usize::from(data[GOD_BYTE_OFFSET] & PRIMARY_BIT != 0);
+        data[TRANSACTION_0_OFFSET
+            + primary_slot * Self::stored_transaction_size(data)
+            + VERSION_OFFSET]
+    }
+
+    // Records `version` in both commit slots of the header in `data`. The checksum of a slot is
+    // only updated if it was valid, so that a corrupted slot is not made to look valid
+    pub(super) fn set_stored_version(data: &mut [u8], version: u8) {
+        let transaction_size = Self::stored_transaction_size(data);
+        for slot in data[TRANSACTION_0_OFFSET..db_header_size(transaction_size)]
+            .chunks_exact_mut(transaction_size)
+        {
+            let checksum_offset = transaction_size - size_of::<Checksum>();
+            let valid = xxh3_checksum(&slot[..checksum_offset])
+                == Checksum::from_le_bytes(slot[checksum_offset..].try_into().unwrap());
+            slot[VERSION_OFFSET] = version;
+            if valid {
+                let checksum = xxh3_checksum(&slot[..checksum_offset]);
+                slot[checksum_offset..].copy_from_slice(&checksum.to_le_bytes());
+            }
+        }
+    }
+
+    fn stored_transaction_size(data: &[u8]) -> usize {
+        match get_u16(&data[TRANSACTION_SIZE_OFFSET..]) {
+            0 => DEFAULT_TRANSACTION_SIZE,
+            x => x.into(),
+        }
+    }
+
+    // Gives the commit slots a different size. The header must then be written in full, before any
+    // other metadata is trusted
+    #[cfg(test)]
+    pub(super) fn set_transaction_size(&mut self, size: usize) {
+        assert!(size >= DEFAULT_TRANSACTION_SIZE && u16::try_from(size).is_ok());
+        self.transaction_size = size;
+    }
+
+    // Checks that the layout fields describe a layout which can be addressed without overflow.
+    // Must be called before layout() on a header read from disk
+    pub(super) fn validate_layout(&self) -> Result<(), StorageError> {
+        let page_size = self.page_size as u64;
+        if !self.page_size.is_power_of_two() || page_size < self.db_header_size() as u64 {
+            return Err(corrupted_header("page size"));
+        }
+        if self.region_max_data_pages == 0
+            || self.region_max_data_pages > MAX_PAGE_INDEX + 1
+            || self.region_max_data_pages as u64 * page_size > MAX_USABLE_REGION_SPACE
+        {
+            return Err(corrupted_header("region size"));
+        }
+        if self.region_header_pages
+            < RegionHeader::header_pages_expensive(self.page_size, self.region_max_data_pages)
+        {
+            return Err(corrupted_header("region header size"));
+        }
+        if self.trailing_partial_region_pages >= self.region_max_data_pages {
+            return Err(corrupted_header("trailing region size"));
+        }
+        let num_regions =
+            self.full_regions as u64 + u64::from(self.trailing_partial_region_pages > 0);
+        if num_regions == 0 || num_regions > MAX_REGIONS {
+            return Err(corrupted_header("region count"));
+        }
+        let region_len = (self.region_header_pages as u64 + self.region_max_data_pages as u64)
+            .checked_mul(page_size);
+        let total_len = region_len
+            .and_then(|x| x.checked_mul(num_regions))
+            .and_then(|x| x.checked_add(page_size));
+        if total_len.is_none() {
+            return Err(corrupted_header("database length"));
+        }
+
+        Ok(())
+    }
+
     pub(super) fn layout(&self) -> DatabaseLayout {
         let full_layout = RegionLayout::new(
             self.region_max_data_pages,
@@ -174,6 +374,14 @@ impl DatabaseHeader {
         self.region_tracker = page;
     }
 
+    pub(super) fn allocator_checksum(&self) -> Checksum {
+        self.allocator_checksum
+    }
+
+    pub(super) fn set_allocator_checksum(&mut self, checksum: Checksum) {
+        self.allocator_checksum = checksum;
+    }
+
     pub(super) fn primary_slot(&self) -> &TransactionHeader {
         &self.transaction_slots[self.primary_slot]
     }
@@ -182,6 +390,10 @@ impl DatabaseHeader {
         &self.transaction_slots[self.primary_slot ^ 1]
     }
 
+    pub(super) fn primary_slot_mut(&mut self) -> &mut TransactionHeader {
+        &mut self.transaction_slots[self.primary_slot]
+    }
+
     pub(super) fn secondary_slot_mut(&mut self) -> &mut TransactionHeader {
         &mut self.transaction_slots[self.primary_slot ^ 1]
     }
@@ -190,9 +402,16 @@ impl DatabaseHeader {
         self.primary_slot ^= 1;
     }
 
+    // `data` must hold at least the number of bytes returned by stored_size()
     // TODO: consider returning an Err with the repair info
     pub(super) fn from_bytes(data: &[u8]) -> (Self, HeaderRepairInfo) {
         let invalid_magic_number = data[..MAGICNUMBER.len()] != MAGICNUMBER;
+        let transaction_size = if invalid_magic_number {
+            DEFAULT_TRANSACTION_SIZE
+        } else {
+            Self::stored_transaction_size(data)
+        };
+        let transaction_1_offset = TRANSACTION_0_OFFSET + transaction_size;
 
         let primary_slot = usize::from(data[GOD_BYTE_OFFSET] & PRIMARY_BIT != 0);
         let recovery_required = (data[GOD_BYTE_OFFSET] & RECOVERY_REQUIRED) != 0;
@@ -207,8 +426,21 @@ impl DatabaseHeader {
                 .try_into()
                 .unwrap(),
         );
-        let (slot0, slot0_corrupted) = TransactionHeader::from_bytes(&data[TRANSACTION_0_OFFSET..]);
-        let (slot1, slot1_corrupted) = TransactionHeader::from_bytes(&data[TRANSACTION_1_OFFSET..]);
+        let allocator_checksum = Checksum::from_le_bytes(
+            data[ALLOCATOR_CHECKSUM_OFFSET..(ALLOCATOR_CHECKSUM_OFFSET + size_of::<Checksum>())]
+                .try_into()
+                .unwrap(),
+        );
+        let mirror_id = u64::from_le_bytes(
+            data[MIRROR_ID_OFFSET..(MIRROR_ID_OFFSET + size_of::<u64>())]
+                .try_into()
+                .unwrap(),
+        );
+        let (slot0, slot0_corrupted) =
+            TransactionHeader::from_bytes(&data[TRANSACTION_0_OFFSET..transaction_1_offset]);
+        let (slot1, slot1_corrupted) = TransactionHeader::from_bytes(
+            &data[transaction_1_offset..(transaction_1_offset + transaction_size)],
+        );
         let (primary_corrupted, secondary_corrupted) = if primary_slot == 0 {
             (slot0_corrupted, slot1_corrupted)
         } else {
@@ -218,12 +450,15 @@ impl DatabaseHeader {
         let result = Self {
             primary_slot,
             recovery_required,
+            transaction_size,
             page_size,
             region_header_pages,
             region_max_data_pages,
             full_regions,
             trailing_partial_region_pages: trailing_data_pages,
             region_tracker,
+            allocator_checksum,
+            mirror_id,
             transaction_slots: [slot0, slot1],
         };
         let repair = HeaderRepairInfo {
@@ -234,12 +469,8 @@ impl DatabaseHeader {
         (result, repair)
     }
 
-    pub(super) fn to_bytes(
-        &self,
-        include_magic_number: bool,
-        swap_primary: bool,
-    ) -> [u8; DB_HEADER_SIZE] {
-        let mut result = [0; DB_HEADER_SIZE];
+    pub(super) fn to_bytes(&self, include_magic_number: bool, swap_primary: bool) -> Vec<u8> {
+        let mut result = vec![0; self.db_header_size()];
         if include_magic_number {
             result[..MAGICNUMBER.len()].copy_from_slice(&MAGICNUMBER);
         }
@@ -250,6 +481,9 @@ impl DatabaseHeader {
         if self.recovery_required {
             result[GOD_BYTE_OFFSET] |= RECOVERY_REQUIRED;
         }
+        let transaction_size: u16 = self.transaction_size.try_into().unwrap();
+        result[TRANSACTION_SIZE_OFFSET..(TRANSACTION_SIZE_OFFSET + size_of::<u16>())]
+            .copy_from_slice(&transaction_size.to_le_bytes());
         result[PAGE_SIZE_OFFSET..(PAGE_SIZE_OFFSET + size_of::<u32>())]
             .copy_from_slice(&self.page_size.to_le_bytes());
         result[REGION_HEADER_PAGES_OFFSET..(REGION_HEADER_PAGES_OFFSET + size_of::<u32>())]
@@ -264,15 +498,252 @@ impl DatabaseHeader {
         result[REGION_TRACKER_PAGE_NUMBER_OFFSET
             ..(REGION_TRACKER_PAGE_NUMBER_OFFSET + PageNumber::serialized_size())]
             .copy_from_slice(&self.region_tracker.to_le_bytes());
-        let slot0 = self.transaction_slots[0].to_bytes();
-        result[TRANSACTION_0_OFFSET..(TRANSACTION_0_OFFSET + slot0.len())].copy_from_slice(&slot0);
-        let slot1 = self.transaction_slots[1].to_bytes();
-        result[TRANSACTION_1_OFFSET..(TRANSACTION_1_OFFSET + slot1.len())].copy_from_slice(&slot1);
+        result[ALLOCATOR_CHECKSUM_OFFSET..(ALLOCATOR_CHECKSUM_OFFSET + size_of::<Checksum>())]
+            .copy_from_slice(&self.allocator_checksum.to_le_bytes());
+        result[MIRROR_ID_OFFSET..(MIRROR_ID_OFFSET + size_of::<u64>())]
+            .copy_from_slice(&self.mirror_id.to_le_bytes());
+        let transaction_1_offset = TRANSACTION_0_OFFSET + self.transaction_size;
+        self.transaction_slots[0].write_to(&mut result[TRANSACTION_0_OFFSET..transaction_1_offset]);
+        self.transaction_slots[1].write_to(&mut result[transaction_1_offset..]);
 
         result
     }
 }
 
+/// Contents of the database header, as returned by [`Database::dump_metapage`](crate::Database::dump_metapage)
+///
+/// The fields are decoded as-is, without validation, so that a corrupted header can be inspected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetapageDump {
+    magic_number_valid: bool,
+    primary_slot: usize,
+    recovery_required: bool,
+    transaction_size: usize,
+    page_size: u32,
+    region_header_pages: u32,
+    region_max_data_pages: u32,
+    full_regions: u32,
+    trailing_region_pages: u32,
+    database_size: Option<u64>,
+    region_tracker: PageNumber,
+    slots: [TransactionSlotDump; 2],
+}
+
+impl MetapageDump {
+    pub(super) fn from_bytes(data: &[u8]) -> Self {
+        let (header, repair_info) = DatabaseHeader::from_bytes(data);
+        let database_size = header.validate_layout().ok().map(|_| header.layout().len());
+        let mut corrupted = [repair_info.primary_corrupted; 2];
+        corrupted[header.primary_slot ^ 1] = repair_info.secondary_corrupted;
+        let slot = |i: usize| TransactionSlotDump::new(&header.transaction_slots[i], !corrupted[i]);
+
+        Self {
+            magic_number_valid: !repair_info.invalid_magic_number,
+            primary_slot: header.primary_slot,
+            recovery_required: header.recovery_required,
+            transaction_size: header.transaction_size,
+            page_size: header.page_size,
+            region_header_pages: header.region_header_pages,
+            region_max_data_pages: header.region_max_data_pages,
+            full_regions: header.full_regions,
+            trailing_region_pages: header.trailing_partial_region_pages,
+            database_size,
+            region_tracker: header.region_tracker,
+            slots: [slot(0), slot(1)],
+        }
+    }
+
+    /// Whether the file starts with the redb magic number
+    pub fn magic_number_valid(&self) -> bool {
+        self.magic_number_valid
+    }
+
+    /// Index of the commit slot which holds the latest commit, either 0 or 1
+    pub fn primary_slot(&self) -> usize {
+        self.primary_slot
+    }
+
+    /// Whether the database was not closed cleanly, and must be repaired when it is next opened.
+    /// This is always set while the database is open
+    pub fn recovery_required(&self) -> bool {
+        self.recovery_required
+    }
+
+    /// Number of bytes in each commit slot
+    pub fn transaction_size(&self) -> usize {
+        self.transaction_size
+    }
+
+    /// Number of bytes per page
+    pub fn page_size(&self) -> usize {
+        self.page_size.try_into().unwrap()
+    }
+
+    /// Number of pages used by the header of each region
+    pub fn region_header_pages(&self) -> u32 {
+        self.region_header_pages
+    }
+
+    /// Maximum number of data pages in each region
+    pub fn region_max_data_pages(&self) -> u32 {
+        self.region_max_data_pages
+    }
+
+    /// Number of full regions
+    pub fn full_regions(&self) -> u32 {
+        self.full_regions
+    }
+
+    /// Number of data pages in the trailing partial region, or 0 if there is none
+    pub fn trailing_region_pages(&self) -> u32 {
+        self.trailing_region_pages
+    }
+
+    /// Size of the database described by the header, in bytes, or `None` if the region fields
+    /// are invalid
+    pub fn database_size(&self) -> Option<u64> {
+        self.database_size
+    }
+
+    /// Page number of the region allocator state, as stored in the header
+    pub fn region_tracker(&self) -> u64 {
+        u64::from_le_bytes(self.region_tracker.to_le_bytes())
+    }
+
+    /// The two commit slots. See [`Self::primary_slot`] for which one holds the latest commit
+    pub fn slots(&self) -> &[TransactionSlotDump; 2] {
+        &self.slots
+    }
+}
+
+/// Contents of one of the commit slots in the database header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionSlotDump {
+    version: u8,
+    checksum_valid: bool,
+    user_root: Option<(PageNumber, Checksum)>,
+    system_root: Option<(PageNumber, Checksum)>,
+    freed_root: Option<(PageNumber, Checksum)>,
+    transaction_id: u64,
+    entry_count: Option<u64>,
+    timestamp: Option<HybridTimestamp>,
+}
+
+impl TransactionSlotDump {
+    pub(super) fn new(slot: &TransactionHeader, checksum_valid: bool) -> Self {
+        Self {
+            version: slot.version,
+            checksum_valid,
+            user_root: slot.user_root,
+            system_root: slot.system_root,
+            freed_root: slot.freed_root,
+            transaction_id: slot.transaction_id.0,
+            entry_count: slot.entry_count,
+            timestamp: slot.timestamp,
+        }
+    }
+
+    /// File format version of the commit
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// Whether the slot matches its checksum. A slot is only trusted if this is `true`
+    pub fn checksum_valid(&self) -> bool {
+        self.checksum_valid
+    }
+
+    /// Page number of the root of the user table tree, if any
+    pub fn user_root(&self) -> Option<u64> {
+        self.user_root
+            .map(|(page, _)| u64::from_le_bytes(page.to_le_bytes()))
+    }
+
+    /// Page number of the root of the system table tree, if any
+    pub fn system_root(&self) -> Option<u64> {
+        self.system_root
+            .map(|(page, _)| u64::from_le_bytes(page.to_le_bytes()))
+    }
+
+    /// Page number of the root of the tree of freed pages, if any
+    pub fn freed_root(&self) -> Option<u64> {
+        self.freed_root
+            .map(|(page, _)| u64::from_le_bytes(page.to_le_bytes()))
+    }
+
+    /// Id of the transaction which wrote this slot
+    pub fn transaction_id(&self) -> u64 {
+        self.transaction_id
+    }
+
+    /// Epoch of the commit, in the high 32 bits of its transaction id. See
+    /// [`crate::Builder::set_transaction_epoch`]
+    pub fn epoch(&self) -> u32 {
+        TransactionId(self.transaction_id).epoch()
+    }
+
+    /// Counter of the commit within its epoch, in the low 32 bits of its transaction id
+    pub fn counter(&self) -> u32 {
+        TransactionId(self.transaction_id).counter()
+    }
+
+    /// Total number of key-value pairs in all user tables, if recorded
+    pub fn entry_count(&self) -> Option<u64> {
+        self.entry_count
+    }
+
+    /// Timestamp of the commit which wrote this slot, if recorded
+    pub fn timestamp(&self) -> Option<HybridTimestamp> {
+        self.timestamp
+    }
+}
+
+/// A hybrid timestamp, recorded with every commit
+///
+/// It consists of the wall-clock time of the commit, in milliseconds since the UNIX epoch, and a
+/// logical counter which distinguishes commits made within the same millisecond. Timestamps are
+/// strictly increasing from one commit to the next, even if the system clock moves backwards, so
+/// they can be used to order the commits of several databases on a shared timeline.
+/// Timestamps are ordered by their physical time first, and then by their logical counter
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct HybridTimestamp {
+    physical: u64,
+    logical: u32,
+}
+
+impl HybridTimestamp {
+    pub(crate) fn new(physical: u64, logical: u32) -> Self {
+        Self { physical, logical }
+    }
+
+    // Returns the timestamp for a commit made at `now`, which follows a commit made at `previous`
+    pub(crate) fn next(previous: Option<Self>, now: u64) -> Self {
+        match previous {
+            Some(previous) if now <= previous.physical => {
+                if let Some(logical) = previous.logical.checked_add(1) {
+                    Self::new(previous.physical, logical)
+                } else {
+                    Self::new(previous.physical + 1, 0)
+                }
+            }
+            _ => Self::new(now, 0),
+        }
+    }
+
+    /// Wall-clock time of the commit, in milliseconds since the UNIX epoch
+    ///
+    /// If the clock was behind the previous commit this is the time of that commit instead
+    pub fn physical_millis(&self) -> u64 {
+        self.physical
+    }
+
+    /// Counter which orders commits with the same physical time. It is zero for the first commit
+    /// in each millisecond
+    pub fn logical(&self) -> u32 {
+        self.logical
+    }
+}
+
 #[derive(Clone)]
 pub(super) struct TransactionHeader {
     pub(super) version: u8,
@@ -280,6 +751,11 @@ pub(super) struct TransactionHeader {
     pub(super) system_root: Option<(PageNumber, Checksum)>,
     pub(super) freed_root: Option<(PageNumber, Checksum)>,
     pub(super) transaction_id: TransactionId,
+    // Total number of key-value pairs in all user tables. May be absent in files written by older
+    // versions
+    pub(super) entry_count: Option<u64>,
+    // Absent in files written by older versions
+    pub(super) timestamp: Option<HybridTimestamp>,
 }
 
 impl TransactionHeader {
@@ -290,18 +766,18 @@ impl TransactionHeader {
             system_root: None,
             freed_root: None,
             transaction_id,
+            entry_count: Some(0),
+            timestamp: None,
         }
     }
 
+    // `data` is the whole slot, which ends with its checksum.
     // Returned bool indicates whether the checksum was corrupted
     pub(super) fn from_bytes(data: &[u8]) -> (Self, bool) {
         let version = data[VERSION_OFFSET];
-        let checksum = Checksum::from_le_bytes(
-            data[SLOT_CHECKSUM_OFFSET..(SLOT_CHECKSUM_OFFSET + size_of::<Checksum>())]
-                .try_into()
-                .unwrap(),
-        );
-        let corrupted = checksum != xxh3_checksum(&data[..SLOT_CHECKSUM_OFFSET]);
+        let checksum_offset = data.len() - size_of::<Checksum>();
+        let checksum = Checksum::from_le_bytes(data[checksum_offset..].try_into().unwrap());
+        let corrupted = checksum != xxh3_checksum(&data[..checksum_offset]);
 
         let user_root = if data[USER_ROOT_NON_NULL_OFFSET] != 0 {
             let page = PageNumber::from_le_bytes(
@@ -354,6 +830,19 @@ impl TransactionHeader {
             None
         };
         let transaction_id = TransactionId(get_u64(&data[TRANSACTION_ID_OFFSET..]));
+        let entry_count = if data[ENTRY_COUNT_NON_NULL_OFFSET] != 0 {
+            Some(get_u64(&data[ENTRY_COUNT_OFFSET..]))
+        } else {
+            None
+        };
+        let timestamp = if data[TIMESTAMP_NON_NULL_OFFSET] != 0 {
+            Some(HybridTimestamp::new(
+                get_u64(&data[TIMESTAMP_PHYSICAL_OFFSET..]),
+                get_u32(&data[TIMESTAMP_LOGICAL_OFFSET..]),
+            ))
+        } else {
+            None
+        };
 
         let result = Self {
             version,
@@ -361,13 +850,15 @@ impl TransactionHeader {
             system_root,
             freed_root,
             transaction_id,
+            entry_count,
+            timestamp,
         };
 
         (result, corrupted)
     }
 
-    pub(super) fn to_bytes(&self) -> [u8; TRANSACTION_SIZE] {
-        let mut result = [0; TRANSACTION_SIZE];
+    // Writes the slot into `result`, which must be zeroed and is the size of a whole slot
+    fn write_to(&self, result: &mut [u8]) {
         result[VERSION_OFFSET] = self.version;
         if let Some((page, checksum)) = self.user_root {
             result[USER_ROOT_NON_NULL_OFFSET] = 1;
@@ -395,31 +886,98 @@ impl TransactionHeader {
         }
         result[TRANSACTION_ID_OFFSET..(TRANSACTION_ID_OFFSET + size_of::<u64>())]
             .copy_from_slice(&self.transaction_id.0.to_le_bytes());
-        let checksum = xxh3_checksum(&result[..SLOT_CHECKSUM_OFFSET]);
-        result[SLOT_CHECKSUM_OFFSET..(SLOT_CHECKSUM_OFFSET + size_of::<Checksum>())]
-            .copy_from_slice(&checksum.to_le_bytes());
-
-        result
+        if let Some(entry_count) = self.entry_count {
+            result[ENTRY_COUNT_NON_NULL_OFFSET] = 1;
+            result[ENTRY_COUNT_OFFSET..(ENTRY_COUNT_OFFSET + size_of::<u64>())]
+                .copy_from_slice(&entry_count.to_le_bytes());
+        }
+        if let Some(timestamp) = self.timestamp {
+            result[TIMESTAMP_NON_NULL_OFFSET] = 1;
+            result[TIMESTAMP_PHYSICAL_OFFSET..(TIMESTAMP_PHYSICAL_OFFSET + size_of::<u64>())]
+                .copy_from_slice(&timestamp.physical.to_le_bytes());
+            result[TIMESTAMP_LOGICAL_OFFSET..(TIMESTAMP_LOGICAL_OFFSET + size_of::<u32>())]
+                .copy_from_slice(&timestamp.logical.to_le_bytes());
+        }
+        let checksum_offset = result.len() - size_of::<Checksum>();
+        let checksum = xxh3_checksum(&result[..checksum_offset]);
+        result[checksum_offset..].copy_from_slice(&checksum.to_le_bytes());
     }
 }
 
 #[cfg(test)]
 mod test {
     use crate::db::TableDefinition;
+    use crate::transaction_tracker::TransactionId;
     use crate::tree_store::page_store::header::{
-        GOD_BYTE_OFFSET, MAGICNUMBER, PAGE_SIZE, PRIMARY_BIT, RECOVERY_REQUIRED,
-        TRANSACTION_0_OFFSET, TRANSACTION_1_OFFSET, USER_ROOT_CHECKSUM_OFFSET,
+        DatabaseHeader, HybridTimestamp, TransactionHeader, TransactionSlotDump,
+        ALLOCATOR_CHECKSUM_OFFSET, DEFAULT_TRANSACTION_SIZE, GOD_BYTE_OFFSET, MAGICNUMBER,
+        PAGE_SIZE, PAGE_SIZE_OFFSET, PRIMARY_BIT, RECOVERY_REQUIRED,
+        REGION_TRACKER_PAGE_NUMBER_OFFSET, TRANSACTION_0_OFFSET, TRANSACTION_ID_OFFSET,
+        TRANSACTION_SIZE_OFFSET, USER_ROOT_CHECKSUM_OFFSET,
+    };
+    use crate::tree_store::page_store::page_manager::xxh3_checksum;
+    use crate::tree_store::page_store::{GrowthPolicy, TransactionalMemory};
+    use crate::tree_store::Checksum;
+    use crate::{
+        Builder, Database, DatabaseError, MigrationStep, ReadableTable, StorageError,
+        FILE_FORMAT_VERSION,
     };
-    use crate::tree_store::page_store::TransactionalMemory;
-    #[cfg(not(target_os = "windows"))]
-    use crate::StorageError;
-    use crate::{Database, ReadableTable};
     use std::fs::OpenOptions;
     use std::io::{Read, Seek, SeekFrom, Write};
     use std::mem::size_of;
 
     const X: TableDefinition<&str, &str> = TableDefinition::new("x");
 
+    #[test]
+    fn hybrid_timestamp_next() {
+        let first = HybridTimestamp::next(None, 100);
+        assert_eq!(first, HybridTimestamp::new(100, 0));
+        // Same clock tick
+        let second = HybridTimestamp::next(Some(first), 100);
+        assert_eq!(second, HybridTimestamp::new(100, 1));
+        // The clock moved backwards
+        let third = HybridTimestamp::next(Some(second), 50);
+        assert_eq!(third, HybridTimestamp::new(100, 2));
+        assert_eq!(
+            HybridTimestamp::next(Some(third), 101),
+            HybridTimestamp::new(101, 0)
+        );
+        assert_eq!(
+            HybridTimestamp::next(Some(HybridTimestamp::new(100, u32::MAX)), 100),
+            HybridTimestamp::new(101, 0)
+        );
+    }
+
+    #[test]
+    fn dump_metapage() {
+        let tmpfile = crate::create_tempfile();
+        let db = Database::builder().create(tmpfile.path()).unwrap();
+        let dump = db.dump_metapage().unwrap();
+        assert!(dump.magic_number_valid());
+        assert_eq!(dump.primary_slot(), 0);
+        // Set while the database is open
+        assert!(dump.recovery_required());
+        assert_eq!(dump.page_size(), PAGE_SIZE);
+        assert_eq!(
+            dump.database_size(),
+            Some(tmpfile.as_file().metadata().unwrap().len())
+        );
+        let slot = TransactionSlotDump::new(&TransactionHeader::new(TransactionId(0)), true);
+        assert_eq!(dump.slots(), &[slot.clone(), slot]);
+
+        let write_txn = db.begin_write().unwrap();
+        {
+            let mut table = write_txn.open_table(X).unwrap();
+            table.insert("hello", "world").unwrap();
+        }
+        write_txn.commit().unwrap();
+        let dump = db.dump_metapage().unwrap();
+        let primary = &dump.slots()[dump.primary_slot()];
+        assert!(primary.checksum_valid());
+        assert!(primary.user_root().is_some());
+        assert_eq!(primary.entry_count(), Some(1));
+    }
+
     #[test]
     fn repair_allocator_checksums() {
         let tmpfile = crate::create_tempfile();
@@ -460,7 +1018,7 @@ mod test {
         let primary_slot_offset = if buffer[0] & PRIMARY_BIT == 0 {
             TRANSACTION_0_OFFSET
         } else {
-            TRANSACTION_1_OFFSET
+            TRANSACTION_0_OFFSET + DEFAULT_TRANSACTION_SIZE
         };
         file.seek(SeekFrom::Start(
             (primary_slot_offset + USER_ROOT_CHECKSUM_OFFSET) as u64,
@@ -468,10 +1026,23 @@ mod test {
         .unwrap();
         file.write_all(&[0; size_of::<u128>()]).unwrap();
 
-        assert!(TransactionalMemory::new(file, PAGE_SIZE, None, 0, 0)
-            .unwrap()
-            .needs_repair()
-            .unwrap());
+        assert!(TransactionalMemory::new(
+            file,
+            None,
+            PAGE_SIZE,
+            false,
+            None,
+            0,
+            0,
+            GrowthPolicy::default(),
+            false,
+            false,
+            false,
+            false,
+        )
+        .unwrap()
+        .needs_repair()
+        .unwrap());
 
         #[allow(unused_mut)]
         let mut db2 = Database::create(tmpfile.path()).unwrap();
@@ -499,7 +1070,7 @@ mod test {
             let primary_slot_offset = if buffer[0] & PRIMARY_BIT == 0 {
                 TRANSACTION_0_OFFSET
             } else {
-                TRANSACTION_1_OFFSET
+                TRANSACTION_0_OFFSET + DEFAULT_TRANSACTION_SIZE
             };
             file.seek(SeekFrom::Start(
                 (primary_slot_offset + USER_ROOT_CHECKSUM_OFFSET) as u64,
@@ -520,7 +1091,8 @@ mod test {
             .unwrap();
             file.write_all(&[0; size_of::<u128>()]).unwrap();
             file.seek(SeekFrom::Start(
-                (TRANSACTION_1_OFFSET + USER_ROOT_CHECKSUM_OFFSET) as u64,
+                (TRANSACTION_0_OFFSET + DEFAULT_TRANSACTION_SIZE + USER_ROOT_CHECKSUM_OFFSET)
+                    as u64,
             ))
             .unwrap();
             file.write_all(&[0; size_of::<u128>()]).unwrap();
@@ -551,10 +1123,23 @@ mod test {
         buffer[0] |= RECOVERY_REQUIRED;
         file.write_all(&buffer).unwrap();
 
-        assert!(TransactionalMemory::new(file, PAGE_SIZE, None, 0, 0)
-            .unwrap()
-            .needs_repair()
-            .unwrap());
+        assert!(TransactionalMemory::new(
+            file,
+            None,
+            PAGE_SIZE,
+            false,
+            None,
+            0,
+            0,
+            GrowthPolicy::default(),
+            false,
+            false,
+            false,
+            false,
+        )
+        .unwrap()
+        .needs_repair()
+        .unwrap());
 
         Database::open(tmpfile.path()).unwrap();
     }
@@ -597,10 +1182,23 @@ mod test {
         buffer[0] |= RECOVERY_REQUIRED;
         file.write_all(&buffer).unwrap();
 
-        assert!(TransactionalMemory::new(file, PAGE_SIZE, None, 0, 0)
-            .unwrap()
-            .needs_repair()
-            .unwrap());
+        assert!(TransactionalMemory::new(
+            file,
+            None,
+            PAGE_SIZE,
+            false,
+            None,
+            0,
+            0,
+            GrowthPolicy::default(),
+            false,
+            false,
+            false,
+            false,
+        )
+        .unwrap()
+        .needs_repair()
+        .unwrap());
 
         Database::open(tmpfile.path()).unwrap();
     }
@@ -626,4 +1224,248 @@ mod test {
             || (0x0E <= *x && *x <= 0x1F)
             || (0x7F <= *x && *x <= 0x9F)));
     }
+
+    #[test]
+    fn larger_transaction_size() {
+        const LARGER_SIZE: usize = 2 * DEFAULT_TRANSACTION_SIZE;
+        let tmpfile = crate::create_tempfile();
+        let db = Database::builder().create(tmpfile.path()).unwrap();
+        let write_txn = db.begin_write().unwrap();
+        {
+            let mut table = write_txn.open_table(X).unwrap();
+            table.insert("hello", "world").unwrap();
+        }
+        write_txn.commit().unwrap();
+        let timestamp = db.begin_read().unwrap().commit_timestamp();
+        assert!(timestamp.is_some());
+        drop(db);
+
+        // Rewrite the header as a later version with larger commit slots would, including a field
+        // which this version doesn't know about
+        let mut data = std::fs::read(tmpfile.path()).unwrap();
+        let (mut header, repair_info) = DatabaseHeader::from_bytes(&data);
+        assert!(!repair_info.primary_corrupted);
+        header.set_transaction_size(LARGER_SIZE);
+        let mut header_bytes = header.to_bytes(true, false);
+        assert_eq!(
+            DatabaseHeader::stored_size(&header_bytes).unwrap(),
+            header_bytes.len()
+        );
+        for slot in [0, 1] {
+            let start = TRANSACTION_0_OFFSET + slot * LARGER_SIZE;
+            let slot_bytes = &mut header_bytes[start..(start + LARGER_SIZE)];
+            let checksum_offset = LARGER_SIZE - size_of::<Checksum>();
+            slot_bytes[DEFAULT_TRANSACTION_SIZE..checksum_offset].fill(0xAB);
+            let checksum = xxh3_checksum(&slot_bytes[..checksum_offset]);
+            slot_bytes[checksum_offset..].copy_from_slice(&checksum.to_le_bytes());
+        }
+        data[..header_bytes.len()].copy_from_slice(&header_bytes);
+        std::fs::write(tmpfile.path(), &data).unwrap();
+
+        // All of the known fields are read from the larger slots
+        let db = Database::open(tmpfile.path()).unwrap();
+        let dump = db.dump_metapage().unwrap();
+        assert_eq!(dump.transaction_size(), LARGER_SIZE);
+        let primary = &dump.slots()[dump.primary_slot()];
+        assert!(primary.checksum_valid());
+        assert_eq!(primary.entry_count(), Some(1));
+        let read_txn = db.begin_read().unwrap();
+        assert_eq!(read_txn.commit_timestamp(), timestamp);
+        let table = read_txn.open_table(X).unwrap();
+        assert_eq!(table.get("hello").unwrap().unwrap().value(), "world");
+        drop(table);
+        drop(read_txn);
+
+        // and written back to them, in the same format
+        let write_txn = db.begin_write().unwrap();
+        {
+            let mut table = write_txn.open_table(X).unwrap();
+            table.insert("hello2", "world2").unwrap();
+        }
+        write_txn.commit().unwrap();
+        drop(db);
+        let db = Database::open(tmpfile.path()).unwrap();
+        let dump = db.dump_metapage().unwrap();
+        assert_eq!(dump.transaction_size(), LARGER_SIZE);
+        assert!(dump.slots().iter().all(|slot| slot.checksum_valid()));
+        assert_eq!(dump.slots()[dump.primary_slot()].entry_count(), Some(2));
+        let read_txn = db.begin_read().unwrap();
+        assert!(read_txn.commit_timestamp() > timestamp);
+        let table = read_txn.open_table(X).unwrap();
+        assert_eq!(table.get("hello").unwrap().unwrap().value(), "world");
+        assert_eq!(table.get("hello2").unwrap().unwrap().value(), "world2");
+        drop(table);
+        drop(read_txn);
+        drop(db);
+
+        // Slots can't be smaller than those of this version
+        let mut data = std::fs::read(tmpfile.path()).unwrap();
+        data[TRANSACTION_SIZE_OFFSET..(TRANSACTION_SIZE_OFFSET + size_of::<u16>())]
+            .copy_from_slice(&100u16.to_le_bytes());
+        std::fs::write(tmpfile.path(), &data).unwrap();
+        assert!(matches!(
+            Database::open(tmpfile.path()).err().unwrap(),
+            DatabaseError::Storage(StorageError::Corrupted(_))
+        ));
+    }
+
+    #[test]
+    fn migrate_older_version() {
+        // A version 0 of the file format, made up for this test, which stored the allocator
+        // checksum before the region tracker page number
+        let fields =
+            REGION_TRACKER_PAGE_NUMBER_OFFSET..(ALLOCATOR_CHECKSUM_OFFSET + size_of::<Checksum>());
+        let tracker_len = ALLOCATOR_CHECKSUM_OFFSET - REGION_TRACKER_PAGE_NUMBER_OFFSET;
+        let downgrade = |path: &std::path::Path| {
+            let mut data = std::fs::read(path).unwrap();
+            data[fields.clone()].rotate_left(tracker_len);
+            DatabaseHeader::set_stored_version(&mut data, 0);
+            std::fs::write(path, &data).unwrap();
+        };
+        let upgrade_fields = fields.clone();
+        let step = MigrationStep::new(0, move |mut file| {
+            let mut data = vec![0; upgrade_fields.len()];
+            file.seek(SeekFrom::Start(upgrade_fields.start as u64))?;
+            file.read_exact(&mut data)?;
+            data.rotate_right(tracker_len);
+            file.seek(SeekFrom::Start(upgrade_fields.start as u64))?;
+            file.write_all(&data)
+        });
+
+        let tmpfile = crate::create_tempfile();
+        let db = Database::create(tmpfile.path()).unwrap();
+        let write_txn = db.begin_write().unwrap();
+        {
+            let mut table = write_txn.open_table(X).unwrap();
+            table.insert("hello", "world").unwrap();
+        }
+        write_txn.commit().unwrap();
+        drop(db);
+        assert_eq!(
+            Database::file_format_version(tmpfile.path()).unwrap(),
+            FILE_FORMAT_VERSION
+        );
+
+        downgrade(tmpfile.path());
+        assert_eq!(Database::file_format_version(tmpfile.path()).unwrap(), 0);
+        assert!(matches!(
+            Database::open(tmpfile.path()),
+            Err(DatabaseError::UpgradeRequired(0))
+        ));
+        assert!(matches!(
+            Database::migrate(tmpfile.path(), &[]),
+            Err(DatabaseError::UpgradeRequired(0))
+        ));
+
+        // Upgraded explicitly
+        Database::migrate(tmpfile.path(), std::slice::from_ref(&step)).unwrap();
+        assert_eq!(
+            Database::file_format_version(tmpfile.path()).unwrap(),
+            FILE_FORMAT_VERSION
+        );
+        let dump = Database::verify_header(tmpfile.path()).unwrap();
+        assert!(dump.slots().iter().all(|slot| slot.checksum_valid()));
+        assert!(Database::corruption_report(tmpfile.path())
+            .unwrap()
+            .is_clean());
+        let db = Database::open(tmpfile.path()).unwrap();
+        let read_txn = db.begin_read().unwrap();
+        let table = read_txn.open_table(X).unwrap();
+        assert_eq!(table.get("hello").unwrap().unwrap().value(), "world");
+        drop(table);
+        drop(read_txn);
+        drop(db);
+
+        // And when opened
+        downgrade(tmpfile.path());
+        let db = Builder::new()
+            .set_migrations([step])
+            .open(tmpfile.path())
+            .unwrap();
+        let read_txn = db.begin_read().unwrap();
+        let table = read_txn.open_table(X).unwrap();
+        assert_eq!(table.get("hello").unwrap().unwrap().value(), "world");
+    }
+
+    #[test]
+    fn corrupted_header() {
+        let tmpfile = crate::create_tempfile();
+        let db = Database::builder().create(tmpfile.path()).unwrap();
+        let write_txn = db.begin_write().unwrap();
+        {
+            let mut table = write_txn.open_table(X).unwrap();
+            table.insert("hello", "world").unwrap();
+        }
+        write_txn.commit().unwrap();
+        drop(db);
+        let original = std::fs::read(tmpfile.path()).unwrap();
+
+        // Every value of every layout field must be rejected or opened, but never panic
+        for offset in GOD_BYTE_OFFSET..TRANSACTION_0_OFFSET {
+            for value in [0, 1, 0x7F, 0x80, 0xFF] {
+                let mut data = original.clone();
+                data[offset] = value;
+                std::fs::write(tmpfile.path(), &data).unwrap();
+                if let Ok(db) = Database::open(tmpfile.path()) {
+                    let read_txn = db.begin_read().unwrap();
+                    let _ = read_txn.open_table(X);
+                }
+            }
+        }
+
+        let mut data = original;
+        data[PAGE_SIZE_OFFSET..(PAGE_SIZE_OFFSET + size_of::<u32>())]
+            .copy_from_slice(&3u32.to_le_bytes());
+        std::fs::write(tmpfile.path(), &data).unwrap();
+        assert!(matches!(
+            Database::open(tmpfile.path()).err().unwrap(),
+            DatabaseError::Storage(StorageError::Corrupted(_))
+        ));
+    }
+
+    #[test]
+    fn commit_journal() {
+        let tmpfile = crate::create_tempfile();
+        let db = Builder::new()
+            .set_commit_journal(true)
+            .create(tmpfile.path())
+            .unwrap();
+        for value in ["a", "b", "c"] {
+            let write_txn = db.begin_write().unwrap();
+            {
+                let mut table = write_txn.open_table(X).unwrap();
+                table.insert("hello", value).unwrap();
+            }
+            write_txn.commit().unwrap();
+        }
+        drop(db);
+
+        // Tear the slot of the latest commit
+        let mut data = std::fs::read(tmpfile.path()).unwrap();
+        let primary = (data[GOD_BYTE_OFFSET] & PRIMARY_BIT) as usize;
+        data[TRANSACTION_0_OFFSET + primary * DEFAULT_TRANSACTION_SIZE + TRANSACTION_ID_OFFSET] ^=
+            0xFF;
+        std::fs::write(tmpfile.path(), &data).unwrap();
+
+        let db = Database::open(tmpfile.path()).unwrap();
+        let read_txn = db.begin_read().unwrap();
+        let table = read_txn.open_table(X).unwrap();
+        assert_eq!(table.get("hello").unwrap().unwrap().value(), "c");
+        drop(table);
+        drop(read_txn);
+        drop(db);
+
+        // Without the journal, a torn slot is only tolerated after a crash
+        let mut data = std::fs::read(tmpfile.path()).unwrap();
+        data[(TRANSACTION_0_OFFSET + 2 * DEFAULT_TRANSACTION_SIZE)..PAGE_SIZE].fill(0);
+        let primary = (data[GOD_BYTE_OFFSET] & PRIMARY_BIT) as usize;
+        data[TRANSACTION_0_OFFSET + primary * DEFAULT_TRANSACTION_SIZE + TRANSACTION_ID_OFFSET] ^=
+            0xFF;
+        std::fs::write(tmpfile.path(), &data).unwrap();
+
+        assert!(matches!(
+            Database::open(tmpfile.path()).err().unwrap(),
+            DatabaseError::Storage(StorageError::Corrupted(_))
+        ));
+    }
 }
@@ -1,11 +1,96 @@
+use crate::tree_store::page_store::page_manager::MAX_MAX_PAGE_ORDER;
 use crate::tree_store::page_store::region::RegionHeader;
+use crate::tree_store::PageNumber;
+use std::cmp::{max, min};
 use std::ops::Range;
 
-fn round_up_to_multiple_of(value: u64, multiple: u64) -> u64 {
+// Returns None if the result does not fit in a u64
+fn round_up_to_multiple_of(value: u64, multiple: u64) -> Option<u64> {
     if value % multiple == 0 {
-        value
+        Some(value)
     } else {
-        value + multiple - value % multiple
+        value.checked_add(multiple - value % multiple)
+    }
+}
+
+/// Controls how much the database file is grown by, when it runs out of space
+///
+/// Once the database spans multiple regions, it is grown one region at a time and this policy only
+/// determines the size at which the first region is filled. The file never grows beyond the
+/// largest size that can be addressed; an allocation which would need more space fails with
+/// [`crate::StorageError::DatabaseFull`].
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GrowthPolicy {
+    /// The usable size of the database is multiplied by at least this factor. Must be >= 1.0
+    pub factor: f64,
+    /// Minimum number of bytes to grow by
+    pub min_bytes: u64,
+    /// Maximum number of bytes to grow by. Larger growth will still occur, if it is required to
+    /// satisfy an allocation
+    pub max_bytes: Option<u64>,
+}
+
+impl GrowthPolicy {
+    // Returns the reason that the policy is invalid, if it is
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if !(self.factor.is_finite() && self.factor >= 1.0) {
+            return Err(format!(
+                "growth factor must be at least 1.0, found {}",
+                self.factor
+            ));
+        }
+        if let Some(max_bytes) = self.max_bytes {
+            if max_bytes < self.min_bytes {
+                return Err(format!(
+                    "maximum growth of {max_bytes} bytes is less than the minimum of {} bytes",
+                    self.min_bytes
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // Returns the new usable size, in bytes, to grow to from `current_bytes`, such that at least
+    // `required_bytes` of additional space are available. The result is always rounded up to a
+    // whole number of pages, even if `current_bytes` or the configured step sizes are not, and is
+    // clamped to `max_usable_bytes`, which must be a whole number of pages. Returns None if the
+    // required space does not fit within `max_usable_bytes`
+    pub(super) fn next_usable_size(
+        &self,
+        current_bytes: u64,
+        required_bytes: u64,
+        page_size: u64,
+        max_usable_bytes: u64,
+    ) -> Option<u64> {
+        // The precision of a f64 is more than sufficient to compute the approximate growth
+        #[allow(
+            clippy::cast_precision_loss,
+            clippy::cast_sign_loss,
+            clippy::cast_possible_truncation
+        )]
+        let scaled = (current_bytes as f64 * self.factor) as u64;
+        let mut growth = max(scaled.saturating_sub(current_bytes), self.min_bytes);
+        if let Some(max_bytes) = self.max_bytes {
+            growth = min(growth, max_bytes);
+        }
+        let growth = max(growth, required_bytes);
+
+        if current_bytes.checked_add(required_bytes)? > max_usable_bytes {
+            return None;
+        }
+        let desired = min(current_bytes.saturating_add(growth), max_usable_bytes);
+        // Rounding up can't pass `max_usable_bytes`, since it is a whole number of pages
+        round_up_to_multiple_of(desired, page_size)
+    }
+}
+
+impl Default for GrowthPolicy {
+    fn default() -> Self {
+        Self {
+            factor: 2.0,
+            min_bytes: 0,
+            max_bytes: None,
+        }
     }
 }
 
@@ -36,8 +121,8 @@ impl RegionLayout {
     ) -> RegionLayout {
         assert!(desired_usable_bytes <= page_capacity as u64 * page_size as u64);
         let header_pages = RegionHeader::header_pages_expensive(page_size, page_capacity);
-        let num_pages =
-            round_up_to_multiple_of(desired_usable_bytes, page_size.into()) / page_size as u64;
+        let num_pages = round_up_to_multiple_of(desired_usable_bytes, page_size.into()).unwrap()
+            / page_size as u64;
 
         Self {
             num_pages: num_pages.try_into().unwrap(),
@@ -248,11 +333,19 @@ impl DatabaseLayout {
             self.full_region_layout
         }
     }
+
+    // Returns true if the page lies entirely within this layout
+    pub(super) fn contains_page(&self, page: PageNumber) -> bool {
+        page.region < self.num_regions()
+            && page.page_order <= MAX_MAX_PAGE_ORDER
+            && ((page.page_index as u64 + 1) << page.page_order)
+                <= self.region_layout(page.region).num_pages() as u64
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::tree_store::page_store::layout::RegionLayout;
+    use crate::tree_store::page_store::layout::{GrowthPolicy, RegionLayout};
 
     #[test]
     fn full_layout() {
@@ -260,4 +353,150 @@ mod test {
         assert_eq!(layout.num_pages, 512);
         assert_eq!(layout.page_size, 4096);
     }
+
+    #[test]
+    fn growth_policy() {
+        let page_size = 4096;
+        let current = 1024 * 1024;
+        let limit = 1 << 40;
+
+        // Default policy doubles
+        let policy = GrowthPolicy::default();
+        assert_eq!(
+            policy.next_usable_size(current, 4096, page_size, limit),
+            Some(2 * current)
+        );
+        // unless the allocation requires more
+        assert_eq!(
+            policy.next_usable_size(current, 3 * current, page_size, limit),
+            Some(4 * current)
+        );
+
+        let policy = GrowthPolicy {
+            factor: 1.25,
+            min_bytes: 0,
+            max_bytes: None,
+        };
+        assert_eq!(
+            policy.next_usable_size(current, 4096, page_size, limit),
+            Some(current + current / 4)
+        );
+
+        // Minimum growth step
+        let policy = GrowthPolicy {
+            factor: 1.0,
+            min_bytes: 10 * page_size,
+            max_bytes: None,
+        };
+        assert_eq!(
+            policy.next_usable_size(current, 4096, page_size, limit),
+            Some(current + 10 * page_size)
+        );
+
+        // Maximum growth step
+        let policy = GrowthPolicy {
+            factor: 4.0,
+            min_bytes: 0,
+            max_bytes: Some(8 * page_size),
+        };
+        assert_eq!(
+            policy.next_usable_size(current, 4096, page_size, limit),
+            Some(current + 8 * page_size)
+        );
+        // is exceeded when the allocation requires it
+        assert_eq!(
+            policy.next_usable_size(current, 16 * page_size, page_size, limit),
+            Some(current + 16 * page_size)
+        );
+
+        // Result is rounded up to a whole number of pages
+        let policy = GrowthPolicy {
+            factor: 1.0,
+            min_bytes: 1,
+            max_bytes: None,
+        };
+        assert_eq!(
+            policy.next_usable_size(current, 1, page_size, limit),
+            Some(current + page_size)
+        );
+        for policy in [
+            GrowthPolicy {
+                factor: 1.3,
+                min_bytes: 0,
+                max_bytes: None,
+            },
+            GrowthPolicy {
+                factor: 1.0,
+                min_bytes: 1000,
+                max_bytes: None,
+            },
+            GrowthPolicy {
+                factor: 3.0,
+                min_bytes: 0,
+                max_bytes: Some(5000),
+            },
+        ] {
+            for current in [0, 1, 4095, 4097, 1_000_000] {
+                for required in [1, 4095, 10_000] {
+                    let size = policy
+                        .next_usable_size(current, required, page_size, limit)
+                        .unwrap();
+                    assert_eq!(size % page_size, 0);
+                    assert!(size >= current + required);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn extreme_growth_policy() {
+        let page_size = 4096;
+        let current = 1024 * 1024;
+        let limit = 1 << 40;
+
+        // Growth which overflows a u64 is clamped to the limit
+        for policy in [
+            GrowthPolicy {
+                factor: 1.0,
+                min_bytes: u64::MAX,
+                max_bytes: None,
+            },
+            GrowthPolicy {
+                factor: 1e30,
+                min_bytes: 0,
+                max_bytes: None,
+            },
+        ] {
+            assert!(policy.validate().is_ok());
+            assert_eq!(
+                policy.next_usable_size(current, 4096, page_size, limit),
+                Some(limit)
+            );
+            assert_eq!(
+                policy.next_usable_size(u64::MAX - 1, 4096, page_size, u64::MAX),
+                None
+            );
+            assert_eq!(
+                policy.next_usable_size(limit - page_size, 1, page_size, limit),
+                Some(limit)
+            );
+        }
+
+        // as is growth which doesn't overflow
+        let policy = GrowthPolicy::default();
+        assert_eq!(
+            policy.next_usable_size(limit - page_size, 1, page_size, limit),
+            Some(limit)
+        );
+        // but the required space must fit
+        assert_eq!(
+            policy.next_usable_size(limit - page_size, page_size + 1, page_size, limit),
+            None
+        );
+        // and mustn't overflow
+        assert_eq!(
+            policy.next_usable_size(u64::MAX - 1, 4096, page_size, limit),
+            None
+        );
+    }
 }
@@ -0,0 +1,86 @@
+use crate::tree_store::page_store::file_lock::LockedFile;
+use crate::tree_store::page_store::header::{DatabaseHeader, MAGICNUMBER};
+use crate::tree_store::page_store::page_manager::{read_header_from_file, FILE_FORMAT_VERSION};
+use crate::{DatabaseError, StorageError};
+use std::fmt::{Debug, Formatter};
+use std::fs::File;
+use std::io;
+use std::sync::Arc;
+
+type MigrationFn = dyn Fn(&File) -> io::Result<()> + Send + Sync;
+
+/// A step which upgrades a database file from one version of the file format to the next, for use
+/// with [`Database::migrate`](crate::Database::migrate)
+#[derive(Clone)]
+pub struct MigrationStep {
+    from_version: u8,
+    apply: Arc<MigrationFn>,
+}
+
+impl MigrationStep {
+    /// Creates a step which upgrades files of version `from_version` to version `from_version + 1`
+    ///
+    /// `apply` rewrites the file in place, and must not change the version recorded in its commit
+    /// slots. The new version is only recorded once `apply` returns and the file has been synced,
+    /// so if it is interrupted by a crash, it runs again on the partially upgraded file
+    pub fn new(
+        from_version: u8,
+        apply: impl Fn(&File) -> io::Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            from_version,
+            apply: Arc::new(apply),
+        }
+    }
+
+    /// The file format version which this step upgrades from
+    pub fn from_version(&self) -> u8 {
+        self.from_version
+    }
+}
+
+impl Debug for MigrationStep {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MigrationStep")
+            .field("from_version", &self.from_version)
+            .finish()
+    }
+}
+
+fn read_header(file: &File) -> Result<Vec<u8>, DatabaseError> {
+    match read_header_from_file(file, 0)? {
+        Some(header) if header[..MAGICNUMBER.len()] == MAGICNUMBER => Ok(header),
+        _ => Err(StorageError::Corrupted("Invalid magic number".to_string()).into()),
+    }
+}
+
+// Returns the file format version recorded in the latest commit of the database in `file`
+pub(crate) fn stored_format_version(file: &File) -> Result<u8, DatabaseError> {
+    Ok(DatabaseHeader::stored_version(&read_header(file)?))
+}
+
+// Upgrades the database in `file` to FILE_FORMAT_VERSION, one step at a time
+pub(crate) fn migrate(file: File, steps: &[MigrationStep]) -> Result<(), DatabaseError> {
+    let file = LockedFile::new(file)?;
+    loop {
+        let version = stored_format_version(file.file())?;
+        if version > FILE_FORMAT_VERSION {
+            return Err(DatabaseError::NewerFileFormat(version));
+        }
+        if version == FILE_FORMAT_VERSION {
+            return Ok(());
+        }
+        let step = steps
+            .iter()
+            .find(|step| step.from_version == version)
+            .ok_or(DatabaseError::UpgradeRequired(version))?;
+        (step.apply)(file.file())?;
+        file.file().sync_data()?;
+
+        // The step may have rewritten the header, so it is read again
+        let mut header = read_header(file.file())?;
+        DatabaseHeader::set_stored_version(&mut header, version + 1);
+        file.write(0, &header)?;
+        file.file().sync_data()?;
+    }
+}
@@ -0,0 +1,94 @@
+use crate::transaction_tracker::TransactionId;
+use crate::tree_store::page_store::file_lock::LockedFile;
+use crate::tree_store::page_store::header::{DatabaseHeader, MAGICNUMBER};
+use crate::tree_store::page_store::page_manager::read_header_from_file;
+use crate::{DatabaseError, Result, StorageError};
+use std::fs::File;
+use std::io;
+use std::io::{Seek, SeekFrom};
+
+// Returns the mirror id of the database in `file`, and the id of its latest commit whose commit
+// slot is intact, or None if the file does not hold a database
+fn read_state(file: &File) -> Result<Option<(u64, Option<TransactionId>)>> {
+    let header = match read_header_from_file(file, 0)? {
+        Some(header) if header[..MAGICNUMBER.len()] == MAGICNUMBER => header,
+        _ => return Ok(None),
+    };
+    let (header, repair_info) = DatabaseHeader::from_bytes(&header);
+    let latest_commit = if !repair_info.primary_corrupted {
+        Some(header.primary_slot().transaction_id)
+    } else if !repair_info.secondary_corrupted {
+        Some(header.secondary_slot().transaction_id)
+    } else {
+        None
+    };
+    Ok(Some((header.mirror_id(), latest_commit)))
+}
+
+// Returns true if the two files have the same length and header
+pub(super) fn same_header(a: &File, b: &File) -> Result<bool> {
+    Ok(a.metadata()?.len() == b.metadata()?.len()
+        && read_header_from_file(a, 0)? == read_header_from_file(b, 0)?)
+}
+
+// Overwrites `to` with the whole contents of `from`, and syncs it
+pub(super) fn copy_file(mut from: &File, mut to: &File) -> io::Result<()> {
+    from.seek(SeekFrom::Start(0))?;
+    to.seek(SeekFrom::Start(0))?;
+    let len = io::copy(&mut from, &mut to)?;
+    to.set_len(len)?;
+    to.sync_data()
+}
+
+// Makes the two copies of a mirrored database identical, before it is opened. Commits are synced
+// to the primary file and then to the mirror, so the two only differ if a crash interrupted a
+// commit between those syncs, or one of the files was lost. The file whose latest intact commit is
+// older is authoritative, since the other may hold a commit which was never acknowledged.
+//
+// An empty file is restored from the other. Otherwise, both files must record the same mirror id,
+// so that a file which is not a copy of the database, such as another database or the mirror of
+// one, is never copied over it
+pub(crate) fn reconcile_mirror(primary: &File, mirror: &File) -> Result<(), DatabaseError> {
+    // Both files are locked while they may be rewritten, as during a migration
+    let primary = LockedFile::new(primary.try_clone()?)?;
+    let mirror = LockedFile::new(mirror.try_clone()?)?;
+    if mirror.file().metadata()?.len() == 0 {
+        // Filled in once the database is opened
+        return Ok(());
+    }
+    if primary.file().metadata()?.len() == 0 {
+        copy_file(mirror.file(), primary.file())?;
+        return Ok(());
+    }
+    if same_header(primary.file(), mirror.file())? {
+        return Ok(());
+    }
+    match (read_state(primary.file())?, read_state(mirror.file())?) {
+        (Some((primary_id, primary_commit)), Some((mirror_id, mirror_commit)))
+            if primary_id != 0 && primary_id == mirror_id =>
+        {
+            match (primary_commit, mirror_commit) {
+                (Some(primary_commit), Some(mirror_commit)) if primary_commit <= mirror_commit => {
+                    copy_file(primary.file(), mirror.file())?;
+                }
+                (_, Some(_)) => {
+                    copy_file(mirror.file(), primary.file())?;
+                }
+                (Some(_), None) => {
+                    copy_file(primary.file(), mirror.file())?;
+                }
+                (None, None) => {
+                    return Err(StorageError::Corrupted(
+                        "Both commit slots are corrupted in the database and its mirror"
+                            .to_string(),
+                    )
+                    .into());
+                }
+            }
+            Ok(())
+        }
+        _ => Err(DatabaseError::InvalidConfiguration(
+            "the mirror file is not empty, and is not a mirror of this database".to_string(),
+        )),
+    }
+}
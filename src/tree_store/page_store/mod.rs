@@ -1,19 +1,36 @@
+mod backup;
 mod base;
 mod bitmap;
 mod buddy_allocator;
 mod cached_file;
+mod cold_pages;
 mod file_lock;
 mod header;
 mod layout;
+mod migration;
+mod mirror;
 mod page_manager;
 mod region;
 mod savepoint;
+mod sync_timer;
 #[allow(dead_code)]
 mod xxh3;
 
+pub(crate) use backup::{restore_backup, write_backup};
 pub(crate) use base::{Page, PageHint, PageNumber, MAX_VALUE_LENGTH};
-pub(crate) use header::PAGE_SIZE;
-pub(crate) use page_manager::{xxh3_checksum, TransactionalMemory, FILE_FORMAT_VERSION};
+pub(crate) use cached_file::EmbeddedRange;
+pub(crate) use cold_pages::ColdPageCallback;
+pub(crate) use header::{MAX_PAGE_SIZE, PAGE_SIZE};
+pub use header::{HybridTimestamp, MetapageDump, TransactionSlotDump};
+pub use layout::GrowthPolicy;
+pub use migration::MigrationStep;
+pub(crate) use migration::{migrate, stored_format_version};
+pub(crate) use mirror::reconcile_mirror;
+pub(crate) use page_manager::{
+    holds_database, is_zeroed, xxh3_checksum, GrowthCallback, TransactionalMemory,
+    DEFAULT_NODE_FILL_TARGET,
+};
+pub use page_manager::{AllocationPolicy, Capacity, FILE_FORMAT_VERSION};
 pub use savepoint::Savepoint;
 pub(crate) use savepoint::SerializedSavepoint;
 
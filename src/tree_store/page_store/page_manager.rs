@@ -1,36 +1,70 @@
 use crate::transaction_tracker::TransactionId;
 use crate::tree_store::btree_base::Checksum;
+use crate::tree_store::page_store::backup::{index_backup, BACKUP_HEADER_OFFSET};
 use crate::tree_store::page_store::base::{PageHint, MAX_PAGE_INDEX};
 use crate::tree_store::page_store::buddy_allocator::BuddyAllocator;
-use crate::tree_store::page_store::cached_file::PagedCachedFile;
-use crate::tree_store::page_store::header::{DatabaseHeader, DB_HEADER_SIZE, MAGICNUMBER};
-use crate::tree_store::page_store::layout::DatabaseLayout;
+use crate::tree_store::page_store::cached_file::{EmbeddedRange, PagedCachedFile};
+use crate::tree_store::page_store::cold_pages::{ColdPageCallback, ColdPageTracker};
+use crate::tree_store::page_store::header::{
+    DatabaseHeader, HybridTimestamp, MetapageDump, TransactionHeader, TransactionSlotDump,
+    DB_HEADER_PREFIX_SIZE, MAGICNUMBER, MAX_PAGE_SIZE, MAX_REGIONS, MIN_DB_HEADER_SIZE,
+};
+use crate::tree_store::page_store::layout::{DatabaseLayout, GrowthPolicy};
 use crate::tree_store::page_store::region::{Allocators, RegionTracker};
+use crate::tree_store::page_store::sync_timer::SyncTimer;
 use crate::tree_store::page_store::{hash128_with_seed, PageImpl, PageMut};
 use crate::tree_store::{Page, PageNumber};
-use crate::{DatabaseError, Result, StorageError};
+use crate::{CorruptionFinding, DatabaseError, Result, StorageError};
 #[cfg(feature = "logging")]
 use log::warn;
 use std::cmp::{max, min};
-#[cfg(debug_assertions)]
+use std::collections::hash_map::RandomState;
+#[cfg(any(debug_assertions, feature = "page_metrics"))]
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::convert::TryInto;
 use std::fs::File;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Mutex;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 // Regions have a maximum size of 4GiB. A `4GiB - overhead` value is the largest that can be represented,
 // because the leaf node format uses 32bit offsets
-const MAX_USABLE_REGION_SPACE: u64 = 4 * 1024 * 1024 * 1024;
+pub(super) const MAX_USABLE_REGION_SPACE: u64 = 4 * 1024 * 1024 * 1024;
 // TODO: remove this constant?
 pub(crate) const MAX_MAX_PAGE_ORDER: u8 = 20;
 pub(super) const MIN_USABLE_PAGES: u32 = 10;
 const MIN_DESIRED_USABLE_BYTES: u64 = 1024 * 1024;
+// Split overfull b-tree nodes evenly
+pub(crate) const DEFAULT_NODE_FILL_TARGET: f64 = 0.5;
+
+// Called with the old and new length of the file, and the id of the write transaction whose
+// allocation grew it
+pub(crate) type GrowthCallback = Arc<dyn Fn(u64, u64, u64) + Send + Sync>;
 
 pub(super) const INITIAL_REGIONS: u32 = 1000; // Enough for a 4TiB database
 
-pub(crate) const FILE_FORMAT_VERSION: u8 = 1;
+/// The version of the file format written by this version of redb
+pub const FILE_FORMAT_VERSION: u8 = 1;
+
+// Milliseconds since the UNIX epoch, or zero if the clock is set before it
+fn wall_clock_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_millis().try_into().unwrap_or(u64::MAX))
+        .unwrap_or(0)
+}
+
+// A random, non-zero id
+fn new_mirror_id() -> u64 {
+    // Each RandomState is seeded with fresh random keys
+    let id = RandomState::new().build_hasher().finish();
+    max(id, 1)
+}
 
 fn ceil_log2(x: usize) -> u8 {
     if x.is_power_of_two() {
@@ -40,10 +74,147 @@ fn ceil_log2(x: usize) -> u8 {
     }
 }
 
+// Checks the file format version recorded in each commit slot. This comes before any other check,
+// since a newer format may lay out the rest of the header differently
+fn validate_version(header: &DatabaseHeader) -> Result<(), DatabaseError> {
+    for version in [
+        header.primary_slot().version,
+        header.secondary_slot().version,
+    ] {
+        if version > FILE_FORMAT_VERSION {
+            return Err(DatabaseError::NewerFileFormat(version));
+        }
+        if version < FILE_FORMAT_VERSION {
+            return Err(DatabaseError::UpgradeRequired(version));
+        }
+    }
+
+    Ok(())
+}
+
+// Checks the file format version and layout recorded in the header, against a file of `file_len` bytes
+fn validate_header(header: &DatabaseHeader, file_len: u64) -> Result<(), DatabaseError> {
+    validate_version(header)?;
+    header.validate_layout()?;
+    // The file is grown before a commit records the larger layout, and only shrunk after, so
+    // a crash cannot leave it shorter than the layout. It was truncated by something else
+    let expected_len = header.layout().len();
+    if file_len < expected_len {
+        return Err(DatabaseError::FileTruncated {
+            expected: expected_len,
+            actual: file_len,
+        });
+    }
+
+    Ok(())
+}
+
+// A file which is not a whole number of pages would leave a partial page at its end, past the
+// layout. Every layout is a whole number of pages, and the file is only ever resized to one
+fn validate_file_len(header: &DatabaseHeader, file_len: u64) -> Result<(), DatabaseError> {
+    let page_size = header.page_size();
+    if file_len % u64::from(page_size) != 0 {
+        return Err(DatabaseError::InvalidFileSize {
+            len: file_len,
+            page_size: page_size.try_into().unwrap(),
+        });
+    }
+
+    Ok(())
+}
+
 pub(crate) fn xxh3_checksum(data: &[u8]) -> Checksum {
     hash128_with_seed(data, 0)
 }
 
+// Reads the header, including both commit slots, from `file` starting at `offset`. Returns None if
+// the file is too short to hold it
+pub(super) fn read_header_from_file(mut file: &File, offset: u64) -> Result<Option<Vec<u8>>> {
+    let file_len = file.metadata()?.len();
+    if file_len < offset + DB_HEADER_PREFIX_SIZE as u64 {
+        return Ok(None);
+    }
+    let mut header_bytes = vec![0; DB_HEADER_PREFIX_SIZE];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut header_bytes)?;
+    let len = DatabaseHeader::stored_size(&header_bytes)?;
+    if file_len < offset + len as u64 {
+        return Ok(None);
+    }
+    header_bytes.resize(len, 0);
+    file.read_exact(&mut header_bytes[DB_HEADER_PREFIX_SIZE..])?;
+
+    Ok(Some(header_bytes))
+}
+
+// Returns true if `file` holds a database starting at `offset`
+pub(crate) fn holds_database(mut file: &File, offset: u64) -> Result<bool> {
+    if file.metadata()?.len() < offset + MAGICNUMBER.len() as u64 {
+        return Ok(false);
+    }
+    let mut magic_number = [0; MAGICNUMBER.len()];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut magic_number)?;
+    Ok(magic_number == MAGICNUMBER)
+}
+
+// Returns true if every byte of `file` in the `len` bytes starting at `offset` is zero. Bytes past
+// the end of the file are not checked
+pub(crate) fn is_zeroed(mut file: &File, offset: u64, len: u64) -> Result<bool> {
+    file.seek(SeekFrom::Start(offset))?;
+    let mut window = file.take(len);
+    let mut buffer = vec![0; 1024 * 1024];
+    loop {
+        let read = window.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(true);
+        }
+        if buffer[..read].iter().any(|&x| x != 0) {
+            return Ok(false);
+        }
+    }
+}
+
+// Reads the header, including both commit slots, ignoring any cached data
+fn read_header_direct(storage: &PagedCachedFile) -> Result<Vec<u8>> {
+    let prefix = storage.read_direct(0, DB_HEADER_PREFIX_SIZE)?;
+    storage.read_direct(0, DatabaseHeader::stored_size(&prefix)?)
+}
+
+// Returns the page size stored in the header, if the file is an existing database
+fn read_stored_page_size(file: &File, offset: u64) -> Result<Option<usize>, DatabaseError> {
+    let header_bytes = if let Some(bytes) = read_header_from_file(file, offset)? {
+        bytes
+    } else {
+        return Ok(None);
+    };
+    let (header, repair_info) = DatabaseHeader::from_bytes(&header_bytes);
+    if repair_info.invalid_magic_number {
+        return Ok(None);
+    }
+    validate_version(&header)?;
+    header.validate_layout()?;
+
+    Ok(Some(header.page_size().try_into().unwrap()))
+}
+
+// Checks that the roots referenced by the primary commit slot lie within the database file
+fn validate_slot_pages(header: &DatabaseHeader) -> Result {
+    let layout = header.layout();
+    let slot = header.primary_slot();
+    for (root, _) in [slot.user_root, slot.system_root, slot.freed_root]
+        .into_iter()
+        .flatten()
+    {
+        if !layout.contains_page(root) {
+            return Err(StorageError::Corrupted(format!(
+                "Root page {root:?} is outside the database file"
+            )));
+        }
+    }
+    Ok(())
+}
+
 struct InMemoryState {
     header: DatabaseHeader,
     allocators: Allocators,
@@ -72,39 +243,192 @@ impl InMemoryState {
     }
 }
 
+/// Controls which free page is chosen when a page is allocated
+///
+/// Both policies are deterministic: the same sequence of operations on a database always produces
+/// the same pages.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum AllocationPolicy {
+    /// Use a free block of exactly the requested size, if there is one, and only split a larger
+    /// block otherwise. This keeps large blocks intact, which limits fragmentation
+    #[default]
+    SmallestFit,
+    /// Use the free page with the lowest index, splitting a larger block if necessary. This keeps
+    /// the data at the start of the file, so the layout depends only on which pages are free
+    LowestIndex,
+}
+
+/// How much space the database has, as returned by [`crate::Database::capacity`]
+///
+/// All counts are in pages
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Capacity {
+    usable: u64,
+    free: u64,
+    largest_run: u64,
+}
+
+impl Capacity {
+    /// Number of pages in the file which can hold data, whether allocated or not
+    pub fn usable(&self) -> u64 {
+        self.usable
+    }
+
+    /// Number of pages which are free. This many single-page allocations can be made before the
+    /// file must be grown
+    pub fn free(&self) -> u64 {
+        self.free
+    }
+
+    /// Length of the longest run of consecutive free pages. Allocations are aligned to their
+    /// size, which is a power of two, so an allocation this large may still require growing the
+    /// file
+    pub fn largest_run(&self) -> u64 {
+        self.largest_run
+    }
+}
+
+// Where in the free space an allocation is placed
+#[derive(Copy, Clone, Debug)]
+enum Placement {
+    SmallestFit,
+    Lowest,
+    Highest,
+}
+
 pub(crate) struct TransactionalMemory {
     // Pages allocated since the last commit
     // TODO: maybe this should be moved to WriteTransaction?
     allocated_since_commit: Mutex<HashSet<PageNumber>>,
+    // Pages which were allocated and then freed since the last commit, most recent last. They are
+    // preferred by the next allocations of the same order, since they are likely to still be
+    // cached. Entries are only hints: they are checked against the allocator before being used
+    recently_freed: Mutex<Vec<PageNumber>>,
+    // First error from scrubbing a page freed by a caller which couldn't return it, such as a Drop
+    // impl. It is returned by the next commit or rollback instead
+    deferred_free_error: Mutex<Option<StorageError>>,
     // True if the allocator state was corrupted when the file was opened
     needs_recovery: AtomicBool,
-    storage: PagedCachedFile,
-    state: Mutex<InMemoryState>,
+    storage: Arc<PagedCachedFile>,
+    // Readers of the commit slots take the read lock, so beginning read transactions doesn't
+    // serialize them. The primary bit is only swapped, and read_from_secondary only changed, while
+    // holding the write lock, so a reader always sees the two consistently
+    state: RwLock<InMemoryState>,
     // The number of PageMut which are outstanding
     #[cfg(debug_assertions)]
     open_dirty_pages: Mutex<HashSet<PageNumber>>,
     // Reference counts of PageImpls that are outstanding
     #[cfg(debug_assertions)]
     read_page_ref_counts: Mutex<HashMap<PageNumber, u64>>,
+    // Number of times each page has been read or written since the database was opened
+    #[cfg(feature = "page_metrics")]
+    page_access_counts: Mutex<HashMap<PageNumber, u64>>,
     // Indicates that a non-durable commit has been made, so reads should be served from the secondary meta page
     read_from_secondary: AtomicBool,
+    // Commit which was written to the secondary slot, but never made primary, and was discarded
+    // when the file was opened
+    discarded_commit: Option<TransactionSlotDump>,
     page_size: u32,
     // We store these separately from the layout because they're static, and accessed on the get_page()
     // code path where there is no locking
     region_size: u64,
     region_header_with_padding_size: u64,
+    // The largest page order which fits in a region. Bounded by MAX_MAX_PAGE_ORDER
+    max_page_order: u8,
+    growth_policy: GrowthPolicy,
+    // Maximum number of pages a write transaction may allocate before it is committed
+    transaction_page_limit: Option<usize>,
+    // Set while a transaction is committing, since the commit itself needs to allocate pages
+    transaction_page_limit_lifted: AtomicBool,
+    // Overwrite pages with zeros when they are freed
+    zero_on_free: bool,
+    // Deallocate the storage of pages when they are freed
+    punch_holes_on_free: bool,
+    // Read the header back after each durable commit, and check that it matches what was written
+    verify_commits: bool,
+    // Commits whose fsyncs are expected to take longer than this are left to be synced later
+    commit_latency_budget: Option<Duration>,
+    // Copy each commit slot into the commit journal, as well as into the header
+    commit_journal: bool,
+    // Commits are timestamped with this, instead of the wall clock, so that the file is the same
+    // every run
+    fixed_clock_millis: Option<u64>,
+    // Fraction of the contents of an overfull b-tree node which is kept in the first node of a split
+    node_fill_target: f64,
+    allocation_policy: AllocationPolicy,
+    // Allocations of at least this many bytes are placed at the end of the free space, so that
+    // they are kept apart from the smaller b-tree pages at the start of the file
+    overflow_threshold: Option<usize>,
+    // The most pages which the leaf storing a single entry may span
+    max_value_pages: u64,
+    // Nothing is written to the file, including by recovery and on drop
+    read_only: bool,
+    growth_callback: Option<GrowthCallback>,
+    // Reports reads of pages which were not read recently
+    cold_pages: Option<ColdPageTracker>,
+    // Id of the write transaction in progress, or of the last one if none is
+    write_transaction_id: AtomicU64,
+    // Syncs commits which were made without an fsync, within the configured interval
+    sync_timer: Option<SyncTimer>,
 }
 
 impl TransactionalMemory {
+    // For an existing database the stored page size is used, and `page_size` is ignored unless
+    // `strict_page_size` is set, in which case a mismatch is an error
     #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         file: File,
+        embedded: Option<EmbeddedRange>,
         page_size: usize,
+        strict_page_size: bool,
         requested_region_size: Option<u64>,
         read_cache_size_bytes: usize,
         write_cache_size_bytes: usize,
+        growth_policy: GrowthPolicy,
+        repair_truncated: bool,
+        discard_non_durable: bool,
+        skip_init_fsync: bool,
+        read_only: bool,
     ) -> Result<Self, DatabaseError> {
-        assert!(page_size.is_power_of_two() && page_size >= DB_HEADER_SIZE);
+        if !page_size.is_power_of_two()
+            || !(MIN_DB_HEADER_SIZE..=MAX_PAGE_SIZE).contains(&page_size)
+        {
+            return Err(DatabaseError::InvalidPageSize {
+                requested: page_size,
+            });
+        }
+        growth_policy
+            .validate()
+            .map_err(DatabaseError::InvalidConfiguration)?;
+
+        // A full backup is read in place, as if it had been restored
+        let backup = if read_only {
+            index_backup(&file)?
+        } else {
+            None
+        };
+        let header_offset = if backup.is_some() {
+            BACKUP_HEADER_OFFSET
+        } else {
+            embedded.map_or(0, |range| range.offset)
+        };
+        let page_size = match read_stored_page_size(&file, header_offset)? {
+            Some(stored) if stored != page_size => {
+                if strict_page_size {
+                    return Err(DatabaseError::PageSizeMismatch {
+                        requested: page_size,
+                        stored,
+                    });
+                }
+                #[cfg(feature = "logging")]
+                warn!(
+                    "Ignoring requested page size {}. Database was created with page size {}",
+                    page_size, stored
+                );
+                stored
+            }
+            _ => page_size,
+        };
 
         let region_size = requested_region_size.unwrap_or(MAX_USABLE_REGION_SPACE);
         let region_size = min(region_size, (MAX_PAGE_INDEX as u64 + 1) * page_size as u64);
@@ -132,27 +456,35 @@ impl TransactionalMemory {
             page_size.try_into().unwrap(),
         );
 
-        {
-            let file_len = file.metadata()?.len();
-
-            if file_len < layout.len() {
-                file.set_len(layout.len())?;
-            }
-        }
-
-        let mut storage = PagedCachedFile::new(
+        let storage = PagedCachedFile::new(
             file,
+            embedded,
+            backup,
             page_size as u64,
             read_cache_size_bytes,
             write_cache_size_bytes,
         )?;
 
-        let magic_number: [u8; MAGICNUMBER.len()] = storage
-            .read_direct(0, MAGICNUMBER.len())?
-            .try_into()
-            .unwrap();
+        let file_len = storage.raw_file_len()?;
+        let magic_number: [u8; MAGICNUMBER.len()] = if file_len >= MAGICNUMBER.len() as u64 {
+            storage
+                .read_direct(0, MAGICNUMBER.len())?
+                .try_into()
+                .unwrap()
+        } else {
+            [0; MAGICNUMBER.len()]
+        };
 
+        if magic_number != MAGICNUMBER && read_only {
+            return Err(StorageError::Corrupted("Invalid magic number".to_string()).into());
+        }
         if magic_number != MAGICNUMBER {
+            // Only a file that is being initialized is extended. An existing database which is too
+            // short is reported as truncated below. An embedded database always starts out with the
+            // initial layout, whatever the size of its window
+            if file_len < layout.len() || storage.is_embedded() {
+                storage.resize(layout.len())?;
+            }
             let mut allocators = Allocators::new(layout);
 
             // Allocate the region tracker in the zeroth region
@@ -168,49 +500,115 @@ impl TransactionalMemory {
 
             let mut header = DatabaseHeader::new(layout, TransactionId(0), tracker_page);
 
+            header.set_allocator_checksum(allocators.checksum(layout));
             header.recovery_required = false;
             storage
-                .write(0, DB_HEADER_SIZE, true)?
+                .write(0, header.db_header_size(), true)?
                 .mem_mut()
                 .copy_from_slice(&header.to_bytes(false, false));
-            allocators.flush_to(tracker_page, layout, &mut storage)?;
+            allocators.flush_to(tracker_page, layout, &storage)?;
 
-            storage.flush()?;
+            // Without the fsyncs, the writes are still issued in the same order, so the file is
+            // consistent as long as the operating system does not crash
+            let flush = |storage: &PagedCachedFile| {
+                if skip_init_fsync {
+                    storage.write_barrier()
+                } else {
+                    storage.flush()
+                }
+            };
+            flush(&storage)?;
             // Write the magic number only after the data structure is initialized and written to disk
             // to ensure that it's crash safe
             storage
-                .write(0, DB_HEADER_SIZE, true)?
+                .write(0, header.db_header_size(), true)?
                 .mem_mut()
                 .copy_from_slice(&header.to_bytes(true, false));
-            storage.flush()?;
+            flush(&storage)?;
         }
-        let header_bytes = storage.read_direct(0, DB_HEADER_SIZE)?;
+        let header_bytes = read_header_direct(&storage)?;
         let (mut header, repair_info) = DatabaseHeader::from_bytes(&header_bytes);
 
-        assert_eq!(header.page_size() as usize, page_size);
-        let version = header.primary_slot().version;
-        if version > FILE_FORMAT_VERSION {
-            return Err(StorageError::Corrupted(format!(
-                "Expected file format version {FILE_FORMAT_VERSION}, found {version}",
-            ))
+        if header.page_size() as usize != page_size {
+            return Err(StorageError::Corrupted(
+                "Database header changed while opening".to_string(),
+            )
             .into());
         }
-        if version < FILE_FORMAT_VERSION {
-            return Err(DatabaseError::UpgradeRequired(version));
+        if repair_truncated {
+            Self::shrink_layout_to_file(&mut header, &storage)?;
         }
-        let version = header.secondary_slot().version;
-        if version > FILE_FORMAT_VERSION {
-            return Err(StorageError::Corrupted(format!(
-                "Expected file format version {FILE_FORMAT_VERSION}, found {version}",
-            ))
-            .into());
+        validate_header(&header, storage.raw_file_len()?)?;
+        // The window of an embedded database may be any length
+        if !storage.is_embedded() {
+            validate_file_len(&header, storage.raw_file_len()?)?;
         }
-        if version < FILE_FORMAT_VERSION {
-            return Err(DatabaseError::UpgradeRequired(version));
+        if storage.is_embedded() {
+            // The window may extend past the end of the database, so its length is taken from
+            // the header instead of the file
+            storage.resize(header.layout().len())?;
+        }
+        let mut repair_info = repair_info;
+        let journal_range = header.journal_range();
+        let journal = storage.read_direct(
+            journal_range.start,
+            (journal_range.end - journal_range.start)
+                .try_into()
+                .unwrap(),
+        )?;
+        if let Some(latest) = header.latest_journal_entry(&journal) {
+            // A commit slot which is corrupted, or older than the journal, was torn or never
+            // written. The journal only replaces a slot which is not the latest valid commit, so
+            // that the other slot still holds an earlier one, should the journaled commit fail
+            // verification
+            let newer_than = |slot: &TransactionHeader, corrupted: bool| {
+                corrupted || latest.transaction_id > slot.transaction_id
+            };
+            if repair_info.primary_corrupted {
+                if newer_than(header.secondary_slot(), repair_info.secondary_corrupted) {
+                    *header.primary_slot_mut() = latest;
+                    repair_info.primary_corrupted = false;
+                    header.recovery_required = true;
+                }
+            } else if newer_than(header.primary_slot(), false)
+                && newer_than(header.secondary_slot(), repair_info.secondary_corrupted)
+            {
+                // Promoted to the primary by the repair below, like a secondary which was written
+                // but never made primary
+                *header.secondary_slot_mut() = latest;
+                repair_info.secondary_corrupted = false;
+                header.recovery_required = true;
+            }
         }
 
+        // The allocator state is only trusted if it matches the checksum written along with it.
+        // Otherwise, it is rebuilt by the repair path, the same as after a crash
+        let allocators = if header.recovery_required {
+            None
+        } else {
+            match Allocators::from_bytes(&header, &storage) {
+                Ok(allocators)
+                    if allocators.checksum(header.layout()) == header.allocator_checksum() =>
+                {
+                    Some(allocators)
+                }
+                _ => {
+                    #[cfg(feature = "logging")]
+                    warn!("Allocator state is corrupted. Repairing");
+                    header.recovery_required = true;
+                    None
+                }
+            }
+        };
+
+        let mut discarded_commit = None;
         let needs_recovery = header.recovery_required;
         if needs_recovery {
+            if repair_info.primary_corrupted && repair_info.secondary_corrupted {
+                return Err(
+                    StorageError::Corrupted("Both commit slots are corrupted".to_string()).into(),
+                );
+            }
             let layout = header.layout();
             let region_max_pages = layout.full_region_layout().num_pages();
             let region_header_pages = layout.full_region_layout().get_header_pages();
@@ -218,8 +616,9 @@ impl TransactionalMemory {
                 storage.raw_file_len()?,
                 region_header_pages,
                 region_max_pages,
-                page_size.try_into().unwrap(),
+                header.page_size(),
             ));
+            header.validate_layout()?;
             if repair_info.primary_corrupted {
                 header.swap_primary_slot();
             } else {
@@ -229,48 +628,316 @@ impl TransactionalMemory {
                 let secondary_newer =
                     header.secondary_slot().transaction_id > header.primary_slot().transaction_id;
                 if secondary_newer && !repair_info.secondary_corrupted {
-                    header.swap_primary_slot();
+                    if discard_non_durable {
+                        // The primary bit was never flipped to this commit, so it was not made
+                        // durable. Overwrite it, so that a later open can't promote it either
+                        discarded_commit =
+                            Some(TransactionSlotDump::new(header.secondary_slot(), true));
+                        *header.secondary_slot_mut() = header.primary_slot().clone();
+                        // The journal may also record it
+                        if !read_only {
+                            storage
+                                .write_unbuffered(journal_range.start, &vec![0; journal.len()])?;
+                        }
+                    } else {
+                        header.swap_primary_slot();
+                    }
                 }
             }
-            assert!(!repair_info.invalid_magic_number);
-            storage
-                .write(0, DB_HEADER_SIZE, true)?
-                .mem_mut()
-                .copy_from_slice(&header.to_bytes(true, false));
-            storage.flush()?;
+            if repair_info.invalid_magic_number {
+                return Err(StorageError::Corrupted("Invalid magic number".to_string()).into());
+            }
+            if !read_only {
+                storage
+                    .write(0, header.db_header_size(), true)?
+                    .mem_mut()
+                    .copy_from_slice(&header.to_bytes(true, false));
+                storage.flush()?;
+            }
+        } else if repair_info.primary_corrupted {
+            return Err(
+                StorageError::Corrupted("Primary commit slot is corrupted".to_string()).into(),
+            );
         }
 
         let layout = header.layout();
-        assert_eq!(layout.len(), storage.raw_file_len()?);
+        if layout.len() != storage.raw_file_len()? {
+            return Err(StorageError::Corrupted(format!(
+                "Expected file length {}, found {}",
+                layout.len(),
+                storage.raw_file_len()?
+            ))
+            .into());
+        }
+        validate_slot_pages(&header)?;
         let region_size = layout.full_region_layout().len();
         let region_header_size = layout.full_region_layout().data_section().start;
+        let region_pages = layout.full_region_layout().num_pages();
+        let max_page_order = min(
+            MAX_MAX_PAGE_ORDER,
+            (u32::BITS - 1 - region_pages.leading_zeros())
+                .try_into()
+                .unwrap(),
+        );
 
-        let state = InMemoryState::from_bytes(header, &storage)?;
-
-        assert!(page_size >= DB_HEADER_SIZE);
+        let allocators = allocators.unwrap_or_else(|| Allocators::new(header.layout()));
+        let state = InMemoryState { header, allocators };
 
         Ok(Self {
             allocated_since_commit: Mutex::new(HashSet::new()),
+            recently_freed: Mutex::new(vec![]),
+            deferred_free_error: Mutex::new(None),
             needs_recovery: AtomicBool::new(needs_recovery),
-            storage,
-            state: Mutex::new(state),
+            storage: Arc::new(storage),
+            state: RwLock::new(state),
             #[cfg(debug_assertions)]
             open_dirty_pages: Mutex::new(HashSet::new()),
             #[cfg(debug_assertions)]
             read_page_ref_counts: Mutex::new(HashMap::new()),
+            #[cfg(feature = "page_metrics")]
+            page_access_counts: Mutex::new(HashMap::new()),
             read_from_secondary: AtomicBool::new(false),
+            discarded_commit,
             page_size: page_size.try_into().unwrap(),
             region_size,
             region_header_with_padding_size: region_header_size,
+            max_page_order,
+            growth_policy,
+            transaction_page_limit: None,
+            transaction_page_limit_lifted: AtomicBool::new(false),
+            zero_on_free: false,
+            punch_holes_on_free: false,
+            verify_commits: false,
+            commit_latency_budget: None,
+            commit_journal: false,
+            fixed_clock_millis: None,
+            node_fill_target: DEFAULT_NODE_FILL_TARGET,
+            allocation_policy: AllocationPolicy::default(),
+            overflow_threshold: None,
+            max_value_pages: u64::MAX,
+            read_only,
+            growth_callback: None,
+            cold_pages: None,
+            write_transaction_id: AtomicU64::new(0),
+            sync_timer: None,
         })
     }
 
+    pub(crate) fn set_zero_on_free(&mut self, enabled: bool) {
+        self.zero_on_free = enabled;
+    }
+
+    pub(crate) fn set_punch_holes_on_free(&mut self, enabled: bool) {
+        self.punch_holes_on_free = enabled;
+    }
+
+    pub(crate) fn set_verify_commits(&mut self, enabled: bool) {
+        self.verify_commits = enabled;
+    }
+
+    pub(crate) fn set_commit_latency_budget(&mut self, budget: Option<Duration>) {
+        self.commit_latency_budget = budget;
+    }
+
+    pub(crate) fn set_commit_journal(&mut self, enabled: bool) {
+        self.commit_journal = enabled;
+    }
+
+    pub(crate) fn set_fixed_clock(&mut self, millis: Option<u64>) {
+        self.fixed_clock_millis = millis;
+    }
+
+    fn clock_millis(&self) -> u64 {
+        self.fixed_clock_millis.unwrap_or_else(wall_clock_millis)
+    }
+
+    // Returns true if the fsyncs of a durable commit are expected to exceed the latency budget,
+    // based on the duration of recent fsyncs. A two phase commit syncs twice
+    pub(crate) fn exceeds_commit_latency_budget(&self, two_phase: bool) -> bool {
+        match (
+            self.commit_latency_budget,
+            self.storage.estimated_sync_time(),
+        ) {
+            (Some(budget), Some(estimate)) => {
+                let syncs = if two_phase { 2 } else { 1 };
+                estimate.saturating_mul(syncs) > budget
+            }
+            _ => false,
+        }
+    }
+
+    pub(crate) fn set_growth_callback(&mut self, callback: Option<GrowthCallback>) {
+        self.growth_callback = callback;
+    }
+
+    pub(crate) fn set_cold_page_callback(&mut self, callback: Option<ColdPageCallback>) {
+        self.cold_pages = callback.map(ColdPageTracker::new);
+    }
+
+    // Must be called before set_max_sync_interval(), since the sync thread shares the storage
+    pub(crate) fn set_mirror(&mut self, mirror: File) -> Result<(), DatabaseError> {
+        // The id is written before the file is copied to the mirror, so that the copy shares it
+        let mut state = self.state.write().unwrap();
+        if state.header.mirror_id() == 0 {
+            state.header.set_mirror_id(new_mirror_id());
+            self.write_header(&state.header, false)?;
+        }
+        drop(state);
+        Arc::get_mut(&mut self.storage)
+            .expect("storage is already shared")
+            .set_mirror(mirror)
+    }
+
+    pub(crate) fn set_max_sync_interval(&mut self, interval: Option<Duration>) -> Result {
+        self.sync_timer = match interval {
+            Some(interval) if !self.read_only => {
+                Some(SyncTimer::new(self.storage.clone(), interval)?)
+            }
+            _ => None,
+        };
+
+        Ok(())
+    }
+
+    // Fails if an earlier I/O error, such as a failed fsync, means the file may not hold what was
+    // written to it
+    pub(crate) fn check_io_errors(&self) -> Result {
+        self.storage.check_fsync_failure()
+    }
+
+    pub(crate) fn begin_write_transaction(&self, id: TransactionId) {
+        self.write_transaction_id.store(id.0, Ordering::Release);
+    }
+
+    pub(crate) fn set_node_fill_target(&mut self, fraction: f64) {
+        self.node_fill_target = fraction;
+    }
+
+    pub(crate) fn get_node_fill_target(&self) -> f64 {
+        self.node_fill_target
+    }
+
+    pub(crate) fn set_allocation_policy(&mut self, policy: AllocationPolicy) {
+        self.allocation_policy = policy;
+    }
+
+    pub(crate) fn set_overflow_threshold(&mut self, threshold: Option<usize>) {
+        self.overflow_threshold = threshold;
+    }
+
+    pub(crate) fn set_max_value_pages(&mut self, pages: u64) {
+        self.max_value_pages = pages;
+    }
+
+    pub(crate) fn get_max_value_pages(&self) -> u64 {
+        self.max_value_pages
+    }
+
+    pub(crate) fn set_transaction_page_limit(&mut self, limit: Option<usize>) {
+        self.transaction_page_limit = limit;
+    }
+
+    // Allow the current transaction to exceed the page limit, until it is committed or rolled back
+    pub(crate) fn lift_transaction_page_limit(&self) {
+        self.transaction_page_limit_lifted
+            .store(true, Ordering::Release);
+    }
+
+    // Runs `f` with the page limit of the current transaction lifted, for example to undo a write
+    // which failed part-way because of it
+    pub(crate) fn without_transaction_page_limit<T>(&self, f: impl FnOnce() -> T) -> T {
+        let lifted = self
+            .transaction_page_limit_lifted
+            .swap(true, Ordering::AcqRel);
+        let result = f();
+        self.transaction_page_limit_lifted
+            .store(lifted, Ordering::Release);
+        result
+    }
+
+    #[cfg(test)]
+    pub(crate) fn cached_bytes(&self) -> usize {
+        self.storage.cached_bytes()
+    }
+
+    // If the file is shorter than the layout, shrinks the layout to the regions which are still
+    // complete, and truncates the file to match. The allocator state is then rebuilt by the repair
+    // path, which fails if any page that is still referenced was lost.
+    //
+    // The header is validated, and the shrunk layout is made durable, before the file is
+    // truncated, so that a file which can't be opened is never modified, and a crash can't leave
+    // the file shorter than the layout recorded in it
+    fn shrink_layout_to_file(
+        header: &mut DatabaseHeader,
+        storage: &PagedCachedFile,
+    ) -> Result<(), DatabaseError> {
+        header.validate_layout()?;
+        let layout = header.layout();
+        let file_len = storage.raw_file_len()?;
+        let page_size = u64::from(header.page_size());
+        let region_header_pages = layout.full_region_layout().get_header_pages();
+        // The super-header and at least one region header must remain
+        if file_len >= layout.len() || file_len < page_size * (1 + u64::from(region_header_pages)) {
+            return Ok(());
+        }
+        #[cfg(feature = "logging")]
+        warn!(
+            "Database file is {} bytes, but should be {}. Repairing",
+            file_len,
+            layout.len()
+        );
+        let new_layout = DatabaseLayout::recalculate(
+            file_len - file_len % page_size,
+            region_header_pages,
+            layout.full_region_layout().num_pages(),
+            header.page_size(),
+        );
+        header.set_layout(new_layout);
+        header.recovery_required = true;
+        validate_header(header, new_layout.len())?;
+        // Only the prefix is rewritten, since rewriting a corrupted commit slot would give it a
+        // valid checksum
+        storage
+            .write(0, DB_HEADER_PREFIX_SIZE, true)?
+            .mem_mut()
+            .copy_from_slice(&header.to_bytes(true, false)[..DB_HEADER_PREFIX_SIZE]);
+        storage.flush()?;
+        storage.resize(new_layout.len())?;
+
+        Ok(())
+    }
+
     #[cfg(any(fuzzing, test))]
     pub(crate) fn set_crash_countdown(&self, value: u64) {
         self.storage.set_crash_countdown(value);
     }
 
-    pub(crate) fn clear_read_cache(&mut self) {
+    #[cfg(any(fuzzing, test))]
+    pub(crate) fn set_power_loss_countdown(&self, value: u64) -> Result {
+        self.storage.set_power_loss_countdown(value)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn fsync_count(&self) -> u64 {
+        self.storage.fsync_count()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn set_sync_delay(&self, delay: Duration) {
+        self.storage.set_sync_delay(delay);
+    }
+
+    #[cfg(test)]
+    pub(crate) fn set_dropped_write(&self, offset: u64) {
+        self.storage.set_dropped_write(offset);
+    }
+
+    #[cfg(test)]
+    pub(crate) fn set_interrupted_fsyncs(&self, value: u64) {
+        self.storage.set_interrupted_fsyncs(value);
+    }
+
+    pub(crate) fn clear_read_cache(&self) {
         self.storage.invalidate_cache_all()
     }
 
@@ -280,7 +947,7 @@ impl TransactionalMemory {
         self.storage.flush()?;
         self.storage.invalidate_cache_all();
 
-        let header_bytes = self.storage.read_direct(0, DB_HEADER_SIZE)?;
+        let header_bytes = read_header_direct(&self.storage)?;
         let (mut header, repair_info) = DatabaseHeader::from_bytes(&header_bytes);
         // TODO: should probably consolidate this logic with Self::new()
         if header.recovery_required {
@@ -309,7 +976,7 @@ impl TransactionalMemory {
                 return Err(StorageError::Corrupted("Invalid magic number".to_string()));
             }
             self.storage
-                .write(0, DB_HEADER_SIZE, true)?
+                .write(0, header.db_header_size(), true)?
                 .mem_mut()
                 .copy_from_slice(&header.to_bytes(true, false));
             self.storage.flush()?;
@@ -318,30 +985,34 @@ impl TransactionalMemory {
         self.needs_recovery
             .store(header.recovery_required, Ordering::Release);
         let state = InMemoryState::from_bytes(header.clone(), &self.storage)?;
-        *self.state.lock().unwrap() = state;
+        *self.state.write().unwrap() = state;
 
         Ok(())
     }
 
     pub(crate) fn begin_writable(&self) -> Result {
-        let mut state = self.state.lock().unwrap();
+        let mut state = self.state.write().unwrap();
         assert!(!state.header.recovery_required);
         state.header.recovery_required = true;
         self.write_header(&state.header, false)?;
         self.storage.flush()
     }
 
+    pub(crate) fn discarded_commit(&self) -> Option<TransactionSlotDump> {
+        self.discarded_commit.clone()
+    }
+
     pub(crate) fn needs_repair(&self) -> Result<bool> {
-        Ok(self.state.lock().unwrap().header.recovery_required)
+        Ok(self.state.read().unwrap().header.recovery_required)
     }
 
     pub(crate) fn repair_primary_corrupted(&self) {
-        let mut state = self.state.lock().unwrap();
+        let mut state = self.state.write().unwrap();
         state.header.swap_primary_slot();
     }
 
     pub(crate) fn begin_repair(&self) -> Result<()> {
-        let mut state = self.state.lock().unwrap();
+        let mut state = self.state.write().unwrap();
         state.allocators = Allocators::new(state.header.layout());
 
         Ok(())
@@ -352,10 +1023,16 @@ impl TransactionalMemory {
         allocated_pages: impl Iterator<Item = Result<PageNumber>>,
         allow_duplicates: bool,
     ) -> Result<()> {
-        let mut state = self.state.lock().unwrap();
+        let mut state = self.state.write().unwrap();
 
+        let layout = state.header.layout();
         for page_number in allocated_pages {
             let page_number = page_number?;
+            if !layout.contains_page(page_number) {
+                return Err(StorageError::Corrupted(format!(
+                    "Page {page_number:?} is past the end of the database"
+                )));
+            }
             let region_index = page_number.region;
             let allocator = state.get_region_mut(region_index);
             if allow_duplicates
@@ -371,7 +1048,7 @@ impl TransactionalMemory {
 
     fn write_header(&self, header: &DatabaseHeader, swap_primary: bool) -> Result {
         self.storage
-            .write(0, DB_HEADER_SIZE, true)?
+            .write(0, header.db_header_size(), true)?
             .mem_mut()
             .copy_from_slice(&header.to_bytes(true, swap_primary));
 
@@ -379,21 +1056,23 @@ impl TransactionalMemory {
     }
 
     pub(crate) fn end_repair(&mut self) -> Result<()> {
-        let state = self.state.lock().unwrap();
+        let state = self.state.read().unwrap();
         let tracker_len = state.allocators.region_tracker.to_vec().len();
         drop(state);
         // Allocate a new tracker page, since the old one will have been overwritten
         let tracker_page = self.allocate(tracker_len)?.get_page_number();
 
-        let mut state = self.state.lock().unwrap();
+        let mut state = self.state.write().unwrap();
         state.header.set_region_tracker(tracker_page);
         self.write_header(&state.header, false)?;
         self.storage.flush()?;
 
         state
             .allocators
-            .flush_to(tracker_page, state.header.layout(), &mut self.storage)?;
+            .flush_to(tracker_page, state.header.layout(), &self.storage)?;
 
+        let checksum = state.allocators.checksum(state.header.layout());
+        state.header.set_allocator_checksum(checksum);
         state.header.recovery_required = false;
         self.write_header(&state.header, false)?;
         let result = self.storage.flush();
@@ -405,7 +1084,7 @@ impl TransactionalMemory {
     // Relocates the region tracker to a lower page, if possible
     // Returns true if the page was moved
     pub(crate) fn relocate_region_tracker(&self) -> Result<bool> {
-        let state = self.state.lock().unwrap();
+        let state = self.state.read().unwrap();
         let region_tracker_size = state
             .header
             .region_tracker()
@@ -415,21 +1094,21 @@ impl TransactionalMemory {
         drop(state);
         let new_page = self.allocate_lowest(region_tracker_size.try_into().unwrap())?;
         if new_page.get_page_number().is_before(old_tracker_page) {
-            let mut state = self.state.lock().unwrap();
+            let mut state = self.state.write().unwrap();
             state.header.set_region_tracker(new_page.get_page_number());
             drop(state);
-            self.free(old_tracker_page);
+            self.free(old_tracker_page)?;
             Ok(true)
         } else {
             let new_page_number = new_page.get_page_number();
             drop(new_page);
-            self.free(new_page_number);
+            self.free(new_page_number)?;
             Ok(false)
         }
     }
 
     pub(crate) fn get_raw_allocator_states(&self) -> Vec<Vec<u8>> {
-        let state = self.state.lock().unwrap();
+        let state = self.state.read().unwrap();
 
         let mut regional_allocators = vec![];
         for i in 0..state.header.layout().num_regions() {
@@ -446,7 +1125,7 @@ impl TransactionalMemory {
         region_states: &[Vec<u8>],
     ) -> Vec<PageNumber> {
         let mut result = vec![];
-        let state = self.state.lock().unwrap();
+        let state = self.state.read().unwrap();
 
         for i in 0..state.header.layout().num_regions() {
             let current_state = state.get_region(i);
@@ -465,23 +1144,84 @@ impl TransactionalMemory {
         result
     }
 
+    // All pages which the allocator state records as allocated, other than the region tracker
+    pub(crate) fn allocated_pages(&self) -> Vec<PageNumber> {
+        let mut result = vec![];
+        let state = self.state.read().unwrap();
+        for i in 0..state.header.layout().num_regions() {
+            state.get_region(i).get_allocated_pages(i, &mut result);
+        }
+        result.retain(|x| *x != state.header.region_tracker());
+
+        result
+    }
+
+    // Ranges of consecutive free pages, as indices of pages from the start of the file
+    pub(crate) fn free_ranges(&self) -> Vec<Range<u64>> {
+        self.free_ranges_locked(&self.state.read().unwrap())
+    }
+
+    fn free_ranges_locked(&self, state: &InMemoryState) -> Vec<Range<u64>> {
+        let mut result: Vec<Range<u64>> = vec![];
+        for region in 0..state.header.layout().num_regions() {
+            for range in state.get_region(region).free_ranges() {
+                let start = PageNumber::new(region, range.start, 0)
+                    .address_range(
+                        self.page_size as u64,
+                        self.region_size,
+                        self.region_header_with_padding_size,
+                        self.page_size,
+                    )
+                    .start
+                    / u64::from(self.page_size);
+                result.push(start..(start + u64::from(range.end - range.start)));
+            }
+        }
+
+        result
+    }
+
+    // Only reads the allocator state, so it can be called at any time, including during a write
+    pub(crate) fn capacity(&self) -> Capacity {
+        let state = self.state.read().unwrap();
+        let layout = state.header.layout();
+        let usable = layout.usable_bytes() / layout.full_region_layout().page_size() as u64;
+        let runs: Vec<u64> = self
+            .free_ranges_locked(&state)
+            .iter()
+            .map(|range| range.end - range.start)
+            .collect();
+
+        Capacity {
+            usable,
+            free: runs.iter().sum(),
+            largest_run: runs.iter().max().copied().unwrap_or(0),
+        }
+    }
+
     // Commit all outstanding changes and make them visible as the primary
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn commit(
         &self,
         data_root: Option<(PageNumber, Checksum)>,
         system_root: Option<(PageNumber, Checksum)>,
         freed_root: Option<(PageNumber, Checksum)>,
         transaction_id: TransactionId,
+        entry_count: Option<u64>,
         eventual: bool,
         two_phase: bool,
+        fsync: bool,
     ) -> Result {
+        self.take_deferred_free_error()?;
         let result = self.commit_inner(
             data_root,
             system_root,
             freed_root,
             transaction_id,
+            entry_count,
             eventual,
             two_phase,
+            fsync,
         );
         if result.is_err() {
             self.needs_recovery.store(true, Ordering::Release);
@@ -489,14 +1229,17 @@ impl TransactionalMemory {
         result
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn commit_inner(
         &self,
         data_root: Option<(PageNumber, Checksum)>,
         system_root: Option<(PageNumber, Checksum)>,
         freed_root: Option<(PageNumber, Checksum)>,
         transaction_id: TransactionId,
+        entry_count: Option<u64>,
         eventual: bool,
         two_phase: bool,
+        fsync: bool,
     ) -> Result {
         // All mutable pages must be dropped, this ensures that when a transaction completes
         // no more writes can happen to the pages it allocated. Thus it is safe to make them visible
@@ -504,34 +1247,68 @@ impl TransactionalMemory {
         #[cfg(debug_assertions)]
         debug_assert!(self.open_dirty_pages.lock().unwrap().is_empty());
         assert!(!self.needs_recovery.load(Ordering::Acquire));
+        // A non-durable commit is made durable by committing it again, with the same id
+        debug_assert!(
+            transaction_id >= self.next_transaction_id()?
+                || (transaction_id == self.get_last_committed_transaction_id()?
+                    && self.read_from_secondary.load(Ordering::Acquire)),
+            "Commit of transaction id {transaction_id:?}, which was already used"
+        );
 
-        let mut state = self.state.lock().unwrap();
+        let mut state = self.state.write().unwrap();
 
         // Trim surplus file space, before finalizing the commit
         let shrunk = self.try_shrink(&mut state)?;
 
+        let non_durable = self.read_from_secondary.load(Ordering::Acquire);
+        let previous = if non_durable {
+            state.header.secondary_slot()
+        } else {
+            state.header.primary_slot()
+        };
+        // Making a non-durable commit durable keeps the timestamp it was given
+        let timestamp = if non_durable && previous.transaction_id == transaction_id {
+            previous.timestamp
+        } else {
+            Some(HybridTimestamp::next(
+                previous.timestamp,
+                self.clock_millis(),
+            ))
+        };
         let secondary = state.header.secondary_slot_mut();
         secondary.transaction_id = transaction_id;
         secondary.user_root = data_root;
         secondary.system_root = system_root;
         secondary.freed_root = freed_root;
+        secondary.entry_count = entry_count;
+        secondary.timestamp = timestamp;
         self.write_header(&state.header, false)?;
 
         // Use 2-phase commit, if checksums are disabled
         if two_phase {
-            if eventual {
-                self.storage.eventual_flush()?;
-            } else {
-                self.storage.flush()?;
+            self.commit_flush(eventual, fsync)?;
+        }
+
+        // Written once the commit's pages are durable, if it uses 2-phase commit, and synced
+        // along with the primary bit, so the journal is never behind the commit slots
+        if self.commit_journal {
+            if let Some((offset, entry)) = state.header.secondary_journal_entry() {
+                self.storage.write_unbuffered(offset, &entry)?;
             }
         }
 
         // Swap the primary bit on-disk
         self.write_header(&state.header, true)?;
-        if eventual {
-            self.storage.eventual_flush()?;
-        } else {
-            self.storage.flush()?;
+        self.commit_flush(eventual, fsync)?;
+        // The header is read back from the storage device, so a write which the OS reported as
+        // successful, but was lost, is caught before the commit is acknowledged. The file no longer
+        // matches the in-memory state then, so no further I/O is allowed until it is reopened
+        if self.verify_commits {
+            let expected = state.header.to_bytes(true, true);
+            if self.storage.read_synced(0, expected.len())? != expected {
+                self.storage.poison();
+                return Err(StorageError::WriteVerificationFailed);
+            }
         }
         // Only swap the in-memory primary bit after the fsync is successful
         state.header.swap_primary_slot();
@@ -541,18 +1318,51 @@ impl TransactionalMemory {
         }
 
         self.allocated_since_commit.lock().unwrap().clear();
+        self.recently_freed.lock().unwrap().clear();
+        self.transaction_page_limit_lifted
+            .store(false, Ordering::Release);
         self.read_from_secondary.store(false, Ordering::Release);
 
         Ok(())
     }
 
-    // Make changes visible, without a durability guarantee
+    fn commit_flush(&self, eventual: bool, fsync: bool) -> Result {
+        if !fsync {
+            // Hand the writes to the OS without syncing them. The commit survives the process
+            // crashing, since the checksums of the new slot are verified on reopen, but not power loss
+            self.storage.write_barrier()?;
+        } else if eventual {
+            self.storage.eventual_flush()?;
+        } else {
+            self.storage.flush()?;
+        }
+        if let Some(timer) = &self.sync_timer {
+            // An eventual flush only issues a write barrier on macOS, and is a full sync elsewhere
+            if fsync && (!eventual || cfg!(not(target_os = "macos"))) {
+                timer.mark_synced();
+            } else {
+                timer.mark_unsynced();
+            }
+        }
+
+        Ok(())
+    }
+
+    // Make changes visible, without a durability guarantee
+    //
+    // Only the in-memory secondary slot is updated. The header on disk, including its primary
+    // slot, is untouched, and the allocator state is only held in memory while the database is
+    // open. A following commit() therefore writes out the secondary slot, which already holds the
+    // latest state, and swaps the primary. Pages freed by a non-durable commit are not reused until
+    // a durable commit has completed, since the write transaction registers it as a reader of its
+    // parent, so a crash at any point leaves the last durable commit intact for repair
     pub(crate) fn non_durable_commit(
         &self,
         data_root: Option<(PageNumber, Checksum)>,
         system_root: Option<(PageNumber, Checksum)>,
         freed_root: Option<(PageNumber, Checksum)>,
         transaction_id: TransactionId,
+        entry_count: Option<u64>,
     ) -> Result {
         // All mutable pages must be dropped, this ensures that when a transaction completes
         // no more writes can happen to the pages it allocated. Thus it is safe to make them visible
@@ -560,15 +1370,30 @@ impl TransactionalMemory {
         #[cfg(debug_assertions)]
         debug_assert!(self.open_dirty_pages.lock().unwrap().is_empty());
         assert!(!self.needs_recovery.load(Ordering::Acquire));
+        debug_assert!(
+            transaction_id >= self.next_transaction_id()?,
+            "Commit of transaction id {transaction_id:?}, which was already used"
+        );
+        self.take_deferred_free_error()?;
 
-        let mut state = self.state.lock().unwrap();
+        let mut state = self.state.write().unwrap();
+        let previous = if self.read_from_secondary.load(Ordering::Acquire) {
+            state.header.secondary_slot().timestamp
+        } else {
+            state.header.primary_slot().timestamp
+        };
         let secondary = state.header.secondary_slot_mut();
         secondary.transaction_id = transaction_id;
         secondary.user_root = data_root;
         secondary.system_root = system_root;
         secondary.freed_root = freed_root;
+        secondary.entry_count = entry_count;
+        secondary.timestamp = Some(HybridTimestamp::next(previous, self.clock_millis()));
 
         self.allocated_since_commit.lock().unwrap().clear();
+        self.recently_freed.lock().unwrap().clear();
+        self.transaction_page_limit_lifted
+            .store(false, Ordering::Release);
         self.storage.write_barrier()?;
         // TODO: maybe we can remove this flag and just update the in-memory DatabaseHeader state?
         self.read_from_secondary.store(true, Ordering::Release);
@@ -576,12 +1401,96 @@ impl TransactionalMemory {
         Ok(())
     }
 
+    // Makes the latest non-durable commit durable, with a single fsync.
+    // Returns false if there was no non-durable commit to flush
+    pub(crate) fn flush_non_durable(&self) -> Result<bool> {
+        if !self.read_from_secondary.load(Ordering::Acquire) {
+            return Ok(false);
+        }
+        let secondary = self.state.read().unwrap().header.secondary_slot().clone();
+        self.commit(
+            secondary.user_root,
+            secondary.system_root,
+            secondary.freed_root,
+            secondary.transaction_id,
+            secondary.entry_count,
+            false,
+            false,
+            true,
+        )?;
+
+        Ok(true)
+    }
+
+    // Makes all commits durable, and writes out the allocator state, so that the file can be
+    // reopened without a repair, as after a clean shutdown. Must not be called while a write
+    // transaction is in progress
+    pub(crate) fn checkpoint(&self) -> Result {
+        if self.needs_recovery.load(Ordering::Acquire) {
+            return Err(StorageError::Corrupted(
+                "A previous failure left the allocator state inconsistent. Repair required"
+                    .to_string(),
+            ));
+        }
+        self.flush_non_durable()?;
+        self.write_allocator_state()?;
+        // A larger region tracker page may have been allocated, which is now part of the state on
+        // disk, and must not be freed if the next write transaction is rolled back
+        self.allocated_since_commit.lock().unwrap().clear();
+        // The allocator state on disk is stale once pages are allocated or freed again, so the
+        // next commit must mark the file as needing recovery, as begin_writable() did at open
+        self.state.write().unwrap().header.recovery_required = true;
+
+        Ok(())
+    }
+
+    // Flushes the allocator state to the region tracker and region headers, and then clears the
+    // recovery flag on disk, unless a previous failure requires a repair anyway
+    fn write_allocator_state(&self) -> Result {
+        let mut state = self.state.write().unwrap();
+        let tracker_len = state.allocators.region_tracker.to_vec().len();
+        let tracker_page_size = state
+            .header
+            .region_tracker()
+            .page_size_bytes(self.page_size);
+        if tracker_page_size < (tracker_len as u64) {
+            drop(state);
+            // Allocate a larger tracker page
+            let tracker_page = self.allocate(tracker_len)?;
+            state = self.state.write().unwrap();
+            state
+                .header
+                .set_region_tracker(tracker_page.get_page_number());
+        }
+        state.allocators.flush_to(
+            state.header.region_tracker(),
+            state.header.layout(),
+            &self.storage,
+        )?;
+
+        self.storage.flush()?;
+        if !self.needs_recovery.load(Ordering::Acquire) {
+            let checksum = state.allocators.checksum(state.header.layout());
+            state.header.set_allocator_checksum(checksum);
+            state.header.recovery_required = false;
+            self.write_header(&state.header, false)?;
+            self.storage.flush()?;
+        }
+        if let Some(timer) = &self.sync_timer {
+            timer.mark_synced();
+        }
+
+        Ok(())
+    }
+
     pub(crate) fn rollback_uncommitted_writes(&self) -> Result {
         let result = self.rollback_uncommitted_writes_inner();
         if result.is_err() {
             self.needs_recovery.store(true, Ordering::Release);
         }
-        result
+        result?;
+        // A page which failed to be scrubbed is still consistently freed, so this needs no recovery
+        self.take_deferred_free_error()
     }
 
     fn rollback_uncommitted_writes_inner(&self) -> Result {
@@ -594,7 +1503,7 @@ impl TransactionalMemory {
             );
         }
         assert!(!self.needs_recovery.load(Ordering::Acquire));
-        let mut state = self.state.lock().unwrap();
+        let mut state = self.state.write().unwrap();
         let mut guard = self.allocated_since_commit.lock().unwrap();
         for page_number in guard.iter() {
             let region_index = page_number.region;
@@ -612,10 +1521,14 @@ impl TransactionalMemory {
                 self.page_size,
             );
             let len: usize = (address.end - address.start).try_into().unwrap();
-            self.storage.invalidate_cache(address.start, len);
-            self.storage.cancel_pending_write(address.start, len);
+            if let Err(err) = self.discard_page(address.start, len) {
+                self.defer_free_error(err);
+            }
         }
         guard.clear();
+        self.recently_freed.lock().unwrap().clear();
+        self.transaction_page_limit_lifted
+            .store(false, Ordering::Release);
 
         Ok(())
     }
@@ -625,6 +1538,54 @@ impl TransactionalMemory {
         self.get_page_extended(page_number, PageHint::None)
     }
 
+    // Overwrites the page with its copy in the mirror, after it failed checksum verification.
+    // Returns false if there is no mirror
+    pub(crate) fn restore_page_from_mirror(&self, page_number: PageNumber) -> Result<bool> {
+        let range = page_number.address_range(
+            self.page_size as u64,
+            self.region_size,
+            self.region_header_with_padding_size,
+            self.page_size,
+        );
+        let len: usize = (range.end - range.start).try_into().unwrap();
+        match self.storage.read_mirror(range.start, len)? {
+            Some(data) => {
+                self.storage.write_primary(range.start, &data)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    pub(crate) fn contains_page(&self, page_number: PageNumber) -> bool {
+        self.state
+            .read()
+            .unwrap()
+            .header
+            .layout()
+            .contains_page(page_number)
+    }
+
+    // Reads a page whose number was supplied by the user, and so may not refer to a page at all.
+    // It is read from the file, bypassing the cache, since it may overlap a cached page of another
+    // order
+    pub(crate) fn read_page_direct(&self, page_number: PageNumber) -> Result<Vec<u8>> {
+        if page_number.page_order > self.max_page_order || !self.contains_page(page_number) {
+            return Err(StorageError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Page {page_number:?} is not a page of the database"),
+            )));
+        }
+        let range = page_number.address_range(
+            self.page_size as u64,
+            self.region_size,
+            self.region_header_with_padding_size,
+            self.page_size,
+        );
+        self.storage
+            .read_direct(range.start, (range.end - range.start).try_into().unwrap())
+    }
+
     pub(crate) fn get_page_extended(
         &self,
         page_number: PageNumber,
@@ -633,10 +1594,17 @@ impl TransactionalMemory {
         // We must not retrieve an immutable reference to a page which already has a mutable ref to it
         #[cfg(debug_assertions)]
         {
-            debug_assert!(
-                !self.open_dirty_pages.lock().unwrap().contains(&page_number),
-                "{page_number:?}",
-            );
+            // The lock is released before panicking, so that it is not poisoned
+            let overlapping = self
+                .open_dirty_pages
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|x| x.overlaps(page_number))
+                .copied();
+            if let Some(other) = overlapping {
+                panic!("Read {page_number:?}, which overlaps the dirty page {other:?}");
+            }
             *(self
                 .read_page_ref_counts
                 .lock()
@@ -645,12 +1613,18 @@ impl TransactionalMemory {
                 .or_default()) += 1;
         }
 
+        #[cfg(feature = "page_metrics")]
+        self.record_page_access(page_number);
+
         let range = page_number.address_range(
             self.page_size as u64,
             self.region_size,
             self.region_header_with_padding_size,
             self.page_size,
         );
+        if let Some(cold_pages) = &self.cold_pages {
+            cold_pages.record_read(range.start);
+        }
         let len: usize = (range.end - range.start).try_into().unwrap();
         let mem = self.storage.read(range.start, len, hint)?;
 
@@ -666,14 +1640,65 @@ impl TransactionalMemory {
 
     // NOTE: the caller must ensure that the read cache has been invalidated or stale reads my occur
     pub(crate) fn get_page_mut(&self, page_number: PageNumber) -> Result<PageMut> {
+        #[cfg(feature = "page_metrics")]
+        self.record_page_access(page_number);
+        self.writable_page(page_number, false)
+    }
+
+    #[cfg(feature = "page_metrics")]
+    fn record_page_access(&self, page_number: PageNumber) {
+        *self
+            .page_access_counts
+            .lock()
+            .unwrap()
+            .entry(page_number)
+            .or_default() += 1;
+    }
+
+    // Returns the `top_n` most accessed pages, most accessed first
+    #[cfg(feature = "page_metrics")]
+    pub(crate) fn hot_pages(&self, top_n: usize) -> Vec<(PageNumber, u64)> {
+        let mut pages: Vec<(PageNumber, u64)> = self
+            .page_access_counts
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(page, count)| (*page, *count))
+            .collect();
+        pages.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        pages.truncate(top_n);
+        pages
+    }
+
+    // Every PageMut is created here. A PageMut holds a copy of the page, which is written back to
+    // the write buffer when it is dropped, so if two pages which overlap in the file were writable
+    // at once, or one was read while the other was being written, one of the writes would be
+    // silently lost or a stale copy read. The pages are tracked in debug builds to catch that.
+    // If overwrite is true, the existing contents are not loaded
+    fn writable_page(&self, page_number: PageNumber, overwrite: bool) -> Result<PageMut> {
         #[cfg(debug_assertions)]
         {
-            assert!(!self
+            // The locks are released before panicking, so that they are not poisoned
+            let overlapping = self
                 .read_page_ref_counts
                 .lock()
                 .unwrap()
-                .contains_key(&page_number));
-            assert!(!self.open_dirty_pages.lock().unwrap().contains(&page_number));
+                .keys()
+                .find(|x| x.overlaps(page_number))
+                .copied();
+            if let Some(other) = overlapping {
+                panic!("Wrote {page_number:?}, which overlaps the referenced page {other:?}");
+            }
+            let overlapping = self
+                .open_dirty_pages
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|x| x.overlaps(page_number))
+                .copied();
+            if let Some(other) = overlapping {
+                panic!("Wrote {page_number:?}, which overlaps the dirty page {other:?}");
+            }
         }
 
         let address_range = page_number.address_range(
@@ -685,7 +1710,7 @@ impl TransactionalMemory {
         let len: usize = (address_range.end - address_range.start)
             .try_into()
             .unwrap();
-        let mem = self.storage.write(address_range.start, len, false)?;
+        let mem = self.storage.write(address_range.start, len, overwrite)?;
 
         #[cfg(debug_assertions)]
         {
@@ -703,7 +1728,7 @@ impl TransactionalMemory {
     }
 
     pub(crate) fn get_version(&self) -> u8 {
-        let state = self.state.lock().unwrap();
+        let state = self.state.read().unwrap();
         if self.read_from_secondary.load(Ordering::Acquire) {
             state.header.secondary_slot().version
         } else {
@@ -712,7 +1737,7 @@ impl TransactionalMemory {
     }
 
     pub(crate) fn get_data_root(&self) -> Option<(PageNumber, Checksum)> {
-        let state = self.state.lock().unwrap();
+        let state = self.state.read().unwrap();
         if self.read_from_secondary.load(Ordering::Acquire) {
             state.header.secondary_slot().user_root
         } else {
@@ -721,7 +1746,7 @@ impl TransactionalMemory {
     }
 
     pub(crate) fn get_system_root(&self) -> Option<(PageNumber, Checksum)> {
-        let state = self.state.lock().unwrap();
+        let state = self.state.read().unwrap();
         if self.read_from_secondary.load(Ordering::Acquire) {
             state.header.secondary_slot().system_root
         } else {
@@ -730,7 +1755,7 @@ impl TransactionalMemory {
     }
 
     pub(crate) fn get_freed_root(&self) -> Option<(PageNumber, Checksum)> {
-        let state = self.state.lock().unwrap();
+        let state = self.state.read().unwrap();
         if self.read_from_secondary.load(Ordering::Acquire) {
             state.header.secondary_slot().freed_root
         } else {
@@ -738,8 +1763,49 @@ impl TransactionalMemory {
         }
     }
 
+    // Reads the data root, system root, and entry count under the same lock, so that they all
+    // belong to the same commit. The last value is true if that commit is non-durable
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn get_read_snapshot(
+        &self,
+    ) -> (
+        Option<(PageNumber, Checksum)>,
+        Option<(PageNumber, Checksum)>,
+        Option<u64>,
+        Option<HybridTimestamp>,
+        bool,
+    ) {
+        let state = self.state.read().unwrap();
+        let non_durable = self.read_from_secondary.load(Ordering::Acquire);
+        let slot = if non_durable {
+            state.header.secondary_slot()
+        } else {
+            state.header.primary_slot()
+        };
+        (
+            slot.user_root,
+            slot.system_root,
+            slot.entry_count,
+            slot.timestamp,
+            non_durable,
+        )
+    }
+
+    pub(crate) fn is_reading_from_secondary(&self) -> bool {
+        self.read_from_secondary.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn get_entry_count(&self) -> Option<u64> {
+        let state = self.state.read().unwrap();
+        if self.read_from_secondary.load(Ordering::Acquire) {
+            state.header.secondary_slot().entry_count
+        } else {
+            state.header.primary_slot().entry_count
+        }
+    }
+
     pub(crate) fn get_last_committed_transaction_id(&self) -> Result<TransactionId> {
-        let state = self.state.lock().unwrap();
+        let state = self.state.read().unwrap();
         if self.read_from_secondary.load(Ordering::Acquire) {
             Ok(state.header.secondary_slot().transaction_id)
         } else {
@@ -747,13 +1813,20 @@ impl TransactionalMemory {
         }
     }
 
-    pub(crate) fn free(&self, page: PageNumber) {
+    // The lowest id which a new commit may use. Ids must increase with every commit, but need not
+    // be contiguous, since aborted transactions also consume them
+    pub(crate) fn next_transaction_id(&self) -> Result<TransactionId> {
+        Ok(self.get_last_committed_transaction_id()?.next())
+    }
+
+    pub(crate) fn free(&self, page: PageNumber) -> Result {
         self.allocated_since_commit.lock().unwrap().remove(&page);
-        self.free_helper(page);
+        self.free_helper(page)
     }
 
-    fn free_helper(&self, page: PageNumber) {
-        let mut state = self.state.lock().unwrap();
+    // The page is freed even if this returns an error, which can only come from scrubbing it
+    fn free_helper(&self, page: PageNumber) -> Result {
+        let mut state = self.state.write().unwrap();
         let region_index = page.region;
         // Free in the regional allocator
         state
@@ -773,109 +1846,176 @@ impl TransactionalMemory {
         let len: usize = (address_range.end - address_range.start)
             .try_into()
             .unwrap();
-        self.storage.invalidate_cache(address_range.start, len);
-        self.storage.cancel_pending_write(address_range.start, len);
+        self.discard_page(address_range.start, len)
+    }
+
+    // Drops any cached or pending contents of a page which has just been freed.
+    // Pages are only freed once no read transaction can reference them, so they can be scrubbed
+    fn discard_page(&self, offset: u64, len: usize) -> Result {
+        self.storage.invalidate_cache(offset, len);
+        if self.punch_holes_on_free && self.storage.punch_hole(offset, len) {
+            // The range now reads back as zeros, so it doesn't need to be scrubbed
+        } else if self.zero_on_free {
+            self.storage.scrub(offset, len)?;
+        } else {
+            self.storage.cancel_pending_write(offset, len);
+        }
+
+        Ok(())
+    }
+
+    // For callers which free pages but can't return an error, such as Drop impls. Only the first
+    // error is kept
+    pub(crate) fn defer_free_error(&self, error: StorageError) {
+        self.deferred_free_error
+            .lock()
+            .unwrap()
+            .get_or_insert(error);
+    }
+
+    pub(crate) fn take_deferred_free_error(&self) -> Result {
+        match self.deferred_free_error.lock().unwrap().take() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
     }
 
     // Frees the page if it was allocated since the last commit. Returns true, if the page was freed
-    pub(crate) fn free_if_uncommitted(&self, page: PageNumber) -> bool {
+    //
+    // No commit or read transaction can reference such a page, so, unlike pages freed from an
+    // earlier commit, it may be reused straight away by this transaction
+    //
+    // If an error is returned, the page was still freed
+    pub(crate) fn free_if_uncommitted(&self, page: PageNumber) -> Result<bool> {
         if self.allocated_since_commit.lock().unwrap().remove(&page) {
-            self.free_helper(page);
-            true
+            let result = self.free_helper(page);
+            self.recently_freed.lock().unwrap().push(page);
+            result?;
+            Ok(true)
         } else {
-            false
+            Ok(false)
         }
     }
 
+    // Number of pages allocated since the last commit, which are still in use
+    pub(crate) fn uncommitted_page_count(&self) -> usize {
+        self.allocated_since_commit.lock().unwrap().len()
+    }
+
+    // Size the sets of pages tracked until the next commit for `pages` allocations, so that they
+    // aren't repeatedly rehashed as a large transaction grows
+    pub(crate) fn reserve_uncommitted_pages(&self, pages: usize) {
+        self.allocated_since_commit.lock().unwrap().reserve(pages);
+        #[cfg(debug_assertions)]
+        self.open_dirty_pages.lock().unwrap().reserve(pages);
+    }
+
     // Page has not been committed
     pub(crate) fn uncommitted(&self, page: PageNumber) -> bool {
         self.allocated_since_commit.lock().unwrap().contains(&page)
     }
 
-    pub(crate) fn allocate_helper(&self, allocation_size: usize, lowest: bool) -> Result<PageMut> {
-        let required_pages = (allocation_size + self.get_page_size() - 1) / self.get_page_size();
+    fn allocate_helper(&self, allocation_size: usize, placement: Placement) -> Result<PageMut> {
+        let required_pages = allocation_size / self.get_page_size()
+            + usize::from(allocation_size % self.get_page_size() != 0);
         let required_order = ceil_log2(required_pages);
+        if required_order > self.max_page_order {
+            return Err(StorageError::AllocationTooLarge(allocation_size));
+        }
+
+        if let Some(limit) = self.transaction_page_limit {
+            if !self.transaction_page_limit_lifted.load(Ordering::Acquire)
+                && self.allocated_since_commit.lock().unwrap().len() >= limit
+            {
+                return Err(StorageError::TransactionTooLarge(limit));
+            }
+        }
 
-        let mut state = self.state.lock().unwrap();
+        let mut state = self.state.write().unwrap();
 
         let page_number = if let Some(page_number) =
-            self.allocate_helper_retry(&mut state, required_order, lowest)?
+            self.reuse_recently_freed(&mut state, required_order, placement)
+        {
+            page_number
+        } else if let Some(page_number) =
+            self.allocate_helper_retry(&mut state, required_order, placement)?
         {
             page_number
         } else {
             self.grow(&mut state, required_order)?;
-            self.allocate_helper_retry(&mut state, required_order, lowest)?
+            self.allocate_helper_retry(&mut state, required_order, placement)?
                 .unwrap()
         };
 
-        #[cfg(debug_assertions)]
-        {
-            assert!(
-                !self
-                    .read_page_ref_counts
-                    .lock()
-                    .unwrap()
-                    .contains_key(&page_number),
-                "Allocated a page that is still referenced! {page_number:?}"
-            );
-            assert!(!self.open_dirty_pages.lock().unwrap().contains(&page_number));
-        }
-
         self.allocated_since_commit
             .lock()
             .unwrap()
             .insert(page_number);
 
-        let address_range = page_number.address_range(
-            self.page_size as u64,
-            self.region_size,
-            self.region_header_with_padding_size,
-            self.page_size,
-        );
-        let len: usize = (address_range.end - address_range.start)
-            .try_into()
-            .unwrap();
-
         #[allow(unused_mut)]
-        let mut mem = self.storage.write(address_range.start, len, true)?;
-        debug_assert!(mem.mem().len() >= allocation_size);
+        let mut page = self.writable_page(page_number, true)?;
+        debug_assert!(page.memory().len() >= allocation_size);
 
+        // Poison the memory in debug mode to help detect uninitialized reads
         #[cfg(debug_assertions)]
-        {
-            assert!(self.open_dirty_pages.lock().unwrap().insert(page_number));
+        page.memory_mut().fill(0xFF);
 
-            // Poison the memory in debug mode to help detect uninitialized reads
-            mem.mem_mut().fill(0xFF);
-        }
+        Ok(page)
+    }
 
-        Ok(PageMut {
-            mem,
-            page_number,
-            #[cfg(debug_assertions)]
-            open_pages: &self.open_dirty_pages,
-            #[cfg(not(debug_assertions))]
-            _debug_lifetime: Default::default(),
-        })
+    // Allocates the most recently freed page of the given order, if it is still free. Placement by
+    // index takes precedence, since it is used to keep the end of the file free
+    fn reuse_recently_freed(
+        &self,
+        state: &mut InMemoryState,
+        required_order: u8,
+        placement: Placement,
+    ) -> Option<PageNumber> {
+        if !matches!(placement, Placement::SmallestFit) {
+            return None;
+        }
+        let mut recently_freed = self.recently_freed.lock().unwrap();
+        while let Some(position) = recently_freed
+            .iter()
+            .rposition(|page| page.page_order == required_order)
+        {
+            let page = recently_freed.remove(position);
+            // The page may have been allocated again, or the region shrunk, since it was freed
+            if page.region >= state.header.layout().num_regions() {
+                continue;
+            }
+            let region = state.get_region_mut(page.region);
+            if region.is_free(page.page_index, page.page_order) {
+                region.record_alloc(page.page_index, page.page_order);
+                return Some(page);
+            }
+        }
+        None
     }
 
     fn allocate_helper_retry(
         &self,
         state: &mut InMemoryState,
         required_order: u8,
-        lowest: bool,
+        placement: Placement,
     ) -> Result<Option<PageNumber>> {
         loop {
-            let candidate_region =
-                if let Some(candidate) = state.get_region_tracker_mut().find_free(required_order) {
-                    candidate
-                } else {
-                    return Ok(None);
-                };
-            let region = state.get_region_mut(candidate_region);
-            let r = if lowest {
-                region.alloc_lowest(required_order)
+            let tracker = state.get_region_tracker_mut();
+            let candidate_region = if let Placement::Highest = placement {
+                tracker.find_last_free(required_order)
             } else {
-                region.alloc(required_order)
+                tracker.find_free(required_order)
+            };
+            let candidate_region = if let Some(candidate) = candidate_region {
+                candidate
+            } else {
+                return Ok(None);
+            };
+            let region = state.get_region_mut(candidate_region);
+            let r = match placement {
+                Placement::SmallestFit => region.alloc(required_order),
+                Placement::Lowest => region.alloc_lowest(required_order),
+                Placement::Highest => region.alloc_highest(required_order),
             };
             if let Some(page) = r {
                 return Ok(Some(PageNumber::new(
@@ -892,15 +2032,26 @@ impl TransactionalMemory {
         }
     }
 
+    // True if the next commit will trim surplus file space
+    pub(crate) fn can_shrink(&self) -> bool {
+        Self::shrinkable(&self.state.read().unwrap())
+    }
+
+    fn shrinkable(state: &InMemoryState) -> bool {
+        let layout = state.header.layout();
+        let last_allocator = state.get_region(layout.num_regions() - 1);
+        last_allocator.trailing_free_pages() >= last_allocator.len() / 2
+    }
+
     fn try_shrink(&self, state: &mut InMemoryState) -> Result<bool> {
+        if !Self::shrinkable(state) {
+            return Ok(false);
+        }
         let layout = state.header.layout();
         let last_region_index = layout.num_regions() - 1;
         let last_allocator = state.get_region(last_region_index);
         let trailing_free = last_allocator.trailing_free_pages();
         let last_allocator_len = last_allocator.len();
-        if trailing_free < last_allocator_len / 2 {
-            return Ok(false);
-        }
         let reduce_by = if layout.num_regions() > 1 && trailing_free == last_allocator_len {
             trailing_free
         } else {
@@ -922,6 +2073,8 @@ impl TransactionalMemory {
             * state.header.page_size() as u64;
         let max_region_size = (state.header.layout().full_region_layout().num_pages() as u64)
             * (state.header.page_size() as u64);
+        // The usable size of the largest layout, in which every addressable region is full
+        let max_usable_bytes = MAX_REGIONS.saturating_mul(max_region_size);
         let next_desired_size = if layout.num_full_regions() > 0 {
             if let Some(trailing) = layout.trailing_region_layout() {
                 if 2 * required_growth < max_region_size - trailing.usable_bytes() {
@@ -936,35 +2089,84 @@ impl TransactionalMemory {
                 layout.usable_bytes() + max_region_size
             }
         } else {
-            max(
-                layout.usable_bytes() * 2,
-                layout.usable_bytes() + required_growth * 2,
-            )
+            self.growth_policy
+                .next_usable_size(
+                    layout.usable_bytes(),
+                    required_growth * 2,
+                    self.page_size.into(),
+                    max_usable_bytes,
+                )
+                .ok_or(StorageError::DatabaseFull)?
         };
+        let next_desired_size = min(next_desired_size, max_usable_bytes);
+        if next_desired_size < layout.usable_bytes() + required_growth {
+            return Err(StorageError::DatabaseFull);
+        }
         let new_layout = DatabaseLayout::calculate(
             next_desired_size,
             state.header.layout().full_region_layout().num_pages(),
             self.page_size,
         );
         assert!(new_layout.len() >= layout.len());
+        debug_assert_eq!(new_layout.len() % u64::from(self.page_size), 0);
 
         self.storage.resize(new_layout.len())?;
 
         state.allocators.resize_to(new_layout);
+        // Every region allocator has the capacity of a full region, so it is sufficient to check
+        // the last one. Reject the growth, rather than later writing allocator state over the start
+        // of its data pages
+        if let Err(err) = state
+            .allocators
+            .check_region_fits(new_layout.num_regions() - 1, new_layout)
+        {
+            state.allocators.resize_to(layout);
+            self.storage.resize(layout.len())?;
+            return Err(err);
+        }
         state.header.set_layout(new_layout);
+        if let Some(callback) = &self.growth_callback {
+            callback(
+                layout.len(),
+                new_layout.len(),
+                self.write_transaction_id.load(Ordering::Acquire),
+            );
+        }
         Ok(())
     }
 
     pub(crate) fn allocate(&self, allocation_size: usize) -> Result<PageMut> {
-        self.allocate_helper(allocation_size, false)
+        let placement = match (self.allocation_policy, self.overflow_threshold) {
+            (_, Some(threshold)) if allocation_size >= threshold => Placement::Highest,
+            // Smaller allocations must also be placed by index, since a smallest fit would choose
+            // the blocks left over from splitting at the end of the free space
+            (_, Some(_)) | (AllocationPolicy::LowestIndex, None) => Placement::Lowest,
+            (AllocationPolicy::SmallestFit, None) => Placement::SmallestFit,
+        };
+        self.allocate_helper(allocation_size, placement)
+    }
+
+    // Copies the page into a newly allocated page, so that it can be modified without affecting
+    // readers of the old version. The old page is pushed onto `freed`, so that it is freed by the
+    // commit once no read transaction references it
+    pub(crate) fn copy_on_write(
+        &self,
+        old: PageNumber,
+        freed: &mut Vec<PageNumber>,
+    ) -> Result<PageMut> {
+        let old_page = self.get_page(old)?;
+        let mut new_page = self.allocate(old_page.memory().len())?;
+        new_page.memory_mut().copy_from_slice(old_page.memory());
+        freed.push(old);
+        Ok(new_page)
     }
 
     pub(crate) fn allocate_lowest(&self, allocation_size: usize) -> Result<PageMut> {
-        self.allocate_helper(allocation_size, true)
+        self.allocate_helper(allocation_size, Placement::Lowest)
     }
 
     pub(crate) fn count_allocated_pages(&self) -> Result<u64> {
-        let state = self.state.lock().unwrap();
+        let state = self.state.read().unwrap();
         let mut count = 0u64;
         for i in 0..state.header.layout().num_regions() {
             count += state.get_region(i).count_allocated_pages() as u64;
@@ -973,13 +2175,199 @@ impl TransactionalMemory {
         Ok(count)
     }
 
+    pub(crate) fn logical_size(&self) -> Result<u64> {
+        self.storage.raw_file_len()
+    }
+
+    pub(crate) fn physical_size(&self) -> Result<u64> {
+        self.storage.physical_file_len()
+    }
+
+    // Fraction of the usable pages which are not allocated
+    pub(crate) fn free_ratio(&self) -> Result<f64> {
+        let usable_pages = {
+            let state = self.state.read().unwrap();
+            let layout = state.header.layout();
+            layout.usable_bytes() / layout.full_region_layout().page_size() as u64
+        };
+        let allocated_pages = self.count_allocated_pages()?;
+        // The precision of a f64 is more than sufficient for a ratio
+        #[allow(clippy::cast_precision_loss)]
+        let ratio = 1.0 - allocated_pages as f64 / usable_pages as f64;
+        Ok(ratio)
+    }
+
+    // Returns every allocated page, in page number order. The state lock is held until the
+    // iterator is dropped, so that no page can be freed and reused before it is read. Pages must
+    // not be allocated or freed on this thread while the iterator is alive
+    #[allow(dead_code)]
+    pub(crate) fn iter_pages(&self) -> impl Iterator<Item = Result<(PageNumber, PageImpl)>> + '_ {
+        let state = self.state.read().unwrap();
+        let mut page_numbers = vec![];
+        for i in 0..state.header.layout().num_regions() {
+            state
+                .get_region(i)
+                .get_allocated_pages(i, &mut page_numbers);
+        }
+        page_numbers.sort();
+
+        page_numbers.into_iter().map(move |page_number| {
+            let _state = &state;
+            Ok((page_number, self.get_page(page_number)?))
+        })
+    }
+
+    // Returns the bytes of a database header whose only commit contains the given user root, and
+    // the length of the file it describes. The header requires recovery, so that the allocator
+    // state is rebuilt from the tree when it is opened
+    pub(crate) fn backup_header(
+        &self,
+        user_root: Option<(PageNumber, Checksum)>,
+        transaction_id: TransactionId,
+    ) -> (Vec<u8>, u64) {
+        let mut header = self.state.read().unwrap().header.clone();
+        // Keep the latest timestamp, so that commits to the restored database follow it
+        let timestamp = if self.read_from_secondary.load(Ordering::Acquire) {
+            header.secondary_slot().timestamp
+        } else {
+            header.primary_slot().timestamp
+        };
+        let slot = TransactionHeader {
+            version: FILE_FORMAT_VERSION,
+            user_root,
+            system_root: None,
+            freed_root: None,
+            transaction_id,
+            entry_count: None,
+            timestamp,
+        };
+        *header.secondary_slot_mut() = slot.clone();
+        header.swap_primary_slot();
+        *header.secondary_slot_mut() = slot;
+        header.recovery_required = true;
+        // A restored database is not tied to the mirror of the original
+        header.set_mirror_id(0);
+
+        (header.to_bytes(true, false).to_vec(), header.layout().len())
+    }
+
+    // Reads and validates the header of the database file at `path`. The file is opened read-only
+    // and is not locked, so this may run while another process has the database open
+    pub(crate) fn verify_header_only(path: &Path) -> Result<MetapageDump, DatabaseError> {
+        let file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+        let header_bytes = if let Some(bytes) = read_header_from_file(&file, 0)? {
+            bytes
+        } else {
+            return Err(StorageError::Corrupted("Invalid magic number".to_string()).into());
+        };
+        let (header, repair_info) = DatabaseHeader::from_bytes(&header_bytes);
+        if repair_info.invalid_magic_number {
+            return Err(StorageError::Corrupted("Invalid magic number".to_string()).into());
+        }
+        validate_header(&header, file_len)?;
+        validate_file_len(&header, file_len)?;
+
+        Ok(MetapageDump::from_bytes(&header_bytes))
+    }
+
+    // Records each problem with the header of the database file at `path`, rather than stopping at
+    // the first one. Returns false if the header is too damaged for the rest of the file to be read
+    pub(crate) fn check_header(
+        path: &Path,
+        findings: &mut Vec<CorruptionFinding>,
+    ) -> Result<bool, DatabaseError> {
+        let file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+        let header_bytes = match read_header_from_file(&file, 0) {
+            Ok(Some(bytes)) => bytes,
+            Ok(None) => {
+                findings.push(CorruptionFinding::InvalidMagicNumber);
+                return Ok(false);
+            }
+            Err(StorageError::Corrupted(msg)) => {
+                findings.push(CorruptionFinding::InvalidHeader(msg));
+                return Ok(false);
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let (header, repair_info) = DatabaseHeader::from_bytes(&header_bytes);
+        if repair_info.invalid_magic_number {
+            findings.push(CorruptionFinding::InvalidMagicNumber);
+            return Ok(false);
+        }
+
+        let mut usable = true;
+        for (slot, primary, corrupted) in [
+            (header.primary_slot(), true, repair_info.primary_corrupted),
+            (
+                header.secondary_slot(),
+                false,
+                repair_info.secondary_corrupted,
+            ),
+        ] {
+            if corrupted {
+                findings.push(CorruptionFinding::CommitSlotCorrupted { primary });
+            } else if slot.version != FILE_FORMAT_VERSION {
+                findings.push(CorruptionFinding::UnsupportedVersion(slot.version));
+                usable = false;
+            }
+        }
+        // Without the recovery flag, a corrupted primary slot is not rolled back when opening
+        if repair_info.primary_corrupted
+            && (repair_info.secondary_corrupted || !header.recovery_required)
+        {
+            usable = false;
+        }
+        if let Err(err) = header.validate_layout() {
+            findings.push(CorruptionFinding::InvalidHeader(err.to_string()));
+            return Ok(false);
+        }
+        let expected_len = header.layout().len();
+        if file_len < expected_len {
+            findings.push(CorruptionFinding::FileTruncated {
+                expected: expected_len,
+                actual: file_len,
+            });
+            usable = false;
+        }
+        let page_size = header.page_size();
+        if file_len % u64::from(page_size) != 0 {
+            findings.push(CorruptionFinding::InvalidFileSize {
+                len: file_len,
+                page_size: page_size.try_into().unwrap(),
+            });
+            usable = false;
+        }
+        if header.recovery_required {
+            findings.push(CorruptionFinding::DirtyAllocator);
+        }
+
+        Ok(usable)
+    }
+
+    pub(crate) fn dump_metapage(&self) -> Result<MetapageDump> {
+        // Hold the lock, so that a concurrent commit can't be observed partially written
+        let _state = self.state.read().unwrap();
+        let header_bytes = read_header_direct(&self.storage)?;
+        Ok(MetapageDump::from_bytes(&header_bytes))
+    }
+
     pub(crate) fn get_page_size(&self) -> usize {
         self.page_size.try_into().unwrap()
     }
+
+    // The size of the largest page which can be allocated, since a page must fit in a region
+    pub(crate) fn max_allocation_size(&self) -> usize {
+        self.get_page_size() << self.max_page_order
+    }
 }
 
 impl Drop for TransactionalMemory {
     fn drop(&mut self) {
+        if self.read_only {
+            return;
+        }
         // Commit any non-durable transactions that are outstanding
         if self.read_from_secondary.load(Ordering::Acquire)
             && !self.needs_recovery.load(Ordering::Acquire)
@@ -988,14 +2376,17 @@ impl Drop for TransactionalMemory {
                 let root = self.get_data_root();
                 let system_root = self.get_system_root();
                 let freed_root = self.get_freed_root();
+                let entry_count = self.get_entry_count();
                 if self
                     .commit(
                         root,
                         system_root,
                         freed_root,
                         non_durable_transaction_id,
+                        entry_count,
                         false,
                         true,
+                        true,
                     )
                     .is_err()
                 {
@@ -1009,44 +2400,9 @@ impl Drop for TransactionalMemory {
                 warn!("Failure while finalizing non-durable commit. Database may have rolled back");
             }
         }
-        let mut state = self.state.lock().unwrap();
-        let tracker_len = state.allocators.region_tracker.to_vec().len();
-        let tracker_page_size = state
-            .header
-            .region_tracker()
-            .page_size_bytes(self.page_size);
-        if tracker_page_size < (tracker_len as u64) {
-            drop(state);
-            // Allocate a larger tracker page
-            if let Ok(tracker_page) = self.allocate(tracker_len) {
-                state = self.state.lock().unwrap();
-                state
-                    .header
-                    .set_region_tracker(tracker_page.get_page_number());
-            } else {
-                #[cfg(feature = "logging")]
-                warn!("Failure while flushing allocator state. Repair required at restart.");
-                return;
-            }
-        }
-        if state
-            .allocators
-            .flush_to(
-                state.header.region_tracker(),
-                state.header.layout(),
-                &mut self.storage,
-            )
-            .is_err()
-        {
+        if self.write_allocator_state().is_err() {
             #[cfg(feature = "logging")]
             warn!("Failure while flushing allocator state. Repair required at restart.");
-            return;
-        }
-
-        if self.storage.flush().is_ok() && !self.needs_recovery.load(Ordering::Acquire) {
-            state.header.recovery_required = false;
-            let _ = self.write_header(&state.header, false);
-            let _ = self.storage.flush();
         }
     }
 }
@@ -1054,7 +2410,13 @@ impl Drop for TransactionalMemory {
 #[cfg(test)]
 mod test {
     use crate::tree_store::page_store::page_manager::INITIAL_REGIONS;
-    use crate::{Database, TableDefinition};
+    use crate::tree_store::page_store::region::{ALLOCATOR_LENGTH_OFFSET, ALLOCATOR_OFFSET};
+    use crate::tree_store::{Page, PageNumber, TransactionalMemory, PAGE_SIZE};
+    use crate::{
+        CorruptionFinding, Database, DatabaseError, Error, GrowthPolicy, ReadableTable,
+        StorageError, TableDefinition,
+    };
+    use std::fs::OpenOptions;
 
     // Test that the region tracker expansion code works, by adding more data than fits into the initial max regions
     #[test]
@@ -1079,4 +2441,411 @@ mod test {
         }
         txn.commit().unwrap();
     }
+
+    // Grow the file one page at a time, across several regions, and check that the allocator
+    // state written at shutdown is intact
+    #[test]
+    fn small_growth_steps() {
+        let tmpfile = crate::create_tempfile();
+        let table_definition: TableDefinition<u32, &[u8]> = TableDefinition::new("x");
+        let page_size = 1024;
+        let value = vec![0u8; page_size / 2];
+
+        let db = Database::builder()
+            .set_region_size((32 * page_size).try_into().unwrap())
+            .set_page_size(page_size)
+            .set_growth_policy(GrowthPolicy {
+                factor: 1.0,
+                min_bytes: page_size as u64,
+                max_bytes: Some(page_size as u64),
+            })
+            .create(tmpfile.path())
+            .unwrap();
+        for i in 0..200 {
+            let txn = db.begin_write().unwrap();
+            txn.open_table(table_definition)
+                .unwrap()
+                .insert(&i, value.as_slice())
+                .unwrap();
+            txn.commit().unwrap();
+        }
+        drop(db);
+        assert!(tmpfile.as_file().metadata().unwrap().len() > 2 * 32 * page_size as u64);
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(tmpfile.path())
+            .unwrap();
+        let mem = TransactionalMemory::new(
+            file,
+            None,
+            page_size,
+            false,
+            None,
+            0,
+            0,
+            GrowthPolicy::default(),
+            false,
+            false,
+            false,
+            false,
+        )
+        .unwrap();
+        assert!(!mem.needs_repair().unwrap());
+    }
+
+    #[test]
+    fn allocation_too_large() {
+        let tmpfile = crate::create_tempfile();
+        let table_definition: TableDefinition<u32, &[u8]> = TableDefinition::new("x");
+        let page_size = 1024;
+        let region_size = 8 * page_size;
+
+        let db = Database::builder()
+            .set_region_size(region_size.try_into().unwrap())
+            .set_page_size(page_size)
+            .create(tmpfile.path())
+            .unwrap();
+        let mem = db.get_memory();
+        assert!(matches!(
+            mem.allocate(region_size + 1),
+            Err(StorageError::AllocationTooLarge(len)) if len == region_size + 1
+        ));
+        drop(mem.allocate(region_size).unwrap());
+        mem.rollback_uncommitted_writes().unwrap();
+
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(table_definition).unwrap();
+            let big_value = vec![0u8; 2 * region_size];
+            assert!(matches!(
+                table.insert(&0, big_value.as_slice()),
+                Err(StorageError::AllocationTooLarge(_))
+            ));
+        }
+        txn.abort().unwrap();
+
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(table_definition).unwrap();
+            let value = vec![0u8; 5 * page_size];
+            table.insert(&0, value.as_slice()).unwrap();
+        }
+        txn.commit().unwrap();
+    }
+
+    #[test]
+    fn iter_pages() {
+        let tmpfile = crate::create_tempfile();
+        let mem = TransactionalMemory::new(
+            tmpfile.into_file(),
+            None,
+            1024,
+            false,
+            None,
+            0,
+            0,
+            GrowthPolicy::default(),
+            false,
+            false,
+            false,
+            false,
+        )
+        .unwrap();
+
+        let mut allocated = vec![];
+        for size in [1, 1024, 3000, 5000, 1] {
+            allocated.push(mem.allocate(size).unwrap().get_page_number());
+        }
+        mem.free(allocated[1]).unwrap();
+
+        let pages: Vec<(PageNumber, _)> = mem.iter_pages().map(|x| x.unwrap()).collect();
+        // count_allocated_pages() counts in units of order 0 pages
+        let yielded: u64 = pages.iter().map(|(x, _)| 1u64 << x.page_order).sum();
+        assert_eq!(yielded, mem.count_allocated_pages().unwrap());
+        for (page_number, page) in pages.iter() {
+            assert_eq!(*page_number, page.get_page_number());
+        }
+        for window in pages.windows(2) {
+            assert!(window[0].0 < window[1].0);
+        }
+        assert!(!pages.iter().any(|(x, _)| *x == allocated[1]));
+        for page_number in [allocated[0], allocated[2], allocated[3], allocated[4]] {
+            assert!(pages.iter().any(|(x, _)| *x == page_number));
+        }
+    }
+
+    #[test]
+    fn corrupted_allocator_state() {
+        let tmpfile = crate::create_tempfile();
+        let table_definition: TableDefinition<u64, u64> = TableDefinition::new("x");
+        let db = Database::create(tmpfile.path()).unwrap();
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(table_definition).unwrap();
+            for i in 0..100 {
+                table.insert(i, i).unwrap();
+            }
+        }
+        txn.commit().unwrap();
+        drop(db);
+
+        let needs_repair = || {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(tmpfile.path())
+                .unwrap();
+            TransactionalMemory::new(
+                file,
+                None,
+                PAGE_SIZE,
+                false,
+                None,
+                0,
+                0,
+                GrowthPolicy::default(),
+                false,
+                false,
+                false,
+                false,
+            )
+            .unwrap()
+            .needs_repair()
+            .unwrap()
+        };
+        assert!(!needs_repair());
+
+        // Flip the last byte of the allocator state of the first region, which holds the bitmap
+        // of allocated pages
+        let mut data = std::fs::read(tmpfile.path()).unwrap();
+        let region_base = PAGE_SIZE;
+        let allocator_len = u32::from_le_bytes(
+            data[(region_base + ALLOCATOR_LENGTH_OFFSET)..(region_base + ALLOCATOR_OFFSET)]
+                .try_into()
+                .unwrap(),
+        ) as usize;
+        data[region_base + ALLOCATOR_OFFSET + allocator_len - 1] ^= 0xFF;
+        std::fs::write(tmpfile.path(), &data).unwrap();
+        assert!(needs_repair());
+
+        // Opening the database rebuilds the allocator state
+        let db = Database::open(tmpfile.path()).unwrap();
+        let txn = db.begin_read().unwrap();
+        let table = txn.open_table(table_definition).unwrap();
+        assert_eq!(table.len().unwrap(), 100);
+        drop(table);
+        drop(txn);
+        drop(db);
+        assert!(!needs_repair());
+    }
+
+    #[test]
+    fn corruption_report() {
+        let tmpfile = crate::create_tempfile();
+        let table_definition: TableDefinition<u64, u64> = TableDefinition::new("report_table");
+        let raw = |page: PageNumber| u64::from_le_bytes(page.to_le_bytes());
+        let db = Database::create(tmpfile.path()).unwrap();
+        let txn = db.begin_write().unwrap();
+        txn.open_table(table_definition)
+            .unwrap()
+            .insert(0, 0)
+            .unwrap();
+        txn.commit().unwrap();
+        drop(db);
+        assert!(Database::corruption_report(tmpfile.path())
+            .unwrap()
+            .is_clean());
+
+        // A page which is allocated, but never referenced, is leaked by the next commit
+        let db = Database::open(tmpfile.path()).unwrap();
+        let leaked = db
+            .get_memory()
+            .allocate(PAGE_SIZE)
+            .unwrap()
+            .get_page_number();
+        db.begin_write().unwrap().commit().unwrap();
+        assert!(matches!(
+            Database::corruption_report(tmpfile.path()),
+            Err(DatabaseError::DatabaseAlreadyOpen)
+        ));
+        let txn = db.begin_read().unwrap();
+        let root_page = txn.read_raw_page(txn.raw_root().unwrap()).unwrap();
+        let root = txn.raw_root().unwrap();
+        drop(txn);
+        drop(db);
+        let report = Database::corruption_report(tmpfile.path()).unwrap();
+        assert_eq!(
+            report.findings(),
+            [CorruptionFinding::LeakedPage { page: raw(leaked) }]
+        );
+
+        // Damage the root of the table of tables, and the allocator state
+        let mut data = std::fs::read(tmpfile.path()).unwrap();
+        let root_offset = (0..data.len())
+            .step_by(PAGE_SIZE)
+            .find(|&offset| data[offset..].starts_with(&root_page))
+            .unwrap();
+        let name_offset = root_page
+            .windows(b"report_table".len())
+            .position(|window| window == b"report_table")
+            .unwrap();
+        data[root_offset + name_offset] ^= 0xFF;
+        let region_base = PAGE_SIZE;
+        let allocator_len = u32::from_le_bytes(
+            data[(region_base + ALLOCATOR_LENGTH_OFFSET)..(region_base + ALLOCATOR_OFFSET)]
+                .try_into()
+                .unwrap(),
+        ) as usize;
+        data[region_base + ALLOCATOR_OFFSET + allocator_len - 1] ^= 0xFF;
+        std::fs::write(tmpfile.path(), &data).unwrap();
+
+        // Both are reported, and the file is not modified
+        let report = Database::corruption_report(tmpfile.path()).unwrap();
+        assert_eq!(
+            report.findings(),
+            [
+                CorruptionFinding::DirtyAllocator,
+                CorruptionFinding::ChecksumMismatch { page: root },
+            ]
+        );
+        assert_eq!(std::fs::read(tmpfile.path()).unwrap(), data);
+    }
+
+    #[test]
+    fn fsck() {
+        let tmpfile = crate::create_tempfile();
+        let table_definition: TableDefinition<u64, u64> = TableDefinition::new("fsck_table");
+        let db = Database::create(tmpfile.path()).unwrap();
+        let txn = db.begin_write().unwrap();
+        txn.open_table(table_definition)
+            .unwrap()
+            .insert(0, 0)
+            .unwrap();
+        txn.commit().unwrap();
+        assert!(matches!(
+            Database::fsck(tmpfile.path()),
+            Err(Error::DatabaseAlreadyOpen)
+        ));
+        drop(db);
+        let report = Database::fsck(tmpfile.path()).unwrap();
+        assert!(report.is_clean());
+        assert_eq!(report.to_string(), "No corruption found");
+
+        let db = Database::open(tmpfile.path()).unwrap();
+        let leaked = db
+            .get_memory()
+            .allocate(PAGE_SIZE)
+            .unwrap()
+            .get_page_number();
+        db.begin_write().unwrap().commit().unwrap();
+        drop(db);
+        let report = Database::fsck(tmpfile.path()).unwrap();
+        let page = u64::from_le_bytes(leaked.to_le_bytes());
+        assert_eq!(report.findings(), [CorruptionFinding::LeakedPage { page }]);
+        assert_eq!(
+            report.to_string(),
+            format!("1 problem found:\n  Page {page:#x} is allocated, but not referenced")
+        );
+    }
+
+    // Returns the message of the panic raised by f
+    #[cfg(debug_assertions)]
+    fn panic_message<T>(f: impl FnOnce() -> T) -> String {
+        let payload = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f))
+            .err()
+            .unwrap();
+        payload.downcast_ref::<String>().unwrap().clone()
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn overlapping_page_access() {
+        let tmpfile = crate::create_tempfile();
+        let table_definition: TableDefinition<u32, u32> = TableDefinition::new("x");
+        let db = Database::create(tmpfile.path()).unwrap();
+        let txn = db.begin_write().unwrap();
+        txn.open_table(table_definition)
+            .unwrap()
+            .insert(0, 0)
+            .unwrap();
+        txn.commit().unwrap();
+        let mem = db.get_memory();
+        // The order 1 page which contains the given page
+        let parent = |page: PageNumber| PageNumber::new(page.region, page.page_index / 2, 1);
+
+        let dirty = mem.allocate(PAGE_SIZE).unwrap();
+        let dirty_page = dirty.get_page_number();
+        let message = panic_message(|| mem.get_page_mut(parent(dirty_page)));
+        assert!(message.contains("overlaps the dirty page"), "{message}");
+        let message = panic_message(|| mem.get_page(parent(dirty_page)));
+        assert!(message.contains("overlaps the dirty page"), "{message}");
+        drop(dirty);
+
+        let (root, _) = mem.get_data_root().unwrap();
+        let referenced = mem.get_page(root).unwrap();
+        let message = panic_message(|| mem.get_page_mut(parent(root)));
+        assert!(
+            message.contains("overlaps the referenced page"),
+            "{message}"
+        );
+        drop(referenced);
+
+        mem.rollback_uncommitted_writes().unwrap();
+    }
+
+    #[test]
+    fn capacity() {
+        let tmpfile = crate::create_tempfile();
+        let db = Database::create(tmpfile.path()).unwrap();
+        let mem = db.get_memory();
+
+        let capacity = db.capacity();
+        assert!(capacity.free() > 0);
+        assert!(capacity.free() < capacity.usable());
+        assert!(capacity.largest_run() > 0 && capacity.largest_run() <= capacity.free());
+        // Reading the capacity doesn't change it
+        assert_eq!(db.capacity(), capacity);
+
+        // Exactly the free pages can be allocated without growing the file
+        let len = mem.logical_size().unwrap();
+        for _ in 0..capacity.free() {
+            mem.allocate(PAGE_SIZE).unwrap();
+        }
+        assert_eq!(mem.logical_size().unwrap(), len);
+        assert_eq!(db.capacity().free(), 0);
+        assert_eq!(db.capacity().largest_run(), 0);
+        mem.allocate(PAGE_SIZE).unwrap();
+        assert!(mem.logical_size().unwrap() > len);
+        assert!(db.capacity().usable() > capacity.usable());
+
+        mem.rollback_uncommitted_writes().unwrap();
+    }
+
+    #[test]
+    fn reuse_recently_freed() {
+        let tmpfile = crate::create_tempfile();
+        let db = Database::create(tmpfile.path()).unwrap();
+        let mem = db.get_memory();
+
+        let pages: Vec<PageNumber> = (0..4)
+            .map(|_| mem.allocate(PAGE_SIZE).unwrap().get_page_number())
+            .collect();
+        assert!(mem.free_if_uncommitted(pages[0]).unwrap());
+        assert!(mem.free_if_uncommitted(pages[2]).unwrap());
+        // The most recently freed page is reused first
+        assert_eq!(mem.allocate(PAGE_SIZE).unwrap().get_page_number(), pages[2]);
+        assert_eq!(mem.allocate(PAGE_SIZE).unwrap().get_page_number(), pages[0]);
+
+        // A page which was merged into a larger free page can still be reused
+        assert!(mem.free_if_uncommitted(pages[3]).unwrap());
+        assert!(mem.free_if_uncommitted(pages[2]).unwrap());
+        assert!(mem.free_if_uncommitted(pages[1]).unwrap());
+        assert_eq!(mem.allocate(PAGE_SIZE).unwrap().get_page_number(), pages[1]);
+        assert_eq!(mem.allocate(PAGE_SIZE).unwrap().get_page_number(), pages[2]);
+        assert_eq!(mem.allocate(PAGE_SIZE).unwrap().get_page_number(), pages[3]);
+
+        mem.rollback_uncommitted_writes().unwrap();
+    }
 }
@@ -3,15 +3,17 @@ use crate::tree_store::page_store::buddy_allocator::BuddyAllocator;
 use crate::tree_store::page_store::cached_file::PagedCachedFile;
 use crate::tree_store::page_store::header::DatabaseHeader;
 use crate::tree_store::page_store::layout::DatabaseLayout;
-use crate::tree_store::page_store::page_manager::{INITIAL_REGIONS, MAX_MAX_PAGE_ORDER};
-use crate::tree_store::PageNumber;
-use crate::Result;
+use crate::tree_store::page_store::page_manager::{
+    xxh3_checksum, INITIAL_REGIONS, MAX_MAX_PAGE_ORDER,
+};
+use crate::tree_store::{Checksum, PageNumber};
+use crate::{Result, StorageError};
 use std::cmp;
 use std::mem::size_of;
 
 const REGION_FORMAT_VERSION: u8 = 1;
-const ALLOCATOR_LENGTH_OFFSET: usize = 4;
-const ALLOCATOR_OFFSET: usize = ALLOCATOR_LENGTH_OFFSET + size_of::<u32>();
+pub(super) const ALLOCATOR_LENGTH_OFFSET: usize = 4;
+pub(super) const ALLOCATOR_OFFSET: usize = ALLOCATOR_LENGTH_OFFSET + size_of::<u32>();
 
 // Tracks the page orders that MAY BE free in each region. This data structure is optimistic, so
 // a region may not actually have a page free for a given order
@@ -47,31 +49,49 @@ impl RegionTracker {
     }
 
     // May contain trailing data
-    pub(super) fn from_page(page: &[u8]) -> Self {
+    pub(super) fn from_page(page: &[u8]) -> Result<Self> {
+        let corrupted = || StorageError::Corrupted("Invalid region tracker".to_string());
+        if page.len() < 2 * size_of::<u32>() {
+            return Err(corrupted());
+        }
         let orders = u32::from_le_bytes(page[..size_of::<u32>()].try_into().unwrap());
         let allocator_len = u32::from_le_bytes(
             page[size_of::<u32>()..2 * size_of::<u32>()]
                 .try_into()
                 .unwrap(),
         ) as usize;
-        let mut data = vec![];
+        if orders != u32::from(MAX_MAX_PAGE_ORDER) + 1 {
+            return Err(corrupted());
+        }
+        let mut data: Vec<BtreeBitmap> = vec![];
         let mut start = 2 * size_of::<u32>();
         for _ in 0..orders {
-            data.push(BtreeBitmap::from_bytes(
-                &page[start..(start + allocator_len)],
-            ));
+            if start + allocator_len > page.len() {
+                return Err(corrupted());
+            }
+            let tracker = BtreeBitmap::from_bytes(&page[start..(start + allocator_len)])?;
+            if let Some(first) = data.first() {
+                if first.len() != tracker.len() {
+                    return Err(corrupted());
+                }
+            }
+            data.push(tracker);
             start += allocator_len;
         }
 
-        Self {
+        Ok(Self {
             order_trackers: data,
-        }
+        })
     }
 
     pub(crate) fn find_free(&self, order: u8) -> Option<u32> {
         self.order_trackers[order as usize].find_first_unset()
     }
 
+    pub(crate) fn find_last_free(&self, order: u8) -> Option<u32> {
+        self.order_trackers[order as usize].find_last_unset()
+    }
+
     pub(crate) fn mark_free(&mut self, order: u8, region: u32) {
         let order: usize = order.into();
         for i in 0..=order {
@@ -81,7 +101,8 @@ impl RegionTracker {
 
     pub(crate) fn mark_full(&mut self, order: u8, region: u32) {
         let order: usize = order.into();
-        assert!(order < self.order_trackers.len());
+        // Orders are bounded by the allocator that was just searched, so this holds by construction
+        debug_assert!(order < self.order_trackers.len());
         for i in order..self.order_trackers.len() {
             self.order_trackers[i].set(region);
         }
@@ -138,6 +159,11 @@ impl Allocators {
     }
 
     pub(super) fn from_bytes(header: &DatabaseHeader, storage: &PagedCachedFile) -> Result<Self> {
+        if !header.layout().contains_page(header.region_tracker()) {
+            return Err(StorageError::Corrupted(
+                "Invalid region tracker page".to_string(),
+            ));
+        }
         let page_size = header.page_size();
         let region_header_size =
             header.layout().full_region_layout().get_header_pages() * page_size;
@@ -150,10 +176,17 @@ impl Allocators {
             region_header_size as u64,
             page_size,
         );
-        let len: usize = (range.end - range.start).try_into().unwrap();
-        let region_tracker = storage.read_direct(range.start, len)?;
+        let len: usize = (range.end - range.start)
+            .try_into()
+            .map_err(|_| StorageError::Corrupted("Invalid region tracker page".to_string()))?;
+        let region_tracker = RegionTracker::from_page(&storage.read_direct(range.start, len)?)?;
         let mut region_allocators = vec![];
         let layout = header.layout();
+        if region_tracker.len() < layout.num_regions() {
+            return Err(StorageError::Corrupted(
+                "Region tracker is too small".to_string(),
+            ));
+        }
         for i in 0..layout.num_regions() {
             let base = layout.region_base_address(i);
             let header_len: usize = layout
@@ -161,23 +194,49 @@ impl Allocators {
                 .data_section()
                 .start
                 .try_into()
-                .unwrap();
+                .map_err(|_| StorageError::Corrupted("Invalid region header".to_string()))?;
 
             let mem = storage.read_direct(base, header_len)?;
-            region_allocators.push(RegionHeader::deserialize(&mem));
+            let allocator = RegionHeader::deserialize(&mem)?;
+            if allocator.len() != layout.region_layout(i).num_pages()
+                || allocator.capacity() < layout.full_region_layout().num_pages()
+            {
+                return Err(StorageError::Corrupted(format!(
+                    "Allocator for region {i} does not match the database layout"
+                )));
+            }
+            region_allocators.push(allocator);
         }
 
         Ok(Self {
-            region_tracker: RegionTracker::from_page(&region_tracker),
+            region_tracker,
             region_allocators,
         })
     }
 
+    // Checksum of the state written by flush_to(). It is computed over a fresh serialization, so
+    // that it does not depend on any stale bytes following the serialized state on disk
+    pub(super) fn checksum(&self, layout: DatabaseLayout) -> Checksum {
+        let mut data = self.region_tracker.to_vec();
+        for i in 0..layout.num_regions() {
+            let len: usize = layout
+                .region_layout(i)
+                .data_section()
+                .start
+                .try_into()
+                .unwrap();
+            let mut header = vec![0; len];
+            RegionHeader::serialize(&self.region_allocators[i as usize], &mut header);
+            data.extend_from_slice(&header);
+        }
+        xxh3_checksum(&data)
+    }
+
     pub(super) fn flush_to(
         &self,
         region_tracker_page: PageNumber,
         layout: DatabaseLayout,
-        storage: &mut PagedCachedFile,
+        storage: &PagedCachedFile,
     ) -> Result {
         let page_size = layout.full_region_layout().page_size();
         let region_header_size =
@@ -195,9 +254,19 @@ impl Allocators {
             storage.write(range.start, len, false)?
         };
         let tracker_bytes = self.region_tracker.to_vec();
-        region_tracker_mem.mem_mut()[..tracker_bytes.len()].copy_from_slice(&tracker_bytes);
+        if tracker_bytes.len() > region_tracker_mem.mem().len() {
+            return Err(StorageError::Corrupted(
+                "Region tracker does not fit in its page".to_string(),
+            ));
+        }
 
         assert_eq!(self.region_allocators.len(), layout.num_regions() as usize);
+        // Check every region before writing any of them, so that allocator state is never written
+        // over data pages
+        for i in 0..layout.num_regions() {
+            self.check_region_fits(i, layout)?;
+        }
+        region_tracker_mem.mem_mut()[..tracker_bytes.len()].copy_from_slice(&tracker_bytes);
         for i in 0..layout.num_regions() {
             let base = layout.region_base_address(i);
             let len: usize = layout
@@ -214,6 +283,19 @@ impl Allocators {
         Ok(())
     }
 
+    // Returns an error if the allocator state of the region is larger than its header
+    pub(super) fn check_region_fits(&self, region: u32, layout: DatabaseLayout) -> Result {
+        let header_len = layout.region_layout(region).data_section().start;
+        let state_len = ALLOCATOR_OFFSET + self.region_allocators[region as usize].to_vec().len();
+        if state_len as u64 > header_len {
+            return Err(StorageError::Corrupted(format!(
+                "Allocator state of region {region} is {state_len} bytes, which does not fit in its header of {header_len} bytes"
+            )));
+        }
+
+        Ok(())
+    }
+
     pub(super) fn resize_to(&mut self, new_layout: DatabaseLayout) {
         let shrink = match (new_layout.num_regions() as usize).cmp(&self.region_allocators.len()) {
             cmp::Ordering::Less => true,
@@ -308,13 +390,41 @@ impl RegionHeader {
             .copy_from_slice(&serialized);
     }
 
-    fn deserialize(data: &[u8]) -> BuddyAllocator {
-        assert_eq!(REGION_FORMAT_VERSION, data[0]);
+    fn deserialize(data: &[u8]) -> Result<BuddyAllocator> {
+        if data.len() < ALLOCATOR_OFFSET || data[0] != REGION_FORMAT_VERSION {
+            return Err(StorageError::Corrupted("Invalid region header".to_string()));
+        }
         let allocator_len = u32::from_le_bytes(
             data[ALLOCATOR_LENGTH_OFFSET..(ALLOCATOR_LENGTH_OFFSET + size_of::<u32>())]
                 .try_into()
                 .unwrap(),
         ) as usize;
+        if ALLOCATOR_OFFSET + allocator_len > data.len() {
+            return Err(StorageError::Corrupted("Invalid region header".to_string()));
+        }
         BuddyAllocator::from_bytes(&data[ALLOCATOR_OFFSET..(ALLOCATOR_OFFSET + allocator_len)])
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::tree_store::page_store::layout::{DatabaseLayout, RegionLayout};
+    use crate::tree_store::page_store::region::{Allocators, RegionHeader};
+
+    #[test]
+    fn allocator_must_fit_in_region_header() {
+        let page_size = 512;
+        let pages_per_region = 1 << 16;
+        let header_pages = RegionHeader::header_pages_expensive(page_size, pages_per_region);
+        assert!(header_pages > 1);
+
+        let full_region = RegionLayout::new(pages_per_region, header_pages, page_size);
+        let layout = DatabaseLayout::new(1, full_region, None);
+        let allocators = Allocators::new(layout);
+        allocators.check_region_fits(0, layout).unwrap();
+
+        let cramped_region = RegionLayout::new(pages_per_region, header_pages - 1, page_size);
+        let cramped = DatabaseLayout::new(1, cramped_region, None);
+        assert!(allocators.check_region_fits(0, cramped).is_err());
+    }
+}
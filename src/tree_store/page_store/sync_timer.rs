@@ -0,0 +1,92 @@
+use crate::tree_store::page_store::cached_file::PagedCachedFile;
+use std::io;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+#[derive(Default)]
+struct SyncState {
+    // A commit has been handed to the OS, but not synced
+    unsynced: bool,
+    shutdown: bool,
+}
+
+// Background thread which syncs the file, once a commit has been left unsynced for the interval
+pub(super) struct SyncTimer {
+    state: Arc<(Mutex<SyncState>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SyncTimer {
+    pub(super) fn new(storage: Arc<PagedCachedFile>, interval: Duration) -> io::Result<Self> {
+        let state: Arc<(Mutex<SyncState>, Condvar)> = Default::default();
+        let thread_state = state.clone();
+        let thread = thread::Builder::new()
+            .name("redb-sync".to_string())
+            .spawn(move || Self::run(&storage, &thread_state, interval))?;
+
+        Ok(Self {
+            state,
+            thread: Some(thread),
+        })
+    }
+
+    fn run(storage: &PagedCachedFile, state: &(Mutex<SyncState>, Condvar), interval: Duration) {
+        let (lock, signal) = state;
+        let mut guard = lock.lock().unwrap();
+        loop {
+            // Sleep until there is something to sync, rather than waking on every interval
+            while !guard.unsynced && !guard.shutdown {
+                guard = signal.wait(guard).unwrap();
+            }
+            let deadline = Instant::now().checked_add(interval);
+            while !guard.shutdown && guard.unsynced {
+                let now = Instant::now();
+                match deadline {
+                    Some(deadline) if now < deadline => {
+                        guard = signal.wait_timeout(guard, deadline - now).unwrap().0;
+                    }
+                    Some(_) => break,
+                    None => guard = signal.wait(guard).unwrap(),
+                }
+            }
+            if guard.shutdown {
+                return;
+            }
+            if !guard.unsynced {
+                // A durable commit synced the file in the meantime
+                continue;
+            }
+            guard.unsynced = false;
+            drop(guard);
+            // Only writes already handed to the OS are synced, since the write buffer may hold
+            // pages of a write transaction in progress. A failure is sticky, and is returned by the
+            // next commit
+            let _ = storage.sync_data();
+            guard = lock.lock().unwrap();
+        }
+    }
+
+    pub(super) fn mark_unsynced(&self) {
+        let (lock, signal) = &*self.state;
+        lock.lock().unwrap().unsynced = true;
+        signal.notify_all();
+    }
+
+    pub(super) fn mark_synced(&self) {
+        let (lock, signal) = &*self.state;
+        lock.lock().unwrap().unsynced = false;
+        signal.notify_all();
+    }
+}
+
+impl Drop for SyncTimer {
+    fn drop(&mut self) {
+        let (lock, signal) = &*self.state;
+        lock.lock().unwrap().shutdown = true;
+        signal.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
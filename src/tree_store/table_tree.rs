@@ -1,13 +1,15 @@
 use crate::error::TableError;
-use crate::multimap_table::finalize_tree_and_subtree_checksums;
-use crate::tree_store::btree::{btree_stats, UntypedBtreeMut};
+use crate::multimap_table::{
+    finalize_tree_and_subtree_checksums, multimap_btree_len, parse_subtree_roots,
+};
+use crate::tree_store::btree::{btree_len, btree_stats, UntypedBtreeMut};
 use crate::tree_store::btree_base::Checksum;
 use crate::tree_store::btree_iters::AllPageNumbersBtreeIter;
 use crate::tree_store::{BtreeMut, BtreeRangeIter, PageNumber, TransactionalMemory};
 use crate::types::{RedbKey, RedbValue, RedbValueMutInPlace, TypeName};
-use crate::{DatabaseStats, Result};
+use crate::{DatabaseStats, Result, SpaceUsage, TableInfo};
 use std::cmp::max;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashSet};
 use std::mem;
 use std::mem::size_of;
 use std::ops::RangeFull;
@@ -232,6 +234,49 @@ impl InternalTableDefinition {
     pub(crate) fn get_type(&self) -> TableType {
         self.table_type
     }
+
+    // Number of key-value pairs stored in the table
+    fn count_entries(&self, mem: &TransactionalMemory) -> Result<u64> {
+        let root = self.table_root.map(|(p, _)| p);
+        match self.table_type {
+            TableType::Normal => btree_len(root, mem, self.fixed_key_size, self.fixed_value_size),
+            TableType::Multimap => {
+                multimap_btree_len(root, mem, self.fixed_key_size, self.fixed_value_size)
+            }
+        }
+    }
+
+    // Number of pages in the table, including the subtrees of a multimap table
+    fn count_pages(&self, mem: &TransactionalMemory) -> Result<u64> {
+        let root = match self.table_root {
+            Some((root, _)) => root,
+            None => return Ok(0),
+        };
+        let mut count = 0;
+        let table_pages_iter =
+            AllPageNumbersBtreeIter::new(root, self.fixed_key_size, self.fixed_value_size, mem)?;
+        for page_number in table_pages_iter {
+            let page_number = page_number?;
+            count += 1;
+            if self.table_type == TableType::Multimap {
+                let page = mem.get_page(page_number)?;
+                for sub_root in parse_subtree_roots(&page, self.fixed_key_size) {
+                    let sub_root_iter = AllPageNumbersBtreeIter::new(
+                        sub_root,
+                        self.fixed_value_size,
+                        <()>::fixed_width(),
+                        mem,
+                    )?;
+                    for sub_page in sub_root_iter {
+                        sub_page?;
+                        count += 1;
+                    }
+                }
+            }
+        }
+
+        Ok(count)
+    }
 }
 
 impl RedbValue for InternalTableDefinition {
@@ -408,7 +453,8 @@ pub(crate) struct TableTree<'txn> {
     tree: BtreeMut<'txn, &'static str, InternalTableDefinition>,
     mem: &'txn TransactionalMemory,
     // Cached updates from tables that have been closed. These must be flushed to the btree
-    pending_table_updates: HashMap<String, Option<(PageNumber, Checksum)>>,
+    // Ordered, so that the roots are flushed in the same order, and to the same pages, every run
+    pending_table_updates: BTreeMap<String, Option<(PageNumber, Checksum)>>,
     freed_pages: Arc<Mutex<Vec<PageNumber>>>,
 }
 
@@ -477,7 +523,7 @@ impl<'txn> TableTree<'txn> {
     }
 
     pub(crate) fn flush_table_root_updates(&mut self) -> Result<Option<(PageNumber, Checksum)>> {
-        for (name, table_root) in self.pending_table_updates.drain() {
+        for (name, table_root) in std::mem::take(&mut self.pending_table_updates) {
             // Bypass .get_table() since the table types are dynamic
             let mut definition = self.tree.get(&name.as_str())?.unwrap().value();
             // No-op if the root has not changed
@@ -568,6 +614,25 @@ impl<'txn> TableTree<'txn> {
         name: &str,
         table_type: TableType,
     ) -> Result<Option<InternalTableDefinition>, TableError> {
+        self.get_table_with_key_width::<K, V>(name, table_type, K::fixed_width())
+    }
+
+    // key_width is the fixed width of the table's keys, which may have been declared for the table
+    // rather than by K
+    pub(crate) fn get_table_with_key_width<K: RedbKey, V: RedbValue>(
+        &self,
+        name: &str,
+        table_type: TableType,
+        key_width: Option<usize>,
+    ) -> Result<Option<InternalTableDefinition>, TableError> {
+        // A width can only be declared for a key type whose values vary in width
+        if K::fixed_width().is_some() && key_width != K::fixed_width() {
+            return Err(TableError::TypeDefinitionChanged {
+                name: K::type_name(),
+                alignment: ALIGNMENT,
+                width: K::fixed_width(),
+            });
+        }
         Ok(
             if let Some(definition) = self.get_table_untyped(name, table_type)? {
                 // Do additional checks on the types to be sure they match
@@ -579,7 +644,7 @@ impl<'txn> TableTree<'txn> {
                         value: definition.value_type,
                     });
                 }
-                if definition.get_fixed_key_size() != K::fixed_width() {
+                if definition.get_fixed_key_size() != key_width {
                     return Err(TableError::TypeDefinitionChanged {
                         name: K::type_name(),
                         alignment: definition.get_key_alignment(),
@@ -629,6 +694,162 @@ impl<'txn> TableTree<'txn> {
         Ok(false)
     }
 
+    pub(crate) fn rename_table(
+        &mut self,
+        name: &str,
+        new_name: &str,
+        table_type: TableType,
+    ) -> Result<(), TableError> {
+        let definition = self
+            .get_table_untyped(name, table_type)?
+            .ok_or_else(|| TableError::TableDoesNotExist(name.to_string()))?;
+        if self.tree.get(&new_name)?.is_some() {
+            return Err(TableError::TableExists(new_name.to_string()));
+        }
+
+        // The definition already includes any pending update to the root
+        self.pending_table_updates.remove(name);
+        self.tree.remove(&name)?;
+        self.tree.insert(&new_name, &definition)?;
+
+        Ok(())
+    }
+
+    // Rebuilds the given table as a dense tree. Only normal tables can be rebuilt, since the values
+    // of a multimap table may reference subtrees
+    pub(crate) fn compact_table(&mut self, name: &str) -> Result<(), TableError> {
+        let definition = self
+            .get_table_untyped(name, TableType::Normal)?
+            .ok_or_else(|| TableError::TableDoesNotExist(name.to_string()))?;
+        let mut tree = UntypedBtreeMut::new(
+            definition.table_root,
+            self.mem,
+            self.freed_pages.clone(),
+            definition.fixed_key_size,
+            definition.fixed_value_size,
+        );
+        tree.rebuild_dense()?;
+        self.pending_table_updates
+            .insert(name.to_string(), tree.get_root());
+
+        Ok(())
+    }
+
+    // Replaces the contents of the given table with a dense tree of `entries`, which must be in key
+    // order without duplicates, and frees the pages of its old tree. The table is created if it
+    // does not exist
+    pub(crate) fn replace_table<K: RedbKey, V: RedbValue>(
+        &mut self,
+        name: &str,
+        key_width: Option<usize>,
+        entries: &[(Vec<u8>, Vec<u8>)],
+    ) -> Result<(), TableError> {
+        let definition =
+            self.get_or_create_table_with_key_width::<K, V>(name, TableType::Normal, key_width)?;
+        let mut tree = UntypedBtreeMut::new(
+            definition.table_root,
+            self.mem,
+            self.freed_pages.clone(),
+            definition.fixed_key_size,
+            definition.fixed_value_size,
+        );
+        tree.replace_dense(
+            entries
+                .iter()
+                .map(|(key, value)| (key.as_slice(), value.as_slice())),
+        )?;
+        self.pending_table_updates
+            .insert(name.to_string(), tree.get_root());
+
+        Ok(())
+    }
+
+    // Number of key-value pairs in the given table, or zero if it does not exist
+    pub(crate) fn count_table_entries(
+        &self,
+        name: &str,
+        table_type: TableType,
+    ) -> Result<u64, TableError> {
+        if let Some(definition) = self.get_table_untyped(name, table_type)? {
+            Ok(definition.count_entries(self.mem)?)
+        } else {
+            Ok(0)
+        }
+    }
+
+    // Serialized keys of the given normal table, in order
+    pub(crate) fn table_keys<K: RedbKey, V: RedbValue>(
+        &self,
+        name: &str,
+        key_width: Option<usize>,
+    ) -> Result<Vec<Vec<u8>>, TableError> {
+        let mut keys = vec![];
+        if let Some(definition) =
+            self.get_table_with_key_width::<K, V>(name, TableType::Normal, key_width)?
+        {
+            let iter = BtreeRangeIter::<K, V>::with_key_width::<RangeFull, K::SelfType<'_>>(
+                &(..),
+                definition.get_root().map(|(p, _)| p),
+                key_width,
+                self.mem,
+            )?;
+            for entry in iter {
+                keys.push(entry?.key_data());
+            }
+        }
+
+        Ok(keys)
+    }
+
+    // Number of key-value pairs in all the tables
+    pub(crate) fn count_entries(&self) -> Result<u64> {
+        let mut total = 0;
+        for entry in self.tree.range::<RangeFull, &str>(&(..))? {
+            let entry = entry?;
+            let mut definition = entry.value();
+            if let Some(updated_root) = self.pending_table_updates.get(entry.key()) {
+                definition.table_root = *updated_root;
+            }
+            total += definition.count_entries(self.mem)?;
+        }
+
+        Ok(total)
+    }
+
+    // Number of pages in the table tree itself. These are not attributed to any table
+    pub(crate) fn catalog_page_count(&self) -> Result<u64> {
+        let mut count = 0;
+        if let Some(iter) = self.tree.all_pages_iter()? {
+            for page in iter {
+                page?;
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+
+    pub(crate) fn table_info(&self) -> Result<Vec<TableInfo>> {
+        let mut result = vec![];
+        for entry in self.tree.range::<RangeFull, &str>(&(..))? {
+            let entry = entry?;
+            let mut definition = entry.value();
+            if let Some(updated_root) = self.pending_table_updates.get(entry.key()) {
+                definition.table_root = *updated_root;
+            }
+            result.push(TableInfo {
+                name: entry.key().to_string(),
+                multimap: definition.table_type == TableType::Multimap,
+                entry_count: definition.count_entries(self.mem)?,
+                page_count: definition.count_pages(self.mem)?,
+                key_type: definition.key_type,
+                value_type: definition.value_type,
+            });
+        }
+
+        Ok(result)
+    }
+
     // Returns a tuple of the table id and the new root page
     // root_page: the root of the master table
     pub(crate) fn get_or_create_table<K: RedbKey, V: RedbValue>(
@@ -636,14 +857,23 @@ impl<'txn> TableTree<'txn> {
         name: &str,
         table_type: TableType,
     ) -> Result<InternalTableDefinition, TableError> {
-        if let Some(found) = self.get_table::<K, V>(name, table_type)? {
+        self.get_or_create_table_with_key_width::<K, V>(name, table_type, K::fixed_width())
+    }
+
+    pub(crate) fn get_or_create_table_with_key_width<K: RedbKey, V: RedbValue>(
+        &mut self,
+        name: &str,
+        table_type: TableType,
+        key_width: Option<usize>,
+    ) -> Result<InternalTableDefinition, TableError> {
+        if let Some(found) = self.get_table_with_key_width::<K, V>(name, table_type, key_width)? {
             return Ok(found);
         }
 
         let table = InternalTableDefinition {
             table_root: None,
             table_type,
-            fixed_key_size: K::fixed_width(),
+            fixed_key_size: key_width,
             fixed_value_size: V::fixed_width(),
             key_alignment: ALIGNMENT,
             value_alignment: ALIGNMENT,
@@ -726,6 +956,46 @@ impl<'txn> TableTree<'txn> {
             page_size: self.mem.get_page_size(),
         })
     }
+
+    pub(crate) fn space_usage(&self) -> Result<SpaceUsage> {
+        let master_tree_stats = self.tree.stats()?;
+        // Count the master tree leaf pages as branches, since they point to the data trees
+        let mut result = SpaceUsage {
+            leaf_pages: 0,
+            leaf_page_bytes: 0,
+            leaf_stored_bytes: 0,
+            leaf_metadata_bytes: 0,
+            branch_pages: master_tree_stats.branch_pages + master_tree_stats.leaf_pages,
+            branch_page_bytes: master_tree_stats.branch_page_bytes
+                + master_tree_stats.leaf_page_bytes,
+            branch_metadata_bytes: master_tree_stats.metadata_bytes
+                + master_tree_stats.stored_leaf_bytes,
+        };
+
+        for entry in self.tree.range::<RangeFull, &str>(&(..))? {
+            let entry = entry?;
+            let mut definition = entry.value();
+            if let Some(updated_root) = self.pending_table_updates.get(entry.key()) {
+                definition.table_root = *updated_root;
+            }
+            let subtree_stats = btree_stats(
+                definition.table_root.map(|(p, _)| p),
+                self.mem,
+                definition.fixed_key_size,
+                definition.fixed_value_size,
+            )?;
+            result.leaf_pages += subtree_stats.leaf_pages;
+            result.leaf_page_bytes += subtree_stats.leaf_page_bytes;
+            result.leaf_stored_bytes += subtree_stats.stored_leaf_bytes;
+            result.leaf_metadata_bytes +=
+                subtree_stats.metadata_bytes - subtree_stats.branch_metadata_bytes;
+            result.branch_pages += subtree_stats.branch_pages;
+            result.branch_page_bytes += subtree_stats.branch_page_bytes;
+            result.branch_metadata_bytes += subtree_stats.branch_metadata_bytes;
+        }
+
+        Ok(result)
+    }
 }
 
 #[cfg(test)]
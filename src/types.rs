@@ -65,7 +65,8 @@ impl TypeName {
         }
     }
 
-    pub(crate) fn name(&self) -> &str {
+    /// The name of the type
+    pub fn name(&self) -> &str {
         &self.name
     }
 }
@@ -325,6 +326,46 @@ impl RedbKey for &str {
     }
 }
 
+impl RedbValue for String {
+    type SelfType<'a> = String
+    where
+        Self: 'a;
+    type AsBytes<'a> = &'a str
+    where
+        Self: 'a;
+
+    fn fixed_width() -> Option<usize> {
+        None
+    }
+
+    fn from_bytes<'a>(data: &'a [u8]) -> String
+    where
+        Self: 'a,
+    {
+        std::str::from_utf8(data).unwrap().to_string()
+    }
+
+    fn as_bytes<'a, 'b: 'a>(value: &'a Self::SelfType<'b>) -> &'a str
+    where
+        Self: 'a,
+        Self: 'b,
+    {
+        value.as_str()
+    }
+
+    fn type_name() -> TypeName {
+        TypeName::internal("String")
+    }
+}
+
+impl RedbKey for String {
+    fn compare(data1: &[u8], data2: &[u8]) -> Ordering {
+        let str1 = <&str>::from_bytes(data1);
+        let str2 = <&str>::from_bytes(data2);
+        str1.cmp(str2)
+    }
+}
+
 macro_rules! be_value {
     ($t:ty) => {
         impl RedbValue for $t {
@@ -1,6 +1,7 @@
 use redb::{
-    Database, MultimapTableDefinition, MultimapTableHandle, Range, ReadableTable, RedbKey,
-    RedbValue, TableDefinition, TableHandle, TypeName,
+    ChangeKind, Database, EtlScanToken, IteratorToken, JoinMode, MissingKey,
+    MultimapTableDefinition, MultimapTableHandle, Range, ReadableTable, RedbKey, RedbValue,
+    SwapError, TableDefinition, TableHandle, TypeName,
 };
 use std::cmp::Ordering;
 #[cfg(not(target_os = "wasi"))]
@@ -184,6 +185,195 @@ fn drain_filter() {
     write_txn.abort().unwrap();
 }
 
+#[test]
+fn swap() {
+    let tmpfile = create_tempfile();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(STR_TABLE).unwrap();
+        table.insert("a", "hello").unwrap();
+        table.insert("b", "world").unwrap();
+        table.swap("a", "b", MissingKey::Fail).unwrap();
+        table.swap("a", "a", MissingKey::Fail).unwrap();
+        // Either key being absent leaves the table unchanged
+        table.swap("a", "missing", MissingKey::Ignore).unwrap();
+        table.swap("missing", "b", MissingKey::Ignore).unwrap();
+        table
+            .swap("missing", "missing", MissingKey::Ignore)
+            .unwrap();
+        for (key_a, key_b) in [("a", "missing"), ("missing", "b"), ("missing", "missing")] {
+            assert!(matches!(
+                table.swap(key_a, key_b, MissingKey::Fail),
+                Err(SwapError::KeyNotFound)
+            ));
+        }
+        assert_eq!(table.len().unwrap(), 2);
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(STR_TABLE).unwrap();
+    assert_eq!(table.len().unwrap(), 2);
+    assert_eq!(table.get("a").unwrap().unwrap().value(), "world");
+    assert_eq!(table.get("b").unwrap().unwrap().value(), "hello");
+    assert!(table.get("missing").unwrap().is_none());
+}
+
+#[test]
+fn last_commit_changes() {
+    let tmpfile = create_tempfile();
+    let db = Database::builder()
+        .set_track_changes(true)
+        .create(tmpfile.path())
+        .unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        for i in 0..5 {
+            table.insert(&i, &i).unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+    assert_eq!(db.last_commit_changes().count(), 5);
+
+    let write_txn = db.begin_write().unwrap();
+    assert_eq!(db.last_commit_changes().count(), 0);
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        table.insert(&0, &10).unwrap();
+        table.remove(&1).unwrap();
+        table.insert(&5, &5).unwrap();
+        // Net changes are reported, so these cancel out
+        table.insert(&6, &6).unwrap();
+        table.remove(&6).unwrap();
+        table.remove(&2).unwrap();
+        table.insert(&2, &20).unwrap();
+        table.drain(3..4).unwrap();
+        table.remove(&100).unwrap();
+    }
+    write_txn.commit().unwrap();
+
+    let key = |k: u64| u64::as_bytes(&k).to_vec();
+    let table = U64_TABLE.name().to_string();
+    let expected = vec![
+        ChangeKind::Updated {
+            table: table.clone(),
+            key: key(0),
+        },
+        ChangeKind::Deleted {
+            table: table.clone(),
+            key: key(1),
+        },
+        ChangeKind::Updated {
+            table: table.clone(),
+            key: key(2),
+        },
+        ChangeKind::Deleted {
+            table: table.clone(),
+            key: key(3),
+        },
+        ChangeKind::Inserted {
+            table: table.clone(),
+            key: key(5),
+        },
+    ];
+    assert_eq!(db.last_commit_changes().collect::<Vec<_>>(), expected);
+
+    // An aborted transaction doesn't report its changes
+    let write_txn = db.begin_write().unwrap();
+    write_txn.open_table(U64_TABLE).unwrap().remove(&0).unwrap();
+    write_txn.abort().unwrap();
+    assert_eq!(db.last_commit_changes().count(), 0);
+
+    // Replacing a table reports the old keys as deleted, unless they are replaced
+    let write_txn = db.begin_write().unwrap();
+    write_txn
+        .replace_table(U64_TABLE, [(2u64, 2u64), (7, 7)])
+        .unwrap();
+    write_txn.commit().unwrap();
+    let expected = vec![
+        ChangeKind::Deleted {
+            table: table.clone(),
+            key: key(0),
+        },
+        ChangeKind::Updated {
+            table: table.clone(),
+            key: key(2),
+        },
+        ChangeKind::Deleted {
+            table: table.clone(),
+            key: key(4),
+        },
+        ChangeKind::Deleted {
+            table: table.clone(),
+            key: key(5),
+        },
+        ChangeKind::Inserted { table, key: key(7) },
+    ];
+    assert_eq!(db.last_commit_changes().collect::<Vec<_>>(), expected);
+    drop(db);
+
+    // Changes are not tracked by default
+    let db = Database::open(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    write_txn
+        .open_table(U64_TABLE)
+        .unwrap()
+        .insert(&8, &8)
+        .unwrap();
+    write_txn.commit().unwrap();
+    assert_eq!(db.last_commit_changes().count(), 0);
+}
+
+#[test]
+fn database_get() {
+    let tmpfile = create_tempfile();
+    let db = Database::create(tmpfile.path()).unwrap();
+    assert!(db.get(STR_TABLE, "hello").unwrap().is_none());
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(STR_TABLE).unwrap();
+        table.insert("hello", "world").unwrap();
+        table.insert("hi", "there").unwrap();
+    }
+    write_txn.commit().unwrap();
+
+    let value = db.get(STR_TABLE, "hello").unwrap().unwrap();
+    assert_eq!(value.value(), "world");
+    assert_eq!(db.get(STR_TABLE, "hi").unwrap().unwrap().value(), "there");
+    assert!(db.get(STR_TABLE, "missing").unwrap().is_none());
+
+    // The value is a copy, so it outlives later writes
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(STR_TABLE).unwrap();
+        table.remove("hello").unwrap();
+    }
+    write_txn.commit().unwrap();
+    assert_eq!(value.value(), "world");
+    assert!(db.get(STR_TABLE, "hello").unwrap().is_none());
+}
+
+#[test]
+fn begin_write_with_hint() {
+    let tmpfile = create_tempfile();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write_with_hint(1000).unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        for i in 0..10_000 {
+            table.insert(&i, &i).unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    assert_eq!(table.len().unwrap(), 10_000);
+}
+
 #[test]
 fn stored_size() {
     let tmpfile = create_tempfile();
@@ -202,6 +392,36 @@ fn stored_size() {
     write_txn.abort().unwrap();
 }
 
+#[test]
+fn space_usage() {
+    let tmpfile = create_tempfile();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        for i in 0..100 {
+            table.insert(&i, &i).unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let usage = read_txn.space_usage().unwrap();
+    let page_size = db.begin_write().unwrap().stats().unwrap().page_size() as u64;
+    // All the pairs fit in a single leaf, with a 4 byte header and no offset table,
+    // since the keys and values are fixed width
+    assert_eq!(usage.leaf_pages(), 1);
+    assert_eq!(usage.leaf_page_bytes(), page_size);
+    assert_eq!(usage.stored_bytes(), 100 * 2 * 8);
+    assert_eq!(usage.leaf_metadata_bytes(), 4);
+    assert_eq!(usage.leaf_fragmented_bytes(), page_size - 1600 - 4);
+    // The table of tables is a single page
+    assert_eq!(usage.branch_pages(), 1);
+    assert_eq!(usage.branch_page_bytes(), page_size);
+    assert!(usage.branch_metadata_bytes() > 0);
+    assert_eq!(usage.page_bytes(), 2 * page_size);
+}
+
 #[test]
 fn create_open() {
     let tmpfile = create_tempfile();
@@ -294,6 +514,298 @@ fn list_tables() {
     assert_eq!(multimap_tables, &["mx", "my"]);
 }
 
+#[test]
+fn list_table_info() {
+    let tmpfile = create_tempfile();
+    let db = Database::create(tmpfile.path()).unwrap();
+
+    let definition_m: MultimapTableDefinition<u64, u64> = MultimapTableDefinition::new("m");
+    let write_txn = db.begin_write().unwrap();
+    {
+        // Created, but never written to
+        write_txn.open_table(STR_TABLE).unwrap();
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        for i in 0..10_000 {
+            table.insert(i, i).unwrap();
+        }
+        let mut multimap = write_txn.open_multimap_table(definition_m).unwrap();
+        for i in 0..3 {
+            for j in 0..1000 {
+                multimap.insert(i, j).unwrap();
+            }
+        }
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let info = read_txn.list_table_info().unwrap();
+    let names: Vec<&str> = info.iter().map(|x| x.name()).collect();
+    assert_eq!(names, &["m", "u64", "x"]);
+
+    assert!(info[0].is_multimap());
+    assert_eq!(info[0].key_type(), &u64::type_name());
+    assert_eq!(info[0].value_type(), &u64::type_name());
+    assert_eq!(info[0].entry_count(), 3000);
+    // The values of each key are stored in a subtree, which is included
+    assert!(info[0].page_count() > 3);
+
+    assert!(!info[1].is_multimap());
+    assert_eq!(info[1].entry_count(), 10_000);
+    assert!(info[1].page_count() > 1);
+
+    assert!(!info[2].is_multimap());
+    assert_eq!(info[2].key_type(), &<&str>::type_name());
+    assert_eq!(info[2].entry_count(), 0);
+    assert_eq!(info[2].page_count(), 0);
+}
+
+#[test]
+fn table_page_counts_are_independent() {
+    let tmpfile = create_tempfile();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let definition_a: TableDefinition<u64, &[u8]> = TableDefinition::new("a");
+    let definition_b: TableDefinition<u64, &[u8]> = TableDefinition::new("b");
+    let value = vec![0u8; 1024];
+
+    let page_counts = || {
+        let read_txn = db.begin_read().unwrap();
+        let info = read_txn.list_table_info().unwrap();
+        assert_eq!(info.len(), 2);
+        assert!(read_txn.catalog_page_count().unwrap() > 0);
+        (info[0].page_count(), info[1].page_count())
+    };
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(definition_a).unwrap();
+        for i in 0..100 {
+            table.insert(i, value.as_slice()).unwrap();
+        }
+        write_txn.open_table(definition_b).unwrap();
+    }
+    write_txn.commit().unwrap();
+    let (a_pages, b_pages) = page_counts();
+    assert!(a_pages > 1);
+    assert_eq!(b_pages, 0);
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(definition_b).unwrap();
+        for i in 0..10 {
+            table.insert(i, value.as_slice()).unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+    let (a_pages2, b_pages) = page_counts();
+    assert_eq!(a_pages2, a_pages);
+    assert!(b_pages > 0);
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(definition_a).unwrap();
+        for i in 0..100 {
+            table.remove(i).unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+    let (a_pages, b_pages2) = page_counts();
+    assert_eq!(a_pages, 0);
+    assert_eq!(b_pages2, b_pages);
+}
+
+#[test]
+fn compact_table() {
+    let tmpfile = create_tempfile();
+    let mut db = Database::create(tmpfile.path()).unwrap();
+    let definition_a: TableDefinition<u64, &[u8]> = TableDefinition::new("a");
+    let definition_b: TableDefinition<u64, &[u8]> = TableDefinition::new("b");
+    let value = vec![0u8; 100];
+
+    let page_counts = || {
+        let read_txn = db.begin_read().unwrap();
+        let info = read_txn.list_table_info().unwrap();
+        (info[0].page_count(), info[1].page_count())
+    };
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(definition_a).unwrap();
+        for i in 0..1000 {
+            table.insert(i, value.as_slice()).unwrap();
+        }
+        let mut table = write_txn.open_table(definition_b).unwrap();
+        for i in 0..100 {
+            table.insert(i, value.as_slice()).unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+
+    // Leave most of the leaves of "a" sparsely filled
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(definition_a).unwrap();
+        for i in 0..1000 {
+            if i % 4 != 0 {
+                table.remove(i).unwrap();
+            }
+        }
+    }
+    write_txn.commit().unwrap();
+    let (a_pages, b_pages) = page_counts();
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let _table = write_txn.open_table(definition_a).unwrap();
+        assert!(write_txn.compact_table(definition_a).is_err());
+    }
+    write_txn.compact_table(definition_a).unwrap();
+    assert!(write_txn
+        .compact_table(TableDefinition::<u64, u64>::new("missing"))
+        .is_err());
+    write_txn.commit().unwrap();
+
+    let (compacted_a_pages, compacted_b_pages) = page_counts();
+    assert!(compacted_a_pages < a_pages);
+    assert_eq!(compacted_b_pages, b_pages);
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(definition_a).unwrap();
+    assert_eq!(table.len().unwrap(), 250);
+    for (i, entry) in table.iter().unwrap().enumerate() {
+        let (key, stored) = entry.unwrap();
+        assert_eq!(key.value(), 4 * i as u64);
+        assert_eq!(stored.value(), value.as_slice());
+    }
+    drop(table);
+    drop(read_txn);
+    db.check_integrity().unwrap();
+}
+
+#[test]
+fn transaction_stats() {
+    let tmpfile = create_tempfile();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let value = vec![0u8; 1024];
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(SLICE_TABLE).unwrap();
+        for i in 0u32..100 {
+            table
+                .insert(i.to_le_bytes().as_slice(), value.as_slice())
+                .unwrap();
+        }
+    }
+    let stats = write_txn.commit_with_stats().unwrap();
+    // 100KiB of values needs at least 25 pages, but shouldn't be spread over more than one per key
+    assert!(stats.allocated() >= 25);
+    assert!(stats.allocated() <= 100);
+    assert_eq!(stats.freed(), 0);
+    assert_eq!(stats.net(), stats.allocated() as isize);
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(SLICE_TABLE).unwrap();
+        for i in 0u32..100 {
+            table.remove(i.to_le_bytes().as_slice()).unwrap();
+        }
+    }
+    let stats = write_txn.commit_with_stats().unwrap();
+    assert!(stats.freed() >= 25);
+    assert!(stats.net() < 0);
+
+    // Releasing the pages freed by the last commit only rewrites the list of freed pages, which
+    // is not counted
+    let stats = db.begin_write().unwrap().commit_with_stats().unwrap();
+    assert_eq!(stats.freed(), 0);
+}
+
+#[test]
+fn commit_dry_run() {
+    let tmpfile = create_tempfile();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let value = vec![0u8; 1024];
+    let write = || {
+        let write_txn = db.begin_write().unwrap();
+        {
+            let mut table = write_txn.open_table(SLICE_TABLE).unwrap();
+            for i in 0u32..100 {
+                table
+                    .insert(i.to_le_bytes().as_slice(), value.as_slice())
+                    .unwrap();
+            }
+        }
+        write_txn
+    };
+
+    let dry_run = write().commit_dry_run().unwrap();
+    assert!(dry_run.allocated() > 0);
+    let read_txn = db.begin_read().unwrap();
+    assert!(read_txn.list_tables().unwrap().next().is_none());
+    drop(read_txn);
+
+    assert_eq!(write().commit_with_stats().unwrap(), dry_run);
+
+    let remove = || {
+        let write_txn = db.begin_write().unwrap();
+        {
+            let mut table = write_txn.open_table(SLICE_TABLE).unwrap();
+            for i in 0u32..50 {
+                table.remove(i.to_le_bytes().as_slice()).unwrap();
+            }
+        }
+        write_txn
+    };
+    let dry_run = remove().commit_dry_run().unwrap();
+    assert!(dry_run.freed() > 0);
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(SLICE_TABLE).unwrap();
+    assert_eq!(table.len().unwrap(), 100);
+    drop(table);
+    drop(read_txn);
+    assert_eq!(remove().commit_with_stats().unwrap(), dry_run);
+}
+
+#[test]
+fn growth_callback() {
+    let tmpfile = create_tempfile();
+    let growths = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+    let recorded = growths.clone();
+    let db = Database::builder()
+        .set_growth_callback(move |old_size, new_size, transaction_id| {
+            recorded
+                .lock()
+                .unwrap()
+                .push((old_size, new_size, transaction_id));
+        })
+        .create(tmpfile.path())
+        .unwrap();
+    growths.lock().unwrap().clear();
+
+    let initial_size = db.logical_size().unwrap();
+    let value = vec![0u8; 1024];
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(SLICE_TABLE).unwrap();
+        let mut i = 0u32;
+        while db.logical_size().unwrap() == initial_size {
+            assert!(growths.lock().unwrap().is_empty());
+            table
+                .insert(i.to_le_bytes().as_slice(), value.as_slice())
+                .unwrap();
+            i += 1;
+        }
+    }
+    write_txn.commit().unwrap();
+
+    let header = Database::verify_header(tmpfile.path()).unwrap();
+    let transaction_id = header.slots()[header.primary_slot()].transaction_id();
+    assert_eq!(
+        *growths.lock().unwrap(),
+        [(initial_size, db.logical_size().unwrap(), transaction_id)]
+    );
+}
+
 #[test]
 // Test that these signatures compile
 fn tuple_type_function_lifetime() {
@@ -862,6 +1374,52 @@ fn read_isolation2() {
     assert_eq!(table.len().unwrap(), 1);
 }
 
+#[test]
+fn read_isolation_across_commits() {
+    let tmpfile = create_tempfile();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        for i in 0..1000 {
+            table.insert(i, i).unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+
+    // Overwrite and remove every key, so that the pages seen by the reader are freed by the writer
+    for commit in 1..=10 {
+        let write_txn = db.begin_write().unwrap();
+        {
+            let mut write_table = write_txn.open_table(U64_TABLE).unwrap();
+            for i in 0..1000 {
+                if commit % 2 == 0 {
+                    write_table.remove(i).unwrap();
+                } else {
+                    write_table.insert(i, i + commit).unwrap();
+                }
+            }
+        }
+        write_txn.commit().unwrap();
+    }
+
+    assert_eq!(table.len().unwrap(), 1000);
+    for (i, entry) in table.iter().unwrap().enumerate() {
+        let (key, value) = entry.unwrap();
+        assert_eq!(key.value(), i as u64);
+        assert_eq!(value.value(), i as u64);
+    }
+    drop(table);
+    drop(read_txn);
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    assert!(table.is_empty().unwrap());
+}
+
 #[test]
 fn reopen_table() {
     let tmpfile = create_tempfile();
@@ -975,6 +1533,41 @@ fn str_type() {
     assert!(iter.next().is_none());
 }
 
+#[test]
+fn string_type() {
+    let tmpfile = create_tempfile();
+    let db = Database::create(tmpfile.path()).unwrap();
+
+    let definition: TableDefinition<u64, String> = TableDefinition::new("x");
+    let reverse: TableDefinition<String, u64> = TableDefinition::new("y");
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(definition).unwrap();
+        table.insert(&0, &"hello".to_string()).unwrap();
+        table.insert(&1, &"world".to_string()).unwrap();
+        let mut table = write_txn.open_table(reverse).unwrap();
+        table.insert(&"world".to_string(), &1).unwrap();
+        table.insert(&"hello".to_string(), &0).unwrap();
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(definition).unwrap();
+    let value: String = table.get(&1).unwrap().unwrap().value();
+    assert_eq!(value, "world");
+
+    let table = read_txn.open_table(reverse).unwrap();
+    let mut iter = table.iter().unwrap();
+    assert_eq!(iter.next().unwrap().unwrap().0.value(), "hello");
+    assert_eq!(iter.next().unwrap().unwrap().0.value(), "world");
+    assert!(iter.next().is_none());
+
+    let mut iter: Range<String, u64> = table.range("a".to_string().."i".to_string()).unwrap();
+    assert_eq!(iter.next().unwrap().unwrap().1.value(), 0);
+    assert!(iter.next().is_none());
+}
+
 #[test]
 fn empty_type() {
     let tmpfile = create_tempfile();
@@ -1403,3 +1996,777 @@ fn signature_lifetimes() {
     }
     write_txn.commit().unwrap();
 }
+
+#[test]
+fn paginate_with_token() {
+    let tmpfile = create_tempfile();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(STR_TABLE).unwrap();
+        for i in 0..1000 {
+            table.insert(format!("{i:04}").as_str(), "value").unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+
+    let mut seen = vec![];
+    let mut token: Option<Vec<u8>> = None;
+    loop {
+        // Each page is served from a fresh snapshot
+        let read_txn = db.begin_read().unwrap();
+        let table = read_txn.open_table(STR_TABLE).unwrap();
+        let mut iter = if let Some(bytes) = &token {
+            table
+                .range_from_token(&IteratorToken::from_bytes(bytes))
+                .unwrap()
+        } else {
+            table.iter().unwrap()
+        };
+        assert!(iter.position_token().is_none());
+        for entry in iter.by_ref().take(64) {
+            seen.push(entry.unwrap().0.value().to_string());
+        }
+        if let Some(next) = iter.position_token() {
+            token = Some(next.to_bytes());
+        }
+        if iter.next().is_none() {
+            break;
+        }
+    }
+
+    let expected: Vec<String> = (0..1000).map(|i| format!("{i:04}")).collect();
+    assert_eq!(seen, expected);
+
+    // A token whose key can't be a u64 is rejected
+    let write_txn = db.begin_write().unwrap();
+    write_txn.open_table(U64_TABLE).unwrap();
+    write_txn.commit().unwrap();
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    assert!(matches!(
+        table.range_from_token(&IteratorToken::from_bytes(b"0999")),
+        Err(redb::StorageError::KeyWrongSize {
+            expected: 8,
+            actual: 4
+        })
+    ));
+}
+
+#[test]
+fn keys() {
+    let tmpfile = create_tempfile();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        for i in 0..100 {
+            table.insert(i, i * 2).unwrap();
+        }
+        let keys: Vec<u64> = table.keys().unwrap().map(|k| k.unwrap().value()).collect();
+        assert_eq!(keys, (0..100).collect::<Vec<u64>>());
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    let reversed: Vec<u64> = table
+        .keys()
+        .unwrap()
+        .rev()
+        .map(|k| k.unwrap().value())
+        .collect();
+    assert_eq!(reversed, (0..100).rev().collect::<Vec<u64>>());
+    let mut iter = table.keys().unwrap();
+    assert_eq!(iter.next().unwrap().unwrap().value(), 0);
+    assert_eq!(iter.next_back().unwrap().unwrap().value(), 99);
+}
+
+#[test]
+fn get_many() {
+    let tmpfile = create_tempfile();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        // Enough entries for a tree with several levels. Only even keys exist
+        for i in 0..10_000 {
+            table.insert(i * 2, i).unwrap();
+        }
+        let values: Vec<_> = table
+            .get_many(&[4, 3, 4])
+            .unwrap()
+            .into_iter()
+            .map(|x| x.map(|x| x.value()))
+            .collect();
+        assert_eq!(values, vec![Some(2), None, Some(2)]);
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    assert!(table.get_many(&[]).unwrap().is_empty());
+    // Unsorted, with duplicates, missing keys, and keys beyond both ends of the table
+    let keys: Vec<u64> = (0..500)
+        .map(|i| i * 7919 % 20_011)
+        .chain([0, 0, 19_998, 19_998, 25_000, 1])
+        .collect();
+    let results = table.get_many(&keys).unwrap();
+    assert_eq!(results.len(), keys.len());
+    for (key, result) in keys.iter().zip(results) {
+        let expected = table.get(key).unwrap().map(|x| x.value());
+        assert_eq!(result.map(|x| x.value()), expected);
+        assert_eq!(
+            expected,
+            if key % 2 == 0 && *key < 20_000 {
+                Some(key / 2)
+            } else {
+                None
+            }
+        );
+    }
+}
+
+#[test]
+fn get_or_insert_with() {
+    let tmpfile = create_tempfile();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(STR_TABLE).unwrap();
+        // Into an empty table
+        let value = table.get_or_insert_with("hello", || "world").unwrap();
+        assert_eq!(value.value(), "world");
+        drop(value);
+        let value = table
+            .get_or_insert_with("hello", || -> &str { panic!() })
+            .unwrap();
+        assert_eq!(value.value(), "world");
+    }
+    write_txn.commit().unwrap();
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        // Enough entries for a tree with several levels. Only even keys exist
+        for i in 0..10_000 {
+            table.insert(i * 2, i).unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        let mut calls = 0;
+        for key in [0, 4, 19_998] {
+            let value = table
+                .get_or_insert_with(key, || {
+                    calls += 1;
+                    0
+                })
+                .unwrap();
+            assert_eq!(value.value(), key / 2);
+        }
+        assert_eq!(calls, 0);
+        for key in [1, 5, 25_000] {
+            let value = table
+                .get_or_insert_with(key, || {
+                    calls += 1;
+                    key * 10
+                })
+                .unwrap();
+            assert_eq!(value.value(), key * 10);
+        }
+        assert_eq!(calls, 3);
+        assert_eq!(table.len().unwrap(), 10_003);
+    }
+    write_txn.commit().unwrap();
+
+    // Rolled back with the transaction
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        assert_eq!(table.get_or_insert_with(7, || 70).unwrap().value(), 70);
+        assert_eq!(table.get(7).unwrap().unwrap().value(), 70);
+    }
+    write_txn.abort().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    assert_eq!(table.len().unwrap(), 10_003);
+    assert_eq!(table.get(4).unwrap().unwrap().value(), 2);
+    assert_eq!(table.get(5).unwrap().unwrap().value(), 50);
+    assert_eq!(table.get(25_000).unwrap().unwrap().value(), 250_000);
+    assert!(table.get(7).unwrap().is_none());
+    let table = read_txn.open_table(STR_TABLE).unwrap();
+    assert_eq!(table.get("hello").unwrap().unwrap().value(), "world");
+}
+
+#[test]
+fn raw_bytes_outlive_writes() {
+    let tmpfile = create_tempfile();
+    let db = Database::builder()
+        .set_cache_size(0)
+        .create(tmpfile.path())
+        .unwrap();
+    let value = vec![7u8; 100_000];
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(SLICE_TABLE).unwrap();
+        table.insert(b"a".as_slice(), value.as_slice()).unwrap();
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(SLICE_TABLE).unwrap();
+    let guard = table.get(b"a".as_slice()).unwrap().unwrap();
+    let bytes = guard.raw_bytes();
+    // The value is borrowed from the page, not copied
+    assert_eq!(bytes.as_ptr(), guard.value().as_ptr());
+    assert_eq!(bytes.as_ptr(), guard.raw_bytes().as_ptr());
+
+    // Overwrite the value from another thread, and fill the space it occupied
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            for i in 0..3u8 {
+                let write_txn = db.begin_write().unwrap();
+                {
+                    let mut table = write_txn.open_table(SLICE_TABLE).unwrap();
+                    table.remove(b"a".as_slice()).unwrap();
+                    table
+                        .insert([i].as_slice(), vec![i; 100_000].as_slice())
+                        .unwrap();
+                }
+                write_txn.commit().unwrap();
+            }
+        });
+    });
+    assert_eq!(bytes, value.as_slice());
+    drop(guard);
+    drop(table);
+    drop(read_txn);
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(SLICE_TABLE).unwrap();
+    assert!(table.get(b"a".as_slice()).unwrap().is_none());
+}
+
+#[test]
+fn get_into() {
+    let tmpfile = create_tempfile();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(SLICE_TABLE).unwrap();
+        table
+            .insert(b"small".as_slice(), [1u8; 4].as_slice())
+            .unwrap();
+        // Stored in its own overflow page
+        table
+            .insert(b"large".as_slice(), vec![2u8; 10_000].as_slice())
+            .unwrap();
+        let mut buffer = vec![];
+        assert!(table.get_into(b"small".as_slice(), &mut buffer).unwrap());
+        assert_eq!(buffer, [1u8; 4]);
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(SLICE_TABLE).unwrap();
+    let mut buffer = Vec::with_capacity(16);
+    assert!(table.get_into(b"small".as_slice(), &mut buffer).unwrap());
+    assert_eq!(buffer, [1u8; 4]);
+    // The buffer grows for a value which does not fit in its capacity
+    assert!(table.get_into(b"large".as_slice(), &mut buffer).unwrap());
+    assert_eq!(buffer, vec![2u8; 10_000]);
+    let capacity = buffer.capacity();
+    // and keeps its capacity when reused
+    assert!(table.get_into(b"small".as_slice(), &mut buffer).unwrap());
+    assert_eq!(buffer, [1u8; 4]);
+    assert_eq!(buffer.capacity(), capacity);
+    assert!(!table.get_into(b"missing".as_slice(), &mut buffer).unwrap());
+    assert!(buffer.is_empty());
+
+    // The buffer holds the serialized value
+    let write_txn = db.begin_write().unwrap();
+    write_txn
+        .open_table(U64_TABLE)
+        .unwrap()
+        .insert(1, 7)
+        .unwrap();
+    write_txn.commit().unwrap();
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    assert!(table.get_into(1, &mut buffer).unwrap());
+    assert_eq!(u64::from_bytes(&buffer), 7);
+}
+
+#[test]
+fn estimate_range_len() {
+    let tmpfile = create_tempfile();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        assert_eq!(table.estimate_range_len::<u64>(..).unwrap(), 0);
+        for i in 0..20_000 {
+            table.insert(i, i).unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    let check = |estimate: u64, actual: u64| {
+        let error = estimate.abs_diff(actual);
+        assert!(error <= actual / 10, "estimate {estimate}, actual {actual}");
+    };
+    check(table.estimate_range_len::<u64>(..).unwrap(), 20_000);
+    check(table.estimate_range_len(1_000..19_000).unwrap(), 18_000);
+    check(table.estimate_range_len(5_000..=9_999).unwrap(), 5_000);
+    check(table.estimate_range_len(..10_000).unwrap(), 10_000);
+    check(table.estimate_range_len(15_000..).unwrap(), 5_000);
+    // Ranges within a single leaf are counted exactly
+    assert_eq!(table.estimate_range_len(100..110).unwrap(), 10);
+    assert_eq!(table.estimate_range_len(100..=110).unwrap(), 11);
+    assert_eq!(table.estimate_range_len(100..100).unwrap(), 0);
+    assert_eq!(table.estimate_range_len(30_000..).unwrap(), 0);
+}
+
+#[test]
+fn prefetch_range() {
+    let tmpfile = create_tempfile();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        assert_eq!(table.prefetch_range::<u64>(..).unwrap(), 0);
+        for i in 0..20_000 {
+            table.insert(i, i).unwrap();
+        }
+        assert!(table.prefetch_range::<u64>(..).unwrap() > 1);
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let table_pages = read_txn.list_table_info().unwrap()[0].page_count();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    assert_eq!(table.prefetch_range::<u64>(..).unwrap(), table_pages);
+    // A range within a single leaf only reads the path to it
+    let path = table.prefetch_range(100..110).unwrap();
+    assert!(path > 1);
+    assert_eq!(table.prefetch_range(100..=100).unwrap(), path);
+    let half = table.prefetch_range(..10_000).unwrap();
+    assert!(half > path);
+    assert!(half < table_pages);
+    assert!(table.prefetch_range(10_000..).unwrap() < table_pages);
+}
+
+#[test]
+fn merge_join() {
+    let tmpfile = create_tempfile();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let left_definition: TableDefinition<u64, u64> = TableDefinition::new("left");
+    let right_definition: TableDefinition<u64, &str> = TableDefinition::new("right");
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut left = write_txn.open_table(left_definition).unwrap();
+        for i in [1, 2, 4, 6, 8] {
+            left.insert(i, i * 10).unwrap();
+        }
+        let mut right = write_txn.open_table(right_definition).unwrap();
+        for i in [0, 2, 3, 4, 8, 9, 10] {
+            right.insert(i, "x").unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+
+    let read_txn = db.begin_read().unwrap();
+    let left = read_txn.open_table(left_definition).unwrap();
+    let right = read_txn.open_table(right_definition).unwrap();
+    let join = |mode| -> Vec<(u64, Option<u64>, bool)> {
+        left.iter()
+            .unwrap()
+            .merge_join(right.iter().unwrap(), mode)
+            .map(|row| {
+                let (key, l, r) = row.unwrap();
+                (key.value(), l.map(|x| x.value()), r.is_some())
+            })
+            .collect()
+    };
+
+    assert_eq!(
+        join(JoinMode::Inner),
+        vec![
+            (2, Some(20), true),
+            (4, Some(40), true),
+            (8, Some(80), true)
+        ]
+    );
+    assert_eq!(
+        join(JoinMode::Left),
+        vec![
+            (1, Some(10), false),
+            (2, Some(20), true),
+            (4, Some(40), true),
+            (6, Some(60), false),
+            (8, Some(80), true),
+        ]
+    );
+    let outer: Vec<u64> = join(JoinMode::Outer).iter().map(|x| x.0).collect();
+    assert_eq!(outer, vec![0, 1, 2, 3, 4, 6, 8, 9, 10]);
+
+    // The right side ends first
+    let inner: Vec<u64> = left
+        .iter()
+        .unwrap()
+        .merge_join(right.range(..3).unwrap(), JoinMode::Inner)
+        .map(|row| row.unwrap().0.value())
+        .collect();
+    assert_eq!(inner, vec![2]);
+}
+
+#[test]
+fn resume_etl_scan() {
+    let tmpfile = create_tempfile();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        for i in 0..100 {
+            table.insert(i, i).unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+
+    let mut scan = db.etl_scan(U64_TABLE).unwrap();
+    assert_eq!(
+        EtlScanToken::from_bytes(&scan.position_token().to_bytes()),
+        Some(scan.position_token())
+    );
+    let first: Vec<u64> = scan
+        .by_ref()
+        .take(50)
+        .map(|entry| entry.unwrap().0.value())
+        .collect();
+    assert_eq!(first, (0..50).collect::<Vec<u64>>());
+    let token = scan.position_token().to_bytes();
+    drop(scan);
+
+    // Changes made after the scan began are not seen by it
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        table.remove(75).unwrap();
+        table.insert(1000, 1000).unwrap();
+    }
+    write_txn.commit().unwrap();
+    drop(db);
+
+    let db = Database::open(tmpfile.path()).unwrap();
+    let token = EtlScanToken::from_bytes(&token).unwrap();
+    let rest: Vec<u64> = db
+        .resume_etl_scan(U64_TABLE, &token)
+        .unwrap()
+        .map(|entry| entry.unwrap().0.value())
+        .collect();
+    assert_eq!(rest, (50..100).collect::<Vec<u64>>());
+
+    // A token whose key can't be a u64 is rejected
+    let mut mangled = token.to_bytes();
+    mangled.pop();
+    assert!(matches!(
+        db.resume_etl_scan(U64_TABLE, &EtlScanToken::from_bytes(&mangled).unwrap()),
+        Err(redb::Error::KeyWrongSize {
+            expected: 8,
+            actual: 7
+        })
+    ));
+
+    // Once the snapshot is discarded, the scan can't be resumed
+    let write_txn = db.begin_write().unwrap();
+    assert!(write_txn
+        .delete_persistent_savepoint(token.savepoint())
+        .unwrap());
+    write_txn.commit().unwrap();
+    assert!(matches!(
+        db.resume_etl_scan(U64_TABLE, &token),
+        Err(redb::Error::InvalidSavepoint)
+    ));
+}
+
+#[test]
+fn cold_page_callback() {
+    let tmpfile = create_tempfile();
+    let table_def: TableDefinition<u64, &[u8]> = TableDefinition::new("x");
+    let entries = 5000;
+    let value = vec![0u8; 1000];
+
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(table_def).unwrap();
+        for i in 0..entries {
+            table.insert(i, value.as_slice()).unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+    drop(db);
+
+    let cold_reads = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+    let recorded = cold_reads.clone();
+    let db = Database::builder()
+        .set_cold_page_callback(move |offset| recorded.lock().unwrap().push(offset))
+        .open(tmpfile.path())
+        .unwrap();
+    cold_reads.lock().unwrap().clear();
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(table_def).unwrap();
+    assert_eq!(table.iter().unwrap().count(), entries as usize);
+
+    // Each page of the table is reported when the scan first reaches it, and rarely again
+    let cold_reads = cold_reads.lock().unwrap();
+    let distinct: std::collections::HashSet<u64> = cold_reads.iter().copied().collect();
+    let min_pages = entries as usize * value.len() / 4096;
+    assert!(distinct.len() >= min_pages);
+    assert!(cold_reads.len() <= distinct.len() + distinct.len() / 10);
+}
+
+#[test]
+fn replace_table() {
+    let tmpfile = create_tempfile();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        for i in 0..1000u64 {
+            table.insert(i, i).unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+
+    let old_snapshot = db.begin_read().unwrap();
+    let write_txn = db.begin_write().unwrap();
+    write_txn
+        .replace_table(U64_TABLE, (500..2000u64).rev().map(|i| (i, i * 2)))
+        .unwrap();
+    // A table which does not exist yet is created, and the last value of a duplicated key is kept
+    write_txn
+        .replace_table(STR_TABLE, [("b", "1"), ("a", "2"), ("b", "3")])
+        .unwrap();
+    write_txn.commit().unwrap();
+
+    let old_table = old_snapshot.open_table(U64_TABLE).unwrap();
+    assert_eq!(old_table.len().unwrap(), 1000);
+    assert_eq!(old_table.get(0).unwrap().unwrap().value(), 0);
+    assert_eq!(old_table.get(999).unwrap().unwrap().value(), 999);
+    drop(old_table);
+
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(U64_TABLE).unwrap();
+    assert_eq!(table.len().unwrap(), 1500);
+    assert!(table.get(0).unwrap().is_none());
+    assert_eq!(table.get(999).unwrap().unwrap().value(), 1998);
+    let keys: Vec<u64> = table
+        .iter()
+        .unwrap()
+        .map(|entry| entry.unwrap().0.value())
+        .collect();
+    assert_eq!(keys, (500..2000).collect::<Vec<u64>>());
+    drop(table);
+    let table = read_txn.open_table(STR_TABLE).unwrap();
+    assert_eq!(table.len().unwrap(), 2);
+    assert_eq!(table.get("a").unwrap().unwrap().value(), "2");
+    assert_eq!(table.get("b").unwrap().unwrap().value(), "3");
+    drop(table);
+    drop(read_txn);
+
+    // The pages of the old tree are only freed once the reader which can see them is dropped
+    let allocated = || {
+        let capacity = db.capacity();
+        capacity.usable() - capacity.free()
+    };
+    let before = allocated();
+    db.begin_write().unwrap().commit().unwrap();
+    assert!(allocated() >= before);
+    drop(old_snapshot);
+    db.begin_write().unwrap().commit().unwrap();
+    assert!(allocated() < before);
+}
+
+#[test]
+fn fixed_key_width() {
+    let tmpfile = create_tempfile();
+    let fixed_def: TableDefinition<&[u8], u64> =
+        TableDefinition::new("fixed").with_fixed_key_width(16);
+    let variable_def: TableDefinition<&[u8], u64> = TableDefinition::new("variable");
+
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut fixed = write_txn.open_table(fixed_def).unwrap();
+        let mut variable = write_txn.open_table(variable_def).unwrap();
+        for i in 0..10_000u128 {
+            fixed.insert(i.to_be_bytes().as_slice(), 1).unwrap();
+            variable.insert(i.to_be_bytes().as_slice(), 1).unwrap();
+        }
+        assert!(matches!(
+            fixed.insert([0u8; 3].as_slice(), 1),
+            Err(redb::StorageError::KeyWrongSize {
+                expected: 16,
+                actual: 3
+            })
+        ));
+        fixed.remove(5u128.to_be_bytes().as_slice()).unwrap();
+    }
+    write_txn.commit().unwrap();
+    drop(db);
+
+    let db = Database::open(tmpfile.path()).unwrap();
+    let read_txn = db.begin_read().unwrap();
+    let table = read_txn.open_table(fixed_def).unwrap();
+    assert_eq!(table.len().unwrap(), 9999);
+    assert!(table.get(5u128.to_be_bytes().as_slice()).unwrap().is_none());
+    let keys: Vec<u128> = table
+        .range(6u128.to_be_bytes().as_slice()..)
+        .unwrap()
+        .map(|entry| u128::from_be_bytes(entry.unwrap().0.value().try_into().unwrap()))
+        .collect();
+    assert_eq!(keys, (6..10_000).collect::<Vec<u128>>());
+
+    // The keys are stored without their lengths, so the table takes fewer pages
+    let info = read_txn.list_table_info().unwrap();
+    let pages = |name: &str| info.iter().find(|t| t.name() == name).unwrap().page_count();
+    assert!(pages("fixed") < pages("variable"));
+
+    // The table must be opened with the width it was created with
+    let undeclared: TableDefinition<&[u8], u64> = TableDefinition::new("fixed");
+    assert!(matches!(
+        read_txn.open_table(undeclared),
+        Err(redb::TableError::TypeDefinitionChanged { .. })
+    ));
+}
+
+#[test]
+fn delete_range() {
+    let tmpfile = create_tempfile();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        for i in 0..10_000 {
+            table.insert(i, i).unwrap();
+        }
+        assert_eq!(table.delete_range(2000..=7999).unwrap(), 6000);
+        assert_eq!(table.len().unwrap(), 4000);
+        assert_eq!(table.get(1999).unwrap().unwrap().value(), 1999);
+        assert!(table.get(2000).unwrap().is_none());
+        assert!(table.get(7999).unwrap().is_none());
+        assert_eq!(table.get(8000).unwrap().unwrap().value(), 8000);
+
+        let excluded = (
+            std::ops::Bound::Excluded(100),
+            std::ops::Bound::Excluded(200),
+        );
+        assert_eq!(table.delete_range(excluded).unwrap(), 99);
+        assert_eq!(table.get(100).unwrap().unwrap().value(), 100);
+        assert_eq!(table.get(200).unwrap().unwrap().value(), 200);
+        // Nothing is left to delete
+        assert_eq!(table.delete_range(150..200).unwrap(), 0);
+
+        let keys: Vec<u64> = table
+            .iter()
+            .unwrap()
+            .map(|x| x.unwrap().0.value())
+            .collect();
+        let expected: Vec<u64> = (0..=100).chain(200..2000).chain(8000..10_000).collect();
+        assert_eq!(keys, expected);
+    }
+    write_txn.commit().unwrap();
+
+    // Delete random ranges, of committed and uncommitted data, from a tree with values of varying
+    // sizes, and compare with a BTreeMap
+    let mut expected = std::collections::BTreeMap::new();
+    let mut state = 1u64;
+    let mut next = move |bound: u64| {
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+        (state >> 33) % bound
+    };
+    for round in 0..20 {
+        let write_txn = db.begin_write().unwrap();
+        {
+            let mut table = write_txn.open_table(SLICE_TABLE).unwrap();
+            for _ in 0..2000 {
+                let key = next(100_000).to_be_bytes();
+                let value = vec![0xAB; next(300) as usize];
+                table.insert(key.as_slice(), value.as_slice()).unwrap();
+                expected.insert(key, value);
+            }
+            for _ in 0..3 {
+                let start = next(100_000);
+                let end = start + next(if round % 2 == 0 { 50_000 } else { 500 });
+                let (start, end) = (start.to_be_bytes(), end.to_be_bytes());
+                let removed = table
+                    .delete_range(start.as_slice()..end.as_slice())
+                    .unwrap();
+                let expected_removed = expected.range(start..end).count();
+                assert_eq!(removed, expected_removed as u64);
+                expected.retain(|key, _| *key < start || *key >= end);
+            }
+            assert_eq!(table.len().unwrap(), expected.len() as u64);
+            let entries: Vec<([u8; 8], Vec<u8>)> = table
+                .iter()
+                .unwrap()
+                .map(|x| {
+                    let (key, value) = x.unwrap();
+                    (key.value().try_into().unwrap(), value.value().to_vec())
+                })
+                .collect();
+            assert!(entries.into_iter().eq(expected.clone()));
+        }
+        write_txn.commit().unwrap();
+    }
+    drop(db);
+
+    let report = Database::corruption_report(tmpfile.path()).unwrap();
+    assert!(report.is_clean(), "{report:?}");
+
+    // The removed keys are collected when changes are tracked, including those of the subtrees
+    // which lie entirely within the range
+    let tmpfile = create_tempfile();
+    let db = Database::builder()
+        .set_track_changes(true)
+        .create(tmpfile.path())
+        .unwrap();
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(U64_TABLE).unwrap();
+        for i in 0..10_000 {
+            table.insert(i, i).unwrap();
+        }
+    }
+    write_txn.commit().unwrap();
+    let write_txn = db.begin_write().unwrap();
+    assert_eq!(
+        write_txn
+            .open_table(U64_TABLE)
+            .unwrap()
+            .delete_range(2000..8000)
+            .unwrap(),
+        6000
+    );
+    write_txn.commit().unwrap();
+    // The changes are ordered by serialized key, which is little endian
+    let mut deleted: Vec<u64> = db
+        .last_commit_changes()
+        .map(|change| match change {
+            ChangeKind::Deleted { key, .. } => u64::from_bytes(&key),
+            other => panic!("{other:?}"),
+        })
+        .collect();
+    deleted.sort_unstable();
+    assert_eq!(deleted, (2000..8000).collect::<Vec<u64>>());
+}
@@ -4,9 +4,12 @@ use std::io::ErrorKind;
 use rand::prelude::SliceRandom;
 use rand::Rng;
 use redb::{
-    Builder, Database, Durability, MultimapTableDefinition, ReadableTable, TableDefinition,
+    Builder, Database, Durability, MissingKey, MultimapTableDefinition, ReadTransaction,
+    ReadableTable, SwapError, TableDefinition,
+};
+use redb::{
+    CommitError, DatabaseError, ReadableMultimapTable, SavepointError, StorageError, TableError,
 };
-use redb::{DatabaseError, ReadableMultimapTable, SavepointError, StorageError, TableError};
 
 const ELEMENTS: usize = 100;
 
@@ -87,6 +90,37 @@ fn non_durable_commit_persistence() {
     }
 }
 
+#[test]
+fn is_reading_non_durable() {
+    let tmpfile = create_tempfile();
+
+    let db = Database::create(tmpfile.path()).unwrap();
+    assert!(!db.is_reading_non_durable());
+
+    let mut txn = db.begin_write().unwrap();
+    txn.set_durability(Durability::None);
+    {
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        table.insert(0, 0).unwrap();
+    }
+    txn.commit().unwrap();
+    assert!(db.is_reading_non_durable());
+    let non_durable_read = db.begin_read().unwrap();
+    assert!(non_durable_read.is_reading_non_durable());
+
+    let mut txn = db.begin_write().unwrap();
+    txn.set_durability(Durability::Eventual);
+    {
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        table.insert(1, 1).unwrap();
+    }
+    txn.commit().unwrap();
+    assert!(!db.is_reading_non_durable());
+    assert!(!db.begin_read().unwrap().is_reading_non_durable());
+    // The flag of an existing transaction is fixed when it begins
+    assert!(non_durable_read.is_reading_non_durable());
+}
+
 fn test_persistence(durability: Durability) {
     let tmpfile = create_tempfile();
 
@@ -1013,6 +1047,73 @@ fn delete_table() {
     assert!(result.is_err());
 }
 
+#[test]
+fn rename_table() {
+    let tmpfile = create_tempfile();
+    let db = Database::create(tmpfile.path()).unwrap();
+
+    let renamed_def: TableDefinition<&str, &str> = TableDefinition::new("renamed");
+    let other_def: TableDefinition<&str, &str> = TableDefinition::new("other");
+    let multimap_def: MultimapTableDefinition<&str, &str> = MultimapTableDefinition::new("mm");
+    let renamed_multimap_def: MultimapTableDefinition<&str, &str> =
+        MultimapTableDefinition::new("mm2");
+
+    let write_txn = db.begin_write().unwrap();
+    {
+        let mut table = write_txn.open_table(STR_TABLE).unwrap();
+        table.insert("hello", "world").unwrap();
+        write_txn.open_table(other_def).unwrap();
+        let mut multimap = write_txn.open_multimap_table(multimap_def).unwrap();
+        multimap.insert("hello", "world").unwrap();
+        multimap.insert("hello", "world2").unwrap();
+    }
+    write_txn.commit().unwrap();
+
+    let write_txn = db.begin_write().unwrap();
+    assert!(matches!(
+        write_txn.rename_table(STR_TABLE, other_def),
+        Err(TableError::TableExists(_))
+    ));
+    assert!(matches!(
+        write_txn.rename_table(renamed_def, other_def),
+        Err(TableError::TableDoesNotExist(_))
+    ));
+    assert!(matches!(
+        write_txn.rename_table(TableDefinition::<&str, &str>::new("mm"), renamed_def),
+        Err(TableError::TableIsMultimap(_))
+    ));
+    {
+        let mut table = write_txn.open_table(STR_TABLE).unwrap();
+        assert!(matches!(
+            write_txn.rename_table(STR_TABLE, renamed_def),
+            Err(TableError::TableAlreadyOpen(_, _))
+        ));
+        // Changes made earlier in the transaction are carried over to the new name
+        table.insert("hello2", "world2").unwrap();
+    }
+    write_txn.rename_table(STR_TABLE, renamed_def).unwrap();
+    write_txn
+        .rename_multimap_table(multimap_def, renamed_multimap_def)
+        .unwrap();
+    write_txn.commit().unwrap();
+    drop(db);
+
+    let db = Database::open(tmpfile.path()).unwrap();
+    let read_txn = db.begin_read().unwrap();
+    assert!(matches!(
+        read_txn.open_table(STR_TABLE),
+        Err(TableError::TableDoesNotExist(_))
+    ));
+    let table = read_txn.open_table(renamed_def).unwrap();
+    assert_eq!(table.len().unwrap(), 2);
+    assert_eq!(table.get("hello").unwrap().unwrap().value(), "world");
+    assert_eq!(table.get("hello2").unwrap().unwrap().value(), "world2");
+    assert!(read_txn.open_multimap_table(multimap_def).is_err());
+    let multimap = read_txn.open_multimap_table(renamed_multimap_def).unwrap();
+    assert_eq!(multimap.get("hello").unwrap().count(), 2);
+    assert_eq!(read_txn.entry_count().unwrap(), 4);
+}
+
 #[test]
 fn delete_all_tables() {
     let tmpfile = create_tempfile();
@@ -1328,3 +1429,1019 @@ fn compaction() {
     let file_size2 = tmpfile.as_file().metadata().unwrap().len();
     assert!(file_size2 < file_size);
 }
+
+#[test]
+fn entry_count() {
+    let tmpfile = create_tempfile();
+    let multimap_definition: MultimapTableDefinition<u64, u64> =
+        MultimapTableDefinition::new("multimap");
+
+    let count_by_walking = |db: &Database| -> u64 {
+        let txn = db.begin_read().unwrap();
+        let mut total = 0;
+        if let Ok(table) = txn.open_table(U64_TABLE) {
+            total += table.len().unwrap();
+        }
+        if let Ok(table) = txn.open_multimap_table(multimap_definition) {
+            total += table.len().unwrap();
+        }
+        total
+    };
+    let entry_count = |db: &Database| -> u64 { db.begin_read().unwrap().entry_count().unwrap() };
+
+    let db = Database::create(tmpfile.path()).unwrap();
+    assert_eq!(entry_count(&db), 0);
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        for i in 0..100 {
+            table.insert(&i, &i).unwrap();
+        }
+        // Overwriting does not add an entry
+        table.insert(&0, &1).unwrap();
+        let mut multimap = txn.open_multimap_table(multimap_definition).unwrap();
+        for i in 0..10 {
+            multimap.insert(&0, &i).unwrap();
+        }
+        // Enough values to be stored in a subtree
+        for i in 0..1000 {
+            multimap.insert(&1, &i).unwrap();
+        }
+        multimap.insert(&1, &0).unwrap();
+    }
+    txn.commit().unwrap();
+    assert_eq!(entry_count(&db), 1110);
+    assert_eq!(entry_count(&db), count_by_walking(&db));
+
+    // Aborted changes are not counted
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        table.drain::<u64>(..50).unwrap();
+        let mut multimap = txn.open_multimap_table(multimap_definition).unwrap();
+        multimap.remove_all(&1).unwrap();
+    }
+    txn.abort().unwrap();
+    assert_eq!(entry_count(&db), 1110);
+
+    let mut txn = db.begin_write().unwrap();
+    txn.set_durability(Durability::None);
+    {
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        table.drain_filter::<u64, _>(.., |k, _| k % 2 == 0).unwrap();
+    }
+    txn.commit().unwrap();
+    assert_eq!(entry_count(&db), 50 + 1010);
+
+    let mut txn = db.begin_write().unwrap();
+    txn.set_durability(Durability::None);
+    {
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        table.drain::<u64>(..10).unwrap();
+        table.remove(&11).unwrap();
+        table.remove(&1000).unwrap();
+        let mut multimap = txn.open_multimap_table(multimap_definition).unwrap();
+        multimap.remove_all(&0).unwrap();
+        multimap.remove(&1, &0).unwrap();
+        multimap.remove(&1, &5000).unwrap();
+    }
+    txn.commit().unwrap();
+    assert_eq!(entry_count(&db), 44 + 999);
+    assert_eq!(entry_count(&db), count_by_walking(&db));
+
+    let txn = db.begin_write().unwrap();
+    txn.delete_table(U64_TABLE).unwrap();
+    txn.commit().unwrap();
+    assert_eq!(entry_count(&db), 999);
+    assert_eq!(entry_count(&db), count_by_walking(&db));
+
+    drop(db);
+    let db = Database::open(tmpfile.path()).unwrap();
+    assert_eq!(entry_count(&db), 999);
+    assert_eq!(entry_count(&db), count_by_walking(&db));
+
+    // Restoring a savepoint recomputes the count
+    let txn = db.begin_write().unwrap();
+    let savepoint = txn.ephemeral_savepoint().unwrap();
+    {
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        table.insert(&0, &0).unwrap();
+    }
+    txn.commit().unwrap();
+    assert_eq!(entry_count(&db), 1000);
+
+    let mut txn = db.begin_write().unwrap();
+    txn.restore_savepoint(&savepoint).unwrap();
+    txn.delete_multimap_table(multimap_definition).unwrap();
+    txn.commit().unwrap();
+    assert_eq!(entry_count(&db), 0);
+    assert_eq!(entry_count(&db), count_by_walking(&db));
+}
+
+#[test]
+fn commit_metadata() {
+    let tmpfile = create_tempfile();
+    let db = Database::create(tmpfile.path()).unwrap();
+    assert!(db
+        .begin_read()
+        .unwrap()
+        .commit_metadata()
+        .unwrap()
+        .is_none());
+
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        table.insert(&0, &0).unwrap();
+    }
+    txn.commit_with_metadata(b"first").unwrap();
+    let first_read = db.begin_read().unwrap();
+
+    let mut txn = db.begin_write().unwrap();
+    txn.set_durability(Durability::None);
+    txn.commit_with_metadata(b"second").unwrap();
+
+    // Each read sees the metadata of the commit it observes
+    assert_eq!(
+        first_read.commit_metadata().unwrap().unwrap(),
+        b"first".to_vec()
+    );
+    assert_eq!(
+        db.begin_read().unwrap().commit_metadata().unwrap().unwrap(),
+        b"second".to_vec()
+    );
+    drop(first_read);
+
+    let txn = db.begin_write().unwrap();
+    txn.commit_with_metadata(b"third").unwrap();
+    drop(db);
+
+    let db = Database::open(tmpfile.path()).unwrap();
+    assert_eq!(
+        db.begin_read().unwrap().commit_metadata().unwrap().unwrap(),
+        b"third".to_vec()
+    );
+
+    // An aborted transaction leaves the metadata unchanged
+    let txn = db.begin_write().unwrap();
+    txn.abort().unwrap();
+    assert_eq!(
+        db.begin_read().unwrap().commit_metadata().unwrap().unwrap(),
+        b"third".to_vec()
+    );
+
+    // Metadata longer than 64 bytes is refused, and the transaction is aborted
+    let txn = db.begin_write().unwrap();
+    txn.open_table(U64_TABLE).unwrap().insert(&1, &1).unwrap();
+    assert!(matches!(
+        txn.commit_with_metadata(&[0; 65]),
+        Err(CommitError::Storage(StorageError::ValueTooLarge(65)))
+    ));
+    let txn = db.begin_write().unwrap();
+    assert!(txn
+        .open_table(U64_TABLE)
+        .unwrap()
+        .get(&1)
+        .unwrap()
+        .is_none());
+    txn.commit_with_metadata(&[0; 64]).unwrap();
+    assert_eq!(
+        db.begin_read().unwrap().commit_metadata().unwrap().unwrap(),
+        vec![0; 64]
+    );
+
+    // A commit without metadata clears it
+    let txn = db.begin_write().unwrap();
+    txn.commit().unwrap();
+    assert!(db
+        .begin_read()
+        .unwrap()
+        .commit_metadata()
+        .unwrap()
+        .is_none());
+    drop(db);
+
+    let db = Database::open(tmpfile.path()).unwrap();
+    assert!(db
+        .begin_read()
+        .unwrap()
+        .commit_metadata()
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn delta_backup() {
+    let tmpfile = create_tempfile();
+    let multimap_def: MultimapTableDefinition<u64, u64> = MultimapTableDefinition::new("mm");
+    let db = Database::create(tmpfile.path()).unwrap();
+
+    let write = |start: u64, end: u64, remove: Option<(u64, u64)>| {
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(U64_TABLE).unwrap();
+            let mut multimap = txn.open_multimap_table(multimap_def).unwrap();
+            for i in start..end {
+                table.insert(i, i * 2).unwrap();
+                for j in 0..10 {
+                    multimap.insert(i % 7, i * 10 + j).unwrap();
+                }
+            }
+            if let Some((start, end)) = remove {
+                for i in start..end {
+                    table.remove(i).unwrap();
+                }
+            }
+        }
+        txn.commit().unwrap();
+    };
+
+    write(0, 1000, None);
+    let mut base = vec![];
+    let watermark0 = db.backup(&mut base).unwrap();
+
+    write(1000, 2000, Some((0, 500)));
+    let mut delta1 = vec![];
+    let watermark1 = db.backup_delta_since(watermark0, &mut delta1).unwrap();
+    assert!(delta1.len() < base.len() * 2);
+
+    // Pages written before the first delta are freed and reused before the second
+    write(2000, 2500, Some((1000, 2000)));
+    write(0, 100, None);
+    let mut delta2 = vec![];
+    db.backup_delta_since(watermark1, &mut delta2).unwrap();
+
+    let restored_file = create_tempfile();
+    // A delta must follow the backup it was taken from
+    assert!(Database::restore(restored_file.path(), [base.as_slice(), delta2.as_slice()]).is_err());
+    let restored = Database::restore(
+        restored_file.path(),
+        [base.as_slice(), delta1.as_slice(), delta2.as_slice()],
+    )
+    .unwrap();
+
+    let live_txn = db.begin_read().unwrap();
+    let restored_txn = restored.begin_read().unwrap();
+    let live_table = live_txn.open_table(U64_TABLE).unwrap();
+    let restored_table = restored_txn.open_table(U64_TABLE).unwrap();
+    let live_entries: Vec<(u64, u64)> = live_table
+        .iter()
+        .unwrap()
+        .map(|x| {
+            let (k, v) = x.unwrap();
+            (k.value(), v.value())
+        })
+        .collect();
+    let restored_entries: Vec<(u64, u64)> = restored_table
+        .iter()
+        .unwrap()
+        .map(|x| {
+            let (k, v) = x.unwrap();
+            (k.value(), v.value())
+        })
+        .collect();
+    assert_eq!(live_entries.len(), 1100);
+    assert_eq!(live_entries, restored_entries);
+
+    let live_multimap = live_txn.open_multimap_table(multimap_def).unwrap();
+    let restored_multimap = restored_txn.open_multimap_table(multimap_def).unwrap();
+    for key in 0..7 {
+        let live_values: Vec<u64> = live_multimap
+            .get(key)
+            .unwrap()
+            .map(|x| x.unwrap().value())
+            .collect();
+        let restored_values: Vec<u64> = restored_multimap
+            .get(key)
+            .unwrap()
+            .map(|x| x.unwrap().value())
+            .collect();
+        assert_eq!(live_values, restored_values);
+    }
+}
+
+#[test]
+fn open_backup_read_only() {
+    let tmpfile = create_tempfile();
+    let backup_file = create_tempfile();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let write = |start: u64, end: u64| {
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(U64_TABLE).unwrap();
+            for i in start..end {
+                table.insert(i, i * 2).unwrap();
+            }
+        }
+        txn.commit().unwrap();
+    };
+    write(0, 1000);
+
+    let done = std::sync::atomic::AtomicBool::new(false);
+    std::thread::scope(|s| {
+        let writer = s.spawn(|| {
+            let mut next = 1000;
+            loop {
+                write(next, next + 10);
+                next += 10;
+                if done.load(std::sync::atomic::Ordering::Acquire) {
+                    return next;
+                }
+            }
+        });
+
+        db.backup(&mut fs::File::create(backup_file.path()).unwrap())
+            .unwrap();
+        // The backup is read while the live database keeps committing
+        let backup = Builder::new().open_read_only(backup_file.path()).unwrap();
+        assert!(backup.check_integrity().unwrap());
+        let txn = backup.begin_read().unwrap();
+        let table = txn.open_table(U64_TABLE).unwrap();
+        let len = table.len().unwrap();
+        assert!(len >= 1000);
+        // The backup holds a single commit, in which every batch of writes is complete
+        assert_eq!(len % 10, 0);
+        for (i, entry) in table.iter().unwrap().enumerate() {
+            let (key, value) = entry.unwrap();
+            assert_eq!(key.value(), i as u64);
+            assert_eq!(value.value(), key.value() * 2);
+        }
+
+        done.store(true, std::sync::atomic::Ordering::Release);
+        assert!(writer.join().unwrap() > 1000);
+    });
+
+    let mut delta = vec![];
+    let watermark = db.backup(&mut vec![]).unwrap();
+    db.backup_delta_since(watermark, &mut delta).unwrap();
+    fs::write(backup_file.path(), delta).unwrap();
+    assert!(Builder::new().open_read_only(backup_file.path()).is_err());
+}
+
+#[test]
+fn transaction_page_limit() {
+    let tmpfile = create_tempfile();
+    let db = Builder::new()
+        .set_transaction_page_limit(16)
+        .create(tmpfile.path())
+        .unwrap();
+    let value = vec![0u8; 1000];
+
+    // A transaction that stays under the limit can commit, even though the commit allocates more pages
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(SLICE_TABLE).unwrap();
+        table.insert(b"small".as_slice(), value.as_slice()).unwrap();
+    }
+    txn.commit().unwrap();
+
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(SLICE_TABLE).unwrap();
+        let mut result = Ok(());
+        for i in 0..1000u32 {
+            result = table
+                .insert(i.to_le_bytes().as_slice(), value.as_slice())
+                .map(|_| ());
+            if result.is_err() {
+                break;
+            }
+        }
+        assert!(matches!(result, Err(StorageError::TransactionTooLarge(16))));
+    }
+    txn.abort().unwrap();
+
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(SLICE_TABLE).unwrap();
+    assert_eq!(table.len().unwrap(), 1);
+}
+
+// A swap which fails part-way must leave both keys unchanged
+#[test]
+fn failed_swap() {
+    let tmpfile = create_tempfile();
+    let db = Database::builder()
+        .set_max_value_pages(1)
+        .create(tmpfile.path())
+        .unwrap();
+    let long_key = vec![1u8; 1000];
+    let large_value = vec![2u8; 3500];
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(SLICE_TABLE).unwrap();
+        table.insert(long_key.as_slice(), b"a".as_slice()).unwrap();
+        table
+            .insert(b"b".as_slice(), large_value.as_slice())
+            .unwrap();
+        // The value of "b" does not fit in a single page with the longer key
+        let result = table.swap(long_key.as_slice(), b"b".as_slice(), MissingKey::Fail);
+        assert!(matches!(
+            result,
+            Err(SwapError::Storage(StorageError::ValueTooLarge(_)))
+        ));
+        assert_eq!(
+            table.get(long_key.as_slice()).unwrap().unwrap().value(),
+            b"a"
+        );
+        assert_eq!(
+            table.get(b"b".as_slice()).unwrap().unwrap().value(),
+            large_value.as_slice()
+        );
+    }
+    txn.commit().unwrap();
+    drop(db);
+
+    let db = Database::create(tmpfile.path()).unwrap();
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        for i in 0..1000 {
+            table.insert(&i, &i).unwrap();
+        }
+    }
+    txn.commit().unwrap();
+    drop(db);
+
+    // The keys lie in different leaves, below a common branch. The smallest limits stop the write
+    // of the second key, and a larger one stops only the write of the first, after which the
+    // second key must be restored
+    for limit in 1..=4 {
+        let db = Database::builder()
+            .set_transaction_page_limit(limit)
+            .open(tmpfile.path())
+            .unwrap();
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(U64_TABLE).unwrap();
+            match table.swap(&0, &999, MissingKey::Fail) {
+                Ok(()) => {
+                    assert_eq!(table.get(&0).unwrap().unwrap().value(), 999);
+                    assert_eq!(table.get(&999).unwrap().unwrap().value(), 0);
+                }
+                Err(SwapError::Storage(StorageError::TransactionTooLarge(_))) => {
+                    assert_eq!(table.get(&0).unwrap().unwrap().value(), 0);
+                    assert_eq!(table.get(&999).unwrap().unwrap().value(), 999);
+                }
+                Err(err) => panic!("{err}"),
+            }
+        }
+        txn.abort().unwrap();
+    }
+}
+
+#[test]
+fn truncated_file() {
+    let tmpfile = create_tempfile();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(SLICE_TABLE).unwrap();
+        table
+            .insert(b"hello".as_slice(), b"world".as_slice())
+            .unwrap();
+    }
+    txn.commit().unwrap();
+    drop(db);
+
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .open(tmpfile.path())
+        .unwrap();
+    let expected = file.metadata().unwrap().len();
+    // Chop off both a whole number of pages, and part of a page
+    for actual in [expected - 4096, expected - 100] {
+        file.set_len(actual).unwrap();
+        match Database::open(tmpfile.path()) {
+            Err(DatabaseError::FileTruncated {
+                expected: e,
+                actual: a,
+            }) => {
+                assert_eq!(e, expected);
+                assert_eq!(a, actual);
+            }
+            other => panic!("unexpected result: {other:?}"),
+        }
+    }
+}
+
+#[test]
+fn file_size_not_page_aligned() {
+    let tmpfile = create_tempfile();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let txn = db.begin_write().unwrap();
+    txn.open_table(U64_TABLE).unwrap().insert(0, 0).unwrap();
+    txn.commit().unwrap();
+    drop(db);
+
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .open(tmpfile.path())
+        .unwrap();
+    let original = file.metadata().unwrap().len();
+    let len = original + 100;
+    file.set_len(len).unwrap();
+    match Database::open(tmpfile.path()) {
+        Err(DatabaseError::InvalidFileSize {
+            len: actual,
+            page_size,
+        }) => {
+            assert_eq!(actual, len);
+            assert_eq!(original % page_size as u64, 0);
+        }
+        other => panic!("unexpected result: {other:?}"),
+    }
+    assert!(matches!(
+        Database::verify_header(tmpfile.path()),
+        Err(DatabaseError::InvalidFileSize { .. })
+    ));
+
+    file.set_len(original).unwrap();
+    let db = Database::open(tmpfile.path()).unwrap();
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(U64_TABLE).unwrap();
+    assert_eq!(table.get(0).unwrap().unwrap().value(), 0);
+}
+
+#[test]
+fn repair_truncated_file() {
+    let tmpfile = create_tempfile();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(SLICE_TABLE).unwrap();
+        table
+            .insert(b"hello".as_slice(), b"world".as_slice())
+            .unwrap();
+    }
+    txn.commit().unwrap();
+    drop(db);
+
+    // Only free pages are lost
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .open(tmpfile.path())
+        .unwrap();
+    let expected = file.metadata().unwrap().len();
+    file.set_len(expected - 100).unwrap();
+    assert!(matches!(
+        Database::open(tmpfile.path()),
+        Err(DatabaseError::FileTruncated { .. })
+    ));
+
+    // A file which fails validation is left as it is
+    let truncated = fs::read(tmpfile.path()).unwrap();
+    let mut newer_version = truncated.clone();
+    // The version byte of each commit slot
+    newer_version[64] = u8::MAX;
+    newer_version[64 + 128] = u8::MAX;
+    fs::write(tmpfile.path(), &newer_version).unwrap();
+    assert!(Builder::new()
+        .set_repair_truncated(true)
+        .open(tmpfile.path())
+        .is_err());
+    assert_eq!(fs::read(tmpfile.path()).unwrap(), newer_version);
+    fs::write(tmpfile.path(), &truncated).unwrap();
+
+    let db = Builder::new()
+        .set_repair_truncated(true)
+        .open(tmpfile.path())
+        .unwrap();
+    assert!(file.metadata().unwrap().len() < expected);
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(SLICE_TABLE).unwrap();
+        assert_eq!(
+            table.get(b"hello".as_slice()).unwrap().unwrap().value(),
+            b"world"
+        );
+        table
+            .insert(b"hello2".as_slice(), b"world2".as_slice())
+            .unwrap();
+    }
+    txn.commit().unwrap();
+    drop(db);
+
+    let db = Database::open(tmpfile.path()).unwrap();
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(SLICE_TABLE).unwrap();
+    assert_eq!(table.len().unwrap(), 2);
+    drop(table);
+    drop(txn);
+
+    // Pages which are in use are lost
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(SLICE_TABLE).unwrap();
+        let value = vec![0xAB; 1024];
+        for i in 0..10_000u32 {
+            table
+                .insert(i.to_le_bytes().as_slice(), value.as_slice())
+                .unwrap();
+        }
+    }
+    txn.commit().unwrap();
+    drop(db);
+    let len = file.metadata().unwrap().len();
+    file.set_len(len / 2).unwrap();
+    assert!(matches!(
+        Database::open(tmpfile.path()),
+        Err(DatabaseError::FileTruncated { .. })
+    ));
+    let result = Builder::new()
+        .set_repair_truncated(true)
+        .open(tmpfile.path());
+    assert!(
+        matches!(
+            result,
+            Err(DatabaseError::Storage(StorageError::Corrupted(_)))
+        ),
+        "{result:?}"
+    );
+}
+
+#[test]
+fn newer_file_format_version() {
+    let tmpfile = create_tempfile();
+    Database::create(tmpfile.path()).unwrap();
+
+    // A newer format may lay out the header differently, so its version is reported rather than
+    // the layout which this version can't parse
+    let mut data = fs::read(tmpfile.path()).unwrap();
+    // The version byte of each commit slot
+    data[64] = u8::MAX;
+    data[64 + 128] = u8::MAX;
+    // The maximum number of data pages in a region
+    data[20..24].copy_from_slice(&[0; 4]);
+    fs::write(tmpfile.path(), &data).unwrap();
+    let mut strict = Builder::new();
+    strict.set_strict_page_size(true);
+    for builder in [Builder::new(), strict] {
+        match builder.open(tmpfile.path()) {
+            Err(DatabaseError::NewerFileFormat(version)) => assert_eq!(version, u8::MAX),
+            Err(err) => panic!("{err}"),
+            Ok(_) => panic!(),
+        }
+    }
+}
+
+#[test]
+fn no_sync_durability() {
+    let tmpfile = create_tempfile();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let mut txn = db.begin_write().unwrap();
+    txn.set_durability(Durability::NoSync);
+    {
+        let mut table = txn.open_table(SLICE_TABLE).unwrap();
+        table
+            .insert(b"hello".as_slice(), b"world".as_slice())
+            .unwrap();
+    }
+    txn.commit().unwrap();
+
+    let txn = db.begin_read().unwrap();
+    assert!(!txn.is_reading_non_durable());
+    let table = txn.open_table(SLICE_TABLE).unwrap();
+    assert_eq!(
+        table.get(b"hello".as_slice()).unwrap().unwrap().value(),
+        b"world"
+    );
+    drop(table);
+    drop(txn);
+    drop(db);
+
+    let db = Database::open(tmpfile.path()).unwrap();
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(SLICE_TABLE).unwrap();
+    assert_eq!(
+        table.get(b"hello".as_slice()).unwrap().unwrap().value(),
+        b"world"
+    );
+}
+
+#[test]
+fn zero_on_free() {
+    let secret: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+    let marker = &secret[1000..1256];
+    let contains_marker = |data: &[u8]| data.windows(marker.len()).any(|window| window == marker);
+
+    for zero in [false, true] {
+        let tmpfile = create_tempfile();
+        let db = Builder::new()
+            .set_zero_on_free(zero)
+            .create(tmpfile.path())
+            .unwrap();
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(SLICE_TABLE).unwrap();
+            table
+                .insert(b"secret".as_slice(), secret.as_slice())
+                .unwrap();
+        }
+        txn.commit().unwrap();
+        assert!(contains_marker(&fs::read(tmpfile.path()).unwrap()));
+
+        let txn = db.begin_write().unwrap();
+        {
+            let mut table = txn.open_table(SLICE_TABLE).unwrap();
+            table.remove(b"secret".as_slice()).unwrap();
+        }
+        txn.commit().unwrap();
+        // The removed value is freed by a following commit, once no reader can see it
+        for _ in 0..2 {
+            let txn = db.begin_write().unwrap();
+            txn.commit().unwrap();
+        }
+
+        assert_eq!(contains_marker(&fs::read(tmpfile.path()).unwrap()), !zero);
+    }
+}
+
+#[test]
+fn verify_header() {
+    let tmpfile = create_tempfile();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(SLICE_TABLE).unwrap();
+        table
+            .insert(b"hello".as_slice(), b"world".as_slice())
+            .unwrap();
+    }
+    txn.commit().unwrap();
+
+    // The recovery flag is set while the database is open
+    let info = Database::verify_header(tmpfile.path()).unwrap();
+    assert!(info.magic_number_valid());
+    assert!(info.recovery_required());
+    drop(db);
+
+    let before = fs::read(tmpfile.path()).unwrap();
+    let info = Database::verify_header(tmpfile.path()).unwrap();
+    assert!(!info.recovery_required());
+    assert_eq!(info.database_size(), Some(before.len() as u64));
+    assert!(info.slots()[info.primary_slot()].checksum_valid());
+    assert_eq!(fs::read(tmpfile.path()).unwrap(), before);
+
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .open(tmpfile.path())
+        .unwrap();
+    file.set_len(before.len() as u64 - 4096).unwrap();
+    assert!(matches!(
+        Database::verify_header(tmpfile.path()),
+        Err(DatabaseError::FileTruncated { .. })
+    ));
+
+    let not_a_database = create_tempfile();
+    fs::write(not_a_database.path(), vec![0u8; 8192]).unwrap();
+    assert!(matches!(
+        Database::verify_header(not_a_database.path()),
+        Err(DatabaseError::Storage(StorageError::Corrupted(_)))
+    ));
+}
+
+#[test]
+fn embedded_database() {
+    let tmpfile = create_tempfile();
+    let prefix = vec![0xAB; 10_000];
+    let suffix = vec![0xCD; 3_000];
+    let offset = prefix.len() as u64;
+    let len = 8 * 1024 * 1024;
+    {
+        let mut contents = prefix.clone();
+        contents.resize(prefix.len() + len as usize, 0);
+        contents.extend_from_slice(&suffix);
+        fs::write(tmpfile.path(), contents).unwrap();
+    }
+
+    let db = Builder::new()
+        .create_embedded(tmpfile.path(), offset, len)
+        .unwrap();
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        for i in 0..1000 {
+            table.insert(&i, &(i * 2)).unwrap();
+        }
+    }
+    txn.commit().unwrap();
+    drop(db);
+
+    let check_surroundings = || {
+        let contents = fs::read(tmpfile.path()).unwrap();
+        assert_eq!(contents.len(), prefix.len() + len as usize + suffix.len());
+        assert_eq!(&contents[..prefix.len()], prefix.as_slice());
+        assert_eq!(
+            &contents[contents.len() - suffix.len()..],
+            suffix.as_slice()
+        );
+    };
+    check_surroundings();
+
+    // The window now holds a database, so it can't be created again
+    let result = Builder::new().create_embedded(tmpfile.path(), offset, len);
+    assert!(
+        matches!(
+            result,
+            Err(DatabaseError::Storage(StorageError::Io(ref err)))
+                if err.kind() == ErrorKind::AlreadyExists
+        ),
+        "{result:?}"
+    );
+    // Nor can a database be opened where there is none
+    let result = Builder::new().open_embedded(tmpfile.path(), offset + len, len);
+    assert!(
+        matches!(
+            result,
+            Err(DatabaseError::Storage(StorageError::Io(ref err)))
+                if err.kind() == ErrorKind::InvalidData
+        ),
+        "{result:?}"
+    );
+    check_surroundings();
+
+    let db = Builder::new()
+        .open_embedded(tmpfile.path(), offset, len)
+        .unwrap();
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(U64_TABLE).unwrap();
+    assert_eq!(table.len().unwrap(), 1000);
+    for i in 0..1000 {
+        assert_eq!(table.get(&i).unwrap().unwrap().value(), i * 2);
+    }
+    drop(table);
+    drop(txn);
+
+    // The database cannot grow out of its window
+    let txn = db.begin_write().unwrap();
+    let result = {
+        let mut table = txn.open_table(SLICE_TABLE).unwrap();
+        let value = vec![0u8; 1024 * 1024];
+        (0..16u32).try_for_each(|i| {
+            table
+                .insert(i.to_le_bytes().as_slice(), value.as_slice())
+                .map(|_| ())
+        })
+    };
+    assert!(matches!(result, Err(StorageError::Io(_))));
+    txn.abort().unwrap();
+    drop(db);
+    check_surroundings();
+
+    let db = Builder::new()
+        .open_embedded(tmpfile.path(), offset, len)
+        .unwrap();
+    let txn = db.begin_read().unwrap();
+    let table = txn.open_table(U64_TABLE).unwrap();
+    assert_eq!(table.len().unwrap(), 1000);
+}
+
+#[test]
+fn raw_root_traversal() {
+    let tmpfile = create_tempfile();
+    let db = Database::create(tmpfile.path()).unwrap();
+    assert_eq!(db.begin_read().unwrap().raw_root(), None);
+
+    let txn = db.begin_write().unwrap();
+    for i in 0..500 {
+        let name = format!("table_{i:04}");
+        let definition: TableDefinition<u64, u64> = TableDefinition::new(&name);
+        txn.open_table(definition).unwrap().insert(0, 0).unwrap();
+    }
+    txn.commit().unwrap();
+
+    // Follows the leftmost child of each branch page, using the documented page layout, and
+    // returns the first key of the leaf that is reached, and its depth
+    let leftmost_leaf = |txn: &ReadTransaction| {
+        let mut page_number = txn.raw_root().unwrap();
+        let mut depth = 0;
+        loop {
+            let page = txn.read_raw_page(page_number).unwrap();
+            let num_entries = u16::from_le_bytes(page[2..4].try_into().unwrap()) as usize;
+            match page[0] {
+                // Branch: header, then num_entries + 1 checksums, then the child page numbers
+                2 => {
+                    let offset = 8 + 16 * (num_entries + 1);
+                    page_number = u64::from_le_bytes(page[offset..offset + 8].try_into().unwrap());
+                    depth += 1;
+                }
+                // Leaf: header, then the key and value end offsets, then the keys
+                1 => {
+                    let key_start = 4 + 2 * 4 * num_entries;
+                    let key_end = u32::from_le_bytes(page[4..8].try_into().unwrap()) as usize;
+                    return (page[key_start..key_end].to_vec(), depth);
+                }
+                x => panic!("unexpected page type {x}"),
+            }
+        }
+    };
+
+    let read_txn = db.begin_read().unwrap();
+    let (key, depth) = leftmost_leaf(&read_txn);
+    assert_eq!(key, b"table_0000");
+    assert!(depth > 0);
+    assert!(read_txn.read_raw_page(u64::MAX).is_err());
+    // A larger page which overlaps the root is read without disturbing the cached root
+    let root = read_txn.raw_root().unwrap();
+    let order = root >> 59;
+    let index = root & 0x000F_FFFF;
+    let alias = (root & !(0x1F << 59) & !0x000F_FFFF) | ((order + 1) << 59) | (index / 2);
+    assert_eq!(
+        read_txn.read_raw_page(alias).unwrap().len(),
+        2 * 4096 << order
+    );
+    assert!(read_txn.read_raw_page(root | (1 << 45)).is_err());
+    assert_eq!(leftmost_leaf(&read_txn).0, b"table_0000");
+
+    // The snapshot's pages are not reused by later writes, while it is held
+    let txn = db.begin_write().unwrap();
+    for i in 0..100 {
+        let name = format!("table_{i:04}");
+        let definition: TableDefinition<u64, u64> = TableDefinition::new(&name);
+        txn.delete_table(definition).unwrap();
+    }
+    txn.commit().unwrap();
+    assert_eq!(leftmost_leaf(&read_txn), (b"table_0000".to_vec(), depth));
+    drop(read_txn);
+
+    let (key, _) = leftmost_leaf(&db.begin_read().unwrap());
+    assert_eq!(key, b"table_0100");
+}
+
+#[test]
+fn commit_timestamps() {
+    let tmpfile = create_tempfile();
+    let db = Database::create(tmpfile.path()).unwrap();
+    assert!(db.begin_read().unwrap().commit_timestamp().is_none());
+
+    // Non-durable commits are fast enough that many of them share a millisecond
+    let mut timestamps = vec![];
+    for i in 0..100 {
+        let mut txn = db.begin_write().unwrap();
+        if i % 10 != 9 {
+            txn.set_durability(Durability::None);
+        }
+        txn.open_table(U64_TABLE).unwrap().insert(i, i).unwrap();
+        txn.commit().unwrap();
+        timestamps.push(db.begin_read().unwrap().commit_timestamp().unwrap());
+    }
+    for pair in timestamps.windows(2) {
+        assert!(pair[0] < pair[1]);
+        if pair[0].physical_millis() == pair[1].physical_millis() {
+            assert_eq!(pair[0].logical() + 1, pair[1].logical());
+        } else {
+            assert_eq!(pair[1].logical(), 0);
+        }
+    }
+    assert!(timestamps.iter().any(|x| x.logical() > 0));
+
+    // The timestamp of the last commit is persisted, and later commits continue after it
+    drop(db);
+    let db = Database::open(tmpfile.path()).unwrap();
+    let last = *timestamps.last().unwrap();
+    assert_eq!(db.begin_read().unwrap().commit_timestamp(), Some(last));
+    let txn = db.begin_write().unwrap();
+    txn.open_table(U64_TABLE).unwrap().insert(0, 0).unwrap();
+    txn.commit().unwrap();
+    assert!(db.begin_read().unwrap().commit_timestamp().unwrap() > last);
+}
+
+#[cfg(feature = "page_metrics")]
+#[test]
+fn hot_pages() {
+    let tmpfile = create_tempfile();
+    let db = Database::create(tmpfile.path()).unwrap();
+    let txn = db.begin_write().unwrap();
+    {
+        let mut table = txn.open_table(U64_TABLE).unwrap();
+        for i in 0..10_000 {
+            table.insert(i, i).unwrap();
+        }
+    }
+    txn.commit().unwrap();
+    drop(db);
+
+    // The counters start from zero when the database is opened
+    let db = Database::open(tmpfile.path()).unwrap();
+    let txn = db.begin_write().unwrap();
+    {
+        let table = txn.open_table(U64_TABLE).unwrap();
+        for _ in 0..1000 {
+            assert_eq!(table.get(0).unwrap().unwrap().value(), 0);
+        }
+        for _ in 0..200 {
+            assert_eq!(table.get(9_999).unwrap().unwrap().value(), 9_999);
+        }
+    }
+    txn.abort().unwrap();
+
+    // Every lookup reads the root, followed by the leaf which holds the key
+    let hot = db.hot_pages(4);
+    assert_eq!(hot.len(), 4);
+    let (root, leaf0, leaf1) = (hot[0], hot[1], hot[2]);
+    assert!(root.1 >= 1200);
+    assert!(leaf0.1 >= 1000 && leaf0.1 < 1200);
+    assert!(leaf1.1 >= 200 && leaf1.1 < 1000);
+    assert!(hot[3].1 < 200);
+    assert_eq!(db.hot_pages(1), vec![root]);
+}
@@ -1,8 +1,12 @@
 #[cfg(not(target_os = "wasi"))]
 mod multithreading_test {
-    use redb::{Database, ReadableTable, TableDefinition};
+    use redb::{
+        Builder, Database, DatabaseError, Durability, Error, ReadableTable, TableDefinition,
+    };
+    use std::sync::atomic::{AtomicBool, Ordering};
     use std::sync::Arc;
     use std::thread;
+    use std::time::Duration;
 
     fn create_tempfile() -> tempfile::NamedTempFile {
         if cfg!(target_os = "wasi") {
@@ -70,4 +74,135 @@ mod multithreading_test {
         let table = read_txn.open_table(DEF2).unwrap();
         assert_eq!(table.len().unwrap(), 2);
     }
+
+    #[test]
+    fn concurrent_readers_see_whole_commits() {
+        const COUNTERS: TableDefinition<u64, u64> = TableDefinition::new("counters");
+        let tmpfile = create_tempfile();
+        let db = Database::create(tmpfile.path()).unwrap();
+        let done = AtomicBool::new(false);
+
+        // Every commit writes the same value to both keys, and alternates between durable and
+        // non-durable commits, which are read from different commit slots
+        thread::scope(|s| {
+            s.spawn(|| {
+                for i in 0..200 {
+                    let mut write_txn = db.begin_write().unwrap();
+                    if i % 2 == 0 {
+                        write_txn.set_durability(Durability::None);
+                    }
+                    {
+                        let mut table = write_txn.open_table(COUNTERS).unwrap();
+                        table.insert(0, i).unwrap();
+                        table.insert(1, i).unwrap();
+                    }
+                    write_txn.commit().unwrap();
+                }
+                done.store(true, Ordering::Release);
+            });
+            for _ in 0..4 {
+                s.spawn(|| {
+                    let mut last = 0;
+                    while !done.load(Ordering::Acquire) {
+                        let read_txn = db.begin_read().unwrap();
+                        let table = match read_txn.open_table(COUNTERS) {
+                            Ok(table) => table,
+                            // The table doesn't exist until the first commit
+                            Err(_) => continue,
+                        };
+                        let first = table.get(0).unwrap().unwrap().value();
+                        assert_eq!(table.get(1).unwrap().unwrap().value(), first);
+                        assert!(first >= last);
+                        last = first;
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn shared_read_only_database() {
+        const NUMBERS: TableDefinition<u64, u64> = TableDefinition::new("numbers");
+        const READERS: u64 = 8;
+        const ENTRIES: u64 = 1000;
+        let tmpfile = create_tempfile();
+        let db = Database::create(tmpfile.path()).unwrap();
+        let write_txn = db.begin_write().unwrap();
+        {
+            let mut table = write_txn.open_table(NUMBERS).unwrap();
+            for i in 0..ENTRIES {
+                table.insert(i, i * i).unwrap();
+            }
+        }
+        write_txn.commit().unwrap();
+        assert!(matches!(
+            Builder::new().open_read_only(tmpfile.path()),
+            Err(DatabaseError::DatabaseAlreadyOpen)
+        ));
+        drop(db);
+
+        let db = Arc::new(Builder::new().open_read_only(tmpfile.path()).unwrap());
+        let threads: Vec<_> = (0..READERS)
+            .map(|reader| {
+                let db = db.clone();
+                thread::spawn(move || {
+                    let mut reads = 0;
+                    for round in 0..10 {
+                        let txn = db.begin_read().unwrap();
+                        let table = txn.open_table(NUMBERS).unwrap();
+                        for i in ((reader + round) % 7..ENTRIES).step_by(7) {
+                            assert_eq!(table.get(i).unwrap().unwrap().value(), i * i);
+                            reads += 1;
+                        }
+                        let sum: u64 = table
+                            .range(100..200)
+                            .unwrap()
+                            .map(|entry| entry.unwrap().1.value())
+                            .sum();
+                        assert_eq!(sum, (100..200).map(|i| i * i).sum());
+                    }
+                    reads
+                })
+            })
+            .collect();
+        let reads: u64 = threads.into_iter().map(|t| t.join().unwrap()).sum();
+        assert!(reads > READERS * ENTRIES);
+    }
+
+    #[test]
+    fn await_transaction() {
+        let tmpfile = create_tempfile();
+        let db = Database::create(tmpfile.path()).unwrap();
+
+        // Already satisfied
+        let committed = db.begin_read().unwrap().transaction_id();
+        let read_txn = db.await_transaction(committed, Duration::ZERO).unwrap();
+        assert_eq!(read_txn.transaction_id(), committed);
+        drop(read_txn);
+
+        let write_txn = db.begin_write().unwrap();
+        let awaited = write_txn.transaction_id();
+        assert!(matches!(
+            db.await_transaction(awaited, Duration::from_millis(10)),
+            Err(Error::Timeout)
+        ));
+
+        thread::scope(|s| {
+            let reader = s.spawn(|| {
+                let read_txn = db
+                    .await_transaction(awaited, Duration::from_secs(60))
+                    .unwrap();
+                assert!(read_txn.transaction_id() >= awaited);
+                let table = read_txn.open_table(TABLE).unwrap();
+                assert_eq!(table.get("hello").unwrap().unwrap().value(), "world");
+            });
+            thread::sleep(Duration::from_millis(10));
+            {
+                let mut table = write_txn.open_table(TABLE).unwrap();
+                table.insert("hello", "world").unwrap();
+            }
+            write_txn.commit().unwrap();
+            reader.join().unwrap();
+        });
+    }
 }